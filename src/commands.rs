@@ -0,0 +1,193 @@
+// Command table, for what can be checked before even running a command
+
+use crate::error::RedisError;
+use crate::resp::RedisValue;
+
+/// Arity of the supported commands, command name included: a positive arity is the exact number of
+/// arguments expected, a negative one the minimum, as in Redis' command table.
+fn arity(command: &str) -> Option<i32> {
+    Some(match command {
+        "ping" => -1,
+        "echo" => 2,
+        "hello" => -1,
+        "multi" => 1,
+        "exec" => 1,
+        "set" => -3,
+        "get" => 2,
+        "setnx" => 3,
+        "setex" => 4,
+        "psetex" => 4,
+        "getset" => 3,
+        "getdel" => 2,
+        "getex" => -2,
+        "mget" => -2,
+        "mset" => -3,
+        "msetnx" => -3,
+        "incr" => 2,
+        "decr" => 2,
+        "incrby" => 3,
+        "decrby" => 3,
+        "incrbyfloat" => 3,
+        "append" => 3,
+        "strlen" => 2,
+        "getrange" => 4,
+        "substr" => 4,
+        "setrange" => 4,
+        "lcs" => -3,
+        "setbit" => 4,
+        "getbit" => 3,
+        "bitcount" => -2,
+        "bitpos" => -3,
+        "bitop" => -4,
+        "bitfield" => -2,
+        "bitfield_ro" => -2,
+        "pfadd" => -2,
+        "pfcount" => -2,
+        "pfmerge" => -2,
+        "geoadd" => -5,
+        "geodist" => -4,
+        "geohash" => -2,
+        "geopos" => -2,
+        "geosearch" => -7,
+        "geosearchstore" => -8,
+        "json.set" => -4,
+        "json.get" => -2,
+        "json.del" => -2,
+        "json.numincrby" => 4,
+        "json.arrappend" => -4,
+        "json.objkeys" => -2,
+        "lpush" => -3,
+        "rpush" => -3,
+        "lpushx" => -3,
+        "rpushx" => -3,
+        "lpop" => -2,
+        "rpop" => -2,
+        "llen" => 2,
+        "lrange" => 4,
+        "lindex" => 3,
+        "lset" => 4,
+        "lrem" => 4,
+        "ltrim" => 4,
+        "linsert" => 5,
+        "lmove" => 5,
+        "rpoplpush" => 3,
+        "lmpop" => -4,
+        "blpop" => -3,
+        "brpop" => -3,
+        "blmove" => 6,
+        "brpoplpush" => 4,
+        "blmpop" => -5,
+        "hset" => -4,
+        "hmset" => -4,
+        "hsetnx" => 4,
+        "hget" => 3,
+        "hmget" => -3,
+        "hgetall" => 2,
+        "hdel" => -3,
+        "hexists" => 3,
+        "hincrby" => 4,
+        "hincrbyfloat" => 4,
+        "hkeys" => 2,
+        "hvals" => 2,
+        "hlen" => 2,
+        "hstrlen" => 3,
+        "hscan" => -3,
+        "hrandfield" => -2,
+        "hexpire" => -6,
+        "hpexpire" => -6,
+        "hexpireat" => -6,
+        "hpexpireat" => -6,
+        "httl" => -5,
+        "hpttl" => -5,
+        "hexpiretime" => -5,
+        "hpexpiretime" => -5,
+        "hpersist" => -5,
+        "sadd" => -3,
+        "srem" => -3,
+        "smembers" => 2,
+        "sismember" => 3,
+        "smismember" => -3,
+        "scard" => 2,
+        "spop" => -2,
+        "srandmember" => -2,
+        "smove" => 4,
+        "sinter" => -2,
+        "sunion" => -2,
+        "sdiff" => -2,
+        "sinterstore" => -3,
+        "sunionstore" => -3,
+        "sdiffstore" => -3,
+        "sintercard" => -3,
+        "sscan" => -3,
+        "zadd" => -4,
+        "zincrby" => 4,
+        "zrem" => -3,
+        "zcard" => 2,
+        "zscore" => 3,
+        "zmscore" => -3,
+        "zrank" => -3,
+        "zrevrank" => -3,
+        "zcount" => 4,
+        "zlexcount" => 4,
+        "zrange" => -4,
+        "zrangestore" => -5,
+        "zpopmin" => -2,
+        "zpopmax" => -2,
+        "bzpopmin" => -3,
+        "bzpopmax" => -3,
+        "zunionstore" => -4,
+        "zinterstore" => -4,
+        "zdiffstore" => -4,
+        "xadd" => -5,
+        "xrange" => -4,
+        "xrevrange" => -4,
+        "xlen" => 2,
+        "xdel" => -3,
+        "xtrim" => -4,
+        "xread" => -4,
+        "xgroup" => -2,
+        "xreadgroup" => -7,
+        "xack" => -4,
+        "xpending" => -3,
+        "xclaim" => -6,
+        "xautoclaim" => -6,
+        "xinfo" => -2,
+        "del" => -2,
+        "unlink" => -2,
+        "exists" => -2,
+        "type" => 2,
+        "rename" => 3,
+        "renamenx" => 3,
+        "copy" => -3,
+        "expire" => -3,
+        "pexpire" => -3,
+        "expireat" => -3,
+        "pexpireat" => -3,
+        "ttl" => 2,
+        "pttl" => 2,
+        "expiretime" => 2,
+        "pexpiretime" => 2,
+        "persist" => 2,
+        _ => return None,
+    })
+}
+
+/// Ensures `command` exists and is called with a valid number of arguments (`args` excludes the name)
+pub fn check_arity(command: &str, args: &[RedisValue]) -> Result<(), RedisError> {
+    let Some(arity) = arity(command) else {
+        let args = args
+            .iter()
+            .map(|a| {
+                let a = a.keyize();
+                format!("'{}' ", String::from_utf8_lossy(&a[..a.len().min(128)]))
+            })
+            .collect::<String>();
+        return Err(RedisError::UnknownCommand(command.to_string(), args));
+    };
+
+    let given = args.len() as i32 + 1;
+    if (arity > 0 && given != arity) || given < -arity {
+        return Err(RedisError::WrongArity(command.to_string()));
+    }
+    Ok(())
+}
@@ -0,0 +1,114 @@
+// Errors surfaced to clients as RESP error replies
+
+use crate::resp::RedisValue;
+use std::fmt;
+use thiserror::Error;
+
+/// Leading word of an error reply, which clients rely on to tell errors apart
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum ErrorCode {
+    Err,
+    WrongType,
+    ExecAbort,
+    NoAuth,
+    WrongPass,
+    NoProto,
+    BusyKey,
+    NoGroup,
+    /// Any other uppercase code
+    Other(String),
+}
+
+impl ErrorCode {
+    /// Reads the code an error line starts with, if the first word of the line looks like one
+    pub fn from_word(word: &str) -> Option<Self> {
+        if word.is_empty() || !word.bytes().all(|c| c.is_ascii_uppercase() || c.is_ascii_digit()) {
+            return None;
+        }
+        Some(match word {
+            "ERR" => ErrorCode::Err,
+            "WRONGTYPE" => ErrorCode::WrongType,
+            "EXECABORT" => ErrorCode::ExecAbort,
+            "NOAUTH" => ErrorCode::NoAuth,
+            "WRONGPASS" => ErrorCode::WrongPass,
+            "NOPROTO" => ErrorCode::NoProto,
+            "BUSYKEY" => ErrorCode::BusyKey,
+            "NOGROUP" => ErrorCode::NoGroup,
+            other => ErrorCode::Other(other.to_string()),
+        })
+    }
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ErrorCode::Err => "ERR",
+            ErrorCode::WrongType => "WRONGTYPE",
+            ErrorCode::ExecAbort => "EXECABORT",
+            ErrorCode::NoAuth => "NOAUTH",
+            ErrorCode::WrongPass => "WRONGPASS",
+            ErrorCode::NoProto => "NOPROTO",
+            ErrorCode::BusyKey => "BUSYKEY",
+            ErrorCode::NoGroup => "NOGROUP",
+            ErrorCode::Other(code) => code,
+        })
+    }
+}
+
+/// Anything that can go wrong while running a client's command.
+///
+/// Each variant renders to the very message Redis would reply with, error code prefix included, so that
+/// client libraries matching on them (`WRONGTYPE`, `EXECABORT`...) behave the same against rustis.
+#[derive(Debug, Error, PartialEq, Clone)]
+pub enum RedisError {
+    #[error("ERR unknown command '{0}', with args beginning with: {1}")]
+    UnknownCommand(String, String),
+    #[error("ERR wrong number of arguments for '{0}' command")]
+    WrongArity(String),
+    #[error("WRONGTYPE Operation against a key holding the wrong kind of value")]
+    WrongType,
+    #[error("ERR value is not an integer or out of range")]
+    NotInteger,
+    #[error("ERR value is not a valid float")]
+    NotFloat,
+    #[error("ERR increment or decrement would overflow")]
+    Overflow,
+    #[error("ERR syntax error")]
+    Syntax,
+    #[error("ERR invalid expire time in '{0}' command")]
+    InvalidExpire(String),
+    #[error("ERR MULTI calls can not be nested")]
+    NestedMulti,
+    #[error("ERR EXEC without MULTI")]
+    ExecWithoutMulti,
+    #[error("EXECABORT Transaction discarded because of previous errors.")]
+    ExecAbort,
+    #[error("NOPROTO unsupported protocol version")]
+    NoProto,
+    #[error("ERR Protocol error: {0}")]
+    Protocol(String),
+    /// Missing consumer group (or stream), the message telling which
+    #[error("NOGROUP {0}")]
+    NoGroup(String),
+    #[error("BUSYGROUP Consumer Group name already exists")]
+    BusyGroup,
+    /// String not holding a HyperLogLog where one is expected
+    #[error("WRONGTYPE Key is not a valid HyperLogLog string value.")]
+    NotHyperLogLog,
+    #[error("INVALIDOBJ Corrupted HLL object detected")]
+    CorruptHyperLogLog,
+    /// JSON value of another type than the command expects at a path
+    #[error("WRONGTYPE wrong type of path value - expected {0} but found {1}")]
+    JsonType(String, String),
+    /// Any other `ERR`-prefixed message
+    #[error("ERR {0}")]
+    Other(String),
+}
+
+pub type CmdResult = std::result::Result<RedisValue, RedisError>;
+
+impl From<RedisError> for RedisValue {
+    fn from(e: RedisError) -> Self {
+        RedisValue::error_line(&e.to_string())
+    }
+}
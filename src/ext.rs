@@ -1,6 +1,12 @@
 // Some utilities and wide traits implementations
 
-use crate::resp::{RedisArray, RedisInt};
+use crate::resp::{Protocol, RedisArray, RedisInt};
+use bytes::{Bytes, BytesMut};
+use std::{
+    cell::Cell,
+    hash::{BuildHasher, Hasher, RandomState},
+    time::{SystemTime, UNIX_EPOCH},
+};
 
 pub struct StackCtr {
     vals: Vec<usize>,
@@ -28,6 +34,101 @@ impl StackCtr {
     }
 }
 
+/// Current Unix time in milliseconds, the clock expiry deadlines are expressed in
+pub fn now_ms() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as i64)
+}
+
+/// Pseudo-random number (xorshift64*), good enough for picking random members, not for anything secret
+pub fn random_u64() -> u64 {
+    thread_local! {
+        static STATE: Cell<u64> = Cell::new(RandomState::new().build_hasher().finish() | 1);
+    }
+    STATE.with(|state| {
+        let mut x = state.get();
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+        state.set(x);
+        x.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    })
+}
+
+/// Whether `text` matches the glob-style `pattern`, as KEYS and SCAN's MATCH take it: `*` and `?` wildcards,
+/// `[...]` sets (with `^` negation and `a-z` ranges) and `\` escapes.
+///
+/// Only the last `*` met is ever backtracked to: the earlier ones could not match anything more than it does,
+/// which keeps the matching within `pattern.len() * text.len()` steps however many wildcards there are.
+pub fn glob_match(pattern: &[u8], text: &[u8]) -> bool {
+    let (mut p, mut t) = (0, 0);
+    // Pattern past the last `*` met, along with the text it was last tried from
+    let mut star: Option<(usize, usize)> = None;
+    while t < text.len() {
+        if pattern.get(p) == Some(&b'*') {
+            p += 1;
+            star = Some((p, t));
+            continue;
+        }
+        if let Some(len) = match_byte(&pattern[p..], text[t]) {
+            p += len;
+            t += 1;
+            continue;
+        }
+        // Let the last `*` swallow one more byte, or give up if there is none to widen
+        let Some((after_star, from)) = star else {
+            return false;
+        };
+        p = after_star;
+        t = from + 1;
+        star = Some((after_star, t));
+    }
+    pattern[p..].iter().all(|&c| c == b'*')
+}
+
+/// Length of the element `pattern` starts with (anything but `*`), if it matches `c`
+fn match_byte(pattern: &[u8], c: u8) -> Option<usize> {
+    let (matched, len) = match pattern {
+        [] | [b'*', ..] => return None,
+        [b'?', ..] => (true, 1),
+        [b'[', rest @ ..] => {
+            let (negate, mut set) = match rest.split_first() {
+                Some((b'^', set)) => (true, set),
+                _ => (false, rest),
+            };
+            let mut matched = false;
+            loop {
+                match set {
+                    // Unterminated set, matched as if it ended with the pattern
+                    [] => break,
+                    [b']', after @ ..] => {
+                        set = after;
+                        break;
+                    }
+                    [b'\\', e, after @ ..] => {
+                        matched |= c == *e;
+                        set = after;
+                    }
+                    [lo, b'-', hi, after @ ..] if *hi != b']' => {
+                        let (lo, hi) = if lo <= hi { (*lo, *hi) } else { (*hi, *lo) };
+                        matched |= (lo..=hi).contains(&c);
+                        set = after;
+                    }
+                    [e, after @ ..] => {
+                        matched |= c == *e;
+                        set = after;
+                    }
+                }
+            }
+            (matched != negate, pattern.len() - set.len())
+        }
+        [b'\\', e, ..] => (c == *e, 2),
+        [p, ..] => (c == *p, 1),
+    };
+    matched.then_some(len)
+}
+
 // Different ways for a non-headless server
 pub enum Notify {
     Info,
@@ -38,7 +139,67 @@ pub enum Notify {
 }
 
 pub trait RediSer {
-    fn serialize(&self) -> String; // 
+    /// Appends the value, encoded for `proto`, to `out`
+    fn serialize_into(&self, out: &mut BytesMut, proto: Protocol);
+
+    fn serialize_as(&self, proto: Protocol) -> Vec<u8> {
+        let mut out = BytesMut::new();
+        self.serialize_into(&mut out, proto);
+        out.to_vec()
+    }
+
+    // RESP2 remains the default, until a client asks for more
+    fn serialize(&self) -> Vec<u8> {
+        self.serialize_as(Protocol::Resp2)
+    }
+}
+
+/// Parses an integer the way Redis does for both arguments and stored values: an optional minus sign
+/// followed by digits, without leading zeros, spaces or plus sign, and fitting in 64 bits.
+pub fn parse_redis_int(b: &[u8]) -> Option<RedisInt> {
+    let digits = b.strip_prefix(b"-").unwrap_or(b);
+    match digits {
+        [b'0'] if digits.len() == b.len() => Some(0),
+        [b'1'..=b'9', rest @ ..] if rest.iter().all(u8::is_ascii_digit) => {
+            std::str::from_utf8(b).ok()?.parse().ok()
+        }
+        _ => None,
+    }
+}
+
+/// Parses a floating point number the way Redis does: no surrounding spaces, and no NaN
+pub fn parse_redis_float(b: &[u8]) -> Option<f64> {
+    let s = std::str::from_utf8(b).ok()?;
+    if s.is_empty() || s.trim() != s {
+        return None;
+    }
+    s.parse::<f64>().ok().filter(|d| !d.is_nan())
+}
+
+/// Formats a double the way Redis stores the result of INCRBYFLOAT: in fixed notation, trailing zeros
+/// removed. Redis computes with long doubles and prints 17 significant digits; rounding to 15 digits
+/// hides the binary representation artifacts the same way with plain doubles (`0.1 + 0.2` gives `0.3`).
+pub fn format_double_human(d: f64) -> String {
+    if !d.is_finite() {
+        return format_double(d);
+    }
+    let magnitude = if d == 0.0 { 0 } else { d.abs().log10().floor() as i32 };
+    let decimals = (14 - magnitude).max(0) as usize;
+    let s = format!("{:.*}", decimals, d);
+    let s = if s.contains('.') { s.trim_end_matches('0').trim_end_matches('.') } else { &s };
+    if s == "-0" { "0".to_string() } else { s.to_string() }
+}
+
+/// Formats a double the way Redis replies with it: shortest round-trip representation, no exponent,
+/// and `inf`/`-inf`/`nan` for the special values.
+pub fn format_double(d: f64) -> String {
+    if d.is_nan() {
+        "nan".to_string()
+    } else if d.is_infinite() {
+        if d > 0.0 { "inf" } else { "-inf" }.to_string()
+    } else {
+        d.to_string()
+    }
 }
 
 pub trait RedisValueInner {}
@@ -47,7 +208,4 @@ impl RedisValueInner for RedisInt {}
 impl RedisValueInner for RedisArray {}
 impl RedisValueInner for &str {}
 impl RedisValueInner for String {}
-
-pub trait RedisValueInenerToStr {}
-
-impl<T: ToString> RedisValueInenerToStr for T {}
+impl RedisValueInner for Bytes {}
@@ -1,10 +1,18 @@
+mod commands;
+mod error;
 mod ext;
 mod resp;
 
 use anyhow::Result;
 use core::option::Option::None;
-pub use ext::{Notify, RedisValueInner, StackCtr, RediSer};
-pub use resp::{Database, RedisArray, RedisInt, RedisValue, RespHandler, ThreadSafeDb};
+pub use error::{CmdResult, ErrorCode, RedisError};
+pub use ext::{
+    Notify, RedisValueInner, StackCtr, RediSer, format_double, format_double_human, glob_match, now_ms,
+    parse_redis_float, parse_redis_int, random_u64,
+};
+pub use resp::{
+    Database, Protocol, RedisArray, RedisInt, RedisObject, RedisValue, RespHandler, ThreadSafeDb, active_expire,
+};
 use std::{collections::VecDeque, fmt::Debug};
 pub use std::{
     clone,
@@ -13,9 +21,11 @@ pub use std::{
 };
 pub use tokio::net::{TcpListener, TcpStream};
 
-pub const OK: &'static str = "OK";
-pub const EMPTY_ARR: &'static str = "0";
-pub const QUEUED: &'static str = "QUEUED";
+pub const OK: &str = "OK";
+pub const EMPTY_ARR: &str = "0";
+pub const QUEUED: &str = "QUEUED";
+/// Redis version whose behavior is mimicked, as advertised to clients through `HELLO`
+pub const REDIS_VERSION: &str = "7.4.0";
 
 pub fn notify<T: Debug>(notification: Notify, content: &T) {
     let prefix = String::from(match notification {
@@ -33,7 +43,8 @@ pub fn notify<T: Debug>(notification: Notify, content: &T) {
 struct Transaction {
     in_transaction: bool,
     in_exec: bool,
-    queue: VecDeque<Option<RedisValue>>,
+    dirty: bool, // A command could not be queued, EXEC will abort
+    queue: VecDeque<(String, Vec<RedisValue>)>,
 }
 
 impl Transaction {
@@ -41,15 +52,16 @@ impl Transaction {
         Transaction {
             in_transaction: false,
             in_exec: false,
+            dirty: false,
             queue: VecDeque::new(),
         }
     }
 
-    fn push(&mut self, elt: Option<RedisValue>) {
+    fn push(&mut self, elt: (String, Vec<RedisValue>)) {
         self.queue.push_front(elt);
     }
 
-    fn pop(&mut self) -> Option<Option<RedisValue>> {
+    fn pop(&mut self) -> Option<(String, Vec<RedisValue>)> {
         self.queue.pop_back()
     }
 
@@ -68,6 +80,8 @@ impl Transaction {
     fn switch_neutral(&mut self) {
         self.in_exec = false;
         self.in_transaction = false; // If for whatever reason it was on
+        self.dirty = false;
+        self.queue.clear();
     }
 }
 
@@ -86,62 +100,66 @@ pub async fn handle_connection(
     let mut transaction = Transaction::init();
 
     loop {
-        let val = handler.read_value().await.unwrap_or_else(|e| {
-            eprintln!("Error reading value: {}", e);
-            return None; // Gracefully return None to break out of the loop
-        });
+        let val = match handler.read_value().await {
+            Ok(val) => val,
+            Err(e) => {
+                eprintln!("Error reading value: {}", e);
+                // The stream cannot be resynchronized past a malformed frame, let the client know before leaving
+                let _ = handler.write_value(&RedisValue::from(RedisError::Protocol(e.to_string()))).await;
+                None // Gracefully return None to break out of the loop
+            }
+        };
 
         println!("-------------");
 
-        let response = if let Some(v) = val.clone() {
-            notify(Notify::Recv, &v.clone().serialize());
-            if transaction.in_transaction {
-                transaction.push(val);
-                RedisValue::SimpleString(QUEUED.to_string())
-            } else {
-                let (command, args) = extract_cmd(v).unwrap();
-                match command.to_ascii_lowercase().as_str() {
-                    "multi" => {
-                        handler.handle_multi(&mut transaction).await
-                    }
-                    "exec" => {
-                        handler.handle_exec(&mut transaction).await
+        let response = if let Some(v) = val {
+            notify(Notify::Recv, &v);
+            match extract_cmd(v) {
+                Err(e) => RedisError::Protocol(e.to_string()).into(),
+                Ok((command, args)) => match command.to_ascii_lowercase().as_str() {
+                    "multi" => handler.handle_multi(&mut transaction).await,
+                    "exec" => handler.handle_exec(&mut transaction).await,
+                    any if transaction.in_transaction => {
+                        handler.handle_queued(&mut transaction, any.to_string(), args)
                     }
 
                     any => handler.handle_command(any, args).await,
-                }
+                },
             }
         } else {
             break;
         };
 
-        let to_send = 
-        if let RedisValue::Command(c) = &response {
-            notify(Notify::Send, &transaction.queue);
-            transaction.switch_neutral();
-            transaction.queue.clone().into_iter().map(|o| o.unwrap()).collect::<Vec<RedisValue>>()
-        } else {
-            notify(Notify::Send, &response.clone().serialize());
-            vec![response]
-        };
-        
-
-        if let Err(e) = handler.write_value(to_send).await {
+        notify(Notify::Send, &response);
+        if let Err(e) = handler.write_value(&response).await {
             eprintln!("Error writing value to to-client handler's buffer: {}", e);
             break; // Stop processing if writing fails
         }
     }
-    handler.cleanup();
+    if let Err(e) = handler.flush().await {
+        eprintln!("Error flushing last replies: {}", e);
+    }
     client_id.lock().expect("unlock failed!").release(id);
 }
 
 fn extract_cmd(val: RedisValue) -> Result<(String, Vec<RedisValue>)> {
     match val {
         RedisValue::SimpleString(s) => Ok((s, vec![])),
-        RedisValue::Array(a) => Ok((
-            unpack_bulk_str(a.first().unwrap().clone())?,
-            a.into_iter().skip(1).collect(),
-        )),
+        RedisValue::Array(a) if !a.is_empty() => {
+            // Arguments are plain strings (or integers), nothing a key or an option could not be made of
+            if let Some(arg) = a.iter().find(|arg| {
+                !matches!(
+                    arg,
+                    RedisValue::BulkString(_) | RedisValue::SimpleString(_) | RedisValue::Int(_)
+                )
+            }) {
+                return Err(anyhow::anyhow!("invalid command argument: {:?}", arg));
+            }
+
+            let mut a = a.into_iter();
+            let command = unpack_bulk_str(a.next().unwrap_or(RedisValue::NullBulkString))?;
+            Ok((command, a.collect()))
+        }
         _ => Err(anyhow::anyhow!(
             "Command is not formed properly (not an array of Redis values?)(V): {:?}",
             val
@@ -151,7 +169,8 @@ fn extract_cmd(val: RedisValue) -> Result<(String, Vec<RedisValue>)> {
 
 pub fn unpack_bulk_str(val: RedisValue) -> Result<String> {
     match val {
-        RedisValue::BulkString(s) => Ok(s),
+        // Command names and options are plain ASCII, anything else is not worth carrying around as text
+        RedisValue::BulkString(s) => Ok(String::from_utf8(s.to_vec())?),
         _ => Err(anyhow::anyhow!(
             "Unpacking invalid bulk string(V): {:?}",
             val
@@ -166,7 +185,7 @@ mod test {
 
     #[test]
     fn _unpack_bulk_str() {
-        let val = RedisValue::BulkString("Should unwrap".to_string());
+        let val = RedisValue::bulk("Should unwrap");
         assert_eq!(unpack_bulk_str(val).unwrap(), "Should unwrap".to_string());
     }
 
@@ -180,7 +199,7 @@ mod test {
     #[test]
     #[should_panic]
     fn unpack_bulk_str_panic_eq() {
-        let val = RedisValue::BulkString("Should unwrap".to_string());
+        let val = RedisValue::bulk("Should unwrap");
         assert_eq!(unpack_bulk_str(val).unwrap(), "Shouldunwrap".to_string());
     }
 }
@@ -1,5 +1,5 @@
-use std::{collections::HashMap, sync::{Arc, Mutex}};
-use rustis::{StackCtr, handle_connection};
+use std::sync::{Arc, Mutex};
+use rustis::{Database, StackCtr, active_expire, handle_connection};
 use tokio::net::{TcpListener};
 
 const DB_SZ: usize = 4_096;
@@ -8,7 +8,8 @@ const IDS: usize = 100;
 #[tokio::main]
 async fn main() {
     let listener = TcpListener::bind("127.0.0.1:6378").await.unwrap();
-    let map = Arc::new(Mutex::new(HashMap::with_capacity(DB_SZ)));
+    let map = Arc::new(Mutex::new(Database::with_capacity(DB_SZ)));
+    tokio::spawn(active_expire(Arc::clone(&map)));
     let mut _client_id = StackCtr::init(IDS);
     let client_id = Arc::new(Mutex::new(_client_id));
 
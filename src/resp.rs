@@ -1,187 +1,705 @@
 use crate::RedisValueInner;
 use crate::RediSer;
+use crate::{format_double, now_ms, parse_redis_float, parse_redis_int};
 use crate::Transaction;
-use crate::{EMPTY_ARR, OK};
+use crate::commands::check_arity;
+use crate::error::{CmdResult, ErrorCode, RedisError};
+use crate::{OK, QUEUED, REDIS_VERSION};
 use anyhow::Result;
-use bytes::BytesMut;
+use bytes::{Buf, BufMut, Bytes, BytesMut};
 use core::option::Option::{self, None};
 use std::{
-    collections::{HashMap, HashSet},
+    borrow::Cow,
+    collections::{HashMap, HashSet, VecDeque},
+    fmt::{Display, Write},
+    ops::{Deref, DerefMut},
     sync::{Arc, Mutex},
-    vec,
 };
 use tokio::{
     io::{AsyncReadExt, AsyncWriteExt},
     net::TcpStream,
-    time::{Duration, Instant},
+    time::Duration,
 };
 
+mod bitmaps;
+mod blocking;
+mod consumer_groups;
+mod expire;
+mod geo;
+mod hashes;
+mod hyperloglog;
+mod json;
+mod keys;
+mod lists;
+mod scan;
+mod sets;
+mod skiplist;
+mod sorted_sets;
+mod streams;
+mod strings;
+
+use blocking::Blocked;
+use sets::SetOp;
+use sorted_sets::ZEnd;
+pub use expire::active_expire;
+pub use hashes::Hash;
+pub use json::Json;
+pub use sets::SetObject;
+pub use sorted_sets::SortedSet;
+pub use streams::Stream;
+
 pub type RedisInt = i64;
 pub type RedisArray = Vec<RedisInt>;
-pub type Database = HashMap<String, Set>;
+/// Database keys are binary-safe, as any bulk string sent by a client.
+pub type Key = Bytes;
+
+/// The keyspace, shared by all clients, along with the clients blocked until some of its keys can serve them
+#[derive(Debug, Default)]
+pub struct Database {
+    entries: HashMap<Key, Set>,
+    /// Keys which may expire (or hold members which may), in the order the background sweep checks them
+    volatile: VecDeque<Key>,
+    /// The keys queued in `volatile`
+    volatile_keys: HashSet<Key>,
+    pub(crate) blocked: Blocked,
+}
+
+impl Database {
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            entries: HashMap::with_capacity(capacity),
+            ..Self::default()
+        }
+    }
+
+    /// Inserts an entry, which the background sweep gets to check if it expires
+    pub fn insert(&mut self, key: Key, set: Set) -> Option<Set> {
+        if set.expires() {
+            self.track_expiry(&key);
+        }
+        self.signal_ready(&key);
+        self.entries.insert(key, set)
+    }
+}
+
+// The database is first and foremost its entries, the blocked clients only matter to a few commands
+impl Deref for Database {
+    type Target = HashMap<Key, Set>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.entries
+    }
+}
+
+impl DerefMut for Database {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.entries
+    }
+}
+
 type LockedDb = Mutex<Database>;
 pub type ThreadSafeDb = Arc<LockedDb>;
-pub type Keys = HashSet<String>;
+
+/// A value stored in the database, typed after the commands able to operate on it
+#[derive(Debug, Clone, PartialEq)]
+pub enum RedisObject {
+    /// Plain string, kept as received (or as an integer once incremented)
+    String(RedisValue),
+    List(VecDeque<Bytes>),
+    Hash(Hash),
+    Set(SetObject),
+    SortedSet(SortedSet),
+    Stream(Stream),
+    /// Parsed JSON document
+    Json(Json),
+}
+
+impl From<RedisValue> for RedisObject {
+    fn from(val: RedisValue) -> Self {
+        RedisObject::String(val)
+    }
+}
+
+impl RedisObject {
+    /// Name of the object's type, as replied by `TYPE`
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            RedisObject::String(_) => "string",
+            RedisObject::List(_) => "list",
+            RedisObject::Hash(_) => "hash",
+            RedisObject::Set(_) => "set",
+            RedisObject::SortedSet(_) => "zset",
+            RedisObject::Stream(_) => "stream",
+            RedisObject::Json(_) => "ReJSON-RL",
+        }
+    }
+
+    pub fn as_string(&self) -> Option<&RedisValue> {
+        match self {
+            RedisObject::String(val) => Some(val),
+            _ => None,
+        }
+    }
+
+    /// The string value, or a WRONGTYPE error for any other type
+    pub fn string(&self) -> Result<&RedisValue, RedisError> {
+        self.as_string().ok_or(RedisError::WrongType)
+    }
+
+    /// Reads the object as a string, integers being turned into their text
+    pub fn string_bytes(&self) -> Option<Cow<'_, [u8]>> {
+        self.as_string().and_then(RedisValue::string_bytes)
+    }
+
+    /// The list, or a WRONGTYPE error for any other type
+    pub fn list_mut(&mut self) -> Result<&mut VecDeque<Bytes>, RedisError> {
+        match self {
+            RedisObject::List(list) => Ok(list),
+            _ => Err(RedisError::WrongType),
+        }
+    }
+
+    /// The hash, or a WRONGTYPE error for any other type
+    pub fn hash_mut(&mut self) -> Result<&mut Hash, RedisError> {
+        match self {
+            RedisObject::Hash(hash) => Ok(hash),
+            _ => Err(RedisError::WrongType),
+        }
+    }
+
+    /// The set, or a WRONGTYPE error for any other type
+    pub fn set_mut(&mut self) -> Result<&mut SetObject, RedisError> {
+        match self {
+            RedisObject::Set(set) => Ok(set),
+            _ => Err(RedisError::WrongType),
+        }
+    }
+
+    /// The sorted set, or a WRONGTYPE error for any other type
+    pub fn zset_mut(&mut self) -> Result<&mut SortedSet, RedisError> {
+        match self {
+            RedisObject::SortedSet(zset) => Ok(zset),
+            _ => Err(RedisError::WrongType),
+        }
+    }
+
+    /// The stream, or a WRONGTYPE error for any other type
+    pub fn stream_mut(&mut self) -> Result<&mut Stream, RedisError> {
+        match self {
+            RedisObject::Stream(stream) => Ok(stream),
+            _ => Err(RedisError::WrongType),
+        }
+    }
+
+    /// The JSON document, or a WRONGTYPE error for any other type
+    pub fn json_mut(&mut self) -> Result<&mut Json, RedisError> {
+        match self {
+            RedisObject::Json(json) => Ok(json),
+            _ => Err(RedisError::WrongType),
+        }
+    }
+
+    /// Drops the members whose own deadline passed at `now` (hash fields), replying with whether there were any
+    pub fn expire_members(&mut self, now: i64) -> bool {
+        match self {
+            RedisObject::Hash(hash) => hash.expire_fields(now) > 0,
+            _ => false,
+        }
+    }
+
+    /// Whether the object is an empty container, which Redis never keeps around
+    pub fn is_empty_container(&self) -> bool {
+        match self {
+            RedisObject::String(_) => false,
+            RedisObject::List(list) => list.is_empty(),
+            RedisObject::Hash(hash) => hash.is_empty(),
+            RedisObject::Set(set) => set.is_empty(),
+            RedisObject::SortedSet(zset) => zset.is_empty(),
+            // Unlike other types, streams stay around once empty
+            RedisObject::Stream(_) => false,
+            RedisObject::Json(_) => false,
+        }
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct Set {
-    org: Instant,
-    pub exp: Option<Duration>,
-    pub val: RedisValue,
+    /// Absolute expiry deadline, in milliseconds since the Unix epoch
+    pub deadline: Option<i64>,
+    pub val: RedisObject,
 }
 
 impl Set {
-    pub fn new(val: RedisValue, exp: Option<Duration>) -> Self {
-        Self {
-            org: Instant::now(),
-            exp,
-            val,
-        }
+    /// New entry, expiring `exp` from now if given
+    pub fn new(val: impl Into<RedisObject>, exp: Option<Duration>) -> Self {
+        let deadline = exp.map(|e| now_ms().saturating_add(e.as_millis() as i64));
+        Self::with_deadline(val, deadline)
+    }
+
+    pub fn with_deadline(val: impl Into<RedisObject>, deadline: Option<i64>) -> Self {
+        Self { deadline, val: val.into() }
     }
 
-    pub fn from_other(other: &Self, value: RedisValue) -> Self {
+    pub fn from_other(other: &Self, value: impl Into<RedisObject>) -> Self {
         let mut new = other.clone();
-        new.val = value;
+        new.val = value.into();
         new
     }
 
     pub fn rtime_valid(&self) -> bool {
-        if let Some(e) = self.exp {
-            self.org.elapsed().as_millis() <= e.as_millis()
-        } else {
-            true
-        }
+        self.deadline.is_none_or(|d| !deadline_passed(d, now_ms()))
+    }
+
+    /// Whether the entry may expire, or some of its members
+    pub(crate) fn expires(&self) -> bool {
+        self.deadline.is_some() || matches!(&self.val, RedisObject::Hash(hash) if hash.has_deadlines())
+    }
+
+    /// Whether the entry is gone at `now`, be it expired or a container whose members all expired on their own
+    pub(crate) fn gone_at(&mut self, now: i64) -> bool {
+        self.deadline.is_some_and(|d| deadline_passed(d, now))
+            || self.val.expire_members(now) && self.val.is_empty_container()
+    }
+
+    /// Milliseconds left to live, if the entry is to expire
+    pub fn ttl_ms(&self) -> Option<i64> {
+        self.deadline.map(|d| (d - now_ms()).max(0))
+    }
+
+    /// Mutates the value in place as a string, integers being turned into their text first
+    pub fn update_string<R>(&mut self, f: impl FnOnce(&mut BytesMut) -> R) -> Result<R, RedisError> {
+        let RedisObject::String(val) = &mut self.val else {
+            return Err(RedisError::WrongType);
+        };
+        let mut bytes = match std::mem::replace(val, RedisValue::NullBulkString) {
+            // No copy as long as the value is not shared with a reply being sent
+            RedisValue::BulkString(b) => b.try_into_mut().unwrap_or_else(|b| BytesMut::from(&b[..])),
+            RedisValue::SimpleString(s) => BytesMut::from(s.as_bytes()),
+            RedisValue::Int(n) => BytesMut::from(n.to_string().as_bytes()),
+            other => {
+                *val = other;
+                return Err(RedisError::WrongType);
+            }
+        };
+        let res = f(&mut bytes);
+        *val = RedisValue::BulkString(bytes.freeze());
+        Ok(res)
+    }
+
+    /// Name of the value's type, as replied by `TYPE`
+    pub fn type_name(&self) -> &'static str {
+        self.val.type_name()
     }
 }
 
+/// Whether something expiring at `deadline` (in milliseconds since the Unix epoch) is gone at `now`
+pub fn deadline_passed(deadline: i64, now: i64) -> bool {
+    now > deadline
+}
+
+/// Looks `key` up, expired entries (and expired members of containers) being treated as absent and removed
+/// on the fly
+pub fn lookup<'a>(db: &'a mut Database, key: &[u8]) -> Option<&'a mut Set> {
+    if db.get_mut(key).is_some_and(|set| set.gone_at(now_ms())) {
+        db.remove(key);
+    }
+    db.get_mut(key)
+}
+
+/// Removes `key` if it holds a container emptied by the last command, replying with whether it did
+pub fn remove_if_empty(db: &mut Database, key: &[u8]) -> bool {
+    let empty = db.get(key).is_some_and(|set| set.val.is_empty_container());
+    if empty {
+        db.remove(key);
+    }
+    empty
+}
+
 #[allow(dead_code)]
 pub struct RespHandler {
     client_id: usize,
     stream: TcpStream,
     buffer: BytesMut,
+    out: BytesMut,        // Replies not sent yet
+    protocol: Protocol,   // Negotiated through HELLO
+    name: Option<Bytes>,  // Set through HELLO's SETNAME
     pub map: ThreadSafeDb, // *Database*
-    self_keys: Keys,       // Server-side for safety reasons
+    in_exec: bool,         // Blocking commands do not block while a transaction runs
+    partial: Option<PartialArray>, // Command received in part so far
 }
 
+/// Output buffer size past which replies are sent without waiting for the end of the pipelined batch
+pub const OUT_FLUSH_LEN: usize = 64 * 1024;
+
 /// Standalone remove_entry procedure
-pub fn _remove_entry_stdln(db: &mut ThreadSafeDb, key: &str) {
+pub fn _remove_entry_stdln(db: &mut ThreadSafeDb, key: &[u8]) {
     db.lock().expect("unlock failed!").remove_entry(key);
 }
 
-impl<'a> RespHandler {
+impl RespHandler {
     pub fn new(stream: TcpStream, id: usize, map: ThreadSafeDb) -> Self {
         Self {
             client_id: id,
             stream,
             buffer: BytesMut::with_capacity(512),
-            map: map,
-            self_keys: HashSet::new(),
+            out: BytesMut::with_capacity(512),
+            protocol: Protocol::default(),
+            name: None,
+            map,
+            in_exec: false,
+            partial: None,
         }
     }
 
     pub(crate) async fn handle_multi(&mut self, transaction: &mut Transaction) -> RedisValue {
+        if transaction.in_transaction {
+            return RedisError::NestedMulti.into();
+        }
         transaction.switch_trans();
         RedisValue::SimpleString(OK.to_string())
     }
 
+    /// Queues a command sent in between MULTI and EXEC, once it is known to be runnable: a command that is
+    /// unknown or of wrong arity makes the whole transaction abort on EXEC.
+    pub(crate) fn handle_queued(
+        &mut self,
+        transaction: &mut Transaction,
+        command: String,
+        args: Vec<RedisValue>,
+    ) -> RedisValue {
+        if let Err(e) = check_arity(&command, &args) {
+            transaction.dirty = true;
+            return e.into();
+        }
+        transaction.push((command, args));
+        RedisValue::SimpleString(QUEUED.to_string())
+    }
+
     pub(crate) async fn handle_exec(&mut self, transaction: &mut Transaction) -> RedisValue {
         if !transaction.in_transaction {
-            RedisValue::ErrorMsg(Vec::from("Exec called with empty queue..."));
-        } else if transaction.queue.is_empty() {
+            return RedisError::ExecWithoutMulti.into();
+        } else if transaction.dirty {
             transaction.switch_neutral();
-            RedisValue::ErrorMsg(Vec::from(EMPTY_ARR));
+            return RedisError::ExecAbort.into();
         }
 
         transaction.switch_exec();
-        RedisValue::Command(RedisCommand::EXEC)
+        self.in_exec = true;
+        let mut replies = Vec::with_capacity(transaction.queue.len());
+        while let Some((command, args)) = transaction.pop() {
+            replies.push(self.handle_command(&command, args).await);
+        }
+        self.in_exec = false;
+        transaction.switch_neutral();
+        // As the transaction runs as a whole, blocked clients only get to see its outcome
+        self.map.lock().expect("unlock failed!").serve_blocked();
+
+        RedisValue::Array(replies)
     }
 
+    /// Runs `command` (lowercase) and replies with its result, or with the error it ran into
     pub async fn handle_command(&mut self, command: &str, args: Vec<RedisValue>) -> RedisValue {
-        match command {
-            "ping" => RedisValue::SimpleString("PONG".to_string()),
-            "echo" => args.first().unwrap().clone(),
-            "set" => {
-                println!("{:?}", args);
-                let mut args_iter = args.iter();
-                let (key, value, sub1, sub2) = (
-                    args_iter.next(),
-                    args_iter.next(),
-                    args_iter.next(),
-                    args_iter.next(),
-                );
-
-                let exp = if let Some(cmd) = sub1 {
-                    match cmd
-                        .unpack_str_variant()
-                        .unwrap()
-                        .to_ascii_lowercase()
-                        .as_str()
-                    {
-                        "px" => {
-                            if let Some(d) = sub2 {
-                                let milli = d.unpack_str_variant().unwrap().parse::<u64>().unwrap();
-                                Some(Duration::from_millis(milli))
-                            } else {
-                                None
-                            }
-                        }
+        let reply = self.dispatch(command, args).await.unwrap_or_else(RedisValue::from);
+        // Whatever the command did, clients blocked on the keys it filled are served before anyone else
+        if !self.in_exec {
+            self.map.lock().expect("unlock failed!").serve_blocked();
+        }
+        reply
+    }
 
-                        _ => None,
-                    }
-                } else {
-                    None
-                };
-                self.insert(key.unwrap(), value.unwrap().clone(), exp).await;
-                RedisValue::SimpleString(OK.to_string())
+    async fn dispatch(&mut self, command: &str, args: Vec<RedisValue>) -> CmdResult {
+        check_arity(command, &args)?;
+
+        match command {
+            "ping" => Ok(match args.into_iter().next() {
+                Some(msg) => msg,
+                None => RedisValue::SimpleString("PONG".to_string()),
+            }),
+            "echo" => Ok(args.into_iter().next().unwrap_or(RedisValue::NullBulkString)),
+            "hello" => self.hello(&args),
+            "set" => self.set(&args),
+            "setnx" => self.setnx(&args[0], &args[1]),
+            "setex" => self.setex(&args, "ex", "setex"),
+            "psetex" => self.setex(&args, "px", "psetex"),
+            "getset" => self.getset(&args[0], &args[1]),
+            "getdel" => self.getdel(&args[0]),
+            "getex" => self.getex(&args),
+            "mget" => self.mget(&args),
+            "mset" => self.mset(&args, false, "mset"),
+            "msetnx" => self.mset(&args, true, "msetnx"),
+            "del" | "unlink" => self.del(&args),
+            "exists" => self.exists(&args),
+            "type" => self.key_type(&args[0]),
+            "rename" => self.rename(&args[0], &args[1], false),
+            "renamenx" => self.rename(&args[0], &args[1], true),
+            "copy" => self.copy(&args),
+            "expire" => self.expire(&args, 1000, false, "expire"),
+            "pexpire" => self.expire(&args, 1, false, "pexpire"),
+            "expireat" => self.expire(&args, 1000, true, "expireat"),
+            "pexpireat" => self.expire(&args, 1, true, "pexpireat"),
+            "ttl" => self.ttl(&args[0], 1000, false),
+            "pttl" => self.ttl(&args[0], 1, false),
+            "expiretime" => self.ttl(&args[0], 1000, true),
+            "pexpiretime" => self.ttl(&args[0], 1, true),
+            "persist" => self.persist(&args[0]),
+            "get" => self.get(&args[0]),
+            "incr" => self.incr_by(&args[0], 1),
+            "decr" => self.incr_by(&args[0], -1),
+            "incrby" => self.incr_by(&args[0], args[1].int_arg()?),
+            "decrby" => match args[1].int_arg()?.checked_neg() {
+                Some(delta) => self.incr_by(&args[0], delta),
+                None => Err(RedisError::Other("decrement would overflow".to_string())),
+            },
+            "incrbyfloat" => self.incr_by_float(&args[0], args[1].float_arg()?),
+            "append" => self.append(&args[0], &args[1]),
+            "strlen" => self.strlen(&args[0]),
+            "getrange" | "substr" => self.getrange(&args[0], args[1].int_arg()?, args[2].int_arg()?),
+            "setrange" => self.setrange(&args[0], args[1].int_arg()?, &args[2]),
+            "lcs" => self.lcs(&args),
+            "setbit" => self.setbit(&args),
+            "getbit" => self.getbit(&args[0], &args[1]),
+            "bitcount" => self.bitcount(&args),
+            "bitpos" => self.bitpos(&args),
+            "bitop" => self.bitop(&args),
+            "bitfield" => self.bitfield(&args, false),
+            "bitfield_ro" => self.bitfield(&args, true),
+            "pfadd" => self.pfadd(&args),
+            "pfcount" => self.pfcount(&args),
+            "pfmerge" => self.pfmerge(&args),
+            "geoadd" => self.geoadd(&args),
+            "geodist" => self.geodist(&args),
+            "geohash" => self.geohash(&args[0], &args[1..]),
+            "geopos" => self.geopos(&args[0], &args[1..]),
+            "geosearch" => self.geosearch(&args, false, "geosearch"),
+            "geosearchstore" => self.geosearch(&args, true, "geosearchstore"),
+            "json.set" => self.json_set(&args),
+            "json.get" => self.json_get(&args),
+            "json.del" => self.json_del(&args),
+            "json.numincrby" => self.json_numincrby(&args),
+            "json.arrappend" => self.json_arrappend(&args),
+            "json.objkeys" => self.json_objkeys(&args),
+            "lpush" => self.push(&args, lists::End::Left, false),
+            "rpush" => self.push(&args, lists::End::Right, false),
+            "lpushx" => self.push(&args, lists::End::Left, true),
+            "rpushx" => self.push(&args, lists::End::Right, true),
+            "lpop" => self.pop(&args, lists::End::Left),
+            "rpop" => self.pop(&args, lists::End::Right),
+            "llen" => self.llen(&args[0]),
+            "lrange" => self.lrange(&args[0], args[1].int_arg()?, args[2].int_arg()?),
+            "lindex" => self.lindex(&args[0], args[1].int_arg()?),
+            "lset" => self.lset(&args[0], args[1].int_arg()?, &args[2]),
+            "lrem" => self.lrem(&args[0], args[1].int_arg()?, &args[2]),
+            "ltrim" => self.ltrim(&args[0], args[1].int_arg()?, args[2].int_arg()?),
+            "linsert" => self.linsert(&args),
+            "lmove" => {
+                let (from, to) = (lists::End::parse(&args[2])?, lists::End::parse(&args[3])?);
+                self.lmove(&args[0], &args[1], from, to)
             }
-            "get" => {
-                if let Some(a) = self.get_val(args.first().unwrap()).await {
-                    a
-                } else {
-                    RedisValue::NullBulkString
-                }
+            "rpoplpush" => self.lmove(&args[0], &args[1], lists::End::Right, lists::End::Left),
+            "lmpop" => self.lmpop(&args, false).await,
+            "blpop" => self.bpop(&args, lists::End::Left).await,
+            "brpop" => self.bpop(&args, lists::End::Right).await,
+            "blmove" => {
+                let (from, to) = (lists::End::parse(&args[2])?, lists::End::parse(&args[3])?);
+                self.blmove(&args, from, to).await
             }
-            "incr" => {
-                let key = &args.iter().next().unwrap().clone();
-                let val = self.get_val(&key).await;
-
-                let response = match val {
-                    Some(RedisValue::Int(_)) | None => {
-                        if let Some(n) = self.incr(key).await {
-                            n
-                        } else {
-                            RedisValue::NullBulkString
-                        }
+            "brpoplpush" => self.blmove(&args, lists::End::Right, lists::End::Left).await,
+            "blmpop" => self.lmpop(&args, true).await,
+            "hset" => self.hset(&args, false),
+            "hmset" => self.hset(&args, true),
+            "hsetnx" => self.hsetnx(&args[0], &args[1], &args[2]),
+            "hget" => self.hget(&args[0], &args[1]),
+            "hmget" => self.hmget(&args[0], &args[1..]),
+            "hgetall" => self.hgetall(&args[0]),
+            "hdel" => self.hdel(&args[0], &args[1..]),
+            "hexists" => self.hexists(&args[0], &args[1]),
+            "hincrby" => self.hincrby(&args[0], &args[1], args[2].int_arg()?),
+            "hincrbyfloat" => self.hincrbyfloat(&args[0], &args[1], args[2].float_arg()?),
+            "hkeys" => self.hfields(&args[0], true, false),
+            "hvals" => self.hfields(&args[0], false, true),
+            "hlen" => self.hlen(&args[0]),
+            "hstrlen" => self.hstrlen(&args[0], &args[1]),
+            "hscan" => self.hscan(&args[0], &args[1..]),
+            "hrandfield" => self.hrandfield(&args),
+            "hexpire" => self.hexpire(&args, 1000, false, "hexpire"),
+            "hpexpire" => self.hexpire(&args, 1, false, "hpexpire"),
+            "hexpireat" => self.hexpire(&args, 1000, true, "hexpireat"),
+            "hpexpireat" => self.hexpire(&args, 1, true, "hpexpireat"),
+            "httl" => self.httl(&args, 1000, false),
+            "hpttl" => self.httl(&args, 1, false),
+            "hexpiretime" => self.httl(&args, 1000, true),
+            "hpexpiretime" => self.httl(&args, 1, true),
+            "hpersist" => self.hpersist(&args),
+            "sadd" => self.sadd(&args[0], &args[1..]),
+            "srem" => self.srem(&args[0], &args[1..]),
+            "smembers" => self.smembers(&args[0]),
+            "sismember" => self.smismember(&args[0], &args[1..], false),
+            "smismember" => self.smismember(&args[0], &args[1..], true),
+            "scard" => self.scard(&args[0]),
+            "spop" => self.spop(&args),
+            "srandmember" => self.srandmember(&args),
+            "smove" => self.smove(&args[0], &args[1], &args[2]),
+            "sinter" => self.set_op(SetOp::Inter, &args, None),
+            "sunion" => self.set_op(SetOp::Union, &args, None),
+            "sdiff" => self.set_op(SetOp::Diff, &args, None),
+            "sinterstore" => self.set_op(SetOp::Inter, &args[1..], Some(&args[0])),
+            "sunionstore" => self.set_op(SetOp::Union, &args[1..], Some(&args[0])),
+            "sdiffstore" => self.set_op(SetOp::Diff, &args[1..], Some(&args[0])),
+            "sintercard" => self.sintercard(&args),
+            "sscan" => self.sscan(&args[0], &args[1..]),
+            "zadd" => self.zadd(&args),
+            "zincrby" => self.zincrby(&args[0], args[1].float_arg()?, &args[2]),
+            "zrem" => self.zrem(&args[0], &args[1..]),
+            "zcard" => self.zcard(&args[0]),
+            "zscore" => self.zmscore(&args[0], &args[1..], false),
+            "zmscore" => self.zmscore(&args[0], &args[1..], true),
+            "zrank" => self.zrank(&args, false),
+            "zrevrank" => self.zrank(&args, true),
+            "zcount" => self.zcount(&args[0], &args[1], &args[2], false),
+            "zlexcount" => self.zcount(&args[0], &args[1], &args[2], true),
+            "zrange" => self.zrange(&args[0], &args[1..], None),
+            "zrangestore" => self.zrange(&args[1], &args[2..], Some(&args[0])),
+            "zpopmin" => self.zpop(&args, ZEnd::Min),
+            "zpopmax" => self.zpop(&args, ZEnd::Max),
+            "bzpopmin" => self.bzpop(&args, ZEnd::Min).await,
+            "bzpopmax" => self.bzpop(&args, ZEnd::Max).await,
+            "zunionstore" => self.zstore(&args, SetOp::Union, "zunionstore"),
+            "zinterstore" => self.zstore(&args, SetOp::Inter, "zinterstore"),
+            "zdiffstore" => self.zstore(&args, SetOp::Diff, "zdiffstore"),
+            "xadd" => self.xadd(&args),
+            "xrange" => self.xrange(&args, false),
+            "xrevrange" => self.xrange(&args, true),
+            "xlen" => self.xlen(&args[0]),
+            "xdel" => self.xdel(&args[0], &args[1..]),
+            "xtrim" => self.xtrim(&args[0], &args[1..]),
+            "xread" => self.xread(&args).await,
+            "xgroup" => self.xgroup(&args),
+            "xreadgroup" => self.xreadgroup(&args).await,
+            "xack" => self.xack(&args),
+            "xpending" => self.xpending(&args),
+            "xclaim" => self.xclaim(&args),
+            "xautoclaim" => self.xautoclaim(&args),
+            "xinfo" => self.xinfo(&args),
+
+            // Known to the command table, but not to the dispatcher
+            c => Err(RedisError::Other(format!("command '{}' is not implemented", c))),
+        }
+    }
+
+    /// `HELLO [protover [AUTH username password] [SETNAME clientname]]`
+    ///
+    /// Switches the reply encoding of the connection to the requested protocol version, and replies with
+    /// the server's properties in that very version.
+    fn hello(&mut self, args: &[RedisValue]) -> CmdResult {
+        let mut args = args.iter();
+        let protocol = match args.next() {
+            None => self.protocol,
+            Some(v) => match v.int_arg() {
+                Ok(2) => Protocol::Resp2,
+                Ok(3) => Protocol::Resp3,
+                Ok(_) => return Err(RedisError::NoProto),
+                Err(_) => {
+                    return Err(RedisError::Other(
+                        "Protocol version is not an integer or out of range".to_string(),
+                    ));
+                }
+            },
+        };
+
+        let mut name = None;
+        while let Some(opt) = args.next() {
+            let opt = opt.option_name();
+            match (opt.as_str(), args.len()) {
+                // No ACL here: any credentials are accepted, as for Redis' default `nopass` user
+                ("auth", 2..) => {
+                    args.nth(1);
+                }
+                ("setname", 1..) => {
+                    let n = args.next().and_then(RedisValue::unpack_bytes_variant).unwrap_or_default();
+                    if n.iter().any(|&c| c <= b' ' || c > b'~') {
+                        return Err(RedisError::Other(
+                            "Client names cannot contain spaces, newlines or special characters.".to_string(),
+                        ));
                     }
-                    _ => RedisValue::ErrorMsg(Vec::from(
-                        "(error) value is not an integer or out of range",
-                    )),
-                };
-                response
+                    name = Some(Bytes::copy_from_slice(n));
+                }
+                _ => {
+                    return Err(RedisError::Other(format!("Syntax error in HELLO option '{}'", opt)));
+                }
             }
+        }
 
-            c => panic!("Erroneous command to handle: {}", c),
+        self.protocol = protocol;
+        if name.is_some() {
+            self.name = name;
         }
+
+        Ok(RedisValue::Map(vec![
+            (RedisValue::bulk("server"), RedisValue::bulk("redis")),
+            (RedisValue::bulk("version"), RedisValue::bulk(REDIS_VERSION)),
+            (RedisValue::bulk("proto"), RedisValue::Int(self.protocol.version())),
+            (RedisValue::bulk("id"), RedisValue::Int(self.client_id as RedisInt)),
+            (RedisValue::bulk("mode"), RedisValue::bulk("standalone")),
+            (RedisValue::bulk("role"), RedisValue::bulk("master")),
+            (RedisValue::bulk("modules"), RedisValue::Array(vec![])),
+        ]))
     }
 
-    fn keyize(&self, key: &RedisValue) -> String {
-        format!("{}{}", key.keyize(), self.client_id)
+    /// Database key named by `key`. The keyspace is shared, so that a client may be served by another's writes.
+    fn keyize(&self, key: &RedisValue) -> Key {
+        Key::from(key.keyize())
     }
 
+    /// Reads the next complete frame sent by the client.
+    ///
+    /// Bytes past the returned frame are kept in the handler's buffer, so that pipelined commands are yielded
+    /// one after the other on the next calls, and a frame split across several TCP segments is waited for
+    /// until it is whole. Returns `Ok(None)` once the client has closed the connection.
     pub async fn read_value(&mut self) -> Result<Option<RedisValue>> {
-        let bytes_read = self.stream.read_buf(&mut self.buffer).await?;
+        loop {
+            if self.partial.is_none() {
+                // Consumed right away, so that a long run of them is not scanned again on every read
+                let blank = blank_lines_len(&self.buffer);
+                self.buffer.advance(blank);
+            }
+            if let Some((v, len)) = parse_msg_resuming(&self.buffer, &mut self.partial)? {
+                self.buffer.advance(len);
+                return Ok(Some(v));
+            }
 
-        if bytes_read == 0 {
-            return Ok(None);
+            // The whole batch of pipelined commands has been handled, time to answer it
+            self.flush().await?;
+            if self.stream.read_buf(&mut self.buffer).await? == 0 {
+                if self.buffer.is_empty() {
+                    return Ok(None);
+                }
+                return Err(anyhow::anyhow!(
+                    "Connection closed with an incomplete frame pending: {:?}",
+                    self.buffer
+                ));
+            }
         }
+    }
 
-        // Here ignore the length result
-        let (v, _) = parse_msg(self.buffer.split())?;
-        Ok(Some(v))
+    /// Queues a reply into the connection's output buffer.
+    ///
+    /// Replies are only sent once every pipelined command already received has been answered (see
+    /// [`RespHandler::read_value`]), or when the buffer grows past [`OUT_FLUSH_LEN`].
+    pub async fn write_value<T: RediSer>(&mut self, value: &T) -> Result<()> {
+        value.serialize_into(&mut self.out, self.protocol);
+        if self.out.len() >= OUT_FLUSH_LEN {
+            self.flush().await?;
+        }
+        Ok(())
     }
 
-    pub async fn write_value<T: RediSer>(&mut self, value: T) -> Result<usize> {
-        Ok(self.stream.write(value.serialize().as_bytes()).await?)
+    /// Sends every queued reply at once
+    pub async fn flush(&mut self) -> Result<()> {
+        if !self.out.is_empty() {
+            self.stream.write_all(&self.out).await?;
+            self.out.clear();
+        }
+        Ok(())
     }
 
     pub async fn insert(&mut self, redval: &RedisValue, value: RedisValue, exp: Option<Duration>) {
@@ -189,56 +707,15 @@ impl<'a> RespHandler {
         self.add_entry(key, value, exp);
     }
 
-    pub fn remove_entry(&mut self, key: &String) {
-        // Remove both from the database and the client handle's inner keys memory
+    pub fn remove_entry(&mut self, key: &[u8]) {
         self.map.lock().unwrap().remove(key);
-        self.self_keys.remove(key);
     }
 
-    pub fn add_entry(&mut self, key: String, value: RedisValue, exp: Option<Duration>) {
+    pub fn add_entry(&mut self, key: Key, value: RedisValue, exp: Option<Duration>) {
         self.map
             .lock()
             .expect("unlock failed!")
-            .insert(key.clone(), Set::new(value, exp));
-        self.self_keys.insert(key);
-    }
-
-    pub fn cleanup(&mut self) {
-        let old_map_len = self.map.lock().expect("unlock failed!").len();
-        println!("Cleanup performs on client (id)[{}]", self.client_id);
-        self.self_keys
-            .iter()
-            .for_each(|s| _remove_entry_stdln(&mut self.map, s));
-        println!(
-            "map length went from {} to {} entries",
-            old_map_len,
-            self.map.lock().expect("unlock failed!").len()
-        );
-    }
-
-    /// Increments the key-corresponding value up to the current value added with `add`.
-    /// As it signatures enforces, matching the `RedisValue::Int` variants apart from the others is done beforehand.
-    ///
-    /// If the Set is not key-present when incrementing, it is inserted with a default value of 1.
-    pub async fn incr(
-        &mut self,
-        key: &RedisValue, /* Should be RedisValue::Int() */
-    ) -> Option<RedisValue> {
-        // let key = self.keyize(key);
-        // let res = self.map.lock().unwrap().get(key)?.val.clone();
-        let (res, new_set) = if let Some(r) = self.map.lock().unwrap().get_mut(&key.keyize()) {
-            let val = RedisValue::Int(r.val.unpack_int_variant()? + 1);
-            (
-                RedisValue::Int(r.val.unpack_int_variant()?),
-                Set::from_other(r, val),
-            )
-        } else {
-            (RedisValue::Int(0), Set::new(RedisValue::Int(1), None)) // If key is not present, default is val 1 and no expiry (as one cannot conceptually be decided)
-            // and preceding value is 0.
-        };
-
-        self.insert(key, new_set.val, new_set.exp).await;
-        Some(res)
+            .insert(key, Set::new(value, exp));
     }
 
     pub async fn get_set(&mut self, key: &RedisValue) -> Option<Set> {
@@ -248,7 +725,7 @@ impl<'a> RespHandler {
             .lock()
             .unwrap()
             .get(&key)
-            .map(|inner| inner.clone())?; // Clone happens here but could happen in `handle_connection` under "GET"
+            .cloned()?; // Clone happens here but could happen in `handle_connection` under "GET"
         if set.rtime_valid() {
             Some(set)
         } else {
@@ -257,15 +734,15 @@ impl<'a> RespHandler {
         }
     }
 
-    // Returns owned RedisValue instead (or call `get_set` and access `.val`)
-    pub async fn get_val(&mut self, key: &RedisValue) -> Option<RedisValue> {
+    // Returns owned RedisObject instead (or call `get_set` and access `.val`)
+    pub async fn get_val(&mut self, key: &RedisValue) -> Option<RedisObject> {
         let key = self.keyize(key);
         let set = self
             .map
             .lock()
             .unwrap()
             .get(&key)
-            .map(|inner| inner.clone())?; // Clone happens here but could happen in `handle_connection` under "GET"
+            .cloned()?; // Clone happens here but could happen in `handle_connection` under "GET"
         if set.rtime_valid() {
             Some(set.val)
         } else {
@@ -275,171 +752,681 @@ impl<'a> RespHandler {
     }
 }
 
-fn parse_msg(buffer: BytesMut) -> Result<(RedisValue, usize)> {
-    match buffer[0] as char {
-        '+' => parse_simple_string(buffer),
-        '$' => parse_bulk_string(buffer),
-        '*' => parse_array(buffer),
-        ':' => parse_int(buffer),
-        _ => Err(anyhow::anyhow!(
-            "Not a valid RESP type: {:?}, starting with prefix: '{}' [byte: {}]",
-            buffer,
-            buffer[0] as char,
-            buffer[0]
-        )),
+/// A decoded frame along with the number of bytes it spans, or `None` if more bytes are needed to decode it
+type Parsed<T> = Result<Option<(T, usize)>>;
+
+/// Upper bound for a single bulk string, as Redis' default `proto-max-bulk-len`.
+pub(crate) const MAX_BULK_LEN: usize = 512 * 1024 * 1024;
+
+/// Nesting of aggregates past which frames are refused, rather than recursed into until the stack overflows
+const MAX_NESTING: usize = 128;
+
+/// Decodes the first frame held by `buffer`.
+///
+/// Returns `Ok(None)` as long as the frame is not fully received, in which case nothing should be consumed
+/// and the caller is expected to read more from the stream. On success, the decoded value is returned along
+/// with the number of bytes it spans, so that pipelined frames following it remain untouched.
+pub(crate) fn parse_msg(buffer: &[u8]) -> Parsed<RedisValue> {
+    parse_value(buffer, 0)
+}
+
+/// Top-level array received over several reads, along with the items decoded so far, so that they are not
+/// decoded again each time more bytes come in (as Redis keeps track of the multibulk being read)
+#[derive(Debug)]
+pub(crate) struct PartialArray {
+    items: Vec<RedisValue>,
+    remaining: usize,
+    /// Bytes spanned by the header and the items decoded so far
+    offset: usize,
+}
+
+/// Same as [`parse_msg`], resuming the top-level array left off in `partial` if any, and leaving off there
+/// the one still incomplete
+pub(crate) fn parse_msg_resuming(buffer: &[u8], partial: &mut Option<PartialArray>) -> Parsed<RedisValue> {
+    if partial.is_none() {
+        if buffer.first() != Some(&b'*') {
+            return parse_msg(buffer);
+        }
+        let Some((count, offset)) = parse_aggregate_len(buffer)? else {
+            return Ok(None);
+        };
+        *partial = Some(PartialArray {
+            // Do not trust the announced length for the allocation, the items may never come
+            items: Vec::with_capacity(count.min(1024)),
+            remaining: count,
+            offset,
+        });
+    }
+
+    let array = partial.as_mut().expect("partial array just set");
+    while array.remaining > 0 {
+        let Some((item, len)) = parse_value(&buffer[array.offset..], 1)? else {
+            return Ok(None);
+        };
+        array.items.push(item);
+        array.offset += len;
+        array.remaining -= 1;
+    }
+    let array = partial.take().expect("partial array just set");
+    Ok(Some((RedisValue::Array(array.items), array.offset)))
+}
+
+/// Length of the empty (or blank) lines `buffer` starts with, which are skipped in between inline commands
+pub(crate) fn blank_lines_len(buffer: &[u8]) -> usize {
+    let mut len = 0;
+    while buffer.get(len).is_some_and(|&c| is_inline_space(c)) {
+        match memchr::memchr(b'\n', &buffer[len..]) {
+            Some(eol) if buffer[len..len + eol].iter().all(|&c| is_inline_space(c)) => len += eol + 1,
+            _ => break,
+        }
     }
+    len
 }
 
-fn parse_simple_string(buffer: BytesMut) -> Result<(RedisValue, usize)> {
-    if let Some((line, len)) = read_until_crlf(&buffer[1..]) {
-        let string = String::from_utf8(line.to_vec()).unwrap();
-        return Ok((RedisValue::SimpleString(string), len + 1));
+fn parse_value(buffer: &[u8], depth: usize) -> Parsed<RedisValue> {
+    if depth > MAX_NESTING {
+        return Err(anyhow::anyhow!("Aggregates nested past {} levels", MAX_NESTING));
     }
+    // Empty lines are simply skipped in between commands, and whatever comes next is the actual frame
+    let skipped = if depth == 0 { blank_lines_len(buffer) } else { 0 };
+    let Some(&prefix) = buffer.get(skipped) else {
+        return Ok(None);
+    };
+    let buffer = &buffer[skipped..];
 
-    Err(anyhow::anyhow!("Not a valid simple string: {:?}", buffer))
+    let parsed = match prefix {
+        b'+' => parse_simple_string(buffer),
+        b'-' => parse_simple_error(buffer),
+        b'$' => parse_bulk_string(buffer),
+        b'*' => parse_array(buffer, depth),
+        b':' => parse_int(buffer),
+        // RESP3 only
+        b'_' => parse_null(buffer),
+        b'#' => parse_boolean(buffer),
+        b',' => parse_double(buffer),
+        b'(' => parse_big_number(buffer),
+        b'!' => parse_bulk_error(buffer),
+        b'=' => parse_verbatim_string(buffer),
+        b'%' => parse_map(buffer, depth),
+        b'~' => parse_set(buffer, depth),
+        b'>' => parse_push(buffer, depth),
+        b'|' => parse_attribute(buffer, depth),
+        // Anything else is typed by hand, as in a telnet session, which only goes for whole commands
+        _ if depth == 0 => parse_inline(buffer),
+        _ => Err(anyhow::anyhow!("Protocol error: expected '$', got '{}'", prefix as char)),
+    };
+    Ok(parsed?.map(|(v, len)| (v, skipped + len)))
 }
 
-fn parse_bulk_string(buffer: BytesMut) -> Result<(RedisValue, usize)> {
-    let (bulk_str_len, bytes_consumed) = if let Some((line, len)) = read_until_crlf(&buffer[1..]) {
-        let bulk_str_len = _parse_int(line)?;
+/// Upper bound for an inline command line, as Redis' `PROTO_INLINE_MAX_SIZE`
+const MAX_INLINE_LEN: usize = 64 * 1024;
 
-        (bulk_str_len, len + 1)
-    } else {
-        return Err(anyhow::anyhow!("Invalid bulk string format: {:?}", buffer));
+/// Parses an inline command, i.e. space-separated words terminated by a (CR)LF, into the very same array of
+/// bulk strings a RESP client would have sent. Words may be quoted: double quotes support the usual escape
+/// sequences (`\n`, `\r`, `\t`, `\b`, `\a`, `\xHH`, ...) whereas single quotes only allow `\'`.
+fn parse_inline(buffer: &[u8]) -> Parsed<RedisValue> {
+    let Some(eol) = memchr::memchr(b'\n', buffer) else {
+        if buffer.len() > MAX_INLINE_LEN {
+            return Err(anyhow::anyhow!("Protocol error: too big inline request"));
+        }
+        return Ok(None);
     };
+    let line = buffer[..eol].strip_suffix(b"\r").unwrap_or(&buffer[..eol]);
 
-    let end_of_bulk_str = bytes_consumed + bulk_str_len as usize;
-    let parsed = end_of_bulk_str + 2;
+    // Blank lines were skipped beforehand, there is at least a word
+    let words = split_inline_args(line)?;
+    Ok(Some((
+        RedisValue::Array(words.into_iter().map(RedisValue::bulk).collect()),
+        eol + 1,
+    )))
+}
 
-    Ok((
-        RedisValue::BulkString(String::from_utf8(
-            buffer[bytes_consumed..end_of_bulk_str].to_vec(),
-        )?),
-        parsed,
-    ))
+fn is_inline_space(c: u8) -> bool {
+    c.is_ascii_whitespace() || c == 0
 }
 
-fn parse_array(buffer: BytesMut) -> Result<(RedisValue, usize)> {
-    let (array_length, mut bytes_consumed) =
-        if let Some((line, len)) = read_until_crlf(&buffer[1..]) {
-            let array_length = _parse_int(line)?;
+fn split_inline_args(line: &[u8]) -> Result<Vec<Vec<u8>>> {
+    let unbalanced = || anyhow::anyhow!("Protocol error: unbalanced quotes in request");
+    let is_space = is_inline_space;
+    let mut words = vec![];
+    let mut i = 0;
 
-            (array_length, len + 1)
-        } else {
-            return Err(anyhow::anyhow!("Invalid array format {:?}", buffer));
+    loop {
+        while i < line.len() && is_space(line[i]) {
+            i += 1;
+        }
+        if i == line.len() {
+            return Ok(words);
+        }
+
+        let mut word = vec![];
+        while i < line.len() && !is_space(line[i]) {
+            match line[i] {
+                quote @ (b'"' | b'\'') => {
+                    i += 1;
+                    loop {
+                        match (line.get(i), line.get(i + 1)) {
+                            (None, _) => return Err(unbalanced()),
+                            (Some(&c), _) if c == quote => break,
+                            (Some(b'\\'), Some(&b'x')) if quote == b'"' => {
+                                let hex = line.get(i + 2..i + 4).and_then(|h| std::str::from_utf8(h).ok());
+                                match hex.and_then(|h| u8::from_str_radix(h, 16).ok()) {
+                                    Some(c) => {
+                                        word.push(c);
+                                        i += 3;
+                                    }
+                                    None => {
+                                        word.push(b'x');
+                                        i += 1;
+                                    }
+                                }
+                            }
+                            (Some(b'\\'), Some(&c)) if quote == b'"' => {
+                                word.push(match c {
+                                    b'n' => b'\n',
+                                    b'r' => b'\r',
+                                    b't' => b'\t',
+                                    b'b' => 0x08,
+                                    b'a' => 0x07,
+                                    c => c,
+                                });
+                                i += 1;
+                            }
+                            (Some(b'\\'), Some(b'\'')) => {
+                                word.push(b'\'');
+                                i += 1;
+                            }
+                            (Some(&c), _) => word.push(c),
+                        }
+                        i += 1;
+                    }
+                    i += 1;
+                    // A closing quote must be followed by a space, or end the line
+                    if i < line.len() && !is_space(line[i]) {
+                        return Err(unbalanced());
+                    }
+                }
+                c => {
+                    word.push(c);
+                    i += 1;
+                }
+            }
+        }
+        words.push(word);
+    }
+}
+
+fn parse_simple_string(buffer: &[u8]) -> Parsed<RedisValue> {
+    let Some((line, len)) = read_until_crlf(&buffer[1..]) else {
+        return Ok(None);
+    };
+    let string = String::from_utf8(line.to_vec())?;
+
+    Ok(Some((RedisValue::SimpleString(string), len + 1)))
+}
+
+fn parse_simple_error(buffer: &[u8]) -> Parsed<RedisValue> {
+    let Some((line, len)) = read_until_crlf(&buffer[1..]) else {
+        return Ok(None);
+    };
+
+    Ok(Some((RedisValue::error_line(&String::from_utf8_lossy(line)), len + 1)))
+}
+
+/// Reads a length-prefixed payload, as found in bulk strings, bulk errors and verbatim strings.
+/// A `-1` length yields no payload (the RESP2 null bulk string).
+fn parse_blob(buffer: &[u8]) -> Parsed<Option<&[u8]>> {
+    let Some((line, len)) = read_until_crlf(&buffer[1..]) else {
+        return Ok(None);
+    };
+    let blob_len = _parse_int(line)?;
+    let bytes_consumed = len + 1;
+
+    if blob_len == -1 {
+        return Ok(Some((None, bytes_consumed)));
+    }
+    let blob_len = usize::try_from(blob_len)
+        .ok()
+        .filter(|&l| l <= MAX_BULK_LEN)
+        .ok_or_else(|| anyhow::anyhow!("Invalid bulk string length: {}", blob_len))?;
+
+    let end_of_blob = bytes_consumed + blob_len;
+    let parsed = end_of_blob + 2;
+    if buffer.len() < parsed {
+        return Ok(None);
+    }
+    if &buffer[end_of_blob..parsed] != b"\r\n" {
+        return Err(anyhow::anyhow!("Bulk string is not CRLF-terminated: {:?}", buffer));
+    }
+
+    Ok(Some((Some(&buffer[bytes_consumed..end_of_blob]), parsed)))
+}
+
+fn parse_bulk_string(buffer: &[u8]) -> Parsed<RedisValue> {
+    Ok(parse_blob(buffer)?.map(|(blob, parsed)| {
+        let value = match blob {
+            Some(b) => RedisValue::BulkString(Bytes::copy_from_slice(b)),
+            None => RedisValue::NullBulkString,
+        };
+        (value, parsed)
+    }))
+}
+
+fn parse_bulk_error(buffer: &[u8]) -> Parsed<RedisValue> {
+    let Some((blob, parsed)) = parse_blob(buffer)? else {
+        return Ok(None);
+    };
+    let blob = blob.ok_or_else(|| anyhow::anyhow!("Bulk errors cannot be null: {:?}", buffer))?;
+
+    Ok(Some((RedisValue::BulkError(Bytes::copy_from_slice(blob)), parsed)))
+}
+
+fn parse_verbatim_string(buffer: &[u8]) -> Parsed<RedisValue> {
+    let Some((blob, parsed)) = parse_blob(buffer)? else {
+        return Ok(None);
+    };
+    // `xxx:` encoding prefix, followed by the actual text
+    let Some(blob) = blob.filter(|b| b.len() >= 4 && b[3] == b':') else {
+        return Err(anyhow::anyhow!("Invalid verbatim string: {:?}", buffer));
+    };
+
+    Ok(Some((
+        RedisValue::VerbatimString {
+            format: [blob[0], blob[1], blob[2]],
+            text: Bytes::copy_from_slice(&blob[4..]),
+        },
+        parsed,
+    )))
+}
+
+/// Parses the `count` consecutive values starting at `offset`, nested at `depth`
+fn parse_items(buffer: &[u8], mut offset: usize, count: usize, depth: usize) -> Parsed<Vec<RedisValue>> {
+    // Do not trust the announced length for the allocation, the items may never come
+    let mut items = Vec::with_capacity(count.min(1024));
+    for _ in 0..count {
+        let Some((item, len)) = parse_value(&buffer[offset..], depth)? else {
+            return Ok(None);
         };
 
-    let mut items = vec![];
-    for _ in 0..array_length {
-        let (array_item, len) = parse_msg(BytesMut::from(&buffer[bytes_consumed..]))?;
+        items.push(item);
+        offset += len;
+    }
+
+    Ok(Some((items, offset)))
+}
+
+/// Parses the header of an aggregate type, into its number of items and the header's length
+fn parse_aggregate_len(buffer: &[u8]) -> Parsed<usize> {
+    let Some((line, len)) = read_until_crlf(&buffer[1..]) else {
+        return Ok(None);
+    };
+    let length = _parse_int(line)?;
+    let length = usize::try_from(length)
+        .map_err(|_| anyhow::anyhow!("Invalid aggregate length: {}", length))?;
+
+    Ok(Some((length, len + 1)))
+}
+
+/// Parses the header of an aggregate type found at `depth`, and then its `n * per_item` elements
+fn parse_aggregate(buffer: &[u8], per_item: usize, depth: usize) -> Parsed<Vec<RedisValue>> {
+    let Some((length, len)) = parse_aggregate_len(buffer)? else {
+        return Ok(None);
+    };
+
+    parse_items(buffer, len, length.saturating_mul(per_item), depth + 1)
+}
+
+fn into_pairs(items: Vec<RedisValue>) -> Vec<(RedisValue, RedisValue)> {
+    let mut items = items.into_iter();
+    std::iter::from_fn(|| Some((items.next()?, items.next()?))).collect()
+}
+
+fn parse_array(buffer: &[u8], depth: usize) -> Parsed<RedisValue> {
+    Ok(parse_aggregate(buffer, 1, depth)?.map(|(items, len)| (RedisValue::Array(items), len)))
+}
+
+fn parse_set(buffer: &[u8], depth: usize) -> Parsed<RedisValue> {
+    Ok(parse_aggregate(buffer, 1, depth)?.map(|(items, len)| (RedisValue::Set(items), len)))
+}
+
+fn parse_push(buffer: &[u8], depth: usize) -> Parsed<RedisValue> {
+    Ok(parse_aggregate(buffer, 1, depth)?.map(|(items, len)| (RedisValue::Push(items), len)))
+}
+
+fn parse_map(buffer: &[u8], depth: usize) -> Parsed<RedisValue> {
+    Ok(parse_aggregate(buffer, 2, depth)?.map(|(items, len)| (RedisValue::Map(into_pairs(items)), len)))
+}
+
+// Attributes come right before the value they describe, which is parsed along
+fn parse_attribute(buffer: &[u8], depth: usize) -> Parsed<RedisValue> {
+    let Some((items, len)) = parse_aggregate(buffer, 2, depth)? else {
+        return Ok(None);
+    };
+    // The value described counts as nested too, or attributes could chain without limit
+    let Some((value, value_len)) = parse_value(&buffer[len..], depth + 1)? else {
+        return Ok(None);
+    };
+
+    Ok(Some((
+        RedisValue::Attribute(into_pairs(items), Box::new(value)),
+        len + value_len,
+    )))
+}
+
+fn parse_null(buffer: &[u8]) -> Parsed<RedisValue> {
+    let Some((line, len)) = read_until_crlf(&buffer[1..]) else {
+        return Ok(None);
+    };
+    if !line.is_empty() {
+        return Err(anyhow::anyhow!("Not a valid null: {:?}", buffer));
+    }
+
+    Ok(Some((RedisValue::Null, len + 1)))
+}
+
+fn parse_boolean(buffer: &[u8]) -> Parsed<RedisValue> {
+    let Some((line, len)) = read_until_crlf(&buffer[1..]) else {
+        return Ok(None);
+    };
+    let b = match line {
+        b"t" => true,
+        b"f" => false,
+        _ => return Err(anyhow::anyhow!("Not a valid boolean: {:?}", buffer)),
+    };
+
+    Ok(Some((RedisValue::Boolean(b), len + 1)))
+}
+
+fn parse_double(buffer: &[u8]) -> Parsed<RedisValue> {
+    let Some((line, len)) = read_until_crlf(&buffer[1..]) else {
+        return Ok(None);
+    };
+    let d = std::str::from_utf8(line)?.parse::<f64>()?;
 
-        items.push(array_item);
-        bytes_consumed += len;
+    Ok(Some((RedisValue::Double(d), len + 1)))
+}
+
+fn parse_big_number(buffer: &[u8]) -> Parsed<RedisValue> {
+    let Some((line, len)) = read_until_crlf(&buffer[1..]) else {
+        return Ok(None);
+    };
+    let digits = line.strip_prefix(b"-").or(line.strip_prefix(b"+")).unwrap_or(line);
+    if digits.is_empty() || !digits.iter().all(u8::is_ascii_digit) {
+        return Err(anyhow::anyhow!("Not a valid big number: {:?}", buffer));
     }
 
-    return Ok((RedisValue::Array(items), bytes_consumed));
+    Ok(Some((
+        RedisValue::BigNumber(String::from_utf8(line.to_vec())?),
+        len + 1,
+    )))
 }
 
 // /!\ Call with buffer[..], not buffer[1..]
 
-pub fn parse_int(buffer: BytesMut) -> Result<(RedisValue, usize)> {
-    let parsed = _parse_int(&buffer[1..])?;
-    Ok((RedisValue::Int(parsed), 0))
+pub fn parse_int(buffer: &[u8]) -> Parsed<RedisValue> {
+    let Some((line, len)) = read_until_crlf(&buffer[1..]) else {
+        return Ok(None);
+    };
+    let parsed = _parse_int(line)?;
+
+    Ok(Some((RedisValue::Int(parsed), len + 1)))
 }
 
 fn _parse_int(buffer: &[u8]) -> Result<RedisInt> {
-    Ok(String::from_utf8(buffer.to_vec())?
-        .trim()
-        .parse::<RedisInt>()?)
+    Ok(std::str::from_utf8(buffer)?.trim().parse::<RedisInt>()?)
 }
 
 // (segment, length_of_segment)
 fn read_until_crlf(buffer: &[u8]) -> Option<(&[u8], usize)> {
-    for i in 1..buffer.len() {
-        if buffer[i - 1] == b'\r' && buffer[i] == b'\n' {
-            return Some((&buffer[0..(i - 1)], i + 1));
-        }
-    }
-
-    return None;
+    memchr::memmem::find(buffer, b"\r\n").map(|i| (&buffer[..i], i + 2))
 }
 
 // Mostly useful in the main (server-side) command handling routine when the MULTI's queue should be flushed
 // rather than getting new commands from the TCP pipe on a call to EXEC.
 #[derive(PartialEq, Eq, Hash, Clone, Debug)]
 pub enum RedisCommand {
-    EXEC, // On EXEC call
+    Exec, // On EXEC call
+}
+
+/// Protocol spoken with a client, RESP2 until it negotiates another version through `HELLO`
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Default)]
+pub enum Protocol {
+    #[default]
+    Resp2,
+    Resp3,
+}
+
+impl Protocol {
+    pub fn version(&self) -> RedisInt {
+        match self {
+            Protocol::Resp2 => 2,
+            Protocol::Resp3 => 3,
+        }
+    }
 }
 
 /// RedisValue represents any object passing through a Redis client or server, may it be an integer, a bulk string or
 /// any other main Redis, part of the RESP documentation which can be found [here](https://redis.io/docs/latest/develop/reference/protocol-spec/).
-#[derive(PartialEq, Clone, Debug, Hash, Eq)]
+///
+/// RESP3-only types are downgraded to their closest RESP2 counterpart when serialized for a RESP2 client.
+#[derive(PartialEq, Clone, Debug)]
 pub enum RedisValue {
     SimpleString(String),
     // Error(Bytes),
-    BulkString(String),
+    BulkString(Bytes),
     Array(Vec<RedisValue>),
 
     #[allow(unused)]
     Int(RedisInt),
-    // NullArray,
+    /// Null reply in place of an array, as blocking commands reply once timed out
+    NullArray,
     NullBulkString,
+    /// Error reply carrying its code (`ERR`, `WRONGTYPE`...) apart from the message
+    Error(ErrorCode, String),
+    /// Raw error line, for errors that do not start with a code
     ErrorMsg(Vec<u8>),
 
+    // RESP3
+    Null,
+    Boolean(bool),
+    Double(f64),
+    BigNumber(String),
+    BulkError(Bytes),
+    VerbatimString { format: [u8; 3], text: Bytes },
+    Map(Vec<(RedisValue, RedisValue)>),
+    Set(Vec<RedisValue>),
+    Push(Vec<RedisValue>),
+    Attribute(Vec<(RedisValue, RedisValue)>, Box<RedisValue>),
+
     // Server private
     Command(RedisCommand),
 }
 
+fn serialize_line(out: &mut BytesMut, prefix: u8, line: &[u8]) {
+    // Simple strings and errors cannot span several lines
+    out.reserve(line.len() + 3);
+    out.put_u8(prefix);
+    out.extend(line.iter().map(|&c| if c == b'\r' || c == b'\n' { b' ' } else { c }));
+    out.put_slice(b"\r\n");
+}
+
+fn serialize_header(out: &mut BytesMut, prefix: u8, len: impl Display) {
+    // Writing into a BytesMut cannot fail
+    let _ = write!(out, "{}{}\r\n", prefix as char, len);
+}
+
+fn serialize_blob(out: &mut BytesMut, prefix: u8, blob: &[u8]) {
+    // The length prefix counts bytes, whatever the payload holds
+    serialize_header(out, prefix, blob.len());
+    out.reserve(blob.len() + 2);
+    out.put_slice(blob);
+    out.put_slice(b"\r\n");
+}
+
+fn serialize_aggregate<'a, I>(out: &mut BytesMut, prefix: u8, len: usize, items: I, proto: Protocol)
+where
+    I: Iterator<Item = &'a RedisValue>,
+{
+    serialize_header(out, prefix, len);
+    items.for_each(|rv| rv.serialize_into(out, proto));
+}
+
 // Only RedisValue and Vec<RedisValue> really need to be serialized
 impl RediSer for RedisValue {
-    fn serialize(&self) -> String {
+    fn serialize_into(&self, out: &mut BytesMut, proto: Protocol) {
+        let resp3 = proto == Protocol::Resp3;
         match self {
-            RedisValue::SimpleString(s) => format!("+{}\r\n", s),
-            RedisValue::BulkString(s) => format!("${}\r\n{}\r\n", s.chars().count(), s),
-            RedisValue::Int(n) => format!(":{}", n),
-            RedisValue::NullBulkString => "$-1\r\n".to_string(),
-            RedisValue::Array(v) => {
-                // Heavy many clones
-                format!(
-                    "*{}\r\n{}",
-                    v.len(),
-                    v.iter()
-                        .map(|rv| rv.clone().serialize()) // /!\
-                        .collect::<String>()
-                )
+            RedisValue::SimpleString(s) => serialize_line(out, b'+', s.as_bytes()),
+            RedisValue::BulkString(s) => serialize_blob(out, b'$', s),
+            RedisValue::Int(n) => serialize_header(out, b':', n),
+            RedisValue::NullBulkString => out.put_slice(b"$-1\r\n"),
+            RedisValue::Array(v) => serialize_aggregate(out, b'*', v.len(), v.iter(), proto),
+            RedisValue::Error(code, msg) if msg.is_empty() => serialize_header(out, b'-', code),
+            RedisValue::Error(code, msg) => {
+                let _ = write!(out, "-{}", code);
+                serialize_line(out, b' ', msg.as_bytes());
             }
-            RedisValue::ErrorMsg(v) => {
-                format!("-{}\r\n{}\r\n", v.len(), String::from_utf8(v.clone()).unwrap())
-            } // `v`` is expected to be correctly created at source => safer implementation could be wanted though
+            RedisValue::ErrorMsg(v) => serialize_line(out, b'-', v),
 
-            _ => unimplemented!(), // Server internal commands should not leak to clients (what's the point?)
+            RedisValue::Null if resp3 => out.put_slice(b"_\r\n"),
+            RedisValue::Null => out.put_slice(b"$-1\r\n"),
+            RedisValue::NullArray if resp3 => out.put_slice(b"_\r\n"),
+            RedisValue::NullArray => out.put_slice(b"*-1\r\n"),
+            RedisValue::Boolean(b) if resp3 => out.put_slice(if *b { b"#t\r\n" } else { b"#f\r\n" }),
+            RedisValue::Boolean(b) => out.put_slice(if *b { b":1\r\n" } else { b":0\r\n" }),
+            RedisValue::Double(d) if resp3 => serialize_header(out, b',', format_double(*d)),
+            RedisValue::Double(d) => serialize_blob(out, b'$', format_double(*d).as_bytes()),
+            RedisValue::BigNumber(n) if resp3 => serialize_header(out, b'(', n),
+            RedisValue::BigNumber(n) => serialize_blob(out, b'$', n.as_bytes()),
+            RedisValue::BulkError(e) if resp3 => serialize_blob(out, b'!', e),
+            RedisValue::BulkError(e) => serialize_line(out, b'-', e),
+            RedisValue::VerbatimString { format, text } if resp3 => {
+                serialize_header(out, b'=', text.len() + 4);
+                out.reserve(text.len() + 6);
+                out.put_slice(format);
+                out.put_u8(b':');
+                out.put_slice(text);
+                out.put_slice(b"\r\n");
+            }
+            RedisValue::VerbatimString { text, .. } => serialize_blob(out, b'$', text),
+            RedisValue::Map(m) => {
+                let items = m.iter().flat_map(|(k, v)| [k, v]);
+                if resp3 {
+                    serialize_aggregate(out, b'%', m.len(), items, proto)
+                } else {
+                    serialize_aggregate(out, b'*', m.len() * 2, items, proto)
+                }
+            }
+            RedisValue::Set(v) => serialize_aggregate(out, if resp3 { b'~' } else { b'*' }, v.len(), v.iter(), proto),
+            RedisValue::Push(v) => serialize_aggregate(out, if resp3 { b'>' } else { b'*' }, v.len(), v.iter(), proto),
+            RedisValue::Attribute(attrs, value) if resp3 => {
+                serialize_aggregate(out, b'|', attrs.len(), attrs.iter().flat_map(|(k, v)| [k, v]), proto);
+                value.serialize_into(out, proto);
+            }
+            // RESP2 clients have no way to receive attributes out of band
+            RedisValue::Attribute(_, value) => value.serialize_into(out, proto),
+
+            RedisValue::Command(_) => unimplemented!(), // Server internal commands should not leak to clients (what's the point?)
         }
     }
 }
 
 impl RediSer for Vec<RedisValue> {
-    fn serialize(&self) -> String {
-        let mut res = String::new();
+    fn serialize_into(&self, out: &mut BytesMut, proto: Protocol) {
         for i in self {
-            res.push_str(&i.serialize());
+            i.serialize_into(out, proto);
         }
-        res
     }
 }
+
 impl RedisValue {
-    pub fn keyize(&self) -> String {
-        self.unpack_for_str().to_string()
+    /// Builds an error reply out of a whole error line, splitting its code apart when it has one
+    pub fn error_line(line: &str) -> Self {
+        let (word, msg) = line.split_once(' ').unwrap_or((line, ""));
+        match ErrorCode::from_word(word) {
+            Some(code) => RedisValue::Error(code, msg.to_string()),
+            None => RedisValue::ErrorMsg(line.as_bytes().to_vec()),
+        }
+    }
+
+    /// Builds a bulk string out of anything convertible into bytes
+    pub fn bulk<T: Into<Bytes>>(b: T) -> Self {
+        RedisValue::BulkString(b.into())
     }
 
-    /// Unpacks only variants that hold string types
+    pub fn keyize(&self) -> Vec<u8> {
+        match self {
+            RedisValue::SimpleString(s) => s.as_bytes().to_vec(),
+            RedisValue::BulkString(s) => s.to_vec(),
+            RedisValue::Int(n) => n.to_string().into_bytes(),
+            _ => panic!(
+                "String key cannot be constructed from any other value than RedisValue::SimpleString/BulkString/Int"
+            ),
+        }
+    }
+
+    /// Unpacks only variants that hold string types, as long as they are valid UTF-8
     pub fn unpack_str_variant(&self) -> Option<&str> {
         match self {
             RedisValue::SimpleString(s) => Some(s),
+            RedisValue::BulkString(s) => std::str::from_utf8(s).ok(),
+            _ => None,
+        }
+    }
+
+    /// Unpacks only variants that hold string types, as raw bytes
+    pub fn unpack_bytes_variant(&self) -> Option<&[u8]> {
+        match self {
+            RedisValue::SimpleString(s) => Some(s.as_bytes()),
             RedisValue::BulkString(s) => Some(s),
             _ => None,
         }
     }
 
+    /// Reads a stored value as a string, integers being turned into their text
+    pub fn string_bytes(&self) -> Option<Cow<'_, [u8]>> {
+        match self {
+            RedisValue::Int(n) => Some(Cow::Owned(n.to_string().into_bytes())),
+            v => v.unpack_bytes_variant().map(Cow::Borrowed),
+        }
+    }
+
+    /// Takes a string argument as an element to store, bulk strings being shared rather than copied
+    pub fn bytes_arg(&self) -> Bytes {
+        match self {
+            RedisValue::BulkString(b) => b.clone(),
+            v => Bytes::from(v.keyize()),
+        }
+    }
+
+    /// Lowercase name of an option given as argument, or an empty string if it cannot be one
+    pub fn option_name(&self) -> String {
+        self.unpack_str_variant().unwrap_or_default().to_ascii_lowercase()
+    }
+
+    /// Reads an integer argument, as strictly as Redis does (no sign prefix, leading zeros or spaces)
+    pub fn int_arg(&self) -> Result<RedisInt, RedisError> {
+        match self {
+            RedisValue::Int(n) => Ok(*n),
+            _ => self
+                .unpack_bytes_variant()
+                .and_then(parse_redis_int)
+                .ok_or(RedisError::NotInteger),
+        }
+    }
+
+    /// Reads a floating point argument, infinities included but not NaN
+    pub fn float_arg(&self) -> Result<f64, RedisError> {
+        match self {
+            RedisValue::Int(n) => Ok(*n as f64),
+            RedisValue::Double(d) if !d.is_nan() => Ok(*d),
+            _ => self
+                .unpack_bytes_variant()
+                .and_then(parse_redis_float)
+                .ok_or(RedisError::NotFloat),
+        }
+    }
+
     /// Unpacks only variants that hold int types
     pub fn unpack_int_variant(&self) -> Option<RedisInt> {
         match self {
@@ -456,15 +1443,336 @@ impl RedisValue {
             _ => unimplemented!(),
         }
     }
+}
 
-    pub fn unpack_for_str(&self) -> &dyn ToString {
-        match self {
-            RedisValue::SimpleString(s) => s,
-            RedisValue::BulkString(s) => s,
-            RedisValue::Int(n) => n,
-            _ => panic!(
-                "String key cannot be constructed from any other value than RedisValue::SimpleString/BulkString/Int"
-            ),
+#[cfg(test)]
+pub(crate) mod test {
+    use super::{Database, RespHandler, ThreadSafeDb, parse_msg, parse_msg_resuming};
+    use bytes::Bytes;
+    use crate::{ErrorCode, Protocol, RediSer, RedisError, RedisValue, Transaction};
+    use std::sync::{Arc, Mutex};
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::{TcpListener, TcpStream},
+    };
+
+    pub(crate) fn bulk(s: &str) -> RedisValue {
+        RedisValue::bulk(s.to_string())
+    }
+
+    #[test]
+    fn parse_incomplete_frames() {
+        let frame = b"*3\r\n$3\r\nSET\r\n$3\r\nfoo\r\n$3\r\nbar\r\n";
+        for cut in 0..frame.len() {
+            assert!(parse_msg(&frame[..cut]).unwrap().is_none(), "cut at {}", cut);
+        }
+
+        let (v, len) = parse_msg(frame).unwrap().unwrap();
+        assert_eq!(len, frame.len());
+        assert_eq!(v, RedisValue::Array(vec![bulk("SET"), bulk("foo"), bulk("bar")]));
+    }
+
+    #[test]
+    fn parse_pipelined_frames() {
+        let frames = b":42\r\n+OK\r\n$-1\r\n";
+        let mut consumed = 0;
+        let mut values = vec![];
+        while let Some((v, len)) = parse_msg(&frames[consumed..]).unwrap() {
+            values.push(v);
+            consumed += len;
+        }
+
+        assert_eq!(consumed, frames.len());
+        assert_eq!(
+            values,
+            vec![
+                RedisValue::Int(42),
+                RedisValue::SimpleString("OK".to_string()),
+                RedisValue::NullBulkString
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_invalid_frames() {
+        assert!(parse_msg(b"$3\r\nfoobar\r\n").is_err());
+        assert!(parse_msg(b"*-2\r\n").is_err());
+        assert!(parse_msg(b"SET \"lol\r\n").is_err());
+        // Nesting is capped rather than recursed into without end
+        assert!(parse_msg(&b"*1\r\n".repeat(200_000)).is_err());
+        assert!(parse_msg(&b"|0\r\n".repeat(200_000)).is_err());
+        let nested = [b"*1\r\n".repeat(100), b":1\r\n".to_vec()].concat();
+        assert_eq!(parse_msg(&nested).unwrap().unwrap().1, nested.len());
+    }
+
+    #[test]
+    fn parse_resuming_arrays() {
+        let frame = b"*3\r\n$3\r\nSET\r\n$3\r\nfoo\r\n$3\r\nbar\r\n:1\r\n";
+        let mut partial = None;
+        for cut in 0..frame.len() - 4 {
+            assert!(parse_msg_resuming(&frame[..cut], &mut partial).unwrap().is_none(), "cut at {}", cut);
+        }
+        let (v, len) = parse_msg_resuming(frame, &mut partial).unwrap().unwrap();
+        assert_eq!(v, RedisValue::Array(vec![bulk("SET"), bulk("foo"), bulk("bar")]));
+        assert_eq!(len, frame.len() - 4);
+        assert!(partial.is_none());
+        assert_eq!(parse_msg_resuming(&frame[len..], &mut partial).unwrap().unwrap(), (RedisValue::Int(1), 4));
+    }
+
+    #[test]
+    fn parse_inline_commands() {
+        assert!(parse_msg(b"SET a").unwrap().is_none());
+
+        let frame = b"\r\n  SET  a \"b c\\x41\\n\" 'd\\'e'\nPING\r\n";
+        let (v, len) = parse_msg(frame).unwrap().unwrap();
+        assert_eq!(
+            v,
+            RedisValue::Array(vec![bulk("SET"), bulk("a"), bulk("b cA\n"), bulk("d'e")])
+        );
+
+        let (v, rest) = parse_msg(&frame[len..]).unwrap().unwrap();
+        assert_eq!(v, RedisValue::Array(vec![bulk("PING")]));
+        assert_eq!(len + rest, frame.len());
+
+        assert!(parse_msg(b"GET \"a\"b\n").is_err());
+        let frame = [b"\n".repeat(200_000), b"PING\n".to_vec()].concat();
+        assert_eq!(parse_msg(&frame).unwrap().unwrap().1, frame.len());
+
+        // Items of a multibulk are never typed by hand
+        for frame in [&b"*2\r\n$3\r\nGET\r\nfoo\r\n"[..], b"*2\r\n$3\r\nGET\r\n\r\n$3\r\nfoo\r\n"] {
+            let err = parse_msg(frame).unwrap_err().to_string();
+            assert!(err.starts_with("Protocol error: expected '$'"), "{}", err);
+            assert!(parse_msg_resuming(frame, &mut None).is_err());
+        }
+    }
+
+    #[test]
+    fn binary_safe_bulk_strings() {
+        let frame = b"$4\r\n\xff\r\n\x00\r\n";
+        let (v, len) = parse_msg(frame).unwrap().unwrap();
+        assert_eq!(len, frame.len());
+        assert_eq!(v, RedisValue::bulk(&b"\xff\r\n\x00"[..]));
+        assert_eq!(v.serialize(), frame.to_vec());
+
+        // Length prefix is expressed in bytes, not in chars
+        assert_eq!(RedisValue::bulk("héhé").serialize(), "$6\r\nhéhé\r\n".as_bytes());
+    }
+
+    #[test]
+    fn parse_resp3_types() {
+        let frame = b"%2\r\n+first\r\n#t\r\n$6\r\nsecond\r\n~3\r\n,-1.5\r\n(-12345678901234567890\r\n_\r\n";
+        let (v, len) = parse_msg(frame).unwrap().unwrap();
+        assert_eq!(len, frame.len());
+        assert_eq!(
+            v,
+            RedisValue::Map(vec![
+                (RedisValue::SimpleString("first".to_string()), RedisValue::Boolean(true)),
+                (
+                    bulk("second"),
+                    RedisValue::Set(vec![
+                        RedisValue::Double(-1.5),
+                        RedisValue::BigNumber("-12345678901234567890".to_string()),
+                        RedisValue::Null
+                    ])
+                )
+            ])
+        );
+
+        let frame = b"|1\r\n+ttl\r\n,inf\r\n=8\r\ntxt:abcd\r\n";
+        let (v, len) = parse_msg(frame).unwrap().unwrap();
+        assert_eq!(len, frame.len());
+        assert_eq!(
+            v,
+            RedisValue::Attribute(
+                vec![(RedisValue::SimpleString("ttl".to_string()), RedisValue::Double(f64::INFINITY))],
+                Box::new(RedisValue::VerbatimString { format: *b"txt", text: "abcd".into() })
+            )
+        );
+        assert!(parse_msg(&frame[..frame.len() - 1]).unwrap().is_none());
+    }
+
+    #[test]
+    fn serialization_round_trip() {
+        let values = vec![
+            RedisValue::SimpleString("OK".to_string()),
+            RedisValue::Error(ErrorCode::WrongType, "Operation against a key holding the wrong kind of value".to_string()),
+            RedisValue::Error(ErrorCode::ExecAbort, String::new()),
+            RedisValue::Error(ErrorCode::Other("MOVED".to_string()), "3999 127.0.0.1:6381".to_string()),
+            RedisValue::ErrorMsg(b"no code here".to_vec()),
+            RedisValue::Int(-42),
+            bulk(""),
+            RedisValue::bulk(&b"\x00\r\n\xff"[..]),
+            RedisValue::NullBulkString,
+            RedisValue::Array(vec![RedisValue::Int(1), RedisValue::Array(vec![]), bulk("x")]),
+            RedisValue::Null,
+            RedisValue::Boolean(true),
+            RedisValue::Double(3.25),
+            RedisValue::Double(f64::NEG_INFINITY),
+            RedisValue::BigNumber("3492890328409238509324850943850943825024385".to_string()),
+            RedisValue::BulkError(Bytes::from("SYNTAX invalid\r\nsyntax")),
+            RedisValue::VerbatimString { format: *b"mkd", text: "# title".into() },
+            RedisValue::Map(vec![(bulk("k"), RedisValue::Set(vec![RedisValue::Int(0)]))]),
+            RedisValue::Push(vec![bulk("message"), bulk("chan"), bulk("hi")]),
+            RedisValue::Attribute(vec![(bulk("ttl"), RedisValue::Int(3))], Box::new(bulk("v"))),
+        ];
+
+        for v in values {
+            let serialized = v.serialize_as(Protocol::Resp3);
+            let (parsed, len) = parse_msg(&serialized).unwrap().unwrap();
+            assert_eq!(len, serialized.len(), "{:?}", v);
+            assert_eq!(parsed, v);
         }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn errors_are_single_lines() {
+        let e: RedisValue = RedisError::WrongArity("get".to_string()).into();
+        assert_eq!(e, RedisValue::Error(ErrorCode::Err, "wrong number of arguments for 'get' command".to_string()));
+        assert_eq!(e.serialize(), b"-ERR wrong number of arguments for 'get' command\r\n".to_vec());
+        assert_eq!(RedisValue::ErrorMsg(b"a\r\nb".to_vec()).serialize(), b"-a  b\r\n".to_vec());
+        assert_eq!(RedisValue::BulkError("ERR a\nb".into()).serialize(), b"-ERR a b\r\n".to_vec());
+        assert_eq!(RedisValue::Int(7).serialize(), b":7\r\n".to_vec());
+    }
+
+    #[test]
+    fn resp3_downgrade_to_resp2() {
+        let v = RedisValue::Map(vec![(bulk("k"), RedisValue::Double(2.5))]);
+        assert_eq!(v.serialize_as(Protocol::Resp3), b"%1\r\n$1\r\nk\r\n,2.5\r\n".to_vec());
+        assert_eq!(v.serialize_as(Protocol::Resp2), b"*2\r\n$1\r\nk\r\n$3\r\n2.5\r\n".to_vec());
+
+        let v = RedisValue::Push(vec![RedisValue::Null, RedisValue::Boolean(false)]);
+        assert_eq!(v.serialize_as(Protocol::Resp3), b">2\r\n_\r\n#f\r\n".to_vec());
+        assert_eq!(v.serialize_as(Protocol::Resp2), b"*2\r\n$-1\r\n:0\r\n".to_vec());
+
+        assert_eq!(RedisValue::NullArray.serialize_as(Protocol::Resp3), b"_\r\n".to_vec());
+        assert_eq!(RedisValue::NullArray.serialize_as(Protocol::Resp2), b"*-1\r\n".to_vec());
+    }
+
+    pub(crate) async fn connected_handler() -> (RespHandler, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).await.unwrap();
+        let (stream, _) = listener.accept().await.unwrap();
+        (RespHandler::new(stream, 0, Arc::new(Mutex::new(Database::default()))), client)
+    }
+
+    /// Handler of another client sharing `map` with the first one
+    pub(crate) async fn connected_handler_on(map: &ThreadSafeDb, id: usize) -> (RespHandler, TcpStream) {
+        let (handler, client) = connected_handler().await;
+        (RespHandler::new(handler.stream, id, Arc::clone(map)), client)
+    }
+
+    #[tokio::test]
+    async fn hello_switches_protocol() {
+        let (mut handler, _client) = connected_handler().await;
+
+        let reply = handler.handle_command("hello", vec![bulk("3"), bulk("SETNAME"), bulk("me")]).await;
+        let RedisValue::Map(props) = reply else {
+            panic!("HELLO should reply with a map, got {:?}", reply);
+        };
+        assert!(props.contains(&(bulk("proto"), RedisValue::Int(3))));
+        assert_eq!(handler.protocol, Protocol::Resp3);
+        assert_eq!(handler.name.as_deref(), Some(&b"me"[..]));
+
+        let reply = handler.handle_command("hello", vec![bulk("4")]).await;
+        assert!(matches!(reply, RedisValue::Error(ErrorCode::NoProto, _)));
+        assert_eq!(handler.protocol, Protocol::Resp3);
+
+        handler.handle_command("hello", vec![bulk("2")]).await;
+        assert_eq!(handler.protocol, Protocol::Resp2);
+    }
+
+    #[tokio::test]
+    async fn command_errors_are_replied() {
+        let (mut handler, _client) = connected_handler().await;
+
+        let reply = handler.handle_command("nope", vec![bulk("a"), bulk("b")]).await;
+        assert_eq!(
+            reply,
+            RedisError::UnknownCommand("nope".to_string(), "'a' 'b' ".to_string()).into()
+        );
+        assert_eq!(
+            handler.handle_command("get", vec![]).await,
+            RedisError::WrongArity("get".to_string()).into()
+        );
+        assert_eq!(
+            handler.handle_command("set", vec![bulk("k"), bulk("v"), bulk("px"), bulk("soon")]).await,
+            RedisError::NotInteger.into()
+        );
+        assert_eq!(
+            handler.handle_command("set", vec![bulk("k"), bulk("v"), bulk("px")]).await,
+            RedisError::Syntax.into()
+        );
+        assert_eq!(handler.handle_command("get", vec![bulk("k")]).await, RedisValue::NullBulkString);
+    }
+
+    #[tokio::test]
+    async fn transactions() {
+        let (mut handler, _client) = connected_handler().await;
+        let mut transaction = Transaction::init();
+
+        assert_eq!(handler.handle_exec(&mut transaction).await, RedisError::ExecWithoutMulti.into());
+
+        handler.handle_multi(&mut transaction).await;
+        assert_eq!(handler.handle_multi(&mut transaction).await, RedisError::NestedMulti.into());
+        handler.handle_queued(&mut transaction, "set".to_string(), vec![bulk("k"), bulk("v")]);
+        handler.handle_queued(&mut transaction, "get".to_string(), vec![bulk("k")]);
+        assert_eq!(handler.handle_command("get", vec![bulk("k")]).await, RedisValue::NullBulkString);
+        assert_eq!(
+            handler.handle_exec(&mut transaction).await,
+            RedisValue::Array(vec![RedisValue::SimpleString("OK".to_string()), bulk("v")])
+        );
+
+        handler.handle_multi(&mut transaction).await;
+        handler.handle_queued(&mut transaction, "set".to_string(), vec![bulk("k"), bulk("w")]);
+        handler.handle_queued(&mut transaction, "get".to_string(), vec![]);
+        assert_eq!(handler.handle_exec(&mut transaction).await, RedisError::ExecAbort.into());
+        assert_eq!(handler.handle_command("get", vec![bulk("k")]).await, bulk("v"));
+    }
+
+    #[tokio::test]
+    async fn replies_are_batched() {
+        let (mut handler, mut client) = connected_handler().await;
+
+        handler.write_value(&RedisValue::SimpleString("OK".to_string())).await.unwrap();
+        handler.write_value(&vec![RedisValue::Int(1), bulk("v")]).await.unwrap();
+        let mut buf = [0u8; 64];
+        assert!(client.try_read(&mut buf).is_err());
+
+        handler.flush().await.unwrap();
+        client.readable().await.unwrap();
+        let expected = b"+OK\r\n:1\r\n$1\r\nv\r\n";
+        client.read_exact(&mut buf[..expected.len()]).await.unwrap();
+        assert_eq!(&buf[..expected.len()], expected);
+    }
+
+    #[tokio::test]
+    async fn read_split_and_pipelined_commands() {
+        let (mut handler, mut client) = connected_handler().await;
+
+        client.write_all(b"*2\r\n$3\r\nGET\r\n$3\r\nf").await.unwrap();
+        client.flush().await.unwrap();
+        let pending = tokio::spawn(async move {
+            client
+                .write_all(b"oo\r\n*1\r\n$4\r\nPING\r\n*2\r\n$4\r\nECHO\r\n$2\r\nhi\r\n")
+                .await
+                .unwrap();
+            client
+        });
+
+        assert_eq!(
+            handler.read_value().await.unwrap(),
+            Some(RedisValue::Array(vec![bulk("GET"), bulk("foo")]))
+        );
+        assert_eq!(
+            handler.read_value().await.unwrap(),
+            Some(RedisValue::Array(vec![bulk("PING")]))
+        );
+        assert_eq!(
+            handler.read_value().await.unwrap(),
+            Some(RedisValue::Array(vec![bulk("ECHO"), bulk("hi")]))
+        );
+
+        drop(pending.await.unwrap());
+        assert_eq!(handler.read_value().await.unwrap(), None);
+    }
+}
@@ -0,0 +1,503 @@
+// Bitmap commands: strings addressed bit by bit, bit 0 being the most significant bit of the first byte
+
+use super::strings::resolve_range;
+use super::{Database, Key, MAX_BULK_LEN, RespHandler, Set, lookup};
+use crate::error::{CmdResult, RedisError};
+use crate::parse_redis_int;
+use crate::resp::{RedisInt, RedisValue};
+
+fn offset_out_of_range() -> RedisError {
+    RedisError::Other("bit offset is not an integer or out of range".to_string())
+}
+
+/// Reads a bit offset, which BITFIELD may give as `#n` for the n-th field of `width` bits
+fn bit_offset(arg: &RedisValue, width: Option<usize>) -> Result<usize, RedisError> {
+    let arg = arg.bytes_arg();
+    let (index, width) = match (arg.strip_prefix(b"#"), width) {
+        (Some(index), Some(width)) => (index, width),
+        _ => (&arg[..], 1),
+    };
+    parse_redis_int(index)
+        .and_then(|n| usize::try_from(n).ok())
+        .and_then(|n| n.checked_mul(width))
+        .filter(|&n| n / 8 < MAX_BULK_LEN)
+        .ok_or_else(offset_out_of_range)
+}
+
+/// Reads the `BYTE | BIT` unit of BITCOUNT and BITPOS ranges, replying with whether it is BIT
+fn bit_unit_arg(arg: &RedisValue) -> Result<bool, RedisError> {
+    match arg.option_name().as_str() {
+        "byte" => Ok(false),
+        "bit" => Ok(true),
+        _ => Err(RedisError::Syntax),
+    }
+}
+
+/// Resolves a range of BITCOUNT or BITPOS over a `len` bytes long string into inclusive bit positions, the
+/// range being counted in bits rather than bytes if `bit_unit`
+fn bit_range(start: RedisInt, end: RedisInt, bit_unit: bool, len: usize) -> Option<(usize, usize)> {
+    match bit_unit {
+        true => resolve_range(start, end, len * 8),
+        false => resolve_range(start, end, len).map(|(start, end)| (start * 8, end * 8 + 7)),
+    }
+}
+
+/// Bit at a position, bits past the end of the string being 0
+fn bit_at(bytes: &[u8], pos: usize) -> bool {
+    bytes.get(pos / 8).is_some_and(|byte| byte >> (7 - pos % 8) & 1 == 1)
+}
+
+fn set_bit(bytes: &mut [u8], pos: usize, bit: bool) {
+    let mask = 1 << (7 - pos % 8);
+    match bit {
+        true => bytes[pos / 8] |= mask,
+        false => bytes[pos / 8] &= !mask,
+    }
+}
+
+/// Number of bits set from `start` to `end` included, both within the string
+fn count_bits(bytes: &[u8], start: usize, end: usize) -> usize {
+    let (first, last) = (bytes[start / 8] as u32, bytes[end / 8] as u32);
+    let whole: u32 = bytes[start / 8..=end / 8].iter().map(|byte| byte.count_ones()).sum();
+    // Minus the bits of the first and last bytes out of the range
+    let before = (first >> (8 - start % 8)).count_ones();
+    let after = (last & ((1 << (7 - end % 8)) - 1)).count_ones();
+    (whole - before - after) as usize
+}
+
+/// Position of the first bit equal to `bit` from `start` to `end` included, both within the string
+fn find_bit(bytes: &[u8], start: usize, end: usize, bit: bool) -> Option<usize> {
+    // Bytes made of the other bit only are skipped whole
+    let skipped = if bit { 0 } else { u8::MAX };
+    let mut pos = start;
+    while pos <= end {
+        if pos.is_multiple_of(8) && pos + 7 <= end && bytes[pos / 8] == skipped {
+            pos += 8;
+            continue;
+        }
+        if bit_at(bytes, pos) == bit {
+            return Some(pos);
+        }
+        pos += 1;
+    }
+    None
+}
+
+/// The string at `key` for a bit command writing to it, created if missing and zero-padded to `len` bytes
+fn string_for_bits<'a>(db: &'a mut Database, key: &Key, len: usize) -> Result<&'a mut Set, RedisError> {
+    if lookup(db, key).is_none() {
+        db.insert(key.clone(), Set::new(RedisValue::bulk(""), None));
+    }
+    let set = lookup(db, key).expect("key just inserted");
+    let current = set.val.string_bytes().ok_or(RedisError::WrongType)?.len();
+    if current < len {
+        set.update_string(|s| s.resize(len, 0))?;
+    }
+    Ok(set)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum BitOp {
+    And,
+    Or,
+    Xor,
+    Not,
+}
+
+/// What BITFIELD does of integers not fitting in their field
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum OverflowMode {
+    /// Keeps the lowest bits, as integer types wrap around
+    Wrap,
+    /// Saturates to the lowest or highest integer of the field
+    Sat,
+    /// Leaves the field as it is, replying nil
+    Fail,
+}
+
+/// Type of a BITFIELD field: `i1` to `i64`, or `u1` to `u63`
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct FieldType {
+    signed: bool,
+    bits: usize,
+}
+
+impl FieldType {
+    fn parse(arg: &RedisValue) -> Result<Self, RedisError> {
+        let arg = arg.bytes_arg();
+        let (signed, max_bits) = match arg.first() {
+            Some(b'i' | b'I') => (true, 64),
+            Some(b'u' | b'U') => (false, 63),
+            _ => (false, 0),
+        };
+        match parse_redis_int(&arg[1.min(arg.len())..]) {
+            Some(bits) if bits >= 1 && bits <= max_bits => Ok(FieldType { signed, bits: bits as usize }),
+            _ => Err(RedisError::Other(
+                "Invalid bitfield type. Use something like i16 u8. Note that u64 is not supported but i64 is."
+                    .to_string(),
+            )),
+        }
+    }
+
+    /// Lowest and highest integers the field holds
+    fn bounds(self) -> (i128, i128) {
+        match self.signed {
+            true => (-(1 << (self.bits - 1)), (1 << (self.bits - 1)) - 1),
+            false => (0, (1 << self.bits) - 1),
+        }
+    }
+
+    fn get(self, bytes: &[u8], offset: usize) -> i64 {
+        let raw = (0..self.bits).fold(0u64, |raw, i| raw << 1 | bit_at(bytes, offset + i) as u64);
+        match self.signed && raw >> (self.bits - 1) & 1 == 1 {
+            true => (raw as i128 - (1 << self.bits)) as i64,
+            false => raw as i64,
+        }
+    }
+
+    fn set(self, bytes: &mut [u8], offset: usize, value: i64) {
+        for i in 0..self.bits {
+            set_bit(bytes, offset + i, value >> (self.bits - 1 - i) & 1 == 1);
+        }
+    }
+
+    /// The value to store for `value`, or `None` if it overflows the field with [`OverflowMode::Fail`]
+    fn fit(self, value: i128, overflow: OverflowMode) -> Option<i64> {
+        let (min, max) = self.bounds();
+        if (min..=max).contains(&value) {
+            return Some(value as i64);
+        }
+        match overflow {
+            OverflowMode::Wrap => {
+                let wrapped = value.rem_euclid(1 << self.bits);
+                Some(if wrapped > max { wrapped - (1 << self.bits) } else { wrapped } as i64)
+            }
+            OverflowMode::Sat => Some(value.clamp(min, max) as i64),
+            OverflowMode::Fail => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum FieldOp {
+    Get,
+    Set(i64),
+    IncrBy(i64),
+}
+
+/// Runs a BITFIELD operation, the string being long enough for any field written. Replies with the value read,
+/// the previous value for SET, the new one for INCRBY, or nil if the write failed to fit.
+fn run_field_op(bytes: &mut [u8], op: FieldOp, ty: FieldType, offset: usize, overflow: OverflowMode) -> RedisValue {
+    let current = ty.get(bytes, offset);
+    let (new, reply) = match op {
+        FieldOp::Get => return RedisValue::Int(current),
+        FieldOp::Set(value) => (ty.fit(value as i128, overflow), current),
+        FieldOp::IncrBy(incr) => {
+            let new = ty.fit(current as i128 + incr as i128, overflow);
+            (new, new.unwrap_or_default())
+        }
+    };
+    match new {
+        Some(new) => {
+            ty.set(bytes, offset, new);
+            RedisValue::Int(reply)
+        }
+        None => RedisValue::Null,
+    }
+}
+
+impl RespHandler {
+    /// `SETBIT key offset value`, replying with the bit previously stored
+    pub(super) fn setbit(&mut self, args: &[RedisValue]) -> CmdResult {
+        let offset = bit_offset(&args[1], None)?;
+        let bit = match args[2].int_arg() {
+            Ok(bit @ (0 | 1)) => bit == 1,
+            _ => return Err(RedisError::Other("bit is not an integer or out of range".to_string())),
+        };
+        let key = self.keyize(&args[0]);
+        let mut db = self.map.lock().expect("unlock failed!");
+
+        let set = string_for_bits(&mut db, &key, offset / 8 + 1)?;
+        let previous = set.update_string(|s| {
+            let previous = bit_at(s, offset);
+            set_bit(s, offset, bit);
+            previous
+        })?;
+        Ok(RedisValue::Int(previous as RedisInt))
+    }
+
+    /// `GETBIT key offset`
+    pub(super) fn getbit(&mut self, key: &RedisValue, offset: &RedisValue) -> CmdResult {
+        let offset = bit_offset(offset, None)?;
+        let key = self.keyize(key);
+        let mut db = self.map.lock().expect("unlock failed!");
+
+        let bit = match lookup(&mut db, &key) {
+            Some(set) => bit_at(&set.val.string_bytes().ok_or(RedisError::WrongType)?, offset),
+            None => false,
+        };
+        Ok(RedisValue::Int(bit as RedisInt))
+    }
+
+    /// `BITCOUNT key [start end [BYTE | BIT]]`
+    pub(super) fn bitcount(&mut self, args: &[RedisValue]) -> CmdResult {
+        let range = match &args[1..] {
+            [] => None,
+            [start, end] => Some((start.int_arg()?, end.int_arg()?, false)),
+            [start, end, unit] => Some((start.int_arg()?, end.int_arg()?, bit_unit_arg(unit)?)),
+            _ => return Err(RedisError::Syntax),
+        };
+        let key = self.keyize(&args[0]);
+        let mut db = self.map.lock().expect("unlock failed!");
+
+        let Some(set) = lookup(&mut db, &key) else {
+            return Ok(RedisValue::Int(0));
+        };
+        let bytes = set.val.string_bytes().ok_or(RedisError::WrongType)?;
+        let (start, end, bit_unit) = range.unwrap_or((0, -1, false));
+        let count = bit_range(start, end, bit_unit, bytes.len())
+            .map_or(0, |(first, last)| count_bits(&bytes, first, last));
+        Ok(RedisValue::Int(count as RedisInt))
+    }
+
+    /// `BITPOS key bit [start [end [BYTE | BIT]]]`, replying with the position of the first bit set to `bit`
+    /// within the range, or -1. Looking for a 0 in a string of 1s without an end finds the first bit past it.
+    pub(super) fn bitpos(&mut self, args: &[RedisValue]) -> CmdResult {
+        let bit = match args[1].int_arg() {
+            Ok(bit @ (0 | 1)) => bit == 1,
+            _ => return Err(RedisError::Other("The bit argument must be 1 or 0.".to_string())),
+        };
+        let (start, end, bit_unit) = match &args[2..] {
+            [] => (0, None, false),
+            [start] => (start.int_arg()?, None, false),
+            [start, end] => (start.int_arg()?, Some(end.int_arg()?), false),
+            [start, end, unit] => (start.int_arg()?, Some(end.int_arg()?), bit_unit_arg(unit)?),
+            _ => return Err(RedisError::Syntax),
+        };
+        let key = self.keyize(&args[0]);
+        let mut db = self.map.lock().expect("unlock failed!");
+
+        let Some(set) = lookup(&mut db, &key) else {
+            return Ok(RedisValue::Int(if bit { -1 } else { 0 }));
+        };
+        let bytes = set.val.string_bytes().ok_or(RedisError::WrongType)?;
+        let Some((first, last)) = bit_range(start, end.unwrap_or(-1), bit_unit, bytes.len()) else {
+            return Ok(RedisValue::Int(-1));
+        };
+        Ok(RedisValue::Int(match find_bit(&bytes, first, last, bit) {
+            Some(pos) => pos as RedisInt,
+            // As if the string went on with 0s
+            None if !bit && end.is_none() => last as RedisInt + 1,
+            None => -1,
+        }))
+    }
+
+    /// `BITOP AND | OR | XOR | NOT destkey key [key ...]`, replying with the length of the string stored. Shorter
+    /// strings and missing keys count as zero-padded, and an empty result deletes `destkey`.
+    pub(super) fn bitop(&mut self, args: &[RedisValue]) -> CmdResult {
+        let op = match args[0].option_name().as_str() {
+            "and" => BitOp::And,
+            "or" => BitOp::Or,
+            "xor" => BitOp::Xor,
+            "not" => BitOp::Not,
+            _ => return Err(RedisError::Syntax),
+        };
+        if op == BitOp::Not && args.len() != 3 {
+            return Err(RedisError::Other("BITOP NOT must be called with a single source key.".to_string()));
+        }
+        let dst = self.keyize(&args[1]);
+        let keys: Vec<_> = args[2..].iter().map(|k| self.keyize(k)).collect();
+        let mut db = self.map.lock().expect("unlock failed!");
+
+        let mut sources = vec![];
+        for key in &keys {
+            sources.push(match lookup(&mut db, key) {
+                Some(set) => set.val.string_bytes().ok_or(RedisError::WrongType)?.into_owned(),
+                None => vec![],
+            });
+        }
+        let len = sources.iter().map(Vec::len).max().unwrap_or(0);
+        let result: Vec<u8> = (0..len)
+            .map(|i| {
+                let mut bytes = sources.iter().map(|s| s.get(i).copied().unwrap_or(0));
+                let first = bytes.next().unwrap_or(0);
+                match op {
+                    BitOp::And => bytes.fold(first, |acc, byte| acc & byte),
+                    BitOp::Or => bytes.fold(first, |acc, byte| acc | byte),
+                    BitOp::Xor => bytes.fold(first, |acc, byte| acc ^ byte),
+                    BitOp::Not => !first,
+                }
+            })
+            .collect();
+
+        if result.is_empty() {
+            db.remove(&dst);
+        } else {
+            db.insert(dst, Set::new(RedisValue::bulk(result), None));
+        }
+        Ok(RedisValue::Int(len as RedisInt))
+    }
+
+    /// `BITFIELD key [GET encoding offset | [OVERFLOW WRAP | SAT | FAIL] SET encoding offset value | INCRBY
+    /// encoding offset increment] ...`, or `BITFIELD_RO key [GET encoding offset ...]` if `read_only`, replying
+    /// with the result of each operation. OVERFLOW applies to the operations after it, WRAP by default.
+    pub(super) fn bitfield(&mut self, args: &[RedisValue], read_only: bool) -> CmdResult {
+        let mut ops = vec![];
+        let mut overflow = OverflowMode::Wrap;
+        let mut rest = args[1..].iter();
+        while let Some(sub) = rest.next() {
+            let sub = sub.option_name();
+            if sub == "overflow" && !read_only {
+                overflow = match rest.next().ok_or(RedisError::Syntax)?.option_name().as_str() {
+                    "wrap" => OverflowMode::Wrap,
+                    "sat" => OverflowMode::Sat,
+                    "fail" => OverflowMode::Fail,
+                    _ => return Err(RedisError::Other("Invalid OVERFLOW type specified".to_string())),
+                };
+                continue;
+            }
+            if read_only && sub != "get" {
+                return Err(RedisError::Other("BITFIELD_RO only supports the GET subcommand".to_string()));
+            }
+            if !matches!(sub.as_str(), "get" | "set" | "incrby") {
+                return Err(RedisError::Syntax);
+            }
+            let ty = FieldType::parse(rest.next().ok_or(RedisError::Syntax)?)?;
+            let offset = bit_offset(rest.next().ok_or(RedisError::Syntax)?, Some(ty.bits))?;
+            let op = match sub.as_str() {
+                "get" => FieldOp::Get,
+                "set" => FieldOp::Set(rest.next().ok_or(RedisError::Syntax)?.int_arg()?),
+                _ => FieldOp::IncrBy(rest.next().ok_or(RedisError::Syntax)?.int_arg()?),
+            };
+            ops.push((op, ty, offset, overflow));
+        }
+        let key = self.keyize(&args[0]);
+        let mut db = self.map.lock().expect("unlock failed!");
+
+        // Writing fields grows the string to hold them all, whether the writes fit or not
+        let len = ops
+            .iter()
+            .filter(|(op, ..)| *op != FieldOp::Get)
+            .map(|(_, ty, offset, _)| (offset + ty.bits).div_ceil(8))
+            .max();
+        let replies = match len {
+            Some(len) => string_for_bits(&mut db, &key, len)?.update_string(|s| {
+                ops.iter().map(|&(op, ty, offset, overflow)| run_field_op(s, op, ty, offset, overflow)).collect()
+            })?,
+            None => {
+                let bytes = match lookup(&mut db, &key) {
+                    Some(set) => set.val.string_bytes().ok_or(RedisError::WrongType)?.into_owned(),
+                    None => vec![],
+                };
+                ops.iter().map(|(_, ty, offset, _)| RedisValue::Int(ty.get(&bytes, *offset))).collect()
+            }
+        };
+        Ok(RedisValue::Array(replies))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::resp::test::{bulk, connected_handler};
+    use crate::{RedisError, RedisValue};
+
+    fn bulks(args: &[&str]) -> Vec<RedisValue> {
+        args.iter().map(|a| bulk(a)).collect()
+    }
+
+    fn ints(values: &[i64]) -> RedisValue {
+        RedisValue::Array(values.iter().map(|&n| RedisValue::Int(n)).collect())
+    }
+
+    #[tokio::test]
+    async fn bits_and_ranges() {
+        let (mut handler, _client) = connected_handler().await;
+        assert_eq!(handler.handle_command("setbit", bulks(&["b", "7", "1"])).await, RedisValue::Int(0));
+        assert_eq!(handler.handle_command("setbit", bulks(&["b", "7", "1"])).await, RedisValue::Int(1));
+        assert_eq!(handler.handle_command("get", vec![bulk("b")]).await, bulk("\x01"));
+        assert_eq!(handler.handle_command("getbit", bulks(&["b", "7"])).await, RedisValue::Int(1));
+        assert_eq!(handler.handle_command("getbit", bulks(&["b", "100"])).await, RedisValue::Int(0));
+        assert_eq!(
+            handler.handle_command("setbit", bulks(&["b", "-1", "1"])).await,
+            RedisError::Other("bit offset is not an integer or out of range".to_string()).into()
+        );
+
+        // "foobar" holds 26 bits set, 4 in "f" and 6 in "o"
+        handler.handle_command("set", bulks(&["s", "foobar"])).await;
+        assert_eq!(handler.handle_command("bitcount", bulks(&["s"])).await, RedisValue::Int(26));
+        assert_eq!(handler.handle_command("bitcount", bulks(&["s", "1", "1"])).await, RedisValue::Int(6));
+        assert_eq!(handler.handle_command("bitcount", bulks(&["s", "5", "30", "BIT"])).await, RedisValue::Int(17));
+        assert_eq!(handler.handle_command("bitcount", bulks(&["s", "-1", "-2"])).await, RedisValue::Int(0));
+        assert_eq!(handler.handle_command("bitcount", bulks(&["s", "1"])).await, RedisError::Syntax.into());
+
+        handler.handle_command("set", bulks(&["p", "\u{0}\u{7f}"])).await;
+        assert_eq!(handler.handle_command("bitpos", bulks(&["p", "1"])).await, RedisValue::Int(9));
+        assert_eq!(handler.handle_command("bitpos", bulks(&["p", "0", "1"])).await, RedisValue::Int(8));
+        assert_eq!(handler.handle_command("bitpos", bulks(&["p", "1", "10", "-1", "BIT"])).await, RedisValue::Int(10));
+        assert_eq!(handler.handle_command("bitpos", bulks(&["p", "1", "0", "0"])).await, RedisValue::Int(-1));
+        // A string of 1s goes on with 0s unless the range has an end
+        handler.handle_command("set", bulks(&["ones", "\u{7f}"])).await;
+        handler.handle_command("setbit", bulks(&["ones", "0", "1"])).await;
+        assert_eq!(handler.handle_command("bitpos", bulks(&["ones", "0"])).await, RedisValue::Int(8));
+        assert_eq!(handler.handle_command("bitpos", bulks(&["ones", "0", "0", "-1"])).await, RedisValue::Int(-1));
+        assert_eq!(handler.handle_command("bitpos", bulks(&["none", "0"])).await, RedisValue::Int(0));
+        assert_eq!(handler.handle_command("bitpos", bulks(&["none", "1"])).await, RedisValue::Int(-1));
+    }
+
+    #[tokio::test]
+    async fn bitop() {
+        let (mut handler, _client) = connected_handler().await;
+        handler.handle_command("set", bulks(&["a", "abc"])).await;
+        handler.handle_command("set", bulks(&["b", "a"])).await;
+        assert_eq!(handler.handle_command("bitop", bulks(&["AND", "d", "a", "b"])).await, RedisValue::Int(3));
+        assert_eq!(handler.handle_command("get", vec![bulk("d")]).await, bulk("a\u{0}\u{0}"));
+        assert_eq!(handler.handle_command("bitop", bulks(&["XOR", "d", "a", "b", "none"])).await, RedisValue::Int(3));
+        assert_eq!(handler.handle_command("get", vec![bulk("d")]).await, bulk("\u{0}bc"));
+        handler.handle_command("bitop", bulks(&["OR", "d", "b", "b"])).await;
+        assert_eq!(handler.handle_command("get", vec![bulk("d")]).await, bulk("a"));
+        handler.handle_command("bitop", bulks(&["NOT", "d", "b"])).await;
+        assert_eq!(handler.handle_command("get", vec![bulk("d")]).await, RedisValue::bulk(vec![!b'a']));
+        assert_eq!(
+            handler.handle_command("bitop", bulks(&["NOT", "d", "a", "b"])).await,
+            RedisError::Other("BITOP NOT must be called with a single source key.".to_string()).into()
+        );
+        assert_eq!(handler.handle_command("bitop", bulks(&["AND", "d", "none"])).await, RedisValue::Int(0));
+        assert_eq!(handler.handle_command("exists", vec![bulk("d")]).await, RedisValue::Int(0));
+    }
+
+    #[tokio::test]
+    async fn bitfield() {
+        let (mut handler, _client) = connected_handler().await;
+        let args = bulks(&["f", "SET", "i8", "0", "100", "INCRBY", "i8", "0", "100", "GET", "u4", "0"]);
+        assert_eq!(handler.handle_command("bitfield", args).await, ints(&[0, -56, 12]));
+        assert_eq!(handler.handle_command("get", vec![bulk("f")]).await, RedisValue::bulk(vec![200]));
+
+        let args = bulks(&["f", "OVERFLOW", "SAT", "INCRBY", "i8", "0", "-100"]);
+        let args = [args, bulks(&["OVERFLOW", "FAIL", "INCRBY", "i8", "0", "-1"])].concat();
+        assert_eq!(
+            handler.handle_command("bitfield", args).await,
+            RedisValue::Array(vec![RedisValue::Int(-128), RedisValue::Null])
+        );
+        // Fields counted in widths with `#`, and unsigned ones wrapping around
+        let args = bulks(&["f", "SET", "u8", "#1", "255", "INCRBY", "u8", "#1", "2", "INCRBY", "u2", "62", "-1"]);
+        assert_eq!(handler.handle_command("bitfield", args).await, ints(&[0, 1, 3]));
+        assert_eq!(handler.handle_command("strlen", vec![bulk("f")]).await, RedisValue::Int(8));
+
+        let field = i64::from_be_bytes([128, 1, 0, 0, 0, 0, 0, 3]);
+        assert_eq!(handler.handle_command("bitfield_ro", bulks(&["f", "GET", "i64", "0"])).await, ints(&[field]));
+        assert_eq!(
+            handler.handle_command("bitfield_ro", bulks(&["f", "SET", "u8", "0", "1"])).await,
+            RedisError::Other("BITFIELD_RO only supports the GET subcommand".to_string()).into()
+        );
+        assert_eq!(
+            handler.handle_command("bitfield", bulks(&["f", "GET", "u64", "0"])).await,
+            RedisError::Other(
+                "Invalid bitfield type. Use something like i16 u8. Note that u64 is not supported but i64 is."
+                    .to_string()
+            )
+            .into()
+        );
+        assert_eq!(handler.handle_command("bitfield", bulks(&["none", "GET", "u8", "0"])).await, ints(&[0]));
+        assert_eq!(handler.handle_command("exists", vec![bulk("none")]).await, RedisValue::Int(0));
+    }
+}
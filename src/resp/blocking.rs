@@ -0,0 +1,432 @@
+// Blocking commands: clients parked until another one adds to the lists, sorted sets or streams they wait on
+
+use super::consumer_groups::group_read;
+use super::lists::{End, list_move, list_pop};
+use super::sorted_sets::{ZEnd, zset_pop};
+use super::streams::{StreamId, stream_read};
+use super::{Database, Key, RedisObject, RespHandler, lookup};
+use crate::error::{CmdResult, RedisError};
+use crate::resp::RedisValue;
+use bytes::Bytes;
+use std::collections::{HashMap, HashSet, VecDeque};
+use tokio::{
+    io::AsyncReadExt,
+    sync::oneshot,
+    time::{Duration, Instant, sleep_until},
+};
+
+/// What a blocked client is to be served with once one of its keys holds a value it waits for
+#[derive(Debug, Clone)]
+pub(super) enum BlockingOp {
+    /// BLPOP and BRPOP: a single element, along with the key it was popped from
+    Pop(End),
+    /// BLMPOP: up to `count` elements, along with the key they were popped from
+    MPop(End, usize),
+    /// BLMOVE: the element moved to `dst`
+    Move { dst: Key, from: End, to: End },
+    /// BZPOPMIN and BZPOPMAX: the member with the lowest or highest score, along with the key and the score
+    ZPop(ZEnd),
+    /// XREAD: the entries of a stream past the ID given for it, which every client waiting on it is served
+    XRead { after: HashMap<Key, StreamId>, count: Option<usize> },
+    /// XREADGROUP: the entries of a stream never delivered to the group, made pending for the consumer unless
+    /// `noack`
+    XReadGroup { group: Bytes, consumer: Bytes, count: Option<usize>, noack: bool },
+}
+
+impl BlockingOp {
+    /// Whether the operation can be served from a value of this type
+    fn serves(&self, val: &RedisObject) -> bool {
+        match self {
+            BlockingOp::ZPop(_) => matches!(val, RedisObject::SortedSet(_)),
+            BlockingOp::XRead { .. } | BlockingOp::XReadGroup { .. } => matches!(val, RedisObject::Stream(_)),
+            _ => matches!(val, RedisObject::List(_)),
+        }
+    }
+
+    /// Serves the operation from the value at `key`, or replies `None` if there is nothing to serve it with
+    fn serve(&self, db: &mut Database, key: &Key) -> Result<Option<RedisValue>, RedisError> {
+        let key_name = || RedisValue::BulkString(key.clone());
+        Ok(match self {
+            BlockingOp::Pop(end) => list_pop(db, key, *end, 1)?
+                .and_then(|mut popped| popped.pop())
+                .map(|elt| RedisValue::Array(vec![key_name(), RedisValue::BulkString(elt)])),
+            BlockingOp::MPop(end, count) => list_pop(db, key, *end, *count)?.map(|popped| {
+                let popped = popped.into_iter().map(RedisValue::BulkString).collect();
+                RedisValue::Array(vec![key_name(), RedisValue::Array(popped)])
+            }),
+            BlockingOp::Move { dst, from, to } => {
+                list_move(db, key, dst, *from, *to)?.map(RedisValue::BulkString)
+            }
+            BlockingOp::ZPop(end) => zset_pop(db, key, *end, 1)?.and_then(|mut popped| popped.pop()).map(
+                |(member, score)| {
+                    RedisValue::Array(vec![key_name(), RedisValue::BulkString(member), RedisValue::Double(score)])
+                },
+            ),
+            BlockingOp::XRead { after, count } => {
+                let after = after.get(key).copied().unwrap_or(StreamId::MAX);
+                stream_read(db, key, after, *count)?
+                    .map(|entries| RedisValue::Array(vec![RedisValue::Array(vec![key_name(), entries])]))
+            }
+            BlockingOp::XReadGroup { group, consumer, count, noack } => {
+                group_read(db, key, group, consumer, None, *count, *noack)?
+                    .map(|entries| RedisValue::Array(vec![RedisValue::Array(vec![key_name(), entries])]))
+            }
+        })
+    }
+}
+
+#[derive(Debug)]
+struct Waiter {
+    keys: Vec<Key>,
+    op: BlockingOp,
+    reply: oneshot::Sender<CmdResult>,
+}
+
+/// Clients blocked on keys, served in the order they blocked in
+#[derive(Debug, Default)]
+pub struct Blocked {
+    next_id: u64,
+    queues: HashMap<Key, VecDeque<u64>>,
+    waiters: HashMap<u64, Waiter>,
+    /// Keys clients block on which were written to since they were last served
+    ready: HashSet<Key>,
+}
+
+impl Blocked {
+    fn register(&mut self, keys: Vec<Key>, op: BlockingOp, reply: oneshot::Sender<CmdResult>) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        for key in &keys {
+            self.queues.entry(key.clone()).or_default().push_back(id);
+        }
+        self.waiters.insert(id, Waiter { keys, op, reply });
+        id
+    }
+
+    fn unregister(&mut self, id: u64) -> Option<Waiter> {
+        let waiter = self.waiters.remove(&id)?;
+        for key in &waiter.keys {
+            if let Some(queue) = self.queues.get_mut(key) {
+                queue.retain(|&other| other != id);
+                if queue.is_empty() {
+                    self.queues.remove(key);
+                }
+            }
+        }
+        Some(waiter)
+    }
+}
+
+impl Database {
+    /// Notes that `key` was written to, so that the clients blocked on it get a chance to be served once the
+    /// command is over
+    pub(crate) fn signal_ready(&mut self, key: &[u8]) {
+        if self.blocked.queues.contains_key(key) && !self.blocked.ready.contains(key) {
+            self.blocked.ready.insert(Bytes::copy_from_slice(key));
+        }
+    }
+
+    /// Serves the clients blocked on the keys written to since last time, first come first served.
+    ///
+    /// Serving a client may fill another list (BLMOVE), so this goes on until no key is left to serve from.
+    pub fn serve_blocked(&mut self) {
+        while !self.blocked.ready.is_empty() {
+            for key in std::mem::take(&mut self.blocked.ready) {
+                self.serve_key(&key);
+            }
+        }
+    }
+
+    /// Serves the clients blocked on `key`, for as long as it holds a value they wait for. Clients that cannot
+    /// be served (XREAD past the last entry) do not hold back the ones queued after them.
+    fn serve_key(&mut self, key: &Key) {
+        let queue: Vec<u64> = self.blocked.queues.get(key).into_iter().flatten().copied().collect();
+        for id in queue {
+            let Some(waiter) = self.blocked.waiters.get(&id) else {
+                continue;
+            };
+            // A client gone without leaving the queue must not be handed elements
+            if waiter.reply.is_closed() {
+                self.blocked.unregister(id);
+                continue;
+            }
+            let op = waiter.op.clone();
+            if !lookup(self, key).is_some_and(|set| op.serves(&set.val)) {
+                break;
+            }
+
+            let reply = match op.serve(self, key) {
+                Ok(Some(reply)) => Ok(reply),
+                Ok(None) => continue,
+                Err(e) => Err(e),
+            };
+            if let Some(waiter) = self.blocked.unregister(id) {
+                let _ = waiter.reply.send(reply);
+            }
+        }
+    }
+}
+
+/// Reads the timeout of a blocking command, in seconds, 0 meaning forever
+fn timeout_arg(arg: &RedisValue) -> Result<Option<Duration>, RedisError> {
+    let secs = arg
+        .float_arg()
+        .map_err(|_| RedisError::Other("timeout is not a float or out of range".to_string()))?;
+    if secs < 0.0 {
+        return Err(RedisError::Other("timeout is negative".to_string()));
+    }
+    if secs == 0.0 {
+        return Ok(None);
+    }
+    Duration::try_from_secs_f64(secs)
+        .map(Some)
+        .map_err(|_| RedisError::Other("timeout is out of range".to_string()))
+}
+
+/// Reads `numkeys key [key ...] LEFT|RIGHT [COUNT count]`, as LMPOP and BLMPOP take it
+fn mpop_args(args: &[RedisValue]) -> Result<(&[RedisValue], End, usize), RedisError> {
+    let numkeys = args[0].int_arg()?;
+    if numkeys <= 0 {
+        return Err(RedisError::Other("numkeys should be greater than 0".to_string()));
+    }
+    let numkeys = numkeys as usize;
+    if args.len() < numkeys + 2 {
+        return Err(RedisError::Syntax);
+    }
+    let (keys, rest) = args[1..].split_at(numkeys);
+    let end = End::parse(&rest[0])?;
+
+    let count = match &rest[1..] {
+        [] => 1,
+        [opt, count] if opt.option_name() == "count" => match count.int_arg()? {
+            n if n > 0 => n as usize,
+            _ => return Err(RedisError::Other("count should be greater than 0".to_string())),
+        },
+        _ => return Err(RedisError::Syntax),
+    };
+    Ok((keys, end, count))
+}
+
+impl RespHandler {
+    /// `BLPOP key [key ...] timeout` and `BRPOP`, replying with the first key holding a list along with the
+    /// element popped from it
+    pub(super) async fn bpop(&mut self, args: &[RedisValue], end: End) -> CmdResult {
+        let (keys, timeout) = args.split_at(args.len() - 1);
+        let timeout = timeout_arg(&timeout[0])?;
+        let keys = keys.iter().map(|k| self.keyize(k)).collect();
+        self.block(keys, BlockingOp::Pop(end), timeout).await
+    }
+
+    /// `BLMOVE source destination LEFT|RIGHT LEFT|RIGHT timeout` and `BRPOPLPUSH source destination timeout`
+    pub(super) async fn blmove(&mut self, args: &[RedisValue], from: End, to: End) -> CmdResult {
+        let timeout = timeout_arg(&args[args.len() - 1])?;
+        let (src, dst) = (self.keyize(&args[0]), self.keyize(&args[1]));
+        self.block(vec![src], BlockingOp::Move { dst, from, to }, timeout).await
+    }
+
+    /// `BZPOPMIN key [key ...] timeout` and `BZPOPMAX`, replying with the first key holding a sorted set along
+    /// with the member popped from it and its score
+    pub(super) async fn bzpop(&mut self, args: &[RedisValue], end: ZEnd) -> CmdResult {
+        let (keys, timeout) = args.split_at(args.len() - 1);
+        let timeout = timeout_arg(&timeout[0])?;
+        let keys = keys.iter().map(|k| self.keyize(k)).collect();
+        self.block(keys, BlockingOp::ZPop(end), timeout).await
+    }
+
+    /// `LMPOP numkeys key [key ...] LEFT|RIGHT [COUNT count]` and `BLMPOP timeout numkeys ...`, replying with
+    /// the first key holding a list along with up to `count` elements popped from it
+    pub(super) async fn lmpop(&mut self, args: &[RedisValue], blocking: bool) -> CmdResult {
+        let (timeout, args) = match blocking {
+            true => (timeout_arg(&args[0])?, &args[1..]),
+            false => (None, args),
+        };
+        let (keys, end, count) = mpop_args(args)?;
+        let keys: Vec<_> = keys.iter().map(|k| self.keyize(k)).collect();
+
+        if !blocking {
+            let mut db = self.map.lock().expect("unlock failed!");
+            let reply = serve_first(&mut db, &keys, &BlockingOp::MPop(end, count))?;
+            return Ok(reply.unwrap_or(RedisValue::NullArray));
+        }
+        self.block(keys, BlockingOp::MPop(end, count), timeout).await
+    }
+
+    /// Serves `op` from the first of `keys` holding a value to serve it, or else waits for another client to
+    /// fill one of them, until `timeout` if any. Replies a null array on timeout.
+    ///
+    /// Inside a transaction, there is no waiting: no other client could run until it is over.
+    pub(super) async fn block(&mut self, keys: Vec<Key>, op: BlockingOp, timeout: Option<Duration>) -> CmdResult {
+        let deadline = timeout.map(|t| Instant::now() + t);
+        let (id, mut rx) = {
+            let mut db = self.map.lock().expect("unlock failed!");
+            if let Some(reply) = serve_first(&mut db, &keys, &op)? {
+                return Ok(reply);
+            }
+            if self.in_exec {
+                return Ok(RedisValue::NullArray);
+            }
+            let (tx, rx) = oneshot::channel();
+            (db.blocked.register(keys, op, tx), rx)
+        };
+
+        // Replies to the commands pipelined before this one must not wait along
+        let mut waiting = self.flush().await.is_ok();
+
+        let expired = async {
+            match deadline {
+                Some(deadline) => sleep_until(deadline).await,
+                None => std::future::pending().await,
+            }
+        };
+        tokio::pin!(expired);
+        while waiting {
+            tokio::select! {
+                reply = &mut rx => return reply.unwrap_or(Ok(RedisValue::NullArray)),
+                _ = &mut expired => waiting = false,
+                // Commands pipelined meanwhile wait in the buffer, a closed connection gives up on waiting
+                read = self.stream.read_buf(&mut self.buffer) => waiting = matches!(read, Ok(n) if n > 0),
+            }
+        }
+
+        // Leave the queues, unless served in between
+        self.map.lock().expect("unlock failed!").blocked.unregister(id);
+        rx.try_recv().unwrap_or(Ok(RedisValue::NullArray))
+    }
+}
+
+/// Serves `op` from the first of `keys` holding a value to serve it
+fn serve_first(db: &mut Database, keys: &[Key], op: &BlockingOp) -> Result<Option<RedisValue>, RedisError> {
+    for key in keys {
+        if let Some(reply) = op.serve(db, key)? {
+            return Ok(Some(reply));
+        }
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::resp::test::{bulk, connected_handler, connected_handler_on};
+    use crate::{RedisError, RedisValue, Transaction};
+    use std::sync::Arc;
+    use tokio::time::{Duration, sleep};
+
+    fn pair(key: &str, elt: &str) -> RedisValue {
+        RedisValue::Array(vec![bulk(key), bulk(elt)])
+    }
+
+    #[tokio::test]
+    async fn non_blocking_paths() {
+        let (mut handler, _client) = connected_handler().await;
+        handler.handle_command("rpush", vec![bulk("b"), bulk("1"), bulk("2"), bulk("3")]).await;
+
+        assert_eq!(handler.handle_command("blpop", vec![bulk("a"), bulk("b"), bulk("0")]).await, pair("b", "1"));
+        assert_eq!(handler.handle_command("brpop", vec![bulk("b"), bulk("0.01")]).await, pair("b", "3"));
+        assert_eq!(handler.handle_command("blpop", vec![bulk("a"), bulk("0.01")]).await, RedisValue::NullArray);
+        assert_eq!(
+            handler.handle_command("blpop", vec![bulk("a"), bulk("-1")]).await,
+            RedisError::Other("timeout is negative".to_string()).into()
+        );
+        let args = ["2", "a", "b", "left", "count", "5"].map(bulk).to_vec();
+        assert_eq!(
+            handler.handle_command("lmpop", args).await,
+            RedisValue::Array(vec![bulk("b"), RedisValue::Array(vec![bulk("2")])])
+        );
+        assert_eq!(
+            handler.handle_command("lmpop", vec![bulk("1"), bulk("a"), bulk("left")]).await,
+            RedisValue::NullArray
+        );
+        assert_eq!(
+            handler.handle_command("lmpop", vec![bulk("0"), bulk("a"), bulk("left")]).await,
+            RedisError::Other("numkeys should be greater than 0".to_string()).into()
+        );
+
+        // No waiting within a transaction
+        let mut transaction = Transaction::init();
+        handler.handle_multi(&mut transaction).await;
+        handler.handle_queued(&mut transaction, "blpop".to_string(), vec![bulk("a"), bulk("0")]);
+        assert_eq!(handler.handle_exec(&mut transaction).await, RedisValue::Array(vec![RedisValue::NullArray]));
+    }
+
+    #[tokio::test]
+    async fn served_by_push() {
+        let (mut pusher, _client) = connected_handler().await;
+        let map = Arc::clone(&pusher.map);
+        let (mut first, _first_client) = connected_handler_on(&map, 1).await;
+        let (mut second, _second_client) = connected_handler_on(&map, 2).await;
+
+        let first = tokio::spawn(async move { first.handle_command("blpop", vec![bulk("q"), bulk("0")]).await });
+        sleep(Duration::from_millis(20)).await;
+        let second = tokio::spawn(async move {
+            second.handle_command("blmove", vec![bulk("q"), bulk("dst"), bulk("right"), bulk("left"), bulk("0")]).await
+        });
+        sleep(Duration::from_millis(20)).await;
+
+        // Waiters are served in the order they blocked in, each with its own element
+        let pushed = pusher.handle_command("rpush", vec![bulk("q"), bulk("a"), bulk("b"), bulk("c")]).await;
+        assert_eq!(pushed, RedisValue::Int(3));
+        assert_eq!(first.await.unwrap(), pair("q", "a"));
+        assert_eq!(second.await.unwrap(), bulk("c"));
+        assert_eq!(
+            pusher.handle_command("lrange", vec![bulk("q"), bulk("0"), bulk("-1")]).await,
+            RedisValue::Array(vec![bulk("b")])
+        );
+        assert_eq!(
+            pusher.handle_command("lrange", vec![bulk("dst"), bulk("0"), bulk("-1")]).await,
+            RedisValue::Array(vec![bulk("c")])
+        );
+    }
+
+    #[tokio::test]
+    async fn served_after_transaction() {
+        let (mut pusher, _client) = connected_handler().await;
+        let (mut waiter, _waiter_client) = connected_handler_on(&pusher.map, 1).await;
+        let waiter = tokio::spawn(async move { waiter.handle_command("blpop", vec![bulk("q"), bulk("0")]).await });
+        sleep(Duration::from_millis(20)).await;
+
+        // Blocked clients only see what the whole transaction left behind
+        let mut transaction = Transaction::init();
+        pusher.handle_multi(&mut transaction).await;
+        pusher.handle_queued(&mut transaction, "rpush".to_string(), vec![bulk("q"), bulk("a")]);
+        pusher.handle_queued(&mut transaction, "lpop".to_string(), vec![bulk("q")]);
+        pusher.handle_queued(&mut transaction, "rpush".to_string(), vec![bulk("q"), bulk("b")]);
+        assert_eq!(
+            pusher.handle_exec(&mut transaction).await,
+            RedisValue::Array(vec![RedisValue::Int(1), bulk("a"), RedisValue::Int(1)])
+        );
+        assert_eq!(waiter.await.unwrap(), pair("q", "b"));
+    }
+
+    #[tokio::test]
+    async fn served_from_written_keys() {
+        let (mut pusher, _client) = connected_handler().await;
+        let (mut waiter, _waiter_client) = connected_handler_on(&pusher.map, 1).await;
+        let waiter = tokio::spawn(async move { waiter.handle_command("blpop", vec![bulk("q"), bulk("0")]).await });
+        sleep(Duration::from_millis(20)).await;
+
+        // Writing to other keys leaves the client be
+        pusher.handle_command("rpush", vec![bulk("other"), bulk("a")]).await;
+        pusher.handle_command("set", vec![bulk("str"), bulk("a")]).await;
+        {
+            let db = pusher.map.lock().unwrap();
+            assert!(db.blocked.ready.is_empty());
+            assert_eq!(db.blocked.waiters.len(), 1);
+        }
+
+        // Renaming a list onto the key serves it as pushing would
+        pusher.handle_command("rename", vec![bulk("other"), bulk("q")]).await;
+        assert_eq!(waiter.await.unwrap(), pair("q", "a"));
+        let db = pusher.map.lock().unwrap();
+        assert!(db.blocked.ready.is_empty() && db.blocked.waiters.is_empty());
+    }
+
+    #[tokio::test]
+    async fn timeout_leaves_queue() {
+        let (mut pusher, _client) = connected_handler().await;
+        let (mut waiter, _waiter_client) = connected_handler_on(&pusher.map, 1).await;
+
+        assert_eq!(waiter.handle_command("brpop", vec![bulk("q"), bulk("0.02")]).await, RedisValue::NullArray);
+        pusher.handle_command("rpush", vec![bulk("q"), bulk("a")]).await;
+        assert_eq!(pusher.handle_command("llen", vec![bulk("q")]).await, RedisValue::Int(1));
+    }
+}
@@ -0,0 +1,967 @@
+// Stream consumer groups: entries delivered once per group, pending until the consumer acknowledges them
+
+use super::blocking::BlockingOp;
+use super::streams::{NODE_ENTRIES, Stream, StreamId, block_arg, entry_reply, range_bound, read_stream, streams_args};
+use super::{Database, RedisObject, RespHandler, Set};
+use crate::error::{CmdResult, RedisError};
+use crate::resp::{RedisInt, RedisValue};
+use crate::{OK, now_ms};
+use bytes::Bytes;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Entries XAUTOCLAIM claims at most when COUNT is not given
+const AUTOCLAIM_COUNT: usize = 100;
+/// Pending entries XAUTOCLAIM looks at per entry it may claim
+const AUTOCLAIM_ATTEMPTS_FACTOR: usize = 10;
+/// Entries XINFO STREAM FULL lists when COUNT is not given
+const INFO_FULL_COUNT: usize = 10;
+
+/// Entry delivered to a consumer of a group, and not acknowledged yet
+#[derive(Debug, Clone, PartialEq)]
+pub struct PendingEntry {
+    pub consumer: Bytes,
+    /// Last time the entry was delivered, in milliseconds since the Unix epoch
+    pub delivered_at: i64,
+    pub deliveries: u64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Consumer {
+    /// Last time the consumer tried reading or claiming entries
+    pub seen_at: i64,
+    /// Last time the consumer was actually handed entries, if ever
+    pub active_at: Option<i64>,
+    /// IDs of the entries pending for the consumer
+    pub pending: BTreeSet<StreamId>,
+}
+
+impl Consumer {
+    fn new(now: i64) -> Self {
+        Consumer { seen_at: now, active_at: None, pending: BTreeSet::new() }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConsumerGroup {
+    /// ID of the last entry delivered to any consumer of the group
+    pub last_delivered: StreamId,
+    /// Number of entries of the stream the group read so far, unknown once entries were deleted in between
+    pub entries_read: Option<u64>,
+    /// Entries pending for any consumer of the group
+    pub pending: BTreeMap<StreamId, PendingEntry>,
+    pub consumers: BTreeMap<Bytes, Consumer>,
+}
+
+impl ConsumerGroup {
+    fn new(last_delivered: StreamId, entries_read: Option<u64>) -> Self {
+        ConsumerGroup { last_delivered, entries_read, pending: BTreeMap::new(), consumers: BTreeMap::new() }
+    }
+
+    /// The consumer with that name, created if missing, as seen at `now`
+    fn consumer(&mut self, name: &Bytes, now: i64) -> &mut Consumer {
+        let consumer = self.consumers.entry(name.clone()).or_insert_with(|| Consumer::new(now));
+        consumer.seen_at = now;
+        consumer
+    }
+
+    /// Makes an entry pending for `consumer`, taking it from any other consumer, as delivered at `at`
+    fn assign(&mut self, id: StreamId, consumer: &Bytes, at: i64) -> &mut PendingEntry {
+        let entry = self.pending.entry(id).or_insert_with(|| PendingEntry {
+            consumer: consumer.clone(),
+            delivered_at: at,
+            deliveries: 0,
+        });
+        if entry.consumer != consumer {
+            if let Some(previous) = self.consumers.get_mut(&entry.consumer) {
+                previous.pending.remove(&id);
+            }
+            entry.consumer = consumer.clone();
+        }
+        entry.delivered_at = at;
+        self.consumers.entry(consumer.clone()).or_insert_with(|| Consumer::new(at)).pending.insert(id);
+        entry
+    }
+
+    /// Drops a pending entry, replying with whether it was pending
+    fn acknowledge(&mut self, id: &StreamId) -> bool {
+        let Some(entry) = self.pending.remove(id) else {
+            return false;
+        };
+        if let Some(consumer) = self.consumers.get_mut(&entry.consumer) {
+            consumer.pending.remove(id);
+        }
+        true
+    }
+}
+
+/// Whether entries from `id` on were deleted, which makes the number of entries read by a group unknown
+fn tombstones_from(stream: &Stream, id: StreamId) -> bool {
+    !stream.is_empty() && stream.max_deleted_id() != StreamId::MIN && stream.max_deleted_id() >= id
+}
+
+/// Number of entries added to the stream up to `id` included, if it can be told despite deletions
+fn estimate_read(stream: &Stream, id: StreamId) -> Option<u64> {
+    let added = stream.entries_added();
+    if added == 0 {
+        return Some(0);
+    }
+    if stream.is_empty() && id <= stream.last_id() {
+        return Some(added);
+    }
+    match id.cmp(&stream.last_id()) {
+        Ordering::Equal => return Some(added),
+        Ordering::Greater => return None,
+        Ordering::Less => {}
+    }
+    // Counting back from the first entry, provided nothing was deleted past it
+    let first = *stream.first()?.0;
+    let len = stream.len() as u64;
+    let max_deleted = stream.max_deleted_id();
+    if max_deleted == StreamId::MIN || max_deleted < first {
+        match id.cmp(&first) {
+            Ordering::Less => return Some(added - len),
+            Ordering::Equal => return Some(added - len + 1),
+            Ordering::Greater => {}
+        }
+    }
+    None
+}
+
+/// Entries of the stream the group has yet to read, if it can be told
+fn lag(stream: &Stream, group: &ConsumerGroup) -> Option<u64> {
+    let added = stream.entries_added();
+    if added == 0 {
+        return Some(0);
+    }
+    match group.entries_read {
+        Some(read) if !tombstones_from(stream, group.last_delivered) => Some(added.saturating_sub(read)),
+        _ => estimate_read(stream, group.last_delivered).map(|read| added - read),
+    }
+}
+
+fn lossy(name: &[u8]) -> std::borrow::Cow<'_, str> {
+    String::from_utf8_lossy(name)
+}
+
+/// Error of XGROUP and XINFO for a missing group
+fn no_such_group(key: &[u8], group: &[u8]) -> RedisError {
+    RedisError::NoGroup(format!("No such consumer group '{}' for key name '{}'", lossy(group), lossy(key)))
+}
+
+/// Error of XPENDING, XCLAIM and XAUTOCLAIM for a missing stream or group
+fn no_such_key_or_group(key: &[u8], group: &[u8]) -> RedisError {
+    RedisError::NoGroup(format!("No such key '{}' or consumer group '{}'", lossy(key), lossy(group)))
+}
+
+/// Looks up the stream at `key` along with one of its groups, `missing` building the error if either is missing
+fn read_group<'a>(
+    db: &'a mut Database,
+    key: &[u8],
+    group: &[u8],
+    missing: fn(&[u8], &[u8]) -> RedisError,
+) -> Result<&'a mut Stream, RedisError> {
+    match read_stream(db, key)? {
+        Some(stream) if stream.groups.contains_key(group) => Ok(stream),
+        _ => Err(missing(key, group)),
+    }
+}
+
+/// Reads the entries of the stream at `key` for `consumer` of `group`: the entries never delivered to the group
+/// if `after` is `None`, which become pending unless `noack`, or else the consumer's own pending entries past
+/// `after`. Replies `None` if there are no new entries.
+pub(super) fn group_read(
+    db: &mut Database,
+    key: &[u8],
+    group_name: &Bytes,
+    consumer: &Bytes,
+    after: Option<StreamId>,
+    count: Option<usize>,
+    noack: bool,
+) -> Result<Option<RedisValue>, RedisError> {
+    let missing = |key: &[u8], group: &[u8]| {
+        RedisError::NoGroup(format!(
+            "No such key '{}' or consumer group '{}' in XREADGROUP with GROUP option",
+            lossy(key),
+            lossy(group)
+        ))
+    };
+    let stream = read_group(db, key, group_name, missing)?;
+    let count = count.unwrap_or(usize::MAX);
+    let now = now_ms();
+
+    let Some(after) = after else {
+        let last_delivered = stream.groups[group_name].last_delivered;
+        let entries: Vec<_> = last_delivered
+            .next()
+            .map(|start| stream.range(start, StreamId::MAX).take(count).map(|(id, f)| (*id, f.clone())).collect())
+            .unwrap_or_default();
+        // How many entries the group read is worked out from the stream as it is, before the group changes
+        let reads: Vec<_> =
+            entries.iter().map(|(id, _)| (tombstones_from(stream, *id), estimate_read(stream, *id))).collect();
+        let added = stream.entries_added();
+
+        let group = stream.groups.get_mut(group_name).expect("group just looked up");
+        group.consumer(consumer, now);
+        if entries.is_empty() {
+            return Ok(None);
+        }
+        for ((id, _), (tombstones, estimate)) in entries.iter().zip(reads) {
+            if !noack {
+                group.assign(*id, consumer, now).deliveries = 1;
+            }
+            group.entries_read = match group.entries_read {
+                Some(read) if !tombstones => Some(read + 1),
+                _ if added > 0 => estimate,
+                read => read,
+            };
+            group.last_delivered = *id;
+        }
+        group.consumer(consumer, now).active_at = Some(now);
+        return Ok(Some(RedisValue::Array(entries.iter().map(|(id, fields)| entry_reply(id, fields)).collect())));
+    };
+
+    let group = stream.groups.get_mut(group_name).expect("group just looked up");
+    let ids: Vec<StreamId> = match after.next() {
+        Some(start) => group.consumer(consumer, now).pending.range(start..).take(count).copied().collect(),
+        None => vec![],
+    };
+    // Entries deleted since they were delivered come without their fields
+    let history = ids.iter().map(|id| match stream.get(id) {
+        Some(fields) => entry_reply(id, fields),
+        None => RedisValue::Array(vec![id.reply(), RedisValue::Null]),
+    });
+    Ok(Some(RedisValue::Array(history.collect())))
+}
+
+/// Reads the ID of a group: `$` standing for the last ID of `stream`
+fn group_id_arg(arg: &RedisValue) -> Result<Option<StreamId>, RedisError> {
+    match arg.bytes_arg().as_ref() {
+        b"$" => Ok(None),
+        id => StreamId::parse(id, 0).map(Some),
+    }
+}
+
+/// Reads the `ENTRIESREAD` option, -1 meaning unknown
+fn entries_read_arg(arg: Option<&RedisValue>) -> Result<Option<u64>, RedisError> {
+    match arg.ok_or(RedisError::Syntax)?.int_arg()? {
+        -1 => Ok(None),
+        n if n < 0 => Err(RedisError::Other("value for ENTRIESREAD must be positive or -1".to_string())),
+        n => Ok(Some(n as u64)),
+    }
+}
+
+fn subcommand_error(command: &str, subcommand: &RedisValue) -> RedisError {
+    RedisError::Other(format!(
+        "unknown subcommand or wrong number of arguments for '{}'. Try {} HELP.",
+        lossy(&subcommand.bytes_arg()),
+        command
+    ))
+}
+
+/// Map of XINFO replies, a flat array in RESP2
+fn info_map(fields: Vec<(&'static str, RedisValue)>) -> RedisValue {
+    RedisValue::Map(fields.into_iter().map(|(name, val)| (RedisValue::bulk(name), val)).collect())
+}
+
+fn optional_int(n: Option<u64>) -> RedisValue {
+    n.map_or(RedisValue::Null, |n| RedisValue::Int(n as RedisInt))
+}
+
+impl RespHandler {
+    /// `XGROUP CREATE key group id | $ [MKSTREAM] [ENTRIESREAD entries-read]`, `XGROUP SETID key group id | $
+    /// [ENTRIESREAD entries-read]`, `XGROUP DESTROY key group`, `XGROUP CREATECONSUMER key group consumer` and
+    /// `XGROUP DELCONSUMER key group consumer`
+    pub(super) fn xgroup(&mut self, args: &[RedisValue]) -> CmdResult {
+        let subcommand = args[0].option_name();
+        let valid = match subcommand.as_str() {
+            "create" => args.len() >= 4,
+            "setid" => args.len() == 4 || args.len() == 6,
+            "destroy" => args.len() == 3,
+            "createconsumer" | "delconsumer" => args.len() == 4,
+            _ => false,
+        };
+        if !valid {
+            return Err(subcommand_error("XGROUP", &args[0]));
+        }
+
+        let (mut mkstream, mut entries_read) = (false, None);
+        if matches!(subcommand.as_str(), "create" | "setid") {
+            let mut opts = args[4..].iter();
+            while let Some(opt) = opts.next() {
+                match opt.option_name().as_str() {
+                    "mkstream" if subcommand == "create" => mkstream = true,
+                    "entriesread" => entries_read = entries_read_arg(opts.next())?,
+                    _ => return Err(RedisError::Syntax),
+                }
+            }
+        }
+        let id = match subcommand.as_str() {
+            "create" | "setid" => group_id_arg(&args[3])?,
+            _ => None,
+        };
+
+        let key = self.keyize(&args[1]);
+        let group = args[2].bytes_arg();
+        let mut db = self.map.lock().expect("unlock failed!");
+        if read_stream(&mut db, &key)?.is_none() {
+            if !mkstream {
+                return Err(RedisError::Other(
+                    "The XGROUP subcommand requires the key to exist. Note that for CREATE you may want to use the \
+                     MKSTREAM option to create an empty stream automatically."
+                        .to_string(),
+                ));
+            }
+            db.insert(key.clone(), Set::new(RedisObject::Stream(Stream::default()), None));
+        }
+        // Clients blocked reading from the group are to read from its new ID, or be told it is gone
+        if matches!(subcommand.as_str(), "setid" | "destroy") {
+            db.signal_ready(&key);
+        }
+        let stream = read_stream(&mut db, &key)?.expect("stream just looked up");
+        let id = id.unwrap_or(stream.last_id());
+
+        match subcommand.as_str() {
+            "create" => {
+                if stream.groups.contains_key(&group) {
+                    return Err(RedisError::BusyGroup);
+                }
+                stream.groups.insert(group, ConsumerGroup::new(id, entries_read));
+                Ok(RedisValue::SimpleString(OK.to_string()))
+            }
+            "setid" => {
+                let group = stream.groups.get_mut(&group).ok_or_else(|| no_such_group(&key, &group))?;
+                group.last_delivered = id;
+                group.entries_read = entries_read;
+                Ok(RedisValue::SimpleString(OK.to_string()))
+            }
+            "destroy" => Ok(RedisValue::Int(stream.groups.remove(&group).is_some() as RedisInt)),
+            "createconsumer" => {
+                let group = stream.groups.get_mut(&group).ok_or_else(|| no_such_group(&key, &group))?;
+                let consumer = args[3].bytes_arg();
+                let created = !group.consumers.contains_key(&consumer);
+                group.consumer(&consumer, now_ms());
+                Ok(RedisValue::Int(created as RedisInt))
+            }
+            _ => {
+                let group = stream.groups.get_mut(&group).ok_or_else(|| no_such_group(&key, &group))?;
+                let Some(consumer) = group.consumers.remove(args[3].bytes_arg().as_ref()) else {
+                    return Ok(RedisValue::Int(0));
+                };
+                for id in &consumer.pending {
+                    group.pending.remove(id);
+                }
+                Ok(RedisValue::Int(consumer.pending.len() as RedisInt))
+            }
+        }
+    }
+
+    /// `XREADGROUP GROUP group consumer [COUNT count] [BLOCK milliseconds] [NOACK] STREAMS key [key ...] id
+    /// [id ...]`, `>` reading entries never delivered to the group (and waiting for some with BLOCK), any other
+    /// ID the consumer's pending entries past it
+    pub(super) async fn xreadgroup(&mut self, args: &[RedisValue]) -> CmdResult {
+        if args[0].option_name() != "group" || args.len() < 3 {
+            return Err(RedisError::Syntax);
+        }
+        let (group, consumer) = (args[1].bytes_arg(), args[2].bytes_arg());
+        let (mut count, mut block, mut noack) = (None, None, false);
+        let mut opts = args.iter().enumerate().skip(3);
+        let streams = loop {
+            let Some((i, opt)) = opts.next() else {
+                return Err(RedisError::Syntax);
+            };
+            match opt.option_name().as_str() {
+                "count" => count = usize::try_from(opts.next().ok_or(RedisError::Syntax)?.1.int_arg()?).ok(),
+                "block" => block = Some(block_arg(opts.next().ok_or(RedisError::Syntax)?.1)?),
+                "noack" => noack = true,
+                "streams" => break &args[i + 1..],
+                _ => return Err(RedisError::Syntax),
+            }
+        };
+        let count = count.filter(|&count| count > 0);
+        let (keys, ids) = streams_args(streams, "xreadgroup")?;
+        let ids = ids
+            .iter()
+            .map(|id| match id.bytes_arg().as_ref() {
+                b">" => Ok(None),
+                id => StreamId::parse(id, 0).map(Some),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let keys: Vec<_> = keys.iter().map(|k| self.keyize(k)).collect();
+
+        let replies = {
+            let mut db = self.map.lock().expect("unlock failed!");
+            let mut replies = vec![];
+            for (key, after) in keys.iter().zip(ids) {
+                if let Some(entries) = group_read(&mut db, key, &group, &consumer, after, count, noack)? {
+                    replies.push(RedisValue::Array(vec![RedisValue::BulkString(key.clone()), entries]));
+                }
+            }
+            replies
+        };
+        // Reading pending entries always replies, so only reads of new entries may be left waiting
+        if !replies.is_empty() {
+            return Ok(self.streams_reply(replies));
+        }
+        let Some(timeout) = block else {
+            return Ok(RedisValue::NullArray);
+        };
+
+        let op = BlockingOp::XReadGroup { group, consumer, count, noack };
+        match self.block(keys, op, timeout).await? {
+            RedisValue::Array(replies) => Ok(self.streams_reply(replies)),
+            reply => Ok(reply),
+        }
+    }
+
+    /// `XACK key group id [id ...]`, replying with the number of entries acknowledged
+    pub(super) fn xack(&mut self, args: &[RedisValue]) -> CmdResult {
+        let ids = args[2..].iter().map(|id| StreamId::parse(&id.bytes_arg(), 0)).collect::<Result<Vec<_>, _>>()?;
+        let key = self.keyize(&args[0]);
+        let group = args[1].bytes_arg();
+        let mut db = self.map.lock().expect("unlock failed!");
+
+        let Some(group) = read_stream(&mut db, &key)?.and_then(|stream| stream.groups.get_mut(&group)) else {
+            return Ok(RedisValue::Int(0));
+        };
+        let acknowledged = ids.iter().filter(|id| group.acknowledge(id)).count();
+        Ok(RedisValue::Int(acknowledged as RedisInt))
+    }
+
+    /// `XPENDING key group [[IDLE min-idle-time] start end count [consumer]]`: a summary of the entries pending
+    /// for the group, or the entries themselves with their consumer, idle time and number of deliveries
+    pub(super) fn xpending(&mut self, args: &[RedisValue]) -> CmdResult {
+        let mut rest = &args[2..];
+        let mut min_idle = 0;
+        if rest.first().is_some_and(|opt| opt.option_name() == "idle") {
+            min_idle = rest.get(1).ok_or(RedisError::Syntax)?.int_arg()?;
+            rest = &rest[2..];
+            if rest.is_empty() {
+                return Err(RedisError::Syntax);
+            }
+        }
+        let range = match rest {
+            [] => None,
+            [start, end, count, consumer @ ..] if consumer.len() <= 1 => Some((
+                range_bound(start, true)?,
+                range_bound(end, false)?,
+                usize::try_from(count.int_arg()?).unwrap_or(0),
+                consumer.first().map(RedisValue::bytes_arg),
+            )),
+            _ => return Err(RedisError::Syntax),
+        };
+        let key = self.keyize(&args[0]);
+        let group = args[1].bytes_arg();
+        let mut db = self.map.lock().expect("unlock failed!");
+
+        let stream = read_group(&mut db, &key, &group, no_such_key_or_group)?;
+        let group = &stream.groups[&group];
+
+        let Some((start, end, count, consumer)) = range else {
+            let (Some(first), Some(last)) = (group.pending.keys().next(), group.pending.keys().next_back()) else {
+                let nothing = vec![RedisValue::Int(0), RedisValue::Null, RedisValue::Null, RedisValue::Null];
+                return Ok(RedisValue::Array(nothing));
+            };
+            let consumers = group
+                .consumers
+                .iter()
+                .filter(|(_, consumer)| !consumer.pending.is_empty())
+                .map(|(name, consumer)| {
+                    let pending = RedisValue::bulk(consumer.pending.len().to_string());
+                    RedisValue::Array(vec![RedisValue::BulkString(name.clone()), pending])
+                });
+            return Ok(RedisValue::Array(vec![
+                RedisValue::Int(group.pending.len() as RedisInt),
+                first.reply(),
+                last.reply(),
+                RedisValue::Array(consumers.collect()),
+            ]));
+        };
+
+        let now = now_ms();
+        let entries = (start <= end)
+            .then(|| group.pending.range(start..=end))
+            .into_iter()
+            .flatten()
+            .filter(|(_, entry)| consumer.as_ref().is_none_or(|consumer| entry.consumer == consumer))
+            .filter(|(_, entry)| now - entry.delivered_at >= min_idle)
+            .take(count)
+            .map(|(id, entry)| {
+                RedisValue::Array(vec![
+                    id.reply(),
+                    RedisValue::BulkString(entry.consumer.clone()),
+                    RedisValue::Int((now - entry.delivered_at).max(0)),
+                    RedisValue::Int(entry.deliveries as RedisInt),
+                ])
+            });
+        Ok(RedisValue::Array(entries.collect()))
+    }
+
+    /// `XCLAIM key group consumer min-idle-time id [id ...] [IDLE ms] [TIME unix-time-milliseconds] [RETRYCOUNT
+    /// count] [FORCE] [JUSTID] [LASTID lastid]`, handing the entries pending for long enough over to `consumer`
+    pub(super) fn xclaim(&mut self, args: &[RedisValue]) -> CmdResult {
+        let min_idle = args[3]
+            .int_arg()
+            .map_err(|_| RedisError::Other("Invalid min-idle-time argument for XCLAIM".to_string()))?
+            .max(0);
+        let mut rest = &args[4..];
+        let mut ids = vec![];
+        while let Some(Ok(id)) = rest.first().map(|id| StreamId::parse(&id.bytes_arg(), 0)) {
+            ids.push(id);
+            rest = &rest[1..];
+        }
+        if ids.is_empty() {
+            StreamId::parse(&args[4].bytes_arg(), 0)?;
+        }
+
+        let now = now_ms();
+        let (mut delivered_at, mut retry_count, mut force, mut justid, mut last_id) = (now, None, false, false, None);
+        let mut opts = rest.iter();
+        while let Some(opt) = opts.next() {
+            let mut int_opt = |name: &str| {
+                opts.next()
+                    .ok_or(RedisError::Syntax)?
+                    .int_arg()
+                    .map_err(|_| RedisError::Other(format!("Invalid {} option argument for XCLAIM", name)))
+            };
+            match opt.option_name().as_str() {
+                "idle" => delivered_at = now.saturating_sub(int_opt("IDLE")?),
+                "time" => delivered_at = int_opt("TIME")?,
+                "retrycount" => retry_count = Some(int_opt("RETRYCOUNT")?.max(0) as u64),
+                "force" => force = true,
+                "justid" => justid = true,
+                "lastid" => {
+                    let id = opts.next().ok_or(RedisError::Syntax)?;
+                    last_id = Some(StreamId::parse(&id.bytes_arg(), 0)?);
+                }
+                _ => return Err(RedisError::Syntax),
+            }
+        }
+        // Times in the future or before the epoch make no sense, so they stand for now
+        let delivered_at = if (0..=now).contains(&delivered_at) { delivered_at } else { now };
+
+        let key = self.keyize(&args[0]);
+        let (group_name, consumer) = (args[1].bytes_arg(), args[2].bytes_arg());
+        let mut db = self.map.lock().expect("unlock failed!");
+        let stream = read_group(&mut db, &key, &group_name, no_such_key_or_group)?;
+        let existing: Vec<bool> = ids.iter().map(|id| stream.get(id).is_some()).collect();
+
+        let group = stream.groups.get_mut(&group_name).expect("group just looked up");
+        if let Some(last_id) = last_id {
+            group.last_delivered = group.last_delivered.max(last_id);
+        }
+        group.consumer(&consumer, now);
+        let mut claimed = vec![];
+        for (id, exists) in ids.into_iter().zip(existing) {
+            // Entries deleted from the stream are not worth claiming, nor keeping pending
+            if !exists {
+                group.acknowledge(&id);
+                continue;
+            }
+            match group.pending.get(&id) {
+                None if !force => continue,
+                Some(entry) if now - entry.delivered_at < min_idle => continue,
+                _ => {}
+            }
+            let entry = group.assign(id, &consumer, delivered_at);
+            match retry_count {
+                Some(count) => entry.deliveries = count,
+                None if !justid => entry.deliveries += 1,
+                None => {}
+            }
+            claimed.push(id);
+        }
+        if !claimed.is_empty() {
+            group.consumer(&consumer, now).active_at = Some(now);
+        }
+
+        Ok(RedisValue::Array(match justid {
+            true => claimed.iter().map(StreamId::reply).collect(),
+            false => claimed.iter().filter_map(|id| Some(entry_reply(id, stream.get(id)?))).collect(),
+        }))
+    }
+
+    /// `XAUTOCLAIM key group consumer min-idle-time start [COUNT count] [JUSTID]`, claiming the entries pending
+    /// for long enough from `start` on. Replies with the ID to resume from (`0-0` once done), the entries claimed
+    /// and the IDs of the pending entries found deleted from the stream.
+    pub(super) fn xautoclaim(&mut self, args: &[RedisValue]) -> CmdResult {
+        let min_idle = args[3]
+            .int_arg()
+            .map_err(|_| RedisError::Other("Invalid min-idle-time argument for XAUTOCLAIM".to_string()))?
+            .max(0);
+        let start = range_bound(&args[4], true)?;
+        let (mut count, mut justid) = (AUTOCLAIM_COUNT, false);
+        let mut opts = args[5..].iter();
+        while let Some(opt) = opts.next() {
+            match opt.option_name().as_str() {
+                "count" => match opts.next().ok_or(RedisError::Syntax)?.int_arg()? {
+                    n if n > 0 && (n as usize) < usize::MAX / AUTOCLAIM_ATTEMPTS_FACTOR => count = n as usize,
+                    _ => return Err(RedisError::Other("COUNT must be > 0".to_string())),
+                },
+                "justid" => justid = true,
+                _ => return Err(RedisError::Syntax),
+            }
+        }
+
+        let key = self.keyize(&args[0]);
+        let (group_name, consumer) = (args[1].bytes_arg(), args[2].bytes_arg());
+        let mut db = self.map.lock().expect("unlock failed!");
+        let stream = read_group(&mut db, &key, &group_name, no_such_key_or_group)?;
+        let now = now_ms();
+
+        // Pending entries looked at, whether they still are in the stream, and the one to resume from
+        let group = &stream.groups[&group_name];
+        let mut scanned = group.pending.range(start..).map(|(id, entry)| (*id, entry.delivered_at));
+        let candidates: Vec<_> = scanned.by_ref().take(count * AUTOCLAIM_ATTEMPTS_FACTOR).collect();
+        let next = scanned.next().map_or(StreamId::MIN, |(id, _)| id);
+        let candidates: Vec<_> =
+            candidates.into_iter().map(|(id, delivered_at)| (id, delivered_at, stream.get(&id).is_some())).collect();
+
+        let group = stream.groups.get_mut(&group_name).expect("group just looked up");
+        group.consumer(&consumer, now);
+        let (mut claimed, mut deleted) = (vec![], vec![]);
+        let mut candidates = candidates.into_iter();
+        for (id, delivered_at, exists) in candidates.by_ref() {
+            if !exists {
+                group.acknowledge(&id);
+                deleted.push(id);
+            } else if now - delivered_at >= min_idle {
+                let entry = group.assign(id, &consumer, now);
+                if !justid {
+                    entry.deliveries += 1;
+                }
+                claimed.push(id);
+                if claimed.len() == count {
+                    break;
+                }
+            }
+        }
+        // Stopping short of the attempts resumes from the next candidate
+        let next = candidates.next().map_or(next, |(id, _, _)| id);
+        if !claimed.is_empty() {
+            group.consumer(&consumer, now).active_at = Some(now);
+        }
+
+        let claimed = match justid {
+            true => claimed.iter().map(StreamId::reply).collect(),
+            false => claimed.iter().filter_map(|id| Some(entry_reply(id, stream.get(id)?))).collect(),
+        };
+        Ok(RedisValue::Array(vec![
+            next.reply(),
+            RedisValue::Array(claimed),
+            RedisValue::Array(deleted.iter().map(StreamId::reply).collect()),
+        ]))
+    }
+
+    /// `XINFO STREAM key [FULL [COUNT count]]`, `XINFO GROUPS key` and `XINFO CONSUMERS key group`
+    pub(super) fn xinfo(&mut self, args: &[RedisValue]) -> CmdResult {
+        let subcommand = args[0].option_name();
+        let full = match (subcommand.as_str(), &args[1..]) {
+            ("stream", [_]) => None,
+            ("stream", [_, opt]) if opt.option_name() == "full" => Some(INFO_FULL_COUNT),
+            ("stream", [_, opt, count_opt, count]) if opt.option_name() == "full" => {
+                if count_opt.option_name() != "count" {
+                    return Err(RedisError::Syntax);
+                }
+                // COUNT 0 lists every entry
+                Some(usize::try_from(count.int_arg()?).ok().filter(|&n| n > 0).unwrap_or(usize::MAX))
+            }
+            ("groups", [_]) | ("consumers", [_, _]) => None,
+            _ => return Err(subcommand_error("XINFO", &args[0])),
+        };
+        let key = self.keyize(&args[1]);
+        let mut db = self.map.lock().expect("unlock failed!");
+        let Some(stream) = read_stream(&mut db, &key)? else {
+            return Err(RedisError::Other("no such key".to_string()));
+        };
+        let now = now_ms();
+
+        match subcommand.as_str() {
+            "stream" => Ok(stream_info(stream, full)),
+            "groups" => Ok(RedisValue::Array(
+                stream
+                    .groups
+                    .iter()
+                    .map(|(name, group)| {
+                        info_map(vec![
+                            ("name", RedisValue::BulkString(name.clone())),
+                            ("consumers", RedisValue::Int(group.consumers.len() as RedisInt)),
+                            ("pending", RedisValue::Int(group.pending.len() as RedisInt)),
+                            ("last-delivered-id", group.last_delivered.reply()),
+                            ("entries-read", optional_int(group.entries_read)),
+                            ("lag", optional_int(lag(stream, group))),
+                        ])
+                    })
+                    .collect(),
+            )),
+            _ => {
+                let group_name = args[2].bytes_arg();
+                let group = stream.groups.get(&group_name).ok_or_else(|| no_such_group(&key, &group_name))?;
+                Ok(RedisValue::Array(
+                    group
+                        .consumers
+                        .iter()
+                        .map(|(name, consumer)| {
+                            info_map(vec![
+                                ("name", RedisValue::BulkString(name.clone())),
+                                ("pending", RedisValue::Int(consumer.pending.len() as RedisInt)),
+                                ("idle", RedisValue::Int((now - consumer.seen_at).max(0))),
+                                ("inactive", RedisValue::Int(consumer.active_at.map_or(-1, |at| (now - at).max(0)))),
+                            ])
+                        })
+                        .collect(),
+                ))
+            }
+        }
+    }
+}
+
+/// Reply of `XINFO STREAM`, with the first `full` entries and the details of the groups if given
+fn stream_info(stream: &Stream, full: Option<usize>) -> RedisValue {
+    // Entries are not stored in nodes here, the figures are those of a stream of nodes of NODE_ENTRIES entries
+    let nodes = stream.len().div_ceil(NODE_ENTRIES);
+    let mut fields = vec![
+        ("length", RedisValue::Int(stream.len() as RedisInt)),
+        ("radix-tree-keys", RedisValue::Int(nodes as RedisInt)),
+        ("radix-tree-nodes", RedisValue::Int(nodes as RedisInt + 1)),
+        ("last-generated-id", stream.last_id().reply()),
+        ("max-deleted-entry-id", stream.max_deleted_id().reply()),
+        ("entries-added", RedisValue::Int(stream.entries_added() as RedisInt)),
+        ("recorded-first-entry-id", stream.first().map_or(StreamId::MIN, |(id, _)| *id).reply()),
+    ];
+    let entry = |entry: Option<(&StreamId, _)>| entry.map_or(RedisValue::Null, |(id, fields)| entry_reply(id, fields));
+
+    let Some(count) = full else {
+        fields.push(("groups", RedisValue::Int(stream.groups.len() as RedisInt)));
+        fields.push(("first-entry", entry(stream.first())));
+        fields.push(("last-entry", entry(stream.last())));
+        return info_map(fields);
+    };
+
+    let entries = stream.range(StreamId::MIN, StreamId::MAX).take(count).map(|(id, f)| entry_reply(id, f));
+    fields.push(("entries", RedisValue::Array(entries.collect())));
+    let groups = stream.groups.iter().map(|(name, group)| {
+        let pending = group.pending.iter().take(count).map(|(id, entry)| {
+            RedisValue::Array(vec![
+                id.reply(),
+                RedisValue::BulkString(entry.consumer.clone()),
+                RedisValue::Int(entry.delivered_at),
+                RedisValue::Int(entry.deliveries as RedisInt),
+            ])
+        });
+        let consumers = group.consumers.iter().map(|(name, consumer)| {
+            let pending = consumer.pending.iter().take(count).filter_map(|id| {
+                let entry = group.pending.get(id)?;
+                Some(RedisValue::Array(vec![
+                    id.reply(),
+                    RedisValue::Int(entry.delivered_at),
+                    RedisValue::Int(entry.deliveries as RedisInt),
+                ]))
+            });
+            info_map(vec![
+                ("name", RedisValue::BulkString(name.clone())),
+                ("seen-time", RedisValue::Int(consumer.seen_at)),
+                ("active-time", RedisValue::Int(consumer.active_at.unwrap_or(-1))),
+                ("pel-count", RedisValue::Int(consumer.pending.len() as RedisInt)),
+                ("pending", RedisValue::Array(pending.collect())),
+            ])
+        });
+        info_map(vec![
+            ("name", RedisValue::BulkString(name.clone())),
+            ("last-delivered-id", group.last_delivered.reply()),
+            ("entries-read", optional_int(group.entries_read)),
+            ("lag", optional_int(lag(stream, group))),
+            ("pel-count", RedisValue::Int(group.pending.len() as RedisInt)),
+            ("pending", RedisValue::Array(pending.collect())),
+            ("consumers", RedisValue::Array(consumers.collect())),
+        ])
+    });
+    fields.push(("groups", RedisValue::Array(groups.collect())));
+    info_map(fields)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::resp::test::{bulk, connected_handler, connected_handler_on};
+    use crate::{RedisError, RedisValue};
+    use tokio::time::{Duration, sleep};
+
+    fn bulks(args: &[&str]) -> Vec<RedisValue> {
+        args.iter().map(|a| bulk(a)).collect()
+    }
+
+    fn entry(id: &str, fields: &[&str]) -> RedisValue {
+        RedisValue::Array(vec![bulk(id), RedisValue::Array(bulks(fields))])
+    }
+
+    fn read_reply(key: &str, entries: Vec<RedisValue>) -> RedisValue {
+        RedisValue::Array(vec![RedisValue::Array(vec![bulk(key), RedisValue::Array(entries)])])
+    }
+
+    /// Value of a field of an XINFO reply
+    fn info_field(info: &RedisValue, name: &str) -> RedisValue {
+        let RedisValue::Map(fields) = info else {
+            panic!("expected a map, got {:?}", info);
+        };
+        fields.iter().find(|(field, _)| *field == bulk(name)).map(|(_, val)| val.clone()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn delivery_and_acknowledgement() {
+        let (mut handler, _client) = connected_handler().await;
+        for id in ["1", "2", "3"] {
+            handler.handle_command("xadd", bulks(&["s", id, "f", id])).await;
+        }
+
+        let ok = RedisValue::SimpleString("OK".into());
+        assert_eq!(handler.handle_command("xgroup", bulks(&["CREATE", "s", "g", "0"])).await, ok);
+        let args = bulks(&["CREATE", "s", "g", "$"]);
+        assert_eq!(handler.handle_command("xgroup", args).await, RedisError::BusyGroup.into());
+        assert_eq!(
+            handler.handle_command("xgroup", bulks(&["CREATE", "nope", "g", "$"])).await,
+            RedisError::Other(
+                "The XGROUP subcommand requires the key to exist. Note that for CREATE you may want to use the \
+                 MKSTREAM option to create an empty stream automatically."
+                    .to_string()
+            )
+            .into()
+        );
+        assert_eq!(handler.handle_command("xgroup", bulks(&["CREATE", "new", "g", "$", "MKSTREAM"])).await, ok);
+        assert_eq!(handler.handle_command("xlen", vec![bulk("new")]).await, RedisValue::Int(0));
+
+        // Competing consumers get distinct entries
+        let args = bulks(&["GROUP", "g", "alice", "COUNT", "2", "STREAMS", "s", ">"]);
+        assert_eq!(
+            handler.handle_command("xreadgroup", args).await,
+            read_reply("s", vec![entry("1-0", &["f", "1"]), entry("2-0", &["f", "2"])])
+        );
+        let args = bulks(&["GROUP", "g", "bob", "STREAMS", "s", ">"]);
+        assert_eq!(handler.handle_command("xreadgroup", args).await, read_reply("s", vec![entry("3-0", &["f", "3"])]));
+        let args = bulks(&["GROUP", "g", "bob", "STREAMS", "s", ">"]);
+        assert_eq!(handler.handle_command("xreadgroup", args).await, RedisValue::NullArray);
+
+        // Pending entries, until acknowledged
+        let args = bulks(&["GROUP", "g", "alice", "STREAMS", "s", "0"]);
+        assert_eq!(
+            handler.handle_command("xreadgroup", args).await,
+            read_reply("s", vec![entry("1-0", &["f", "1"]), entry("2-0", &["f", "2"])])
+        );
+        assert_eq!(
+            handler.handle_command("xpending", bulks(&["s", "g"])).await,
+            RedisValue::Array(vec![
+                RedisValue::Int(3),
+                bulk("1-0"),
+                bulk("3-0"),
+                RedisValue::Array(vec![
+                    RedisValue::Array(vec![bulk("alice"), bulk("2")]),
+                    RedisValue::Array(vec![bulk("bob"), bulk("1")]),
+                ]),
+            ])
+        );
+        assert_eq!(handler.handle_command("xack", bulks(&["s", "g", "1-0", "9-0"])).await, RedisValue::Int(1));
+        let args = bulks(&["s", "g", "-", "+", "10", "alice"]);
+        let RedisValue::Array(pending) = handler.handle_command("xpending", args).await else { panic!() };
+        assert_eq!(pending.len(), 1);
+        let RedisValue::Array(pending) = &pending[0] else { panic!() };
+        assert_eq!((&pending[0], &pending[1], &pending[3]), (&bulk("2-0"), &bulk("alice"), &RedisValue::Int(1)));
+
+        // Deleted entries come without their fields
+        handler.handle_command("xdel", bulks(&["s", "2-0"])).await;
+        let args = bulks(&["GROUP", "g", "alice", "STREAMS", "s", "0"]);
+        assert_eq!(
+            handler.handle_command("xreadgroup", args).await,
+            read_reply("s", vec![RedisValue::Array(vec![bulk("2-0"), RedisValue::Null])])
+        );
+
+        let args = bulks(&["GROUP", "nope", "alice", "STREAMS", "s", ">"]);
+        assert_eq!(
+            handler.handle_command("xreadgroup", args).await,
+            RedisError::NoGroup("No such key 's' or consumer group 'nope' in XREADGROUP with GROUP option".to_string())
+                .into()
+        );
+        let args = bulks(&["DELCONSUMER", "s", "g", "alice"]);
+        assert_eq!(handler.handle_command("xgroup", args).await, RedisValue::Int(1));
+        assert_eq!(handler.handle_command("xgroup", bulks(&["DESTROY", "s", "g"])).await, RedisValue::Int(1));
+    }
+
+    #[tokio::test]
+    async fn claims() {
+        let (mut handler, _client) = connected_handler().await;
+        for id in ["1", "2", "3"] {
+            handler.handle_command("xadd", bulks(&["s", id, "f", id])).await;
+        }
+        handler.handle_command("xgroup", bulks(&["CREATE", "s", "g", "0"])).await;
+        handler.handle_command("xreadgroup", bulks(&["GROUP", "g", "alice", "STREAMS", "s", ">"])).await;
+
+        // Not idle for long enough
+        let args = bulks(&["s", "g", "bob", "60000", "1-0"]);
+        assert_eq!(handler.handle_command("xclaim", args).await, RedisValue::Array(vec![]));
+        let args = bulks(&["s", "g", "bob", "0", "1-0", "2-0", "RETRYCOUNT", "5"]);
+        assert_eq!(
+            handler.handle_command("xclaim", args).await,
+            RedisValue::Array(vec![entry("1-0", &["f", "1"]), entry("2-0", &["f", "2"])])
+        );
+        let args = bulks(&["s", "g", "-", "1", "1"]);
+        let RedisValue::Array(pending) = handler.handle_command("xpending", args).await else { panic!() };
+        let RedisValue::Array(pending) = &pending[0] else { panic!() };
+        assert_eq!((&pending[1], &pending[3]), (&bulk("bob"), &RedisValue::Int(5)));
+
+        // Delivery times before the epoch are taken as now
+        for (opt, at) in [("TIME", "-5000"), ("IDLE", "9223372036854775807")] {
+            let args = bulks(&["s", "g", "bob", "0", "1-0", opt, at, "JUSTID"]);
+            assert_eq!(handler.handle_command("xclaim", args).await, RedisValue::Array(vec![bulk("1-0")]));
+            let args = bulks(&["s", "g", "-", "1", "1"]);
+            let RedisValue::Array(pending) = handler.handle_command("xpending", args).await else { panic!() };
+            let RedisValue::Array(pending) = &pending[0] else { panic!() };
+            assert!(matches!(pending[2], RedisValue::Int(idle) if (0..1000).contains(&idle)));
+        }
+
+        handler.handle_command("xdel", bulks(&["s", "3-0"])).await;
+        let args = bulks(&["s", "g", "carol", "0", "0", "COUNT", "1", "JUSTID"]);
+        assert_eq!(
+            handler.handle_command("xautoclaim", args).await,
+            RedisValue::Array(vec![bulk("2-0"), RedisValue::Array(vec![bulk("1-0")]), RedisValue::Array(vec![])])
+        );
+        let args = bulks(&["s", "g", "carol", "0", "2-0"]);
+        assert_eq!(
+            handler.handle_command("xautoclaim", args).await,
+            RedisValue::Array(vec![
+                bulk("0-0"),
+                RedisValue::Array(vec![entry("2-0", &["f", "2"])]),
+                RedisValue::Array(vec![bulk("3-0")]),
+            ])
+        );
+
+        let groups = handler.handle_command("xinfo", bulks(&["GROUPS", "s"])).await;
+        let RedisValue::Array(groups) = groups else { panic!() };
+        assert_eq!(info_field(&groups[0], "pending"), RedisValue::Int(2));
+        assert_eq!(info_field(&groups[0], "last-delivered-id"), bulk("3-0"));
+        assert_eq!(info_field(&groups[0], "lag"), RedisValue::Int(0));
+        let RedisValue::Array(consumers) = handler.handle_command("xinfo", bulks(&["CONSUMERS", "s", "g"])).await else {
+            panic!()
+        };
+        let names: Vec<_> = consumers.iter().map(|c| info_field(c, "name")).collect();
+        assert_eq!(names, bulks(&["alice", "bob", "carol"]));
+        let info = handler.handle_command("xinfo", bulks(&["STREAM", "s"])).await;
+        assert_eq!(info_field(&info, "length"), RedisValue::Int(2));
+        assert_eq!(info_field(&info, "first-entry"), entry("1-0", &["f", "1"]));
+        assert_eq!(info_field(&info, "max-deleted-entry-id"), bulk("3-0"));
+    }
+
+    #[tokio::test]
+    async fn blocking_read() {
+        let (mut writer, _client) = connected_handler().await;
+        let (mut reader, _reader_client) = connected_handler_on(&writer.map, 1).await;
+        writer.handle_command("xgroup", bulks(&["CREATE", "s", "g", "$", "MKSTREAM"])).await;
+
+        let args = bulks(&["GROUP", "g", "alice", "BLOCK", "0", "STREAMS", "s", ">"]);
+        let waiting = tokio::spawn(async move { reader.handle_command("xreadgroup", args).await });
+        sleep(Duration::from_millis(20)).await;
+        writer.handle_command("xadd", bulks(&["s", "1", "f", "v"])).await;
+        assert_eq!(waiting.await.unwrap(), read_reply("s", vec![entry("1-0", &["f", "v"])]));
+        assert_eq!(writer.handle_command("xpending", bulks(&["s", "g"])).await, {
+            let consumers = RedisValue::Array(vec![RedisValue::Array(vec![bulk("alice"), bulk("1")])]);
+            RedisValue::Array(vec![RedisValue::Int(1), bulk("1-0"), bulk("1-0"), consumers])
+        });
+    }
+}
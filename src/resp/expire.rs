@@ -0,0 +1,298 @@
+// Expiry commands, reading and updating the deadline of existing keys
+
+use super::{Database, Key, RespHandler, ThreadSafeDb, lookup};
+use crate::error::{CmdResult, RedisError};
+use crate::now_ms;
+use crate::resp::RedisValue;
+use tokio::time::{Duration, interval};
+
+/// Period of the background expiry sweep
+const ACTIVE_EXPIRE_PERIOD: Duration = Duration::from_millis(100);
+/// Keys checked per round of a sweep, as Redis' `ACTIVE_EXPIRE_CYCLE_KEYS_PER_LOOP`
+const ACTIVE_EXPIRE_KEYS_PER_LOOP: usize = 20;
+/// Rounds of a sweep at most, however many keys keep turning out expired
+const ACTIVE_EXPIRE_MAX_LOOPS: usize = 16;
+
+/// Conditions an expiry update is subject to (`NX`, `XX`, `GT` and/or `LT`)
+#[derive(PartialEq, Clone, Copy, Debug, Default)]
+pub(super) struct ExpireCond {
+    nx: bool,
+    xx: bool,
+    gt: bool,
+    lt: bool,
+}
+
+impl ExpireCond {
+    /// Reads the options trailing an expiry command
+    pub(super) fn parse(opts: &[RedisValue]) -> Result<Self, RedisError> {
+        let mut cond = ExpireCond::default();
+        for opt in opts {
+            match opt.option_name().as_str() {
+                "nx" => cond.nx = true,
+                "xx" => cond.xx = true,
+                "gt" => cond.gt = true,
+                "lt" => cond.lt = true,
+                _ => {
+                    return Err(RedisError::Other(format!(
+                        "Unsupported option {}",
+                        String::from_utf8_lossy(&opt.keyize())
+                    )));
+                }
+            }
+        }
+
+        if cond.nx && (cond.xx || cond.gt || cond.lt) {
+            return Err(RedisError::Other(
+                "NX and XX, GT or LT options at the same time are not compatible".to_string(),
+            ));
+        }
+        if cond.gt && cond.lt {
+            return Err(RedisError::Other(
+                "GT and LT options at the same time are not compatible".to_string(),
+            ));
+        }
+        Ok(cond)
+    }
+
+    /// Whether an entry currently expiring at `current` may be given the `new` deadline.
+    /// Entries without expiry count as living forever.
+    pub(super) fn allows(&self, current: Option<i64>, new: i64) -> bool {
+        !(self.nx && current.is_some()
+            || self.xx && current.is_none()
+            || self.gt && !current.is_some_and(|c| new > c)
+            || self.lt && !current.is_none_or(|c| new < c))
+    }
+}
+
+/// Turns a relative (or absolute, if `at`) expiry expressed in `unit` milliseconds into a deadline,
+/// failing the way Redis does when it cannot be represented.
+pub(super) fn deadline_from(time: i64, unit: i64, at: bool, command: &str) -> Result<i64, RedisError> {
+    let invalid = || RedisError::InvalidExpire(command.to_string());
+    let ms = time.checked_mul(unit).ok_or_else(invalid)?;
+    if at { Ok(ms) } else { ms.checked_add(now_ms()).ok_or_else(invalid) }
+}
+
+impl Database {
+    /// Queues `key` for the background sweep to check, once it (or some of its members) got a deadline
+    pub(crate) fn track_expiry(&mut self, key: &Key) {
+        if self.volatile_keys.insert(key.clone()) {
+            self.volatile.push_back(key.clone());
+        }
+    }
+
+    /// Removes the expired entries, as well as the expired members of containers, among a few of the keys
+    /// which may expire, going on with a few more as long as over a quarter of them were expired (as Redis
+    /// does), so that the database is never locked for long. Replies with the number of entries removed.
+    pub fn expire_cycle(&mut self) -> usize {
+        let now = now_ms();
+        let mut removed = 0;
+        for _ in 0..ACTIVE_EXPIRE_MAX_LOOPS {
+            let sampled = ACTIVE_EXPIRE_KEYS_PER_LOOP.min(self.volatile.len());
+            let mut expired = 0;
+            for _ in 0..sampled {
+                let key = self.volatile.pop_front().expect("key sampled within the queue");
+                let (gone, expires) = match self.get_mut(&key) {
+                    Some(set) => (set.gone_at(now), set.expires()),
+                    None => (false, false),
+                };
+                if gone {
+                    self.remove(&key);
+                    expired += 1;
+                }
+                // Keys which may still expire go to the back of the queue, the others are forgotten
+                match !gone && expires {
+                    true => self.volatile.push_back(key),
+                    false => {
+                        self.volatile_keys.remove(&key);
+                    }
+                }
+            }
+            removed += expired;
+            if expired * 4 <= sampled {
+                break;
+            }
+        }
+        removed
+    }
+}
+
+/// Reclaims the memory held by expired keys and hash fields that nobody looks up anymore, which lazy expiry
+/// on lookup would keep around forever
+pub async fn active_expire(db: ThreadSafeDb) {
+    let mut ticks = interval(ACTIVE_EXPIRE_PERIOD);
+    loop {
+        ticks.tick().await;
+        db.lock().expect("unlock failed!").expire_cycle();
+    }
+}
+
+impl RespHandler {
+    /// `EXPIRE key seconds [NX | XX | GT | LT]`, as well as PEXPIRE, EXPIREAT and PEXPIREAT, time being
+    /// expressed in `unit` milliseconds, and as a Unix timestamp if `at`.
+    ///
+    /// Replies with whether the deadline was updated. A deadline already in the past deletes the key.
+    pub(super) fn expire(&mut self, args: &[RedisValue], unit: i64, at: bool, command: &str) -> CmdResult {
+        let key = self.keyize(&args[0]);
+        let time = args[1].int_arg()?;
+        let cond = ExpireCond::parse(&args[2..])?;
+        let deadline = deadline_from(time, unit, at, command)?;
+
+        let mut db = self.map.lock().expect("unlock failed!");
+        let Some(set) = lookup(&mut db, &key) else {
+            return Ok(RedisValue::Int(0));
+        };
+        if !cond.allows(set.deadline, deadline) {
+            return Ok(RedisValue::Int(0));
+        }
+
+        if deadline <= now_ms() {
+            db.remove(&key);
+        } else {
+            set.deadline = Some(deadline);
+            db.track_expiry(&key);
+        }
+        Ok(RedisValue::Int(1))
+    }
+
+    /// `TTL key` and `PTTL key`, or `EXPIRETIME key` and `PEXPIRETIME key` if `absolute`, in `unit`
+    /// milliseconds. Replies with -2 if the key does not exist, and -1 if it does not expire.
+    pub(super) fn ttl(&mut self, key: &RedisValue, unit: i64, absolute: bool) -> CmdResult {
+        let key = self.keyize(key);
+        let mut db = self.map.lock().expect("unlock failed!");
+
+        let reply = match lookup(&mut db, &key) {
+            None => -2,
+            Some(set) => match (set.deadline, set.ttl_ms()) {
+                // Rounded to the closest unit, as Redis does
+                (Some(deadline), _) if absolute => (deadline + unit / 2) / unit,
+                (_, Some(ttl)) => (ttl + unit / 2) / unit,
+                _ => -1,
+            },
+        };
+        Ok(RedisValue::Int(reply))
+    }
+
+    /// `PERSIST key`, replying with whether an expiry was removed
+    pub(super) fn persist(&mut self, key: &RedisValue) -> CmdResult {
+        let key = self.keyize(key);
+        let mut db = self.map.lock().expect("unlock failed!");
+
+        let persisted = lookup(&mut db, &key).and_then(|set| set.deadline.take()).is_some();
+        Ok(RedisValue::Int(persisted as i64))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ACTIVE_EXPIRE_KEYS_PER_LOOP, ACTIVE_EXPIRE_MAX_LOOPS, ExpireCond};
+    use crate::resp::test::{bulk, connected_handler};
+    use std::time::Duration;
+    use crate::{RedisError, RedisValue, now_ms};
+
+    #[test]
+    fn expire_conditions() {
+        let parse = |opts: &[&str]| ExpireCond::parse(&opts.iter().map(|o| bulk(o)).collect::<Vec<_>>());
+        assert!(parse(&["nx", "xx"]).is_err());
+        assert!(parse(&["gt", "lt"]).is_err());
+        assert!(parse(&["whatever"]).is_err());
+
+        let lt = parse(&["LT"]).unwrap();
+        assert!(lt.allows(None, 10));
+        assert!(!lt.allows(Some(5), 10));
+        // XX restricts LT to volatile keys
+        assert!(!parse(&["xx", "lt"]).unwrap().allows(None, 10));
+        let gt = parse(&["gt"]).unwrap();
+        assert!(!gt.allows(None, 10));
+        assert!(gt.allows(Some(5), 10));
+        assert!(!parse(&["nx"]).unwrap().allows(Some(5), 10));
+        assert!(ExpireCond::default().allows(Some(5), 1));
+    }
+
+    #[tokio::test]
+    async fn expire_ttl_persist() {
+        let (mut handler, _client) = connected_handler().await;
+        handler.handle_command("set", vec![bulk("k"), bulk("v")]).await;
+
+        assert_eq!(handler.handle_command("ttl", vec![bulk("k")]).await, RedisValue::Int(-1));
+        assert_eq!(handler.handle_command("ttl", vec![bulk("nope")]).await, RedisValue::Int(-2));
+        assert_eq!(handler.handle_command("expire", vec![bulk("nope"), bulk("10")]).await, RedisValue::Int(0));
+        assert_eq!(
+            handler.handle_command("expire", vec![bulk("k"), bulk("100"), bulk("xx")]).await,
+            RedisValue::Int(0)
+        );
+
+        assert_eq!(handler.handle_command("expire", vec![bulk("k"), bulk("100")]).await, RedisValue::Int(1));
+        assert_eq!(handler.handle_command("ttl", vec![bulk("k")]).await, RedisValue::Int(100));
+        assert_eq!(
+            handler.handle_command("pexpire", vec![bulk("k"), bulk("200000"), bulk("lt")]).await,
+            RedisValue::Int(0)
+        );
+        assert_eq!(
+            handler.handle_command("pexpire", vec![bulk("k"), bulk("200000"), bulk("gt")]).await,
+            RedisValue::Int(1)
+        );
+        let RedisValue::Int(pttl) = handler.handle_command("pttl", vec![bulk("k")]).await else {
+            panic!("PTTL should reply with an integer");
+        };
+        assert!((199_000..=200_000).contains(&pttl));
+
+        let at = now_ms() / 1000 + 1000;
+        let expireat = handler.handle_command("expireat", vec![bulk("k"), bulk(&at.to_string())]).await;
+        assert_eq!(expireat, RedisValue::Int(1));
+        assert_eq!(handler.handle_command("expiretime", vec![bulk("k")]).await, RedisValue::Int(at));
+        let pexpireat = (at * 1000 + 600).to_string();
+        handler.handle_command("pexpireat", vec![bulk("k"), bulk(&pexpireat)]).await;
+        assert_eq!(handler.handle_command("expiretime", vec![bulk("k")]).await, RedisValue::Int(at + 1));
+
+        assert_eq!(handler.handle_command("persist", vec![bulk("k")]).await, RedisValue::Int(1));
+        assert_eq!(handler.handle_command("persist", vec![bulk("k")]).await, RedisValue::Int(0));
+        assert_eq!(handler.handle_command("pexpiretime", vec![bulk("k")]).await, RedisValue::Int(-1));
+    }
+
+    #[tokio::test]
+    async fn expire_in_the_past_deletes() {
+        let (mut handler, _client) = connected_handler().await;
+        handler.handle_command("set", vec![bulk("k"), bulk("v")]).await;
+
+        assert_eq!(handler.handle_command("expire", vec![bulk("k"), bulk("-1")]).await, RedisValue::Int(1));
+        assert_eq!(handler.handle_command("exists", vec![bulk("k")]).await, RedisValue::Int(0));
+        assert_eq!(
+            handler.handle_command("expire", vec![bulk("k"), bulk(&i64::MAX.to_string())]).await,
+            RedisError::InvalidExpire("expire".to_string()).into()
+        );
+    }
+
+    #[tokio::test]
+    async fn background_expiry() {
+        let (mut handler, _client) = connected_handler().await;
+        handler.handle_command("set", vec![bulk("k"), bulk("v"), bulk("px"), bulk("10")]).await;
+        handler.handle_command("set", vec![bulk("kept"), bulk("v")]).await;
+        handler.handle_command("hset", vec![bulk("h"), bulk("f"), bulk("v")]).await;
+        handler.handle_command("hpexpire", ["h", "10", "FIELDS", "1", "f"].map(bulk).to_vec()).await;
+
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        // Nothing looked the keys up, they are still there until the sweep
+        let mut db = handler.map.lock().unwrap();
+        assert_eq!(db.len(), 3);
+        assert_eq!(db.expire_cycle(), 2);
+        assert!(db.contains_key(&b"kept"[..]));
+    }
+
+    #[tokio::test]
+    async fn bounded_background_expiry() {
+        let (mut handler, _client) = connected_handler().await;
+        for i in 0..1000 {
+            handler.handle_command("set", vec![bulk(&format!("k{}", i)), bulk("v"), bulk("px"), bulk("1")]).await;
+        }
+        handler.handle_command("set", vec![bulk("later"), bulk("v"), bulk("ex"), bulk("100")]).await;
+        handler.handle_command("persist", vec![bulk("later")]).await;
+
+        tokio::time::sleep(Duration::from_millis(5)).await;
+        // Each sweep only goes through so many keys, the following ones picking up where it left off
+        let mut db = handler.map.lock().unwrap();
+        assert_eq!(db.expire_cycle(), ACTIVE_EXPIRE_KEYS_PER_LOOP * ACTIVE_EXPIRE_MAX_LOOPS);
+        while db.expire_cycle() > 0 {}
+        assert_eq!(db.len(), 1);
+        assert!(db.volatile.is_empty() && db.volatile_keys.is_empty());
+    }
+}
@@ -0,0 +1,601 @@
+// Geospatial commands: points stored as sorted set members, scored by the 52-bit geohash of their coordinates
+// so that nearby points get close scores, and searched through the score ranges of the cells around a center.
+
+use super::RespHandler;
+use super::sorted_sets::{SortedSet, read_zset, store_zset};
+use crate::error::{CmdResult, RedisError};
+use crate::resp::{RedisInt, RedisValue};
+use bytes::Bytes;
+
+/// Bits per coordinate of the geohashes stored, 52 bits in all
+const STEP_MAX: u32 = 26;
+const LON_RANGE: (f64, f64) = (-180.0, 180.0);
+/// Latitudes of the Web Mercator projection, the ones Redis takes
+const LAT_RANGE: (f64, f64) = (-85.05112878, 85.05112878);
+/// Latitudes of standard geohashes, which GEOHASH replies with
+const STANDARD_LAT_RANGE: (f64, f64) = (-90.0, 90.0);
+const EARTH_RADIUS: f64 = 6372797.560856;
+/// Half the circumference of the Earth in the Web Mercator projection
+const MERCATOR_MAX: f64 = 20037726.37;
+const GEOHASH_ALPHABET: &[u8] = b"0123456789bcdefghjkmnpqrstuvwxyz";
+
+/// Spreads the bits of a coordinate to the even bits of the result
+fn spread(v: u32) -> u64 {
+    let mut x = v as u64;
+    x = (x | x << 16) & 0x0000_ffff_0000_ffff;
+    x = (x | x << 8) & 0x00ff_00ff_00ff_00ff;
+    x = (x | x << 4) & 0x0f0f_0f0f_0f0f_0f0f;
+    x = (x | x << 2) & 0x3333_3333_3333_3333;
+    (x | x << 1) & 0x5555_5555_5555_5555
+}
+
+/// Gathers the even bits, undoing [`spread`]
+fn squash(x: u64) -> u32 {
+    let mut x = x & 0x5555_5555_5555_5555;
+    x = (x | x >> 1) & 0x3333_3333_3333_3333;
+    x = (x | x >> 2) & 0x0f0f_0f0f_0f0f_0f0f;
+    x = (x | x >> 4) & 0x00ff_00ff_00ff_00ff;
+    x = (x | x >> 8) & 0x0000_ffff_0000_ffff;
+    ((x | x >> 16) & 0xffff_ffff) as u32
+}
+
+/// Cell of the grid splitting each coordinate range into 2^step parts: its latitude and longitude indices
+/// interleaved, latitude bits first
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct GeoHash {
+    bits: u64,
+    step: u32,
+}
+
+/// Coordinates range of a cell
+#[derive(Debug, Clone, Copy)]
+struct Area {
+    lon: (f64, f64),
+    lat: (f64, f64),
+}
+
+impl GeoHash {
+    fn encode(lon: f64, lat: f64, lat_range: (f64, f64), step: u32) -> Self {
+        let cells = (1u64 << step) as f64;
+        let index = |v: f64, (min, max): (f64, f64)| (((v - min) / (max - min) * cells) as u32).min((1 << step) - 1);
+        GeoHash { bits: spread(index(lat, lat_range)) | spread(index(lon, LON_RANGE)) << 1, step }
+    }
+
+    fn from_score(score: f64) -> Self {
+        GeoHash { bits: score as u64, step: STEP_MAX }
+    }
+
+    fn area(self, lat_range: (f64, f64)) -> Area {
+        let cells = (1u64 << self.step) as f64;
+        let bounds = |index: u32, (min, max): (f64, f64)| {
+            (min + index as f64 / cells * (max - min), min + (index as f64 + 1.0) / cells * (max - min))
+        };
+        Area { lat: bounds(squash(self.bits), lat_range), lon: bounds(squash(self.bits >> 1), LON_RANGE) }
+    }
+
+    /// Coordinates of the center of the cell, as longitude and latitude
+    fn decode(self) -> (f64, f64) {
+        let area = self.area(LAT_RANGE);
+        let lon = ((area.lon.0 + area.lon.1) / 2.0).clamp(LON_RANGE.0, LON_RANGE.1);
+        let lat = ((area.lat.0 + area.lat.1) / 2.0).clamp(LAT_RANGE.0, LAT_RANGE.1);
+        (lon, lat)
+    }
+
+    /// The cell `lat` rows north and `lon` columns east, wrapping around the grid
+    fn moved(self, lat: i64, lon: i64) -> Self {
+        let mask = (1u64 << self.step) - 1;
+        let shift = |index: u32, by: i64| ((index as u64).wrapping_add(by as u64) & mask) as u32;
+        let (lat, lon) = (shift(squash(self.bits), lat), shift(squash(self.bits >> 1), lon));
+        GeoHash { bits: spread(lat) | spread(lon) << 1, step: self.step }
+    }
+
+    /// Scores of the points within the cell, from `min` included to `max` excluded
+    fn score_range(self) -> (f64, f64) {
+        let shift = 2 * (STEP_MAX - self.step);
+        ((self.bits << shift) as f64, ((self.bits + 1) << shift) as f64)
+    }
+}
+
+/// Distance in meters between two points along the surface of the Earth, with the haversine formula
+fn distance((lon1, lat1): (f64, f64), (lon2, lat2): (f64, f64)) -> f64 {
+    let (lat1, lat2) = (lat1.to_radians(), lat2.to_radians());
+    let u = ((lat2 - lat1) / 2.0).sin();
+    let v = ((lon2.to_radians() - lon1.to_radians()) / 2.0).sin();
+    2.0 * EARTH_RADIUS * (u * u + lat1.cos() * lat2.cos() * v * v).sqrt().asin()
+}
+
+fn check_coordinates(lon: f64, lat: f64) -> Result<(), RedisError> {
+    match (LON_RANGE.0..=LON_RANGE.1).contains(&lon) && (LAT_RANGE.0..=LAT_RANGE.1).contains(&lat) {
+        true => Ok(()),
+        false => Err(RedisError::Other(format!("invalid longitude,latitude pair {:.6},{:.6}", lon, lat))),
+    }
+}
+
+/// Reads a distance unit, replying with the meters it stands for
+fn unit_arg(arg: Option<&RedisValue>) -> Result<f64, RedisError> {
+    match arg.ok_or(RedisError::Syntax)?.option_name().as_str() {
+        "m" => Ok(1.0),
+        "km" => Ok(1000.0),
+        "ft" => Ok(0.3048),
+        "mi" => Ok(1609.34),
+        _ => Err(RedisError::Other("unsupported unit provided. please use M, KM, FT, MI".to_string())),
+    }
+}
+
+/// Distances are replied as strings with 4 decimals
+fn distance_reply(meters: f64, unit: f64) -> RedisValue {
+    RedisValue::bulk(format!("{:.4}", meters / unit))
+}
+
+fn coordinates_reply((lon, lat): (f64, f64)) -> RedisValue {
+    RedisValue::Array(vec![RedisValue::Double(lon), RedisValue::Double(lat)])
+}
+
+/// Area GEOSEARCH looks within, in meters
+#[derive(Debug, Clone, Copy)]
+enum Shape {
+    Radius(f64),
+    Box { width: f64, height: f64 },
+}
+
+impl Shape {
+    /// Distance from `center` to `point`, if the point is within the shape around `center`
+    fn distance(self, center: (f64, f64), point: (f64, f64)) -> Option<f64> {
+        match self {
+            Shape::Radius(radius) => Some(distance(center, point)).filter(|&d| d <= radius),
+            Shape::Box { width, height } => {
+                let lat_distance = EARTH_RADIUS * (point.1.to_radians() - center.1.to_radians()).abs();
+                let lon_distance = distance((point.0, point.1), (center.0, point.1));
+                (lat_distance <= height / 2.0 && lon_distance <= width / 2.0).then(|| distance(center, point))
+            }
+        }
+    }
+
+    /// Longitudes and latitudes bounding the shape around `center`
+    fn bounds(self, (lon, lat): (f64, f64)) -> Area {
+        let (half_width, half_height) = match self {
+            Shape::Radius(radius) => (radius, radius),
+            Shape::Box { width, height } => (width / 2.0, height / 2.0),
+        };
+        let lat_delta = (half_height / EARTH_RADIUS).to_degrees();
+        // Degrees of longitude are shortest on the side nearer to the pole, which takes the most of them
+        let widest = if lat < 0.0 { lat - lat_delta } else { lat + lat_delta };
+        let lon_delta = (half_width / EARTH_RADIUS / widest.to_radians().cos()).to_degrees();
+        Area { lon: (lon - lon_delta, lon + lon_delta), lat: (lat - lat_delta, lat + lat_delta) }
+    }
+
+    /// Cells covering the shape around `center`: the cell of the center and its 8 neighbors, at a step coarse
+    /// enough for them to hold the whole shape, those sure to be outside of it left out
+    fn cells(self, center: (f64, f64)) -> Vec<GeoHash> {
+        let radius = match self {
+            Shape::Radius(radius) => radius,
+            Shape::Box { width, height } => (width / 2.0).hypot(height / 2.0),
+        };
+        let bounds = self.bounds(center);
+        let mut step = estimate_step(radius, center.1);
+        let around = |step| {
+            let hash = GeoHash::encode(center.0, center.1, LAT_RANGE, step);
+            let neighbors = [(1, 0), (-1, 0), (0, 1), (0, -1), (1, 1), (1, -1), (-1, 1), (-1, -1)];
+            (hash, neighbors.map(|(lat, lon)| hash.moved(lat, lon)))
+        };
+        let (mut hash, mut neighbors) = around(step);
+
+        // The neighbors may still fall short of the bounds of the shape
+        let [north, south, east, west, ..] = neighbors.map(|n| n.area(LAT_RANGE));
+        let short = north.lat.1 < bounds.lat.1
+            || south.lat.0 > bounds.lat.0
+            || east.lon.1 < bounds.lon.1
+            || west.lon.0 > bounds.lon.0;
+        if step > 1 && short {
+            step -= 1;
+            (hash, neighbors) = around(step);
+        }
+
+        let area = hash.area(LAT_RANGE);
+        let [north, south, east, west, north_east, north_west, south_east, south_west] = neighbors.map(Some);
+        let mut cells = [Some(hash), north, south, east, west, north_east, north_west, south_east, south_west];
+        if step >= 2 {
+            let mut exclude = |indices: [usize; 3]| indices.map(|i| cells[i] = None);
+            if area.lat.0 < bounds.lat.0 {
+                exclude([2, 7, 8]);
+            }
+            if area.lat.1 > bounds.lat.1 {
+                exclude([1, 5, 6]);
+            }
+            if area.lon.0 < bounds.lon.0 {
+                exclude([4, 6, 8]);
+            }
+            if area.lon.1 > bounds.lon.1 {
+                exclude([3, 5, 7]);
+            }
+        }
+        // Small grids wrap around to the same cells
+        let mut unique: Vec<GeoHash> = vec![];
+        for cell in cells.into_iter().flatten() {
+            if !unique.contains(&cell) {
+                unique.push(cell);
+            }
+        }
+        unique
+    }
+}
+
+/// Coarsest step whose cells still hold a circle of `radius` meters, coarser towards the poles
+fn estimate_step(mut radius: f64, lat: f64) -> u32 {
+    if radius == 0.0 {
+        return STEP_MAX;
+    }
+    let mut step: i32 = 1;
+    while radius < MERCATOR_MAX {
+        radius *= 2.0;
+        step += 1;
+    }
+    step -= 2;
+    if lat.abs() > 66.0 {
+        step -= 1;
+        if lat.abs() > 80.0 {
+            step -= 1;
+        }
+    }
+    step.clamp(1, STEP_MAX as i32) as u32
+}
+
+/// Points of `zset` within `shape` around `center`: members along with their scores and distances, stopping at
+/// `limit` of them if given
+fn search(zset: &SortedSet, center: (f64, f64), shape: Shape, limit: Option<usize>) -> Vec<(Bytes, f64, f64)> {
+    let mut found = vec![];
+    for cell in shape.cells(center) {
+        let (min, max) = cell.score_range();
+        for (member, score) in zset.score_range(min, max) {
+            let Some(dist) = shape.distance(center, GeoHash::from_score(score).decode()) else {
+                continue;
+            };
+            found.push((member.clone(), score, dist));
+            if limit.is_some_and(|limit| found.len() >= limit) {
+                return found;
+            }
+        }
+    }
+    found
+}
+
+fn float_opt(opts: &mut std::slice::Iter<'_, RedisValue>) -> Result<f64, RedisError> {
+    opts.next().ok_or(RedisError::Syntax)?.float_arg()
+}
+
+/// Where GEOSEARCH searches from
+enum Origin {
+    Member(Bytes),
+    Coordinates(f64, f64),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Order {
+    Asc,
+    Desc,
+}
+
+impl RespHandler {
+    /// `GEOADD key [NX | XX] [CH] longitude latitude member [longitude latitude member ...]`, adding the points
+    /// to the sorted set at `key` as ZADD would, scored by their geohash
+    pub(super) fn geoadd(&mut self, args: &[RedisValue]) -> CmdResult {
+        let mut zadd_args = vec![args[0].clone()];
+        let mut points = &args[1..];
+        while let Some(opt) = points.first() {
+            match opt.option_name().as_str() {
+                "nx" | "xx" | "ch" => zadd_args.push(opt.clone()),
+                _ => break,
+            }
+            points = &points[1..];
+        }
+        let nx_xx = zadd_args.iter().filter(|opt| matches!(opt.option_name().as_str(), "nx" | "xx")).count();
+        if points.is_empty() || !points.len().is_multiple_of(3) || nx_xx > 1 {
+            return Err(RedisError::Syntax);
+        }
+        for point in points.chunks(3) {
+            let (lon, lat) = (point[0].float_arg()?, point[1].float_arg()?);
+            check_coordinates(lon, lat)?;
+            let hash = GeoHash::encode(lon, lat, LAT_RANGE, STEP_MAX);
+            zadd_args.push(RedisValue::bulk(hash.bits.to_string()));
+            zadd_args.push(point[2].clone());
+        }
+        self.zadd(&zadd_args)
+    }
+
+    /// `GEODIST key member1 member2 [M | KM | FT | MI]`, nil if either member is missing
+    pub(super) fn geodist(&mut self, args: &[RedisValue]) -> CmdResult {
+        let unit = match args.get(3) {
+            Some(unit) => unit_arg(Some(unit))?,
+            None => 1.0,
+        };
+        if args.len() > 4 {
+            return Err(RedisError::Syntax);
+        }
+        let key = self.keyize(&args[0]);
+        let mut db = self.map.lock().expect("unlock failed!");
+
+        let Some(zset) = read_zset(&mut db, &key)? else {
+            return Ok(RedisValue::NullBulkString);
+        };
+        let point = |member: &RedisValue| zset.score(&member.bytes_arg()).map(|s| GeoHash::from_score(s).decode());
+        Ok(match (point(&args[1]), point(&args[2])) {
+            (Some(from), Some(to)) => distance_reply(distance(from, to), unit),
+            _ => RedisValue::NullBulkString,
+        })
+    }
+
+    /// `GEOHASH key [member [member ...]]`, replying with the standard 11 characters geohash of each point
+    pub(super) fn geohash(&mut self, key: &RedisValue, members: &[RedisValue]) -> CmdResult {
+        let key = self.keyize(key);
+        let mut db = self.map.lock().expect("unlock failed!");
+
+        let zset = read_zset(&mut db, &key)?;
+        let hashes = members.iter().map(|member| {
+            let Some(score) = zset.as_ref().and_then(|zset| zset.score(&member.bytes_arg())) else {
+                return RedisValue::NullBulkString;
+            };
+            // Stored geohashes only span Mercator latitudes, standard ones go from pole to pole
+            let (lon, lat) = GeoHash::from_score(score).decode();
+            let bits = GeoHash::encode(lon, lat, STANDARD_LAT_RANGE, STEP_MAX).bits;
+            // 52 bits make 10 characters and 2 bits, padded with zeros as Redis does
+            let hash: Vec<u8> = (1..=11)
+                .map(|i| {
+                    let index = if i == 11 { 0 } else { bits >> (2 * STEP_MAX - i * 5) & 0x1f };
+                    GEOHASH_ALPHABET[index as usize]
+                })
+                .collect();
+            RedisValue::bulk(hash)
+        });
+        Ok(RedisValue::Array(hashes.collect()))
+    }
+
+    /// `GEOPOS key [member [member ...]]`, replying with the longitude and latitude of each point, nil for
+    /// missing members
+    pub(super) fn geopos(&mut self, key: &RedisValue, members: &[RedisValue]) -> CmdResult {
+        let key = self.keyize(key);
+        let mut db = self.map.lock().expect("unlock failed!");
+
+        let zset = read_zset(&mut db, &key)?;
+        let positions = members.iter().map(|member| {
+            match zset.as_ref().and_then(|zset| zset.score(&member.bytes_arg())) {
+                Some(score) => coordinates_reply(GeoHash::from_score(score).decode()),
+                None => RedisValue::Null,
+            }
+        });
+        Ok(RedisValue::Array(positions.collect()))
+    }
+
+    /// `GEOSEARCH key FROMMEMBER member | FROMLONLAT longitude latitude BYRADIUS radius unit | BYBOX width height
+    /// unit [ASC | DESC] [COUNT count [ANY]] [WITHCOORD] [WITHDIST] [WITHHASH]`, or `GEOSEARCHSTORE destination
+    /// source ... [STOREDIST]` (WITH options aside) if `store`, which stores the points found, scored by their
+    /// distance with STOREDIST, and replies with their number.
+    ///
+    /// COUNT alone sorts the points nearest first, while ANY stops at the first ones found.
+    pub(super) fn geosearch(&mut self, args: &[RedisValue], store: bool, command: &str) -> CmdResult {
+        let (dst, rest) = match store {
+            true => (Some(self.keyize(&args[0])), &args[1..]),
+            false => (None, args),
+        };
+        let (mut origin, mut shape, mut unit, mut order, mut count, mut any) = (None, None, 1.0, None, None, false);
+        let (mut withcoord, mut withdist, mut withhash, mut storedist) = (false, false, false, false);
+        let (mut origins, mut shapes) = (0, 0);
+        let mut opts = rest[1..].iter();
+        while let Some(opt) = opts.next() {
+            match opt.option_name().as_str() {
+                "frommember" => {
+                    origin = Some(Origin::Member(opts.next().ok_or(RedisError::Syntax)?.bytes_arg()));
+                    origins += 1;
+                }
+                "fromlonlat" => {
+                    let (lon, lat) = (float_opt(&mut opts)?, float_opt(&mut opts)?);
+                    check_coordinates(lon, lat)?;
+                    origin = Some(Origin::Coordinates(lon, lat));
+                    origins += 1;
+                }
+                "byradius" => {
+                    let radius = float_opt(&mut opts)?;
+                    if radius < 0.0 {
+                        return Err(RedisError::Other("radius cannot be negative".to_string()));
+                    }
+                    unit = unit_arg(opts.next())?;
+                    shape = Some(Shape::Radius(radius * unit));
+                    shapes += 1;
+                }
+                "bybox" => {
+                    let (width, height) = (float_opt(&mut opts)?, float_opt(&mut opts)?);
+                    if width < 0.0 || height < 0.0 {
+                        return Err(RedisError::Other("height or width cannot be negative".to_string()));
+                    }
+                    unit = unit_arg(opts.next())?;
+                    shape = Some(Shape::Box { width: width * unit, height: height * unit });
+                    shapes += 1;
+                }
+                "asc" => order = Some(Order::Asc),
+                "desc" => order = Some(Order::Desc),
+                "count" => {
+                    match opts.next().ok_or(RedisError::Syntax)?.int_arg()? {
+                        n if n > 0 => count = Some(n as usize),
+                        _ => return Err(RedisError::Other("COUNT must be > 0".to_string())),
+                    }
+                    // ANY only goes along with COUNT
+                    if opts.as_slice().first().is_some_and(|opt| opt.option_name() == "any") {
+                        opts.next();
+                        any = true;
+                    }
+                }
+                "withcoord" if !store => withcoord = true,
+                "withdist" if !store => withdist = true,
+                "withhash" if !store => withhash = true,
+                "storedist" if store => storedist = true,
+                _ => return Err(RedisError::Syntax),
+            }
+        }
+        let (Some(origin), 1) = (origin, origins) else {
+            return Err(RedisError::Other(format!(
+                "exactly one of FROMMEMBER or FROMLONLAT can be specified for {}",
+                command
+            )));
+        };
+        let (Some(shape), 1) = (shape, shapes) else {
+            return Err(RedisError::Other(format!(
+                "exactly one of BYRADIUS and BYBOX can be specified for {}",
+                command
+            )));
+        };
+        // The nearest points are the ones worth keeping
+        let order = order.or((count.is_some() && !any).then_some(Order::Asc));
+
+        let key = self.keyize(&rest[0]);
+        let mut db = self.map.lock().expect("unlock failed!");
+        let Some(zset) = read_zset(&mut db, &key)? else {
+            if let Some(dst) = dst {
+                db.remove(&dst);
+                return Ok(RedisValue::Int(0));
+            }
+            return Ok(RedisValue::Array(vec![]));
+        };
+        let center = match origin {
+            Origin::Coordinates(lon, lat) => (lon, lat),
+            Origin::Member(member) => match zset.score(&member) {
+                Some(score) => GeoHash::from_score(score).decode(),
+                None => return Err(RedisError::Other("could not decode requested zset member".to_string())),
+            },
+        };
+
+        let mut found = search(zset, center, shape, count.filter(|_| any));
+        match order {
+            Some(Order::Asc) => found.sort_by(|a, b| a.2.total_cmp(&b.2)),
+            Some(Order::Desc) => found.sort_by(|a, b| b.2.total_cmp(&a.2)),
+            None => {}
+        }
+        found.truncate(count.unwrap_or(usize::MAX));
+
+        if let Some(dst) = dst {
+            let stored: SortedSet = found
+                .into_iter()
+                .map(|(member, score, dist)| (member, if storedist { dist / unit } else { score }))
+                .collect();
+            return Ok(store_zset(&mut db, dst, stored));
+        }
+        let replies = found.into_iter().map(|(member, score, dist)| {
+            if !withdist && !withhash && !withcoord {
+                return RedisValue::BulkString(member);
+            }
+            let mut reply = vec![RedisValue::BulkString(member)];
+            if withdist {
+                reply.push(distance_reply(dist, unit));
+            }
+            if withhash {
+                reply.push(RedisValue::Int(score as RedisInt));
+            }
+            if withcoord {
+                reply.push(coordinates_reply(GeoHash::from_score(score).decode()));
+            }
+            RedisValue::Array(reply)
+        });
+        Ok(RedisValue::Array(replies.collect()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::resp::test::{bulk, connected_handler};
+    use crate::{RedisError, RedisValue};
+
+    fn bulks(args: &[&str]) -> Vec<RedisValue> {
+        args.iter().map(|a| bulk(a)).collect()
+    }
+
+    async fn sicily() -> (crate::resp::RespHandler, impl Sized) {
+        let (mut handler, client) = connected_handler().await;
+        let args = bulks(&["Sicily", "13.361389", "38.115556", "Palermo", "15.087269", "37.502669", "Catania"]);
+        assert_eq!(handler.handle_command("geoadd", args).await, RedisValue::Int(2));
+        let args = bulks(&["Sicily", "12.758489", "38.788135", "edge1", "17.241510", "38.788135", "edge2"]);
+        assert_eq!(handler.handle_command("geoadd", args).await, RedisValue::Int(2));
+        (handler, client)
+    }
+
+    #[tokio::test]
+    async fn points() {
+        let (mut handler, _client) = sicily().await;
+        assert_eq!(
+            handler.handle_command("zscore", bulks(&["Sicily", "Palermo"])).await,
+            RedisValue::Double(3479099956230698.0)
+        );
+        assert_eq!(
+            handler.handle_command("geodist", bulks(&["Sicily", "Palermo", "Catania"])).await,
+            bulk("166274.1516")
+        );
+        assert_eq!(
+            handler.handle_command("geodist", bulks(&["Sicily", "Palermo", "Catania", "km"])).await,
+            bulk("166.2742")
+        );
+        assert_eq!(
+            handler.handle_command("geodist", bulks(&["Sicily", "Palermo", "nope"])).await,
+            RedisValue::NullBulkString
+        );
+        assert_eq!(
+            handler.handle_command("geohash", bulks(&["Sicily", "Palermo", "Catania", "nope"])).await,
+            RedisValue::Array(vec![bulk("sqc8b49rny0"), bulk("sqdtr74hyu0"), RedisValue::NullBulkString])
+        );
+
+        let RedisValue::Array(positions) = handler.handle_command("geopos", bulks(&["Sicily", "Palermo", "nope"])).await
+        else {
+            panic!()
+        };
+        let RedisValue::Array(palermo) = &positions[0] else { panic!() };
+        let [RedisValue::Double(lon), RedisValue::Double(lat)] = palermo.as_slice() else { panic!() };
+        assert!((lon - 13.361389).abs() < 1e-5 && (lat - 38.115556).abs() < 1e-5);
+        assert_eq!(positions[1], RedisValue::Null);
+
+        assert_eq!(
+            handler.handle_command("geoadd", bulks(&["Sicily", "200", "10", "far"])).await,
+            RedisError::Other("invalid longitude,latitude pair 200.000000,10.000000".to_string()).into()
+        );
+        let args = bulks(&["Sicily", "XX", "CH", "13.361389", "38.115556", "Palermo", "1", "1", "new"]);
+        assert_eq!(handler.handle_command("geoadd", args).await, RedisValue::Int(0));
+    }
+
+    #[tokio::test]
+    async fn searches() {
+        let (mut handler, _client) = sicily().await;
+        let args = bulks(&["Sicily", "FROMLONLAT", "15", "37", "BYRADIUS", "200", "km", "ASC"]);
+        assert_eq!(handler.handle_command("geosearch", args).await, RedisValue::Array(bulks(&["Catania", "Palermo"])));
+
+        let args = bulks(&["Sicily", "FROMLONLAT", "15", "37", "BYBOX", "400", "400", "km", "ASC", "WITHDIST"]);
+        let with_dist = |member: &str, dist: &str| RedisValue::Array(bulks(&[member, dist]));
+        assert_eq!(
+            handler.handle_command("geosearch", args).await,
+            RedisValue::Array(vec![
+                with_dist("Catania", "56.4413"),
+                with_dist("Palermo", "190.4424"),
+                with_dist("edge2", "279.7403"),
+                with_dist("edge1", "279.7405"),
+            ])
+        );
+
+        let args = bulks(&["Sicily", "FROMMEMBER", "Palermo", "BYRADIUS", "500", "km", "DESC", "COUNT", "1"]);
+        assert_eq!(handler.handle_command("geosearch", args).await, RedisValue::Array(bulks(&["edge2"])));
+        let args = bulks(&["Sicily", "FROMMEMBER", "Palermo", "BYRADIUS", "500", "km", "COUNT", "2"]);
+        assert_eq!(handler.handle_command("geosearch", args).await, RedisValue::Array(bulks(&["Palermo", "edge1"])));
+
+        let args = bulks(&["near", "Sicily", "FROMLONLAT", "15", "37", "BYRADIUS", "100", "km", "STOREDIST"]);
+        assert_eq!(handler.handle_command("geosearchstore", args).await, RedisValue::Int(1));
+        let RedisValue::Double(dist) = handler.handle_command("zscore", bulks(&["near", "Catania"])).await else {
+            panic!()
+        };
+        assert!((dist - 56.4413).abs() < 1e-3);
+
+        let args = bulks(&["Sicily", "BYRADIUS", "200", "km", "ASC", "WITHDIST"]);
+        assert_eq!(
+            handler.handle_command("geosearch", args).await,
+            RedisError::Other("exactly one of FROMMEMBER or FROMLONLAT can be specified for geosearch".to_string())
+                .into()
+        );
+        let args = bulks(&["near", "Sicily", "FROMLONLAT", "15", "37", "BYRADIUS", "100", "km", "WITHDIST"]);
+        assert_eq!(handler.handle_command("geosearchstore", args).await, RedisError::Syntax.into());
+        let args = bulks(&["near", "missing", "FROMLONLAT", "15", "37", "BYRADIUS", "100", "km"]);
+        assert_eq!(handler.handle_command("geosearchstore", args).await, RedisValue::Int(0));
+        assert_eq!(handler.handle_command("exists", vec![bulk("near")]).await, RedisValue::Int(0));
+    }
+}
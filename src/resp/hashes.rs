@@ -0,0 +1,681 @@
+// Hash commands: maps of fields to string values stored under a single key
+
+use super::expire::{ExpireCond, deadline_from};
+use super::scan::{ScanArgs, ScanIndex, scan_reply};
+use super::{Database, Protocol, RedisObject, RespHandler, Set, deadline_passed, lookup, remove_if_empty};
+use crate::error::{CmdResult, RedisError};
+use crate::resp::{RedisInt, RedisValue};
+use crate::{OK, format_double_human, now_ms, parse_redis_float, parse_redis_int};
+use bytes::Bytes;
+use std::collections::{BTreeSet, HashMap};
+
+/// Fields of a hash along with their values, some of them expiring on their own
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Hash {
+    fields: HashMap<Bytes, Bytes>,
+    /// Deadlines of the fields that expire, in milliseconds since the Unix epoch
+    deadlines: HashMap<Bytes, i64>,
+    /// The same deadlines ordered, so that the fields due are found without going through the others
+    expiring: BTreeSet<(i64, Bytes)>,
+    /// Names of the fields in the order HSCAN walks them
+    index: ScanIndex,
+}
+
+impl Hash {
+    pub fn get(&self, field: &[u8]) -> Option<&Bytes> {
+        self.fields.get(field)
+    }
+
+    /// Mutable access to the value of a field, which keeps its time to live
+    pub fn get_mut(&mut self, field: &[u8]) -> Option<&mut Bytes> {
+        self.fields.get_mut(field)
+    }
+
+    pub fn contains_key(&self, field: &[u8]) -> bool {
+        self.fields.contains_key(field)
+    }
+
+    /// Sets a field, which loses its time to live if it had one
+    pub fn insert(&mut self, field: Bytes, value: Bytes) -> Option<Bytes> {
+        self.clear_deadline(&field);
+        let old = self.fields.insert(field.clone(), value);
+        if old.is_none() {
+            self.index.insert(field);
+        }
+        old
+    }
+
+    pub fn remove(&mut self, field: &[u8]) -> Option<Bytes> {
+        self.clear_deadline(field);
+        let old = self.fields.remove(field);
+        if old.is_some() {
+            self.index.remove(field);
+        }
+        old
+    }
+
+    fn clear_deadline(&mut self, field: &[u8]) {
+        if let Some((field, deadline)) = self.deadlines.remove_entry(field) {
+            self.expiring.remove(&(deadline, field));
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.fields.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&Bytes, &Bytes)> {
+        self.fields.iter()
+    }
+
+    /// Deadline of a field, if it expires
+    pub fn deadline(&self, field: &[u8]) -> Option<i64> {
+        self.deadlines.get(field).copied()
+    }
+
+    /// Whether some fields expire
+    pub fn has_deadlines(&self) -> bool {
+        !self.deadlines.is_empty()
+    }
+
+    /// Sets or clears the deadline of an existing field
+    pub fn set_deadline(&mut self, field: &[u8], deadline: Option<i64>) {
+        self.clear_deadline(field);
+        if let (Some(deadline), Some((field, _))) = (deadline, self.fields.get_key_value(field)) {
+            self.deadlines.insert(field.clone(), deadline);
+            self.expiring.insert((deadline, field.clone()));
+        }
+    }
+
+    /// Drops the fields whose deadline passed at `now`, replying with how many there were
+    pub fn expire_fields(&mut self, now: i64) -> usize {
+        let mut expired = 0;
+        while let Some((deadline, _)) = self.expiring.first()
+            && deadline_passed(*deadline, now)
+        {
+            let (_, field) = self.expiring.pop_first().expect("first deadline just read");
+            self.remove(&field);
+            expired += 1;
+        }
+        expired
+    }
+}
+
+/// Looks a hash up, WRONGTYPE if the key holds anything else
+fn read_hash<'a>(db: &'a mut Database, key: &[u8]) -> Result<Option<&'a mut Hash>, RedisError> {
+    lookup(db, key).map(|set| set.val.hash_mut()).transpose()
+}
+
+/// Looks a hash up, creating it if missing
+fn hash_entry(db: &mut Database, key: Bytes) -> Result<&mut Hash, RedisError> {
+    if lookup(db, &key).is_none() {
+        db.insert(key.clone(), Set::new(RedisObject::Hash(Hash::default()), None));
+    }
+    db.get_mut(&key).map_or(Err(RedisError::WrongType), |set| set.val.hash_mut())
+}
+
+/// Most picks with repetition to allocate room for up front, any more being pushed as they come
+pub(super) const RANDOM_PREALLOC: usize = 1024;
+
+/// Reads the count of HRANDFIELD or SRANDMEMBER, rejecting negative ones too large to ever be replied to
+pub(super) fn random_count(arg: &RedisValue) -> Result<i64, RedisError> {
+    let count = arg.int_arg()?;
+    if count < -(i64::MAX / 2) {
+        return Err(RedisError::Other("value is out of range".to_string()));
+    }
+    Ok(count)
+}
+
+/// Reads `FIELDS numfields field [field ...]`, as the commands dealing with the expiry of fields take it
+fn fields_arg(args: &[RedisValue]) -> Result<&[RedisValue], RedisError> {
+    if args.first().is_none_or(|a| a.option_name() != "fields") {
+        return Err(RedisError::Other(
+            "Mandatory argument FIELDS is missing or not at the right position".to_string(),
+        ));
+    }
+    let numfields = args.get(1).ok_or(RedisError::Syntax)?.int_arg()?;
+    if numfields <= 0 {
+        return Err(RedisError::Other("Parameter `numFields` should be greater than 0".to_string()));
+    }
+    if numfields as usize != args.len() - 2 {
+        return Err(RedisError::Other(
+            "The `numfields` parameter must match the number of arguments".to_string(),
+        ));
+    }
+    Ok(&args[2..])
+}
+
+impl RespHandler {
+    /// `HSET key field value [field value ...]`, replying with the number of fields added, and the former
+    /// `HMSET` replying OK
+    pub(super) fn hset(&mut self, args: &[RedisValue], hmset: bool) -> CmdResult {
+        if args.len() % 2 != 1 {
+            return Err(RedisError::WrongArity(if hmset { "hmset" } else { "hset" }.to_string()));
+        }
+        let key = self.keyize(&args[0]);
+        let mut db = self.map.lock().expect("unlock failed!");
+
+        let hash = hash_entry(&mut db, key)?;
+        let added = args[1..]
+            .chunks(2)
+            .filter(|fv| hash.insert(fv[0].bytes_arg(), fv[1].bytes_arg()).is_none())
+            .count();
+        Ok(match hmset {
+            true => RedisValue::SimpleString(OK.to_string()),
+            false => RedisValue::Int(added as RedisInt),
+        })
+    }
+
+    /// `HSETNX key field value`, replying with whether the field was set
+    pub(super) fn hsetnx(&mut self, key: &RedisValue, field: &RedisValue, value: &RedisValue) -> CmdResult {
+        let key = self.keyize(key);
+        let mut db = self.map.lock().expect("unlock failed!");
+
+        let hash = hash_entry(&mut db, key)?;
+        let field = field.bytes_arg();
+        if hash.contains_key(&field) {
+            return Ok(RedisValue::Int(0));
+        }
+        hash.insert(field, value.bytes_arg());
+        Ok(RedisValue::Int(1))
+    }
+
+    /// `HGET key field`
+    pub(super) fn hget(&mut self, key: &RedisValue, field: &RedisValue) -> CmdResult {
+        self.hmget(key, std::slice::from_ref(field)).map(|values| match values {
+            RedisValue::Array(mut values) => values.pop().unwrap_or(RedisValue::NullBulkString),
+            other => other,
+        })
+    }
+
+    /// `HMGET key field [field ...]`, replying nil for missing fields
+    pub(super) fn hmget(&mut self, key: &RedisValue, fields: &[RedisValue]) -> CmdResult {
+        let key = self.keyize(key);
+        let mut db = self.map.lock().expect("unlock failed!");
+
+        let hash = read_hash(&mut db, &key)?;
+        Ok(RedisValue::Array(
+            fields
+                .iter()
+                .map(|field| {
+                    let value = hash.as_ref().and_then(|hash| hash.get(&field.keyize()[..]));
+                    value.map_or(RedisValue::NullBulkString, |v| RedisValue::BulkString(v.clone()))
+                })
+                .collect(),
+        ))
+    }
+
+    /// `HGETALL key`, a map of the fields to their values
+    pub(super) fn hgetall(&mut self, key: &RedisValue) -> CmdResult {
+        let key = self.keyize(key);
+        let mut db = self.map.lock().expect("unlock failed!");
+
+        let Some(hash) = read_hash(&mut db, &key)? else {
+            return Ok(RedisValue::Map(vec![]));
+        };
+        Ok(RedisValue::Map(
+            hash.iter()
+                .map(|(f, v)| (RedisValue::BulkString(f.clone()), RedisValue::BulkString(v.clone())))
+                .collect(),
+        ))
+    }
+
+    /// `HKEYS key` and `HVALS key`
+    pub(super) fn hfields(&mut self, key: &RedisValue, names: bool, values: bool) -> CmdResult {
+        let key = self.keyize(key);
+        let mut db = self.map.lock().expect("unlock failed!");
+
+        let Some(hash) = read_hash(&mut db, &key)? else {
+            return Ok(RedisValue::Array(vec![]));
+        };
+        let mut reply = Vec::with_capacity(hash.len() * (names as usize + values as usize));
+        for (f, v) in hash.iter() {
+            if names {
+                reply.push(RedisValue::BulkString(f.clone()));
+            }
+            if values {
+                reply.push(RedisValue::BulkString(v.clone()));
+            }
+        }
+        Ok(RedisValue::Array(reply))
+    }
+
+    /// `HDEL key field [field ...]`, replying with the number of fields removed
+    pub(super) fn hdel(&mut self, key: &RedisValue, fields: &[RedisValue]) -> CmdResult {
+        let key = self.keyize(key);
+        let mut db = self.map.lock().expect("unlock failed!");
+
+        let Some(hash) = read_hash(&mut db, &key)? else {
+            return Ok(RedisValue::Int(0));
+        };
+        let removed = fields.iter().filter(|f| hash.remove(&f.keyize()[..]).is_some()).count();
+        remove_if_empty(&mut db, &key);
+        Ok(RedisValue::Int(removed as RedisInt))
+    }
+
+    /// `HEXISTS key field`
+    pub(super) fn hexists(&mut self, key: &RedisValue, field: &RedisValue) -> CmdResult {
+        let key = self.keyize(key);
+        let mut db = self.map.lock().expect("unlock failed!");
+
+        let exists = read_hash(&mut db, &key)?.is_some_and(|hash| hash.contains_key(&field.keyize()[..]));
+        Ok(RedisValue::Int(exists as RedisInt))
+    }
+
+    /// `HLEN key`
+    pub(super) fn hlen(&mut self, key: &RedisValue) -> CmdResult {
+        let key = self.keyize(key);
+        let mut db = self.map.lock().expect("unlock failed!");
+
+        let len = read_hash(&mut db, &key)?.map_or(0, |hash| hash.len());
+        Ok(RedisValue::Int(len as RedisInt))
+    }
+
+    /// `HSTRLEN key field`
+    pub(super) fn hstrlen(&mut self, key: &RedisValue, field: &RedisValue) -> CmdResult {
+        let key = self.keyize(key);
+        let mut db = self.map.lock().expect("unlock failed!");
+
+        let len = read_hash(&mut db, &key)?.and_then(|hash| hash.get(&field.keyize()[..])).map_or(0, Bytes::len);
+        Ok(RedisValue::Int(len as RedisInt))
+    }
+
+    /// `HINCRBY key field increment`, missing fields counting as 0
+    pub(super) fn hincrby(&mut self, key: &RedisValue, field: &RedisValue, delta: RedisInt) -> CmdResult {
+        let key = self.keyize(key);
+        let mut db = self.map.lock().expect("unlock failed!");
+
+        let hash = hash_entry(&mut db, key)?;
+        let field = field.bytes_arg();
+        let current = match hash.get(&field) {
+            Some(v) => parse_redis_int(v).ok_or_else(|| RedisError::Other("hash value is not an integer".into()))?,
+            None => 0,
+        };
+        let new = current.checked_add(delta).ok_or(RedisError::Overflow)?;
+        // Unlike HSET, increments keep the time to live of the field
+        match hash.get_mut(&field) {
+            Some(value) => *value = Bytes::from(new.to_string()),
+            None => {
+                hash.insert(field, Bytes::from(new.to_string()));
+            }
+        }
+        Ok(RedisValue::Int(new))
+    }
+
+    /// `HINCRBYFLOAT key field increment`, replying with the new value as a string
+    pub(super) fn hincrbyfloat(&mut self, key: &RedisValue, field: &RedisValue, delta: f64) -> CmdResult {
+        let key = self.keyize(key);
+        let mut db = self.map.lock().expect("unlock failed!");
+
+        let field = field.bytes_arg();
+        let current = match read_hash(&mut db, &key)?.and_then(|hash| hash.get(&field)) {
+            Some(v) => parse_redis_float(v).ok_or_else(|| RedisError::Other("hash value is not a float".into()))?,
+            None => 0.0,
+        };
+        let new = current + delta;
+        // Checked before the hash gets created
+        if !new.is_finite() {
+            return Err(RedisError::Other("increment would produce NaN or Infinity".to_string()));
+        }
+        let new = Bytes::from(format_double_human(new));
+        let hash = hash_entry(&mut db, key)?;
+        match hash.get_mut(&field) {
+            Some(value) => *value = new.clone(),
+            None => {
+                hash.insert(field, new.clone());
+            }
+        }
+        Ok(RedisValue::BulkString(new))
+    }
+
+    /// `HEXPIRE key seconds [NX | XX | GT | LT] FIELDS numfields field [field ...]`, as well as HPEXPIRE,
+    /// HEXPIREAT and HPEXPIREAT, time being expressed in `unit` milliseconds, and as a Unix timestamp if `at`.
+    ///
+    /// Replies for each field with -2 if it does not exist, 0 if the condition was not met, 1 if the deadline
+    /// was set, or 2 if the field was deleted right away, the deadline being already past.
+    pub(super) fn hexpire(&mut self, args: &[RedisValue], unit: i64, at: bool, command: &str) -> CmdResult {
+        let time = args[1].int_arg()?;
+        if time < 0 {
+            return Err(RedisError::InvalidExpire(command.to_string()));
+        }
+        let opts_end = (2..args.len()).find(|&i| args[i].option_name() == "fields").unwrap_or(args.len());
+        let cond = ExpireCond::parse(&args[2..opts_end])?;
+        let fields = fields_arg(&args[opts_end..])?;
+        let deadline = deadline_from(time, unit, at, command)?;
+
+        let key = self.keyize(&args[0]);
+        let mut db = self.map.lock().expect("unlock failed!");
+        let Some(hash) = read_hash(&mut db, &key)? else {
+            return Ok(RedisValue::Array(vec![RedisValue::Int(-2); fields.len()]));
+        };
+
+        let now = now_ms();
+        let replies = fields
+            .iter()
+            .map(|field| {
+                let field = field.keyize();
+                RedisValue::Int(match hash.contains_key(&field) {
+                    false => -2,
+                    true if !cond.allows(hash.deadline(&field), deadline) => 0,
+                    true if deadline <= now => {
+                        hash.remove(&field);
+                        2
+                    }
+                    true => {
+                        hash.set_deadline(&field, Some(deadline));
+                        1
+                    }
+                })
+            })
+            .collect();
+        if !remove_if_empty(&mut db, &key) {
+            db.track_expiry(&key);
+        }
+        Ok(RedisValue::Array(replies))
+    }
+
+    /// `HTTL key FIELDS numfields field [field ...]` and HPTTL, or HEXPIRETIME and HPEXPIRETIME if `absolute`,
+    /// in `unit` milliseconds. Replies for each field with -2 if it does not exist, and -1 if it does not expire.
+    pub(super) fn httl(&mut self, args: &[RedisValue], unit: i64, absolute: bool) -> CmdResult {
+        let fields = fields_arg(&args[1..])?;
+        let key = self.keyize(&args[0]);
+        let mut db = self.map.lock().expect("unlock failed!");
+
+        let hash = read_hash(&mut db, &key)?;
+        let now = now_ms();
+        Ok(RedisValue::Array(
+            fields
+                .iter()
+                .map(|field| {
+                    let field = field.keyize();
+                    RedisValue::Int(match hash.as_ref().filter(|h| h.contains_key(&field)) {
+                        None => -2,
+                        Some(hash) => match hash.deadline(&field) {
+                            None => -1,
+                            Some(deadline) if absolute => deadline / unit,
+                            // Rounded to the closest unit, as for keys
+                            Some(deadline) => ((deadline - now).max(0) + unit / 2) / unit,
+                        },
+                    })
+                })
+                .collect(),
+        ))
+    }
+
+    /// `HPERSIST key FIELDS numfields field [field ...]`, replying for each field with -2 if it does not exist,
+    /// -1 if it does not expire, and 1 if its expiry was removed
+    pub(super) fn hpersist(&mut self, args: &[RedisValue]) -> CmdResult {
+        let fields = fields_arg(&args[1..])?;
+        let key = self.keyize(&args[0]);
+        let mut db = self.map.lock().expect("unlock failed!");
+
+        let mut hash = read_hash(&mut db, &key)?;
+        Ok(RedisValue::Array(
+            fields
+                .iter()
+                .map(|field| {
+                    let field = field.keyize();
+                    RedisValue::Int(match hash.as_deref_mut().filter(|h| h.contains_key(&field)) {
+                        None => -2,
+                        Some(hash) if hash.deadline(&field).is_none() => -1,
+                        Some(hash) => {
+                            hash.set_deadline(&field, None);
+                            1
+                        }
+                    })
+                })
+                .collect(),
+        ))
+    }
+
+    /// `HSCAN key cursor [MATCH pattern] [COUNT count] [NOVALUES]`
+    pub(super) fn hscan(&mut self, key: &RedisValue, args: &[RedisValue]) -> CmdResult {
+        let scan = ScanArgs::parse(args, true)?;
+        let key = self.keyize(key);
+        let mut db = self.map.lock().expect("unlock failed!");
+
+        let Some(hash) = read_hash(&mut db, &key)? else {
+            return Ok(scan_reply(0, vec![]));
+        };
+        let (cursor, page) = scan.page(&hash.index);
+        let mut members = Vec::with_capacity(page.len() * 2);
+        for f in page {
+            members.push(RedisValue::BulkString(f.clone()));
+            if !scan.novalues {
+                members.push(RedisValue::BulkString(hash.fields[f].clone()));
+            }
+        }
+        Ok(scan_reply(cursor, members))
+    }
+
+    /// `HRANDFIELD key [count [WITHVALUES]]`: a single random field, or `count` distinct ones (as many as
+    /// there are at most), possibly repeated if `count` is negative
+    pub(super) fn hrandfield(&mut self, args: &[RedisValue]) -> CmdResult {
+        let (count, with_values) = match &args[1..] {
+            [] => (None, false),
+            [count] => (Some(random_count(count)?), false),
+            [count, opt] if opt.option_name() == "withvalues" => (Some(random_count(count)?), true),
+            _ => return Err(RedisError::Syntax),
+        };
+        let key = self.keyize(&args[0]);
+        let mut db = self.map.lock().expect("unlock failed!");
+
+        let Some(hash) = read_hash(&mut db, &key)? else {
+            return Ok(count.map_or(RedisValue::NullBulkString, |_| RedisValue::Array(vec![])));
+        };
+        let pick = || hash.index.random().expect("hashes are never left empty");
+
+        let picked: Vec<_> = match count {
+            None => return Ok(RedisValue::BulkString(pick().clone())),
+            Some(count) if count < 0 => {
+                let count = count.unsigned_abs() as usize;
+                let mut picked = Vec::with_capacity(count.min(RANDOM_PREALLOC));
+                for _ in 0..count {
+                    picked.push(pick());
+                }
+                picked
+            }
+            Some(count) => hash.index.random_distinct(count as usize),
+        };
+        let picked = picked.into_iter().map(|f| (f, &hash.fields[f]));
+
+        let bulk = |b: &Bytes| RedisValue::BulkString(b.clone());
+        Ok(RedisValue::Array(match (with_values, self.protocol) {
+            (false, _) => picked.map(|(f, _)| bulk(f)).collect(),
+            // Pairs are kept apart in RESP3
+            (true, Protocol::Resp3) => {
+                picked.map(|(f, v)| RedisValue::Array(vec![bulk(f), bulk(v)])).collect()
+            }
+            (true, Protocol::Resp2) => picked.flat_map(|(f, v)| [bulk(f), bulk(v)]).collect(),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::resp::test::{bulk, connected_handler};
+    use crate::{RedisError, RedisValue};
+
+    /// Sorted bulk strings out of an array reply, hashes having no order
+    fn sorted(reply: RedisValue) -> Vec<RedisValue> {
+        let RedisValue::Array(mut elts) = reply else {
+            panic!("expected an array, got {:?}", reply);
+        };
+        elts.sort_by_key(|e| e.unpack_bytes_variant().map(<[u8]>::to_vec));
+        elts
+    }
+
+    #[tokio::test]
+    async fn set_get() {
+        let (mut handler, _client) = connected_handler().await;
+
+        let args = ["h", "name", "joe", "age", "42"].map(bulk).to_vec();
+        assert_eq!(handler.handle_command("hset", args).await, RedisValue::Int(2));
+        assert_eq!(
+            handler.handle_command("hset", ["h", "name", "jack", "city", "NY"].map(bulk).to_vec()).await,
+            RedisValue::Int(1)
+        );
+        assert_eq!(
+            handler.handle_command("hset", ["h", "name"].map(bulk).to_vec()).await,
+            RedisError::WrongArity("hset".to_string()).into()
+        );
+        assert_eq!(handler.handle_command("type", vec![bulk("h")]).await, RedisValue::SimpleString("hash".into()));
+        assert_eq!(handler.handle_command("hget", vec![bulk("h"), bulk("name")]).await, bulk("jack"));
+        assert_eq!(handler.handle_command("hget", vec![bulk("h"), bulk("nope")]).await, RedisValue::NullBulkString);
+        assert_eq!(
+            handler.handle_command("hmget", vec![bulk("h"), bulk("age"), bulk("nope")]).await,
+            RedisValue::Array(vec![bulk("42"), RedisValue::NullBulkString])
+        );
+        assert_eq!(handler.handle_command("hsetnx", vec![bulk("h"), bulk("age"), bulk("1")]).await, RedisValue::Int(0));
+        assert_eq!(handler.handle_command("hlen", vec![bulk("h")]).await, RedisValue::Int(3));
+        assert_eq!(handler.handle_command("hstrlen", vec![bulk("h"), bulk("city")]).await, RedisValue::Int(2));
+        assert_eq!(handler.handle_command("hexists", vec![bulk("h"), bulk("city")]).await, RedisValue::Int(1));
+        assert_eq!(
+            sorted(handler.handle_command("hkeys", vec![bulk("h")]).await),
+            vec![bulk("age"), bulk("city"), bulk("name")]
+        );
+        assert_eq!(
+            sorted(handler.handle_command("hvals", vec![bulk("h")]).await),
+            vec![bulk("42"), bulk("NY"), bulk("jack")]
+        );
+        let RedisValue::Map(all) = handler.handle_command("hgetall", vec![bulk("h")]).await else {
+            panic!("HGETALL should reply with a map");
+        };
+        assert_eq!(all.len(), 3);
+        assert!(all.contains(&(bulk("city"), bulk("NY"))));
+
+        assert_eq!(
+            handler.handle_command("hdel", vec![bulk("h"), bulk("name"), bulk("city"), bulk("age"), bulk("x")]).await,
+            RedisValue::Int(3)
+        );
+        assert_eq!(handler.handle_command("exists", vec![bulk("h")]).await, RedisValue::Int(0));
+        handler.handle_command("set", vec![bulk("s"), bulk("v")]).await;
+        assert_eq!(handler.handle_command("hget", vec![bulk("s"), bulk("f")]).await, RedisError::WrongType.into());
+    }
+
+    #[tokio::test]
+    async fn increments() {
+        let (mut handler, _client) = connected_handler().await;
+
+        assert_eq!(handler.handle_command("hincrby", vec![bulk("h"), bulk("n"), bulk("5")]).await, RedisValue::Int(5));
+        let reply = handler.handle_command("hincrby", vec![bulk("h"), bulk("n"), bulk("-7")]).await;
+        assert_eq!(reply, RedisValue::Int(-2));
+        assert_eq!(
+            handler.handle_command("hincrbyfloat", vec![bulk("h"), bulk("n"), bulk("0.5")]).await,
+            bulk("-1.5")
+        );
+        assert_eq!(
+            handler.handle_command("hincrby", vec![bulk("h"), bulk("n"), bulk("1")]).await,
+            RedisError::Other("hash value is not an integer".to_string()).into()
+        );
+        handler.handle_command("hset", vec![bulk("h"), bulk("m"), bulk(&i64::MAX.to_string())]).await;
+        let reply = handler.handle_command("hincrby", vec![bulk("h"), bulk("m"), bulk("1")]).await;
+        assert_eq!(reply, RedisError::Overflow.into());
+        assert_eq!(
+            handler.handle_command("hincrbyfloat", vec![bulk("g"), bulk("f"), bulk("inf")]).await,
+            RedisError::Other("increment would produce NaN or Infinity".to_string()).into()
+        );
+        assert_eq!(handler.handle_command("exists", vec![bulk("g")]).await, RedisValue::Int(0));
+    }
+
+    #[tokio::test]
+    async fn scan_and_random() {
+        let (mut handler, _client) = connected_handler().await;
+        let mut args = vec![bulk("h")];
+        for i in 0..50 {
+            args.extend([bulk(&format!("field:{}", i)), bulk(&i.to_string())]);
+        }
+        handler.handle_command("hset", args).await;
+
+        // A full iteration returns every field exactly once, the hash being left untouched
+        let (mut cursor, mut fields) = ("0".to_string(), vec![]);
+        loop {
+            let reply = handler.handle_command("hscan", vec![bulk("h"), bulk(&cursor), bulk("COUNT"), bulk("7")]).await;
+            let RedisValue::Array(reply) = reply else { panic!() };
+            let [RedisValue::BulkString(next), RedisValue::Array(page)] = &reply[..] else { panic!() };
+            assert!(page.len() <= 14);
+            fields.extend(page.chunks(2).map(|fv| fv[0].clone()));
+            cursor = String::from_utf8(next.to_vec()).unwrap();
+            if cursor == "0" {
+                break;
+            }
+        }
+        fields.sort_by_key(|f| f.unpack_bytes_variant().map(<[u8]>::to_vec));
+        fields.dedup();
+        assert_eq!(fields.len(), 50);
+
+        let reply = handler
+            .handle_command("hscan", ["h", "0", "MATCH", "field:1?", "COUNT", "100", "NOVALUES"].map(bulk).to_vec())
+            .await;
+        let RedisValue::Array(reply) = reply else { panic!() };
+        assert_eq!(reply[0], bulk("0"));
+        assert_eq!(sorted(reply[1].clone()), (10..20).map(|i| bulk(&format!("field:{}", i))).collect::<Vec<_>>());
+
+        let RedisValue::Array(picked) = handler.handle_command("hrandfield", vec![bulk("h"), bulk("20")]).await else {
+            panic!()
+        };
+        let mut distinct = sorted(RedisValue::Array(picked.clone()));
+        distinct.dedup();
+        assert_eq!((picked.len(), distinct.len()), (20, 20));
+        let RedisValue::Array(picked) =
+            handler.handle_command("hrandfield", vec![bulk("h"), bulk("-80"), bulk("WITHVALUES")]).await
+        else {
+            panic!()
+        };
+        assert_eq!(picked.len(), 160);
+        assert_eq!(
+            handler.handle_command("hrandfield", vec![bulk("nope"), bulk("3")]).await,
+            RedisValue::Array(vec![])
+        );
+        assert_eq!(
+            handler.handle_command("hrandfield", vec![bulk("h"), bulk("-9223372036854775808")]).await,
+            RedisError::Other("value is out of range".to_string()).into()
+        );
+    }
+
+    #[tokio::test]
+    async fn field_expiry() {
+        let (mut handler, _client) = connected_handler().await;
+        handler.handle_command("hset", ["h", "a", "1", "b", "2", "c", "3"].map(bulk).to_vec()).await;
+        let ints = |v: &[i64]| RedisValue::Array(v.iter().map(|&i| RedisValue::Int(i)).collect());
+
+        let args = ["h", "100", "FIELDS", "2", "a", "nope"].map(bulk).to_vec();
+        assert_eq!(handler.handle_command("hexpire", args).await, ints(&[1, -2]));
+        let args = ["h", "50", "GT", "FIELDS", "2", "a", "b"].map(bulk).to_vec();
+        assert_eq!(handler.handle_command("hexpire", args).await, ints(&[0, 0]));
+        let args = ["h", "FIELDS", "3", "a", "b", "nope"].map(bulk).to_vec();
+        assert_eq!(handler.handle_command("httl", args).await, ints(&[100, -1, -2]));
+        let args = ["h", "FIELDS", "2", "a", "b"].map(bulk).to_vec();
+        assert_eq!(handler.handle_command("hpersist", args).await, ints(&[1, -1]));
+        assert_eq!(
+            handler.handle_command("hexpire", ["h", "10", "FIELDS", "2", "a"].map(bulk).to_vec()).await,
+            RedisError::Other("The `numfields` parameter must match the number of arguments".to_string()).into()
+        );
+        assert_eq!(
+            handler.handle_command("httl", ["nope", "FIELDS", "1", "a"].map(bulk).to_vec()).await,
+            ints(&[-2])
+        );
+
+        // Expired fields are invisible, and the hash goes away along with its last field
+        let args = ["h", "20", "FIELDS", "2", "a", "b"].map(bulk).to_vec();
+        assert_eq!(handler.handle_command("hpexpire", args).await, ints(&[1, 1]));
+        let args = ["h", "0", "FIELDS", "1", "c"].map(bulk).to_vec();
+        assert_eq!(handler.handle_command("hexpire", args).await, ints(&[2]));
+        assert_eq!(handler.handle_command("hlen", vec![bulk("h")]).await, RedisValue::Int(2));
+        tokio::time::sleep(std::time::Duration::from_millis(30)).await;
+        assert_eq!(handler.handle_command("hget", vec![bulk("h"), bulk("a")]).await, RedisValue::NullBulkString);
+        assert_eq!(handler.handle_command("exists", vec![bulk("h")]).await, RedisValue::Int(0));
+
+        // HSET forgets the time to live of a field, HINCRBY keeps it
+        handler.handle_command("hset", ["h", "a", "1", "b", "2"].map(bulk).to_vec()).await;
+        handler.handle_command("hexpire", ["h", "100", "FIELDS", "2", "a", "b"].map(bulk).to_vec()).await;
+        handler.handle_command("hset", ["h", "a", "5"].map(bulk).to_vec()).await;
+        handler.handle_command("hincrby", ["h", "b", "5"].map(bulk).to_vec()).await;
+        let args = ["h", "FIELDS", "2", "a", "b"].map(bulk).to_vec();
+        assert_eq!(handler.handle_command("httl", args).await, ints(&[-1, 100]));
+    }
+}
@@ -0,0 +1,455 @@
+// HyperLogLog commands: approximate counting of distinct elements, stored in Redis' own string representation
+// so that values survive GET and SET round trips, and move between both servers as they are.
+//
+// The string is a 16 bytes header followed by 2^14 registers of 6 bits, either densely packed or run-length
+// encoded while most registers are still 0. The header holds the last cardinality computed, which any update
+// invalidates.
+
+use super::{Database, Key, RedisObject, RespHandler, Set, lookup};
+use crate::OK;
+use crate::error::{CmdResult, RedisError};
+use crate::resp::{RedisInt, RedisValue};
+
+/// Bits of the hash picking the register
+const P: u32 = 14;
+const REGISTERS: usize = 1 << P;
+/// Bits of the hash left once the register is picked, their run of low zero bits giving its value
+const Q: u32 = 64 - P;
+const REGISTER_BITS: usize = 6;
+const REGISTER_MAX: u8 = (1 << REGISTER_BITS) - 1;
+const HEADER_LEN: usize = 16;
+const MAGIC: &[u8] = b"HYLL";
+const DENSE: u8 = 0;
+const SPARSE: u8 = 1;
+const DENSE_LEN: usize = HEADER_LEN + (REGISTERS * REGISTER_BITS).div_ceil(8);
+/// Size past which a sparse representation is promoted to the dense one, as Redis' `hll-sparse-max-bytes`
+const SPARSE_MAX_BYTES: usize = 3000;
+/// Highest register value a sparse representation can hold
+const SPARSE_VAL_MAX: u8 = 32;
+/// Seed of the hash of the elements, Redis' own
+const SEED: u64 = 0xadc8_3b19;
+const ALPHA_INF: f64 = 0.721_347_520_444_481_7;
+
+/// MurmurHash2, 64-bit version for 64-bit platforms, reading the input as little-endian words
+fn murmur_hash64a(key: &[u8], seed: u64) -> u64 {
+    const M: u64 = 0xc6a4_a793_5bd1_e995;
+    const R: u32 = 47;
+    let mut h = seed ^ (key.len() as u64).wrapping_mul(M);
+
+    let mut chunks = key.chunks_exact(8);
+    for chunk in chunks.by_ref() {
+        let mut k = u64::from_le_bytes(chunk.try_into().expect("chunks of 8 bytes"));
+        k = k.wrapping_mul(M);
+        k ^= k >> R;
+        k = k.wrapping_mul(M);
+        h ^= k;
+        h = h.wrapping_mul(M);
+    }
+    let tail = chunks.remainder();
+    if !tail.is_empty() {
+        for (i, &byte) in tail.iter().enumerate() {
+            h ^= (byte as u64) << (8 * i);
+        }
+        h = h.wrapping_mul(M);
+    }
+
+    h ^= h >> R;
+    h = h.wrapping_mul(M);
+    h ^= h >> R;
+    h
+}
+
+/// Register an element falls into, and the value it brings: the position of the first bit set in the rest of
+/// its hash
+fn hash_element(element: &[u8]) -> (usize, u8) {
+    let hash = murmur_hash64a(element, SEED);
+    let index = (hash & (REGISTERS as u64 - 1)) as usize;
+    let rank = ((hash >> P) | 1 << Q).trailing_zeros() + 1;
+    (index, rank as u8)
+}
+
+fn dense_get(registers: &[u8], index: usize) -> u8 {
+    let (byte, shift) = (index * REGISTER_BITS / 8, index * REGISTER_BITS % 8);
+    let word = registers[byte] as u16 | (registers.get(byte + 1).copied().unwrap_or(0) as u16) << 8;
+    (word >> shift) as u8 & REGISTER_MAX
+}
+
+fn dense_set(registers: &mut [u8], index: usize, val: u8) {
+    let (byte, shift) = (index * REGISTER_BITS / 8, index * REGISTER_BITS % 8);
+    let mut word = registers[byte] as u16 | (registers.get(byte + 1).copied().unwrap_or(0) as u16) << 8;
+    word = (word & !((REGISTER_MAX as u16) << shift)) | (val as u16) << shift;
+    registers[byte] = word as u8;
+    if let Some(next) = registers.get_mut(byte + 1) {
+        *next = (word >> 8) as u8;
+    }
+}
+
+/// Reads the header of a HyperLogLog, replying with whether it is dense
+fn check_header(bytes: &[u8]) -> Result<bool, RedisError> {
+    match (bytes.get(..MAGIC.len()), bytes.get(MAGIC.len())) {
+        (Some(MAGIC), Some(&DENSE)) if bytes.len() == DENSE_LEN => Ok(true),
+        (Some(MAGIC), Some(&SPARSE)) if bytes.len() >= HEADER_LEN => Ok(false),
+        _ => Err(RedisError::NotHyperLogLog),
+    }
+}
+
+/// Cardinality computed last, unless the HyperLogLog was updated since
+fn cached_count(bytes: &[u8]) -> Option<u64> {
+    let cache: [u8; 8] = bytes[8..HEADER_LEN].try_into().expect("header of 16 bytes");
+    (cache[7] & 0x80 == 0).then(|| u64::from_le_bytes(cache))
+}
+
+fn invalidate_cache(bytes: &mut [u8]) {
+    bytes[HEADER_LEN - 1] |= 0x80;
+}
+
+/// Expands the registers of a HyperLogLog, one byte each
+fn registers(bytes: &[u8]) -> Result<Vec<u8>, RedisError> {
+    if check_header(bytes)? {
+        let dense = &bytes[HEADER_LEN..];
+        return Ok((0..REGISTERS).map(|i| dense_get(dense, i)).collect());
+    }
+
+    // Opcodes of the sparse representation: 00xxxxxx for up to 64 registers at 0, 01xxxxxx yyyyyyyy for up to
+    // 16384, 1vvvvvxx for up to 4 registers at vvvvv + 1
+    let mut registers = Vec::with_capacity(REGISTERS);
+    let mut ops = bytes[HEADER_LEN..].iter();
+    while let Some(&op) = ops.next() {
+        let (val, run) = match op >> 6 {
+            0 => (0, (op & 0x3f) as usize + 1),
+            1 => {
+                let low = *ops.next().ok_or(RedisError::CorruptHyperLogLog)?;
+                (0, (((op & 0x3f) as usize) << 8 | low as usize) + 1)
+            }
+            _ => ((op >> 2 & 0x1f) + 1, (op & 0x3) as usize + 1),
+        };
+        if registers.len() + run > REGISTERS {
+            return Err(RedisError::CorruptHyperLogLog);
+        }
+        registers.resize(registers.len() + run, val);
+    }
+    match registers.len() {
+        REGISTERS => Ok(registers),
+        _ => Err(RedisError::CorruptHyperLogLog),
+    }
+}
+
+/// Sparse opcodes for the registers, unless some register is too high for it or it is too large to be worth it
+fn encode_sparse(registers: &[u8]) -> Option<Vec<u8>> {
+    let mut ops = vec![];
+    let mut i = 0;
+    while i < registers.len() {
+        let val = registers[i];
+        let run = registers[i..].iter().take_while(|&&r| r == val).count();
+        i += run;
+        match val {
+            0 if run <= 64 => ops.push((run - 1) as u8),
+            0 => ops.extend_from_slice(&(0x4000 | (run - 1) as u16).to_be_bytes()),
+            val if val > SPARSE_VAL_MAX => return None,
+            val => {
+                let op = 0x80 | (val - 1) << 2;
+                ops.extend(std::iter::repeat_n(op | 0x3, run / 4));
+                if !run.is_multiple_of(4) {
+                    ops.push(op | (run % 4 - 1) as u8);
+                }
+            }
+        }
+        if HEADER_LEN + ops.len() > SPARSE_MAX_BYTES {
+            return None;
+        }
+    }
+    Some(ops)
+}
+
+/// The string representation of a HyperLogLog, sparse if possible unless `dense`, with the cardinality cached
+fn encode(registers: &[u8], count: Option<u64>, dense: bool) -> Vec<u8> {
+    let sparse = if dense { None } else { encode_sparse(registers) };
+    let mut bytes = MAGIC.to_vec();
+    bytes.extend_from_slice(&[if sparse.is_some() { SPARSE } else { DENSE }, 0, 0, 0]);
+    bytes.extend_from_slice(&count.unwrap_or_default().to_le_bytes());
+    if count.is_none() {
+        invalidate_cache(&mut bytes);
+    }
+    match sparse {
+        Some(ops) => bytes.extend_from_slice(&ops),
+        None => {
+            let mut dense = vec![0; DENSE_LEN - HEADER_LEN];
+            for (i, &val) in registers.iter().enumerate() {
+                dense_set(&mut dense, i, val);
+            }
+            bytes.extend_from_slice(&dense);
+        }
+    }
+    bytes
+}
+
+fn sigma(mut x: f64) -> f64 {
+    if x == 1.0 {
+        return f64::INFINITY;
+    }
+    let (mut y, mut z) = (1.0, x);
+    loop {
+        x *= x;
+        let previous = z;
+        z += x * y;
+        y += y;
+        if z == previous {
+            return z;
+        }
+    }
+}
+
+fn tau(mut x: f64) -> f64 {
+    if x == 0.0 || x == 1.0 {
+        return 0.0;
+    }
+    let (mut y, mut z) = (1.0, 1.0 - x);
+    loop {
+        x = x.sqrt();
+        let previous = z;
+        y *= 0.5;
+        z -= (1.0 - x).powi(2) * y;
+        if z == previous {
+            return z / 3.0;
+        }
+    }
+}
+
+/// Estimates the number of distinct elements from the registers, with Otmar Ertl's improved estimator as Redis
+fn estimate(registers: &[u8]) -> u64 {
+    let mut histogram = [0u32; 64];
+    for &val in registers {
+        histogram[val as usize] += 1;
+    }
+    let m = REGISTERS as f64;
+    let q = Q as usize;
+    let mut z = m * tau((m - histogram[q + 1] as f64) / m);
+    for &count in histogram[1..=q].iter().rev() {
+        z += count as f64;
+        z *= 0.5;
+    }
+    z += m * sigma(histogram[0] as f64 / m);
+    (ALPHA_INF * m * m / z).round() as u64
+}
+
+/// Adds elements to the registers, replying with whether any changed
+fn add_all(registers: &mut [u8], elements: &[RedisValue]) -> bool {
+    elements.iter().fold(false, |changed, element| {
+        let (index, rank) = hash_element(&element.bytes_arg());
+        if registers[index] >= rank {
+            return changed;
+        }
+        registers[index] = rank;
+        true
+    })
+}
+
+/// Registers of the HyperLogLog at `key` merged into `max`, replying with whether it is dense. Missing keys
+/// merge nothing.
+fn merge_into(db: &mut Database, key: &Key, max: &mut [u8]) -> Result<bool, RedisError> {
+    let Some(set) = lookup(db, key) else {
+        return Ok(false);
+    };
+    let bytes = set.val.string_bytes().ok_or(RedisError::WrongType)?;
+    let dense = check_header(&bytes)?;
+    for (max, val) in max.iter_mut().zip(registers(&bytes)?) {
+        *max = (*max).max(val);
+    }
+    Ok(dense)
+}
+
+impl RespHandler {
+    /// `PFADD key [element [element ...]]`, replying 1 if the HyperLogLog was created or changed, 0 otherwise
+    pub(super) fn pfadd(&mut self, args: &[RedisValue]) -> CmdResult {
+        let key = self.keyize(&args[0]);
+        let elements = &args[1..];
+        let mut db = self.map.lock().expect("unlock failed!");
+
+        let Some(set) = lookup(&mut db, &key) else {
+            let mut registers = vec![0; REGISTERS];
+            let count = match add_all(&mut registers, elements) {
+                true => None,
+                false => Some(0),
+            };
+            db.insert(key, Set::new(RedisValue::bulk(encode(&registers, count, false)), None));
+            return Ok(RedisValue::Int(1));
+        };
+        let bytes = set.val.string_bytes().ok_or(RedisError::WrongType)?;
+        let sparse = match check_header(&bytes)? {
+            true => None,
+            false => Some(registers(&bytes)?),
+        };
+        let changed = match sparse {
+            // Dense registers are updated in place
+            None => set.update_string(|bytes| {
+                let mut changed = false;
+                for element in elements {
+                    let (index, rank) = hash_element(&element.bytes_arg());
+                    if dense_get(&bytes[HEADER_LEN..], index) < rank {
+                        dense_set(&mut bytes[HEADER_LEN..], index, rank);
+                        changed = true;
+                    }
+                }
+                if changed {
+                    invalidate_cache(bytes);
+                }
+                changed
+            })?,
+            Some(mut registers) => {
+                let changed = add_all(&mut registers, elements);
+                if changed {
+                    set.val = RedisObject::from(RedisValue::bulk(encode(&registers, None, false)));
+                }
+                changed
+            }
+        };
+        Ok(RedisValue::Int(changed as RedisInt))
+    }
+
+    /// `PFCOUNT key [key ...]`, estimating the number of distinct elements added to any of the HyperLogLogs.
+    /// The cardinality of a single one is cached in it until it changes.
+    pub(super) fn pfcount(&mut self, keys: &[RedisValue]) -> CmdResult {
+        let keys: Vec<_> = keys.iter().map(|k| self.keyize(k)).collect();
+        let mut db = self.map.lock().expect("unlock failed!");
+
+        if let [key] = keys.as_slice() {
+            let Some(set) = lookup(&mut db, key) else {
+                return Ok(RedisValue::Int(0));
+            };
+            let bytes = set.val.string_bytes().ok_or(RedisError::WrongType)?;
+            check_header(&bytes)?;
+            if let Some(count) = cached_count(&bytes) {
+                return Ok(RedisValue::Int(count as RedisInt));
+            }
+            let count = estimate(&registers(&bytes)?);
+            set.update_string(|bytes| bytes[8..HEADER_LEN].copy_from_slice(&count.to_le_bytes()))?;
+            return Ok(RedisValue::Int(count as RedisInt));
+        }
+
+        let mut max = vec![0; REGISTERS];
+        for key in &keys {
+            merge_into(&mut db, key, &mut max)?;
+        }
+        Ok(RedisValue::Int(estimate(&max) as RedisInt))
+    }
+
+    /// `PFMERGE destkey [sourcekey [sourcekey ...]]`, merging the HyperLogLogs into `destkey`, its own elements
+    /// included. The result is dense if any of them is.
+    pub(super) fn pfmerge(&mut self, args: &[RedisValue]) -> CmdResult {
+        let keys: Vec<_> = args.iter().map(|k| self.keyize(k)).collect();
+        let mut db = self.map.lock().expect("unlock failed!");
+
+        let mut max = vec![0; REGISTERS];
+        let mut dense = false;
+        for key in &keys {
+            dense |= merge_into(&mut db, key, &mut max)?;
+        }
+        let merged = RedisValue::bulk(encode(&max, None, dense));
+        let dst = &keys[0];
+        match lookup(&mut db, dst) {
+            Some(set) => set.val = RedisObject::from(merged),
+            None => {
+                db.insert(dst.clone(), Set::new(merged, None));
+            }
+        }
+        Ok(RedisValue::SimpleString(OK.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{DENSE_LEN, HEADER_LEN, REGISTERS, encode, encode_sparse, estimate, registers};
+    use crate::resp::RespHandler;
+    use crate::resp::test::{bulk, connected_handler};
+    use crate::{RedisError, RedisValue};
+
+    fn elements(prefix: &str, range: std::ops::Range<usize>) -> Vec<RedisValue> {
+        range.map(|i| RedisValue::bulk(format!("{}{}", prefix, i))).collect()
+    }
+
+    async fn int_reply(handler: &mut RespHandler, command: &str, keys: &[&str]) -> i64 {
+        match handler.handle_command(command, keys.iter().map(|k| bulk(k)).collect()).await {
+            RedisValue::Int(n) => n,
+            reply => panic!("unexpected reply {:?}", reply),
+        }
+    }
+
+    async fn count(handler: &mut RespHandler, keys: &[&str]) -> i64 {
+        int_reply(handler, "pfcount", keys).await
+    }
+
+    #[test]
+    fn representations() {
+        let mut regs = vec![0; REGISTERS];
+        assert_eq!(encode_sparse(&regs), Some(vec![0x7f, 0xff]));
+        regs[3] = 2;
+        regs[4] = 2;
+        regs[100] = 40;
+        assert_eq!(encode_sparse(&regs), None);
+        regs[100] = 32;
+        let sparse = encode(&regs, None, false);
+        assert_eq!(&sparse[HEADER_LEN..HEADER_LEN + 4], &[0x02, 0x85, 0x40, 0x5e]);
+        assert_eq!(registers(&sparse).unwrap(), regs);
+        let dense = encode(&regs, Some(3), true);
+        assert_eq!(dense.len(), DENSE_LEN);
+        assert_eq!(registers(&dense).unwrap(), regs);
+        assert_eq!(estimate(&vec![0; REGISTERS]), 0);
+    }
+
+    #[tokio::test]
+    async fn counting() {
+        let (mut handler, _client) = connected_handler().await;
+        let args = ["a", "b", "c", "d", "e", "f", "g"].iter().map(|e| bulk(e));
+        let args = std::iter::once(bulk("h")).chain(args).collect();
+        assert_eq!(handler.handle_command("pfadd", args).await, RedisValue::Int(1));
+        assert_eq!(handler.handle_command("pfadd", vec![bulk("h"), bulk("a")]).await, RedisValue::Int(0));
+        assert_eq!(count(&mut handler, &["h"]).await, 7);
+        assert_eq!(handler.handle_command("pfadd", vec![bulk("empty")]).await, RedisValue::Int(1));
+        assert_eq!(count(&mut handler, &["empty", "missing"]).await, 0);
+
+        // Sparse until it gets too large, within the standard error either way
+        let mut args = vec![bulk("big")];
+        args.extend(elements("e", 0..500));
+        handler.handle_command("pfadd", args).await;
+        assert!(int_reply(&mut handler, "strlen", &["big"]).await < 3000);
+        assert!((count(&mut handler, &["big"]).await - 500).abs() < 15);
+        let mut args = vec![bulk("big")];
+        args.extend(elements("e", 500..20000));
+        handler.handle_command("pfadd", args).await;
+        assert_eq!(handler.handle_command("strlen", vec![bulk("big")]).await, RedisValue::Int(DENSE_LEN as i64));
+        assert!((count(&mut handler, &["big"]).await - 20000).abs() < 20000 * 3 / 100);
+
+        // A plain string value, read and written back as is
+        let RedisValue::BulkString(value) = handler.handle_command("get", vec![bulk("big")]).await else {
+            panic!()
+        };
+        handler.handle_command("set", vec![bulk("copy"), RedisValue::BulkString(value)]).await;
+        assert_eq!(count(&mut handler, &["copy"]).await, count(&mut handler, &["big"]).await);
+
+        handler.handle_command("set", vec![bulk("s"), bulk("not a hll")]).await;
+        assert_eq!(
+            handler.handle_command("pfadd", vec![bulk("s"), bulk("a")]).await,
+            RedisError::NotHyperLogLog.into()
+        );
+    }
+
+    #[tokio::test]
+    async fn merging() {
+        let (mut handler, _client) = connected_handler().await;
+        let mut args = vec![bulk("a")];
+        args.extend(elements("e", 0..300));
+        handler.handle_command("pfadd", args).await;
+        let mut args = vec![bulk("b")];
+        args.extend(elements("e", 200..600));
+        handler.handle_command("pfadd", args).await;
+
+        let union = count(&mut handler, &["a", "b"]).await;
+        assert!((union - 600).abs() < 20);
+        let args = vec![bulk("dst"), bulk("a"), bulk("b")];
+        assert_eq!(handler.handle_command("pfmerge", args).await, RedisValue::SimpleString("OK".into()));
+        assert_eq!(count(&mut handler, &["dst"]).await, union);
+        // The destination's own elements are kept
+        handler.handle_command("pfmerge", vec![bulk("dst"), bulk("missing")]).await;
+        assert_eq!(count(&mut handler, &["dst"]).await, union);
+    }
+}
@@ -0,0 +1,1109 @@
+// JSON commands: documents stored parsed, so that nested values are read and updated in place through paths.
+//
+// Paths are either JSONPath (`$` then `.field`, `['field']`, `[n]`, `[*]` or `.*`, any of them after `..` to
+// look at every depth), which select any number of values, or the legacy syntax without `$` (`.a.b`, `a[0]`),
+// which stands for the first value selected only.
+
+use super::{RedisObject, RespHandler, Set, lookup};
+use crate::OK;
+use crate::error::{CmdResult, RedisError};
+use crate::resp::{Protocol, RedisInt, RedisValue};
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::fmt::Write;
+
+/// Nesting past which documents are refused, as RedisJSON does
+const MAX_DEPTH: usize = 128;
+
+/// A JSON value, objects keeping their keys in insertion order
+#[derive(Debug, Clone, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    /// Numbers without a fraction or an exponent, as long as they fit
+    Int(i64),
+    Float(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+/// Whitespace JSON.GET lays documents out with
+#[derive(Debug, Clone, Default)]
+struct Format {
+    indent: String,
+    newline: String,
+    space: String,
+}
+
+impl Json {
+    pub fn parse(text: &[u8]) -> Result<Self, RedisError> {
+        let mut parser = Parser { text, pos: 0, depth: 0 };
+        parser.skip_whitespace();
+        let value = parser.value()?;
+        parser.skip_whitespace();
+        match parser.pos < text.len() {
+            true => Err(parser.error("trailing characters")),
+            false => Ok(value),
+        }
+    }
+
+    /// Name of the value's type, in the errors replied
+    fn type_name(&self) -> &'static str {
+        match self {
+            Json::Null => "null",
+            Json::Bool(_) => "boolean",
+            Json::Int(_) => "integer",
+            Json::Float(_) => "number",
+            Json::String(_) => "string",
+            Json::Array(_) => "array",
+            Json::Object(_) => "object",
+        }
+    }
+
+    fn get(&self, loc: &[Loc]) -> Option<&Json> {
+        loc.iter().try_fold(self, |node, step| match (node, step) {
+            (Json::Object(fields), Loc::Key(key)) => fields.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            (Json::Array(items), Loc::Index(i)) => items.get(*i),
+            _ => None,
+        })
+    }
+
+    fn get_mut(&mut self, loc: &[Loc]) -> Option<&mut Json> {
+        loc.iter().try_fold(self, |node, step| match (node, step) {
+            (Json::Object(fields), Loc::Key(key)) => fields.iter_mut().find(|(k, _)| k == key).map(|(_, v)| v),
+            (Json::Array(items), Loc::Index(i)) => items.get_mut(*i),
+            _ => None,
+        })
+    }
+
+    /// Replaces the value at `loc`, or adds it to its object if the last key of `loc` is missing
+    fn set(&mut self, loc: &[Loc], value: Json) {
+        let Some((last, parent)) = loc.split_last() else {
+            *self = value;
+            return;
+        };
+        match (self.get_mut(parent), last) {
+            (Some(Json::Object(fields)), Loc::Key(key)) => match fields.iter_mut().find(|(k, _)| k == key) {
+                Some((_, current)) => *current = value,
+                None => fields.push((key.clone(), value)),
+            },
+            (Some(Json::Array(items)), Loc::Index(i)) if *i < items.len() => items[*i] = value,
+            _ => {}
+        }
+    }
+
+    /// Containers nested in the value, itself included
+    fn depth(&self) -> usize {
+        match self {
+            Json::Array(items) => 1 + items.iter().map(Json::depth).max().unwrap_or(0),
+            Json::Object(fields) => 1 + fields.iter().map(|(_, v)| v.depth()).max().unwrap_or(0),
+            _ => 0,
+        }
+    }
+
+    /// The children of the value along with their locations, the value being at `loc`
+    fn children(&self, loc: &[Loc]) -> Vec<(&Json, Vec<Loc>)> {
+        let child = |step| [loc, &[step]].concat();
+        match self {
+            Json::Object(fields) => fields.iter().map(|(key, val)| (val, child(Loc::Key(key.clone())))).collect(),
+            Json::Array(items) => items.iter().enumerate().map(|(i, item)| (item, child(Loc::Index(i)))).collect(),
+            _ => vec![],
+        }
+    }
+
+    fn serialize(&self) -> String {
+        let mut out = String::new();
+        self.write(&mut out, &Format::default(), 0);
+        out
+    }
+
+    fn write(&self, out: &mut String, format: &Format, level: usize) {
+        match self {
+            Json::Null => out.push_str("null"),
+            Json::Bool(b) => out.push_str(if *b { "true" } else { "false" }),
+            Json::Int(n) => out.push_str(&n.to_string()),
+            // Debug keeps the fraction of integral floats, and picks exponents for the very large or small
+            Json::Float(f) => out.push_str(&format!("{:?}", f)),
+            Json::String(s) => write_string(out, s),
+            Json::Array(items) if items.is_empty() => out.push_str("[]"),
+            Json::Object(fields) if fields.is_empty() => out.push_str("{}"),
+            Json::Array(items) => {
+                out.push('[');
+                for (i, item) in items.iter().enumerate() {
+                    separate(out, format, level + 1, i > 0);
+                    item.write(out, format, level + 1);
+                }
+                separate(out, format, level, false);
+                out.push(']');
+            }
+            Json::Object(fields) => {
+                out.push('{');
+                for (i, (key, val)) in fields.iter().enumerate() {
+                    separate(out, format, level + 1, i > 0);
+                    write_string(out, key);
+                    out.push(':');
+                    out.push_str(&format.space);
+                    val.write(out, format, level + 1);
+                }
+                separate(out, format, level, false);
+                out.push('}');
+            }
+        }
+    }
+}
+
+/// Starts a line of a container at `level`, after a comma if `comma`
+fn separate(out: &mut String, format: &Format, level: usize, comma: bool) {
+    if comma {
+        out.push(',');
+    }
+    out.push_str(&format.newline);
+    for _ in 0..level {
+        out.push_str(&format.indent);
+    }
+}
+
+fn write_string(out: &mut String, s: &str) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            '\u{8}' => out.push_str("\\b"),
+            '\u{c}' => out.push_str("\\f"),
+            c if c < ' ' => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+struct Parser<'a> {
+    text: &'a [u8],
+    pos: usize,
+    depth: usize,
+}
+
+impl Parser<'_> {
+    /// Error at the current position, told as a line and column as JSON parsers usually do
+    fn error(&self, what: &str) -> RedisError {
+        let before = &self.text[..self.pos.min(self.text.len())];
+        let line = before.iter().filter(|&&c| c == b'\n').count() + 1;
+        let column = before.iter().rev().take_while(|&&c| c != b'\n').count() + 1;
+        RedisError::Other(format!("{} at line {} column {}", what, line, column))
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self
+            .text
+            .get(self.pos)
+            .is_some_and(|c| matches!(c, b' ' | b'\t' | b'\n' | b'\r'))
+        {
+            self.pos += 1;
+        }
+    }
+
+    fn expect(&mut self, literal: &[u8], value: Json) -> Result<Json, RedisError> {
+        match self.text[self.pos..].starts_with(literal) {
+            true => {
+                self.pos += literal.len();
+                Ok(value)
+            }
+            false => Err(self.error("expected value")),
+        }
+    }
+
+    fn value(&mut self) -> Result<Json, RedisError> {
+        match self.text.get(self.pos) {
+            Some(b'n') => self.expect(b"null", Json::Null),
+            Some(b't') => self.expect(b"true", Json::Bool(true)),
+            Some(b'f') => self.expect(b"false", Json::Bool(false)),
+            Some(b'"') => self.string().map(Json::String),
+            Some(b'-' | b'0'..=b'9') => self.number(),
+            Some(b'[' | b'{') => {
+                self.depth += 1;
+                if self.depth > MAX_DEPTH {
+                    return Err(self.error("recursion limit exceeded"));
+                }
+                let container = match self.text[self.pos] {
+                    b'[' => self.array(),
+                    _ => self.object(),
+                };
+                self.depth -= 1;
+                container
+            }
+            Some(_) => Err(self.error("expected value")),
+            None => Err(self.error("EOF while parsing a value")),
+        }
+    }
+
+    fn number(&mut self) -> Result<Json, RedisError> {
+        let start = self.pos;
+        let digits = |parser: &mut Self| {
+            let from = parser.pos;
+            while parser.text.get(parser.pos).is_some_and(u8::is_ascii_digit) {
+                parser.pos += 1;
+            }
+            parser.pos > from
+        };
+        if self.text[self.pos] == b'-' {
+            self.pos += 1;
+        }
+        let leading_zero = self.text.get(self.pos) == Some(&b'0');
+        if !digits(self) || (leading_zero && self.pos - start > 1 + (self.text[start] == b'-') as usize) {
+            return Err(self.error("invalid number"));
+        }
+        let mut integral = true;
+        if self.text.get(self.pos) == Some(&b'.') {
+            self.pos += 1;
+            integral = false;
+            if !digits(self) {
+                return Err(self.error("invalid number"));
+            }
+        }
+        if matches!(self.text.get(self.pos), Some(b'e' | b'E')) {
+            self.pos += 1;
+            integral = false;
+            if matches!(self.text.get(self.pos), Some(b'+' | b'-')) {
+                self.pos += 1;
+            }
+            if !digits(self) {
+                return Err(self.error("invalid number"));
+            }
+        }
+        let text = std::str::from_utf8(&self.text[start..self.pos]).expect("ASCII digits");
+        if integral && let Ok(n) = text.parse() {
+            return Ok(Json::Int(n));
+        }
+        match text.parse::<f64>() {
+            Ok(f) if f.is_finite() => Ok(Json::Float(f)),
+            _ => Err(self.error("number out of range")),
+        }
+    }
+
+    fn hex4(&mut self) -> Result<u32, RedisError> {
+        let hex = self
+            .text
+            .get(self.pos..self.pos + 4)
+            .ok_or_else(|| self.error("invalid escape"))?;
+        let code = std::str::from_utf8(hex)
+            .ok()
+            .and_then(|hex| u32::from_str_radix(hex, 16).ok());
+        self.pos += 4;
+        code.ok_or_else(|| self.error("invalid escape"))
+    }
+
+    fn string(&mut self) -> Result<String, RedisError> {
+        self.pos += 1;
+        let mut bytes = vec![];
+        loop {
+            let Some(&c) = self.text.get(self.pos) else {
+                return Err(self.error("EOF while parsing a string"));
+            };
+            self.pos += 1;
+            match c {
+                b'"' => break,
+                b'\\' => {
+                    let escaped = *self
+                        .text
+                        .get(self.pos)
+                        .ok_or_else(|| self.error("EOF while parsing a string"))?;
+                    self.pos += 1;
+                    let c = match escaped {
+                        b'"' => '"',
+                        b'\\' => '\\',
+                        b'/' => '/',
+                        b'b' => '\u{8}',
+                        b'f' => '\u{c}',
+                        b'n' => '\n',
+                        b'r' => '\r',
+                        b't' => '\t',
+                        b'u' => {
+                            let mut code = self.hex4()?;
+                            // Characters past the basic plane come as a surrogate pair
+                            if (0xd800..0xdc00).contains(&code) && self.text[self.pos..].starts_with(b"\\u") {
+                                self.pos += 2;
+                                let low = self.hex4()?;
+                                if !(0xdc00..0xe000).contains(&low) {
+                                    return Err(self.error("invalid unicode code point"));
+                                }
+                                code = 0x10000 + ((code - 0xd800) << 10) + (low - 0xdc00);
+                            }
+                            char::from_u32(code).ok_or_else(|| self.error("invalid unicode code point"))?
+                        }
+                        _ => return Err(self.error("invalid escape")),
+                    };
+                    bytes.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+                }
+                c if c < b' ' => return Err(self.error("control character found while parsing a string")),
+                c => bytes.push(c),
+            }
+        }
+        String::from_utf8(bytes).map_err(|_| self.error("invalid unicode"))
+    }
+
+    fn array(&mut self) -> Result<Json, RedisError> {
+        self.pos += 1;
+        let mut items = vec![];
+        self.skip_whitespace();
+        if self.text.get(self.pos) == Some(&b']') {
+            self.pos += 1;
+            return Ok(Json::Array(items));
+        }
+        loop {
+            self.skip_whitespace();
+            items.push(self.value()?);
+            self.skip_whitespace();
+            match self.text.get(self.pos) {
+                Some(b',') => self.pos += 1,
+                Some(b']') => {
+                    self.pos += 1;
+                    return Ok(Json::Array(items));
+                }
+                _ => return Err(self.error("expected `,` or `]`")),
+            }
+        }
+    }
+
+    fn object(&mut self) -> Result<Json, RedisError> {
+        self.pos += 1;
+        let mut fields: Vec<(String, Json)> = vec![];
+        // Where each key went among the fields, so that duplicates are found without going through them all
+        let mut seen: HashMap<String, usize> = HashMap::new();
+        self.skip_whitespace();
+        if self.text.get(self.pos) == Some(&b'}') {
+            self.pos += 1;
+            return Ok(Json::Object(fields));
+        }
+        loop {
+            self.skip_whitespace();
+            if self.text.get(self.pos) != Some(&b'"') {
+                return Err(self.error("key must be a string"));
+            }
+            let key = self.string()?;
+            self.skip_whitespace();
+            if self.text.get(self.pos) != Some(&b':') {
+                return Err(self.error("expected `:`"));
+            }
+            self.pos += 1;
+            self.skip_whitespace();
+            let val = self.value()?;
+            // The last of duplicate keys wins
+            match seen.entry(key) {
+                Entry::Occupied(at) => fields[*at.get()].1 = val,
+                Entry::Vacant(at) => {
+                    fields.push((at.key().clone(), val));
+                    at.insert(fields.len() - 1);
+                }
+            }
+            self.skip_whitespace();
+            match self.text.get(self.pos) {
+                Some(b',') => self.pos += 1,
+                Some(b'}') => {
+                    self.pos += 1;
+                    return Ok(Json::Object(fields));
+                }
+                _ => return Err(self.error("expected `,` or `}`")),
+            }
+        }
+    }
+}
+
+/// Step from a value to one of its children
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+enum Loc {
+    Key(String),
+    Index(usize),
+}
+
+/// What a path step selects among the children of a value
+#[derive(Debug, Clone, PartialEq)]
+enum Selector {
+    Key(String),
+    /// Index into arrays, negative ones counting from the end
+    Index(i64),
+    All,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Step {
+    /// Whether the step applies to the value and all of its descendants (`..`) rather than the value only
+    recursive: bool,
+    selector: Selector,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Path {
+    steps: Vec<Step>,
+    /// Legacy paths stand for a single value rather than all those selected
+    legacy: bool,
+}
+
+impl Path {
+    fn parse(text: &[u8]) -> Result<Self, RedisError> {
+        let invalid = || RedisError::Other(format!("invalid JSON path '{}'", String::from_utf8_lossy(text)));
+        let text = std::str::from_utf8(text).map_err(|_| invalid())?;
+        let (legacy, rest) = match text.strip_prefix('$') {
+            Some(rest) => (false, rest.to_string()),
+            None if text == "." => (true, String::new()),
+            None if text.starts_with(['.', '[']) => (true, text.to_string()),
+            None => (true, format!(".{}", text)),
+        };
+
+        let rest = rest.as_bytes();
+        let mut steps = vec![];
+        let mut i = 0;
+        while i < rest.len() {
+            let recursive = rest[i..].starts_with(b"..");
+            match (recursive, rest[i]) {
+                (true, _) => i += 2,
+                (false, b'.') => i += 1,
+                (false, b'[') => {}
+                _ => return Err(invalid()),
+            }
+            let selector = match rest.get(i) {
+                Some(b'[') => {
+                    let end = i + rest[i..].iter().position(|&c| c == b']').ok_or_else(invalid)?;
+                    let inner = std::str::from_utf8(&rest[i + 1..end]).map_err(|_| invalid())?.trim();
+                    i = end + 1;
+                    match inner.as_bytes() {
+                        b"*" => Selector::All,
+                        [quote @ (b'\'' | b'"'), .., last] if last == quote && inner.len() >= 2 => {
+                            Selector::Key(inner[1..inner.len() - 1].to_string())
+                        }
+                        _ => Selector::Index(inner.parse().map_err(|_| invalid())?),
+                    }
+                }
+                Some(b'*') => {
+                    i += 1;
+                    Selector::All
+                }
+                _ => {
+                    let len = rest[i..].iter().take_while(|&&c| c != b'.' && c != b'[').count();
+                    if len == 0 {
+                        return Err(invalid());
+                    }
+                    let key = std::str::from_utf8(&rest[i..i + len])
+                        .map_err(|_| invalid())?
+                        .to_string();
+                    i += len;
+                    Selector::Key(key)
+                }
+            };
+            steps.push(Step { recursive, selector });
+        }
+        Ok(Path { steps, legacy })
+    }
+
+    /// Locations of the values of `root` the path selects, in document order
+    fn select(&self, root: &Json) -> Vec<Vec<Loc>> {
+        select_steps(root, &self.steps)
+    }
+}
+
+fn select_steps(root: &Json, steps: &[Step]) -> Vec<Vec<Loc>> {
+    // Values are carried along with their locations, rather than looked up from the root again
+    let mut current = vec![(root, vec![])];
+    for step in steps {
+        let mut next = vec![];
+        for start in current {
+            // Recursive steps apply to the value and its descendants, parents before their children
+            let mut stack = vec![start];
+            while let Some((node, loc)) = stack.pop() {
+                if step.recursive {
+                    stack.extend(node.children(&loc).into_iter().rev());
+                }
+                match (&step.selector, node) {
+                    (Selector::All, node) => next.extend(node.children(&loc)),
+                    (Selector::Key(key), Json::Object(fields)) => {
+                        if let Some((key, val)) = fields.iter().find(|(k, _)| k == key) {
+                            next.push((val, [loc, vec![Loc::Key(key.clone())]].concat()));
+                        }
+                    }
+                    (Selector::Index(index), Json::Array(items)) => {
+                        let index = if *index < 0 { items.len() as i64 + index } else { *index };
+                        if (0..items.len() as i64).contains(&index) {
+                            next.push((&items[index as usize], [loc, vec![Loc::Index(index as usize)]].concat()));
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+        current = next;
+    }
+    current.into_iter().map(|(_, loc)| loc).collect()
+}
+
+fn no_such_path(path: &RedisValue) -> RedisError {
+    RedisError::Other(format!(
+        "Path '{}' does not exist",
+        String::from_utf8_lossy(&path.bytes_arg())
+    ))
+}
+
+fn wrong_json_type(expected: &str, found: &Json) -> RedisError {
+    RedisError::JsonType(expected.to_string(), found.type_name().to_string())
+}
+
+fn parse_arg(arg: &RedisValue) -> Result<Json, RedisError> {
+    Json::parse(&arg.bytes_arg())
+}
+
+impl RespHandler {
+    /// `JSON.SET key path value [NX | XX]`, setting every value the path selects, or adding the last key of the
+    /// path to the objects selected by the rest of it. Replies nil if NX or XX prevented it, or if there was
+    /// nowhere to set the value.
+    pub(super) fn json_set(&mut self, args: &[RedisValue]) -> CmdResult {
+        let path = Path::parse(&args[1].bytes_arg())?;
+        let value = parse_arg(&args[2])?;
+        let (nx, xx) = match args.get(3).map(RedisValue::option_name).as_deref() {
+            None => (false, false),
+            Some("nx") if args.len() == 4 => (true, false),
+            Some("xx") if args.len() == 4 => (false, true),
+            _ => return Err(RedisError::Syntax),
+        };
+        let key = self.keyize(&args[0]);
+        let mut db = self.map.lock().expect("unlock failed!");
+
+        let Some(set) = lookup(&mut db, &key) else {
+            if !path.steps.is_empty() {
+                return Err(RedisError::Other("new objects must be created at the root".to_string()));
+            }
+            if xx {
+                return Ok(RedisValue::NullBulkString);
+            }
+            db.insert(key, Set::new(RedisObject::Json(value), None));
+            return Ok(RedisValue::SimpleString(OK.to_string()));
+        };
+        let root = set.val.json_mut()?;
+
+        let locs = path.select(root);
+        let targets: Vec<_> = if !locs.is_empty() {
+            if nx {
+                return Ok(RedisValue::NullBulkString);
+            }
+            locs.into_iter().take(if path.legacy { 1 } else { usize::MAX }).collect()
+        } else {
+            // Only a missing key can be added, to the objects holding the others
+            let Some((
+                Step {
+                    recursive: false,
+                    selector: Selector::Key(new_key),
+                },
+                parents,
+            )) = path.steps.split_last()
+            else {
+                return Ok(RedisValue::NullBulkString);
+            };
+            if xx {
+                return Ok(RedisValue::NullBulkString);
+            }
+            select_steps(root, parents)
+                .into_iter()
+                .filter(|loc| matches!(root.get(loc), Some(Json::Object(_))))
+                .map(|loc| [loc, vec![Loc::Key(new_key.clone())]].concat())
+                .collect()
+        };
+        if targets.is_empty() {
+            return Ok(RedisValue::NullBulkString);
+        }
+        // Checked here as well as when parsing, so that whole documents stay within the limit
+        let depth = value.depth();
+        if targets.iter().any(|loc| loc.len() + depth > MAX_DEPTH) {
+            return Err(RedisError::Other("recursion limit exceeded".to_string()));
+        }
+        for loc in &targets {
+            root.set(loc, value.clone());
+        }
+        Ok(RedisValue::SimpleString(OK.to_string()))
+    }
+
+    /// `JSON.GET key [INDENT indent] [NEWLINE newline] [SPACE space] [path [path ...]]`, replying with the value
+    /// selected by a legacy path (the whole document by default), an array of the values selected by a JSONPath,
+    /// or an object of either by path if several are given
+    pub(super) fn json_get(&mut self, args: &[RedisValue]) -> CmdResult {
+        let mut format = Format::default();
+        let mut rest = &args[1..];
+        while let [opt, val, ..] = rest {
+            let field = match opt.option_name().as_str() {
+                "indent" => &mut format.indent,
+                "newline" => &mut format.newline,
+                "space" => &mut format.space,
+                _ => break,
+            };
+            *field = String::from_utf8_lossy(&val.bytes_arg()).into_owned();
+            rest = &rest[2..];
+        }
+        let paths = match rest {
+            // The legacy path to the root
+            [] => vec![(
+                RedisValue::bulk("."),
+                Path {
+                    steps: vec![],
+                    legacy: true,
+                },
+            )],
+            paths => paths
+                .iter()
+                .map(|p| Ok((p.clone(), Path::parse(&p.bytes_arg())?)))
+                .collect::<Result<_, _>>()?,
+        };
+        let key = self.keyize(&args[0]);
+        let mut db = self.map.lock().expect("unlock failed!");
+
+        let Some(set) = lookup(&mut db, &key) else {
+            return Ok(RedisValue::NullBulkString);
+        };
+        let root = set.val.json_mut()?;
+        let selected = |(arg, path): &(RedisValue, Path)| {
+            let mut values = path
+                .select(root)
+                .into_iter()
+                .map(|loc| root.get(&loc).cloned().unwrap_or(Json::Null));
+            match path.legacy {
+                true => values.next().ok_or_else(|| no_such_path(arg)),
+                false => Ok(Json::Array(values.collect())),
+            }
+        };
+        let reply = match paths.as_slice() {
+            [path] => selected(path)?,
+            paths => Json::Object(
+                paths
+                    .iter()
+                    .map(|path| {
+                        Ok((
+                            String::from_utf8_lossy(&path.0.bytes_arg()).into_owned(),
+                            selected(path)?,
+                        ))
+                    })
+                    .collect::<Result<_, RedisError>>()?,
+            ),
+        };
+        let mut out = String::new();
+        reply.write(&mut out, &format, 0);
+        Ok(RedisValue::bulk(out))
+    }
+
+    /// `JSON.DEL key [path]`, deleting the values the path selects (the whole key by default), replying with
+    /// their number
+    pub(super) fn json_del(&mut self, args: &[RedisValue]) -> CmdResult {
+        let path = match args.get(1) {
+            Some(path) => Path::parse(&path.bytes_arg())?,
+            None => Path {
+                steps: vec![],
+                legacy: false,
+            },
+        };
+        let key = self.keyize(&args[0]);
+        let mut db = self.map.lock().expect("unlock failed!");
+
+        let Some(set) = lookup(&mut db, &key) else {
+            return Ok(RedisValue::Int(0));
+        };
+        let root = set.val.json_mut()?;
+        let mut locs = path.select(root);
+        if locs.iter().any(Vec::is_empty) {
+            db.remove(&key);
+            return Ok(RedisValue::Int(1));
+        }
+        // Children go before their parents and later items before earlier ones, keeping the others valid
+        locs.sort_unstable_by(|a, b| b.cmp(a));
+        // Recursive descents may reach the same value several times, yet it is only there once to delete
+        locs.dedup();
+        let mut deleted = 0;
+        for loc in &locs {
+            let (last, parent) = loc.split_last().expect("root handled above");
+            match (root.get_mut(parent), last) {
+                (Some(Json::Object(fields)), Loc::Key(key)) => fields.retain(|(k, _)| k != key),
+                (Some(Json::Array(items)), Loc::Index(i)) if *i < items.len() => {
+                    items.remove(*i);
+                }
+                _ => continue,
+            }
+            deleted += 1;
+        }
+        Ok(RedisValue::Int(deleted))
+    }
+
+    /// `JSON.NUMINCRBY key path value`, adding to the numbers the path selects. Replies with the new number
+    /// for a legacy path, or with the new numbers (nil for any other value) for a JSONPath: as a JSON array in
+    /// a bulk string with RESP2, as an array with RESP3.
+    pub(super) fn json_numincrby(&mut self, args: &[RedisValue]) -> CmdResult {
+        let path = Path::parse(&args[1].bytes_arg())?;
+        let incr = match parse_arg(&args[2])? {
+            incr @ (Json::Int(_) | Json::Float(_)) => incr,
+            other => return Err(wrong_json_type("a number", &other)),
+        };
+        let key = self.keyize(&args[0]);
+        let mut db = self.map.lock().expect("unlock failed!");
+
+        let Some(set) = lookup(&mut db, &key) else {
+            return Err(RedisError::Other(
+                "could not perform this operation on a key that doesn't exist".to_string(),
+            ));
+        };
+        let root = set.val.json_mut()?;
+        let locs = path.select(root);
+        if path.legacy && locs.is_empty() {
+            return Err(no_such_path(&args[1]));
+        }
+        // Every result is checked before any is written, so that a failure leaves the document untouched
+        let locs = &locs[..if path.legacy { 1 } else { locs.len() }];
+        let mut results = vec![];
+        for loc in locs {
+            let new = match (root.get(loc).expect("location just selected"), &incr) {
+                (Json::Int(n), Json::Int(incr)) => n
+                    .checked_add(*incr)
+                    .map_or(Json::Float(*n as f64 + *incr as f64), Json::Int),
+                (Json::Int(n), Json::Float(incr)) => Json::Float(*n as f64 + incr),
+                (Json::Float(f), Json::Int(incr)) => Json::Float(f + *incr as f64),
+                (Json::Float(f), Json::Float(incr)) => Json::Float(f + incr),
+                (other, _) if path.legacy => return Err(wrong_json_type("a number", other)),
+                _ => Json::Null,
+            };
+            if matches!(new, Json::Float(f) if !f.is_finite()) {
+                return Err(RedisError::Other("result is not a number".to_string()));
+            }
+            results.push(new);
+        }
+        for (loc, new) in locs.iter().zip(&results) {
+            if *new != Json::Null {
+                root.set(loc, new.clone());
+            }
+        }
+
+        if path.legacy {
+            return Ok(RedisValue::bulk(results[0].serialize()));
+        }
+        Ok(match self.protocol {
+            Protocol::Resp2 => RedisValue::bulk(Json::Array(results).serialize()),
+            Protocol::Resp3 => RedisValue::Array(
+                results
+                    .into_iter()
+                    .map(|n| match n {
+                        Json::Int(n) => RedisValue::Int(n),
+                        Json::Float(f) => RedisValue::Double(f),
+                        _ => RedisValue::Null,
+                    })
+                    .collect(),
+            ),
+        })
+    }
+
+    /// `JSON.ARRAPPEND key path value [value ...]`, appending to the arrays the path selects. Replies with the
+    /// new length for a legacy path, or with the new lengths (nil for any other value) for a JSONPath.
+    pub(super) fn json_arrappend(&mut self, args: &[RedisValue]) -> CmdResult {
+        let path = Path::parse(&args[1].bytes_arg())?;
+        let values = args[2..].iter().map(parse_arg).collect::<Result<Vec<_>, _>>()?;
+        let key = self.keyize(&args[0]);
+        let mut db = self.map.lock().expect("unlock failed!");
+
+        let Some(set) = lookup(&mut db, &key) else {
+            return Err(RedisError::Other(
+                "could not perform this operation on a key that doesn't exist".to_string(),
+            ));
+        };
+        let root = set.val.json_mut()?;
+        let locs = path.select(root);
+        if path.legacy && locs.is_empty() {
+            return Err(no_such_path(&args[1]));
+        }
+        let mut lengths = vec![];
+        for loc in locs.iter().take(if path.legacy { 1 } else { usize::MAX }) {
+            match root.get_mut(loc).expect("location just selected") {
+                Json::Array(items) => {
+                    items.extend(values.iter().cloned());
+                    lengths.push(RedisValue::Int(items.len() as RedisInt));
+                }
+                other if path.legacy => return Err(wrong_json_type("an array", other)),
+                _ => lengths.push(RedisValue::Null),
+            }
+        }
+        Ok(match path.legacy {
+            true => lengths.swap_remove(0),
+            false => RedisValue::Array(lengths),
+        })
+    }
+
+    /// `JSON.OBJKEYS key [path]`, replying with the keys of the object a legacy path selects (the document by
+    /// default), or with those of each value a JSONPath selects (nil for any other value than an object)
+    pub(super) fn json_objkeys(&mut self, args: &[RedisValue]) -> CmdResult {
+        let path = match args.get(1) {
+            Some(path) => Path::parse(&path.bytes_arg())?,
+            None => Path {
+                steps: vec![],
+                legacy: true,
+            },
+        };
+        if args.len() > 2 {
+            return Err(RedisError::Syntax);
+        }
+        let key = self.keyize(&args[0]);
+        let mut db = self.map.lock().expect("unlock failed!");
+
+        let Some(set) = lookup(&mut db, &key) else {
+            return Ok(RedisValue::Null);
+        };
+        let root = set.val.json_mut()?;
+        let keys = |node: &Json| match node {
+            Json::Object(fields) => Ok(RedisValue::Array(
+                fields.iter().map(|(k, _)| RedisValue::bulk(k.clone())).collect(),
+            )),
+            other => Err(other.type_name()),
+        };
+        let mut nodes = path.select(root).into_iter().filter_map(|loc| root.get(&loc));
+        if path.legacy {
+            return match nodes.next() {
+                Some(node) => keys(node).map_err(|_| wrong_json_type("an object", node)),
+                None => Ok(RedisValue::Null),
+            };
+        }
+        Ok(RedisValue::Array(
+            nodes.map(|node| keys(node).unwrap_or(RedisValue::Null)).collect(),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Json, Path};
+    use crate::resp::Protocol;
+    use crate::resp::test::{bulk, connected_handler};
+    use crate::{RedisError, RedisValue};
+
+    fn bulks(args: &[&str]) -> Vec<RedisValue> {
+        args.iter().map(|a| bulk(a)).collect()
+    }
+
+    #[test]
+    fn parse_and_serialize() {
+        let text = r#" {"a": [1, -2.5, 3e2, true, null], "b": {"c": "x\"é😀\n"}, "a": []} "#;
+        let json = Json::parse(text.as_bytes()).unwrap();
+        assert_eq!(json.serialize(), "{\"a\":[],\"b\":{\"c\":\"x\\\"é😀\\n\"}}");
+        let json = Json::parse(b"[1,-2.5,3e2,0.1,10000000000000000000]").unwrap();
+        assert_eq!(json.serialize(), "[1,-2.5,300.0,0.1,1e19]");
+
+        for invalid in ["", "[1,]", "{\"a\" 1}", "01", "\"abc", "[1] 2", "tru"] {
+            assert!(Json::parse(invalid.as_bytes()).is_err(), "{} parsed", invalid);
+        }
+        assert_eq!(
+            Json::parse(b"[1,\n x]"),
+            Err(RedisError::Other("expected value at line 2 column 2".to_string()))
+        );
+        assert!(Json::parse("[".repeat(200).as_bytes()).is_err());
+
+        // Keys are told apart without comparing each of them with all the others
+        let keys: Vec<String> = (0..100_000).map(|i| format!("\"k{}\":{}", i % 80_000, i)).collect();
+        let Ok(Json::Object(fields)) = Json::parse(format!("{{{}}}", keys.join(",")).as_bytes()) else { panic!() };
+        assert_eq!((fields.len(), &fields[0]), (80_000, &("k0".to_string(), Json::Int(80_000))));
+    }
+
+    #[test]
+    fn paths() {
+        let json = Json::parse(br#"{"a": {"b": [10, 20, {"b": 30}]}, "c": {"b": 40}}"#).unwrap();
+        let selected = |path: &str| -> String {
+            let locs = Path::parse(path.as_bytes()).unwrap().select(&json);
+            Json::Array(locs.iter().map(|loc| json.get(loc).unwrap().clone()).collect()).serialize()
+        };
+        assert_eq!(selected("$.a.b[1]"), "[20]");
+        assert_eq!(selected("$.a.b[-1].b"), "[30]");
+        assert_eq!(selected("$['c']"), "[{\"b\":40}]");
+        assert_eq!(selected("$..b"), "[[10,20,{\"b\":30}],30,40]");
+        assert_eq!(selected("$.*.b"), "[[10,20,{\"b\":30}],40]");
+        assert_eq!(selected("$.a.b[*]"), "[10,20,{\"b\":30}]");
+        assert_eq!(selected("$.nope"), "[]");
+        assert_eq!(selected(".c.b"), "[40]");
+        assert_eq!(selected("c"), "[{\"b\":40}]");
+        assert!(Path::parse(b"$a").is_err());
+        assert!(Path::parse(b"$.a[x]").is_err());
+    }
+
+    #[tokio::test]
+    async fn documents() {
+        let (mut handler, _client) = connected_handler().await;
+        let ok = RedisValue::SimpleString("OK".into());
+        let doc = r#"{"name": "shop", "stock": {"apples": 3, "pears": 1.5}, "tags": ["a"]}"#;
+        assert_eq!(handler.handle_command("json.set", bulks(&["doc", "$", doc])).await, ok);
+        assert_eq!(
+            handler.handle_command("type", vec![bulk("doc")]).await,
+            RedisValue::SimpleString("ReJSON-RL".into())
+        );
+        assert_eq!(
+            handler.handle_command("json.set", bulks(&["new", "$.a", "1"])).await,
+            RedisError::Other("new objects must be created at the root".to_string()).into()
+        );
+
+        assert_eq!(
+            handler
+                .handle_command("json.set", bulks(&["doc", "$.stock.kiwis", "7"]))
+                .await,
+            ok
+        );
+        assert_eq!(
+            handler
+                .handle_command("json.set", bulks(&["doc", "$.name", "\"x\"", "NX"]))
+                .await,
+            RedisValue::NullBulkString
+        );
+        assert_eq!(
+            handler.handle_command("json.set", bulks(&["doc", "$.stock.plums", "1", "XX"])).await,
+            RedisValue::NullBulkString
+        );
+
+        // Documents may not grow deeper than they could be parsed
+        let nested = |depth| format!("{}{}", "[".repeat(depth), "]".repeat(depth));
+        assert_eq!(handler.handle_command("json.set", bulks(&["deep", "$", &nested(100)])).await, ok);
+        let path = format!("${}", "[0]".repeat(99));
+        assert_eq!(handler.handle_command("json.set", bulks(&["deep", &path, &nested(29)])).await, ok);
+        assert_eq!(
+            handler.handle_command("json.set", bulks(&["deep", &path, &nested(30)])).await,
+            RedisError::Other("recursion limit exceeded".to_string()).into()
+        );
+        assert_eq!(
+            handler.handle_command("json.set", bulks(&["doc", "$.a.b", "1"])).await,
+            RedisValue::NullBulkString
+        );
+        assert_eq!(
+            handler
+                .handle_command("json.get", bulks(&["doc", "$.stock.kiwis"]))
+                .await,
+            bulk("[7]")
+        );
+        assert_eq!(
+            handler
+                .handle_command("json.get", bulks(&["doc", ".stock.kiwis"]))
+                .await,
+            bulk("7")
+        );
+        assert_eq!(
+            handler.handle_command("json.get", bulks(&["doc", ".nope"])).await,
+            RedisError::Other("Path '.nope' does not exist".to_string()).into()
+        );
+        assert_eq!(
+            handler
+                .handle_command(
+                    "json.get",
+                    bulks(&["doc", "INDENT", "  ", "NEWLINE", "\n", "SPACE", " ", "$.tags"])
+                )
+                .await,
+            bulk("[\n  [\n    \"a\"\n  ]\n]")
+        );
+        assert_eq!(
+            handler
+                .handle_command("json.get", bulks(&["doc", "$.name", ".tags"]))
+                .await,
+            bulk("{\"$.name\":[\"shop\"],\".tags\":[\"a\"]}")
+        );
+
+        assert_eq!(
+            handler
+                .handle_command("json.numincrby", bulks(&["doc", "$.stock.*", "2"]))
+                .await,
+            bulk("[5,3.5,9]")
+        );
+        assert_eq!(
+            handler
+                .handle_command("json.numincrby", bulks(&["doc", ".stock.apples", "0.5"]))
+                .await,
+            bulk("5.5")
+        );
+        assert_eq!(
+            handler
+                .handle_command("json.numincrby", bulks(&["doc", ".name", "1"]))
+                .await,
+            RedisError::JsonType("a number".to_string(), "string".to_string()).into()
+        );
+        // Either all the numbers are updated, or none
+        let big = r#"{"a": 1, "b": 1.7976931348623157e308}"#;
+        assert_eq!(handler.handle_command("json.set", bulks(&["big", "$", big])).await, ok);
+        assert_eq!(
+            handler.handle_command("json.numincrby", bulks(&["big", "$.*", "1e308"])).await,
+            RedisError::Other("result is not a number".to_string()).into()
+        );
+        assert_eq!(handler.handle_command("json.get", bulks(&["big", "$.a"])).await, bulk("[1]"));
+        handler.protocol = Protocol::Resp3;
+        assert_eq!(
+            handler
+                .handle_command("json.numincrby", bulks(&["doc", "$..*", "1"]))
+                .await,
+            RedisValue::Array(vec![
+                RedisValue::Null,
+                RedisValue::Null,
+                RedisValue::Null,
+                RedisValue::Double(6.5),
+                RedisValue::Double(4.5),
+                RedisValue::Int(10),
+                RedisValue::Null,
+            ])
+        );
+        handler.protocol = Protocol::Resp2;
+
+        assert_eq!(
+            handler
+                .handle_command("json.arrappend", bulks(&["doc", "$.*", "\"b\"", "{}"]))
+                .await,
+            RedisValue::Array(vec![RedisValue::Null, RedisValue::Null, RedisValue::Int(3)])
+        );
+        assert_eq!(
+            handler.handle_command("json.get", bulks(&["doc", "$.tags"])).await,
+            bulk("[[\"a\",\"b\",{}]]")
+        );
+        assert_eq!(
+            handler
+                .handle_command("json.objkeys", bulks(&["doc", "$..stock"]))
+                .await,
+            RedisValue::Array(vec![RedisValue::Array(bulks(&["apples", "pears", "kiwis"]))])
+        );
+        assert_eq!(
+            handler.handle_command("json.objkeys", vec![bulk("doc")]).await,
+            RedisValue::Array(bulks(&["name", "stock", "tags"]))
+        );
+
+        assert_eq!(
+            handler.handle_command("json.del", bulks(&["doc", "$.tags[0]"])).await,
+            RedisValue::Int(1)
+        );
+        assert_eq!(
+            handler.handle_command("json.del", bulks(&["doc", "$.tags[*]"])).await,
+            RedisValue::Int(2)
+        );
+        assert_eq!(
+            handler.handle_command("json.del", bulks(&["doc", "$..kiwis"])).await,
+            RedisValue::Int(1)
+        );
+        assert_eq!(
+            handler.handle_command("json.get", bulks(&["doc", "$.tags"])).await,
+            bulk("[[]]")
+        );
+        assert_eq!(
+            handler.handle_command("json.del", vec![bulk("doc")]).await,
+            RedisValue::Int(1)
+        );
+        assert_eq!(
+            handler.handle_command("exists", vec![bulk("doc")]).await,
+            RedisValue::Int(0)
+        );
+
+        handler.handle_command("json.set", bulks(&["nested", "$", r#"{"a":{"a":[1,2,3]}}"#])).await;
+        assert_eq!(
+            handler.handle_command("json.del", bulks(&["nested", "$..a..[0]"])).await,
+            RedisValue::Int(1)
+        );
+        assert_eq!(
+            handler.handle_command("json.get", bulks(&["nested", "$"])).await,
+            bulk(r#"[{"a":{"a":[2,3]}}]"#)
+        );
+        handler.handle_command("set", bulks(&["s", "v"])).await;
+        assert_eq!(
+            handler.handle_command("json.get", vec![bulk("s")]).await,
+            RedisError::WrongType.into()
+        );
+    }
+}
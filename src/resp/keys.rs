@@ -0,0 +1,167 @@
+// Generic keyspace commands, whatever the type of the values behind the keys
+
+use super::{RespHandler, lookup};
+use crate::error::{CmdResult, RedisError};
+use crate::resp::RedisValue;
+use crate::OK;
+
+impl RespHandler {
+    /// `DEL key [key ...]` and `UNLINK key [key ...]`, replying with the number of keys actually removed
+    pub(super) fn del(&mut self, args: &[RedisValue]) -> CmdResult {
+        let keys = args.iter().map(|k| self.keyize(k)).collect::<Vec<_>>();
+        let mut db = self.map.lock().expect("unlock failed!");
+
+        let mut removed = 0;
+        for key in keys {
+            if lookup(&mut db, &key).is_some() {
+                db.remove(&key);
+                removed += 1;
+            }
+        }
+        Ok(RedisValue::Int(removed))
+    }
+
+    /// `EXISTS key [key ...]`, a key mentioned several times being counted as many times
+    pub(super) fn exists(&mut self, args: &[RedisValue]) -> CmdResult {
+        let keys = args.iter().map(|k| self.keyize(k)).collect::<Vec<_>>();
+        let mut db = self.map.lock().expect("unlock failed!");
+
+        let found = keys.iter().filter(|key| lookup(&mut db, key).is_some()).count();
+        Ok(RedisValue::Int(found as i64))
+    }
+
+    /// `TYPE key`
+    pub(super) fn key_type(&mut self, key: &RedisValue) -> CmdResult {
+        let key = self.keyize(key);
+        let mut db = self.map.lock().expect("unlock failed!");
+
+        let type_name = match lookup(&mut db, &key) {
+            Some(set) => set.type_name(),
+            None => "none",
+        };
+        Ok(RedisValue::SimpleString(type_name.to_string()))
+    }
+
+    /// `RENAME key newkey` and `RENAMENX key newkey`: the value moves along with its time to live.
+    /// Replies with whether the key was renamed for RENAMENX, which never overwrites `newkey`.
+    pub(super) fn rename(&mut self, src: &RedisValue, dst: &RedisValue, nx: bool) -> CmdResult {
+        let (src, dst) = (self.keyize(src), self.keyize(dst));
+        let mut db = self.map.lock().expect("unlock failed!");
+
+        if lookup(&mut db, &src).is_none() {
+            return Err(RedisError::Other("no such key".to_string()));
+        }
+        let dst_exists = lookup(&mut db, &dst).is_some();
+        if nx && dst_exists {
+            return Ok(RedisValue::Int(0));
+        }
+        if src == dst {
+            return Ok(if nx { RedisValue::Int(0) } else { RedisValue::SimpleString(OK.to_string()) });
+        }
+
+        if let Some(set) = db.remove(&src) {
+            db.insert(dst, set);
+        }
+
+        Ok(if nx { RedisValue::Int(1) } else { RedisValue::SimpleString(OK.to_string()) })
+    }
+
+    /// `COPY source destination [DB destination-db] [REPLACE]`
+    ///
+    /// There is a single database in rustis, so that `DB` may only designate it (index 0).
+    pub(super) fn copy(&mut self, args: &[RedisValue]) -> CmdResult {
+        let (src, dst) = (self.keyize(&args[0]), self.keyize(&args[1]));
+
+        let mut replace = false;
+        let mut opts = args[2..].iter();
+        while let Some(opt) = opts.next() {
+            match (opt.option_name().as_str(), opts.len()) {
+                ("replace", _) => replace = true,
+                ("db", 1..) => {
+                    let db = opts.next().map(RedisValue::int_arg).transpose()?;
+                    if db != Some(0) {
+                        return Err(RedisError::Other("DB index is out of range".to_string()));
+                    }
+                }
+                _ => return Err(RedisError::Syntax),
+            }
+        }
+        if src == dst {
+            return Err(RedisError::Other("source and destination objects are the same".to_string()));
+        }
+
+        let mut db = self.map.lock().expect("unlock failed!");
+        let Some(set) = lookup(&mut db, &src).cloned() else {
+            return Ok(RedisValue::Int(0));
+        };
+        if !replace && lookup(&mut db, &dst).is_some() {
+            return Ok(RedisValue::Int(0));
+        }
+
+        db.insert(dst, set);
+        Ok(RedisValue::Int(1))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::resp::test::{bulk, connected_handler};
+    use crate::{RedisError, RedisValue};
+
+    fn ok() -> RedisValue {
+        RedisValue::SimpleString("OK".to_string())
+    }
+
+    #[tokio::test]
+    async fn del_exists_type() {
+        let (mut handler, _client) = connected_handler().await;
+        handler.handle_command("set", vec![bulk("a"), bulk("1")]).await;
+        handler.handle_command("set", vec![bulk("b"), bulk("2")]).await;
+
+        let exists = handler.handle_command("exists", vec![bulk("a"), bulk("a"), bulk("c")]).await;
+        assert_eq!(exists, RedisValue::Int(2));
+        let key_type = handler.handle_command("type", vec![bulk("a")]).await;
+        assert_eq!(key_type, RedisValue::SimpleString("string".to_string()));
+
+        let del = handler.handle_command("del", vec![bulk("a"), bulk("b"), bulk("c")]).await;
+        assert_eq!(del, RedisValue::Int(2));
+        let key_type = handler.handle_command("type", vec![bulk("a")]).await;
+        assert_eq!(key_type, RedisValue::SimpleString("none".to_string()));
+        assert_eq!(handler.handle_command("unlink", vec![bulk("a")]).await, RedisValue::Int(0));
+    }
+
+    #[tokio::test]
+    async fn expired_keys_are_absent() {
+        let (mut handler, _client) = connected_handler().await;
+        handler.handle_command("set", vec![bulk("a"), bulk("1"), bulk("px"), bulk("1")]).await;
+        tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+
+        assert_eq!(handler.handle_command("exists", vec![bulk("a")]).await, RedisValue::Int(0));
+        assert_eq!(handler.handle_command("del", vec![bulk("a")]).await, RedisValue::Int(0));
+        assert_eq!(
+            handler.handle_command("rename", vec![bulk("a"), bulk("b")]).await,
+            RedisError::Other("no such key".to_string()).into()
+        );
+    }
+
+    #[tokio::test]
+    async fn rename_and_copy() {
+        let (mut handler, _client) = connected_handler().await;
+        handler.handle_command("set", vec![bulk("a"), bulk("1")]).await;
+        handler.handle_command("set", vec![bulk("b"), bulk("2")]).await;
+
+        assert_eq!(handler.handle_command("renamenx", vec![bulk("a"), bulk("b")]).await, RedisValue::Int(0));
+        assert_eq!(handler.handle_command("rename", vec![bulk("a"), bulk("c")]).await, ok());
+        assert_eq!(handler.handle_command("get", vec![bulk("a")]).await, RedisValue::NullBulkString);
+        assert_eq!(handler.handle_command("get", vec![bulk("c")]).await, bulk("1"));
+
+        assert_eq!(handler.handle_command("copy", vec![bulk("c"), bulk("b")]).await, RedisValue::Int(0));
+        let copy = handler.handle_command("copy", vec![bulk("c"), bulk("b"), bulk("REPLACE")]).await;
+        assert_eq!(copy, RedisValue::Int(1));
+        assert_eq!(handler.handle_command("get", vec![bulk("b")]).await, bulk("1"));
+        assert_eq!(handler.handle_command("get", vec![bulk("c")]).await, bulk("1"));
+
+        let copy = handler.handle_command("copy", vec![bulk("c"), bulk("d"), bulk("db"), bulk("1")]).await;
+        assert_eq!(copy, RedisError::Other("DB index is out of range".to_string()).into());
+    }
+}
@@ -0,0 +1,419 @@
+// List commands: sequences of strings pushed and popped at both ends
+
+use super::strings::resolve_range;
+use super::{Database, Key, RedisObject, RespHandler, Set, lookup, remove_if_empty};
+use crate::OK;
+use crate::error::{CmdResult, RedisError};
+use crate::resp::{RedisInt, RedisValue};
+use bytes::Bytes;
+use std::collections::VecDeque;
+
+/// End of a list, as the LEFT/RIGHT arguments of LMOVE and its variants designate it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(super) enum End {
+    Left,
+    Right,
+}
+
+impl End {
+    pub(super) fn parse(arg: &RedisValue) -> Result<Self, RedisError> {
+        match arg.option_name().as_str() {
+            "left" => Ok(End::Left),
+            "right" => Ok(End::Right),
+            _ => Err(RedisError::Syntax),
+        }
+    }
+}
+
+/// Looks a list up, WRONGTYPE if the key holds anything else
+fn read_list<'a>(db: &'a mut Database, key: &[u8]) -> Result<Option<&'a mut VecDeque<Bytes>>, RedisError> {
+    lookup(db, key).map(|set| set.val.list_mut()).transpose()
+}
+
+/// Pops up to `count` elements from the list at `key`, removing it once emptied.
+/// Replies `None` if there is no such list.
+pub(super) fn list_pop(
+    db: &mut Database,
+    key: &[u8],
+    end: End,
+    count: usize,
+) -> Result<Option<Vec<Bytes>>, RedisError> {
+    let Some(list) = read_list(db, key)? else {
+        return Ok(None);
+    };
+    let count = count.min(list.len());
+    let popped = match end {
+        End::Left => list.drain(..count).collect(),
+        End::Right => {
+            let at = list.len() - count;
+            list.drain(at..).rev().collect()
+        }
+    };
+    remove_if_empty(db, key);
+    Ok(Some(popped))
+}
+
+/// Moves an element from one end of the list at `src` to one end of the list at `dst`, the latter being
+/// created if needed. Replies with the element moved, or `None` if there is no list at `src`.
+pub(super) fn list_move(
+    db: &mut Database,
+    src: &[u8],
+    dst: &Key,
+    from: End,
+    to: End,
+) -> Result<Option<Bytes>, RedisError> {
+    if read_list(db, src)?.is_none() {
+        return Ok(None);
+    }
+    // Nothing must be popped if it cannot be pushed
+    read_list(db, dst)?;
+
+    let Some(elt) = list_pop(db, src, from, 1)?.and_then(|mut popped| popped.pop()) else {
+        return Ok(None);
+    };
+    let list = match lookup(db, dst) {
+        Some(set) => set.val.list_mut()?,
+        None => {
+            let set = db.entry(dst.clone()).insert_entry(Set::new(RedisObject::List(VecDeque::new()), None));
+            set.into_mut().val.list_mut()?
+        }
+    };
+    match to {
+        End::Left => list.push_front(elt.clone()),
+        End::Right => list.push_back(elt.clone()),
+    }
+    db.signal_ready(dst);
+    Ok(Some(elt))
+}
+
+fn bulk_array(elts: impl IntoIterator<Item = Bytes>) -> RedisValue {
+    RedisValue::Array(elts.into_iter().map(RedisValue::BulkString).collect())
+}
+
+impl RespHandler {
+    /// `LPUSH key element [element ...]`, `RPUSH` and their `X` variants, which only push to existing lists.
+    /// Replies with the length of the list after the push.
+    pub(super) fn push(&mut self, args: &[RedisValue], end: End, only_existing: bool) -> CmdResult {
+        let key = self.keyize(&args[0]);
+        let mut db = self.map.lock().expect("unlock failed!");
+
+        let list = match lookup(&mut db, &key) {
+            Some(set) => set.val.list_mut()?,
+            None if only_existing => return Ok(RedisValue::Int(0)),
+            None => {
+                let set = db.entry(key.clone()).insert_entry(Set::new(RedisObject::List(VecDeque::new()), None));
+                set.into_mut().val.list_mut()?
+            }
+        };
+        for elt in &args[1..] {
+            match end {
+                End::Left => list.push_front(elt.bytes_arg()),
+                End::Right => list.push_back(elt.bytes_arg()),
+            }
+        }
+        let len = list.len();
+        db.signal_ready(&key);
+        Ok(RedisValue::Int(len as RedisInt))
+    }
+
+    /// `LPOP key [count]` and `RPOP key [count]`, replying with a single element unless `count` is given
+    pub(super) fn pop(&mut self, args: &[RedisValue], end: End) -> CmdResult {
+        if args.len() > 2 {
+            let command = if end == End::Left { "lpop" } else { "rpop" };
+            return Err(RedisError::WrongArity(command.to_string()));
+        }
+        let count = match args.get(1) {
+            Some(count) => Some(usize::try_from(count.int_arg()?).map_err(|_| {
+                RedisError::Other("value is out of range, must be positive".to_string())
+            })?),
+            None => None,
+        };
+        let key = self.keyize(&args[0]);
+        let mut db = self.map.lock().expect("unlock failed!");
+
+        let popped = list_pop(&mut db, &key, end, count.unwrap_or(1))?;
+        Ok(match (popped, count) {
+            (None, Some(_)) => RedisValue::NullArray,
+            (None, None) => RedisValue::NullBulkString,
+            (Some(popped), Some(_)) => bulk_array(popped),
+            (Some(popped), None) => {
+                popped.into_iter().next().map_or(RedisValue::NullBulkString, RedisValue::BulkString)
+            }
+        })
+    }
+
+    /// `LLEN key`
+    pub(super) fn llen(&mut self, key: &RedisValue) -> CmdResult {
+        let key = self.keyize(key);
+        let mut db = self.map.lock().expect("unlock failed!");
+
+        let len = read_list(&mut db, &key)?.map_or(0, |list| list.len());
+        Ok(RedisValue::Int(len as RedisInt))
+    }
+
+    /// `LRANGE key start stop`, both ends being inclusive and possibly negative
+    pub(super) fn lrange(&mut self, key: &RedisValue, start: RedisInt, stop: RedisInt) -> CmdResult {
+        let key = self.keyize(key);
+        let mut db = self.map.lock().expect("unlock failed!");
+
+        let Some(list) = read_list(&mut db, &key)? else {
+            return Ok(RedisValue::Array(vec![]));
+        };
+        Ok(match resolve_range(start, stop, list.len()) {
+            Some((start, stop)) => bulk_array(list.range(start..=stop).cloned()),
+            None => RedisValue::Array(vec![]),
+        })
+    }
+
+    /// `LINDEX key index`
+    pub(super) fn lindex(&mut self, key: &RedisValue, index: RedisInt) -> CmdResult {
+        let key = self.keyize(key);
+        let mut db = self.map.lock().expect("unlock failed!");
+
+        let elt = read_list(&mut db, &key)?.and_then(|list| {
+            let index = if index < 0 { list.len() as RedisInt + index } else { index };
+            list.get(usize::try_from(index).ok()?).cloned()
+        });
+        Ok(elt.map_or(RedisValue::NullBulkString, RedisValue::BulkString))
+    }
+
+    /// `LSET key index element`
+    pub(super) fn lset(&mut self, key: &RedisValue, index: RedisInt, elt: &RedisValue) -> CmdResult {
+        let key = self.keyize(key);
+        let mut db = self.map.lock().expect("unlock failed!");
+
+        let list = read_list(&mut db, &key)?.ok_or_else(|| RedisError::Other("no such key".to_string()))?;
+        let index = if index < 0 { list.len() as RedisInt + index } else { index };
+        let slot = usize::try_from(index)
+            .ok()
+            .and_then(|index| list.get_mut(index))
+            .ok_or_else(|| RedisError::Other("index out of range".to_string()))?;
+        *slot = elt.bytes_arg();
+        Ok(RedisValue::SimpleString(OK.to_string()))
+    }
+
+    /// `LREM key count element`: removes the first `count` occurrences of `element` from the head, the last
+    /// ones from the tail if `count` is negative, or all of them if it is 0. Replies with the number removed.
+    pub(super) fn lrem(&mut self, key: &RedisValue, count: RedisInt, elt: &RedisValue) -> CmdResult {
+        let elt = elt.bytes_arg();
+        let key = self.keyize(key);
+        let mut db = self.map.lock().expect("unlock failed!");
+
+        let Some(list) = read_list(&mut db, &key)? else {
+            return Ok(RedisValue::Int(0));
+        };
+        let limit = if count == 0 { usize::MAX } else { count.unsigned_abs() as usize };
+        let mut removed = 0;
+        if count < 0 {
+            let mut i = list.len();
+            while i > 0 && removed < limit {
+                i -= 1;
+                if list[i] == elt {
+                    list.remove(i);
+                    removed += 1;
+                }
+            }
+        } else {
+            list.retain(|e| {
+                let drop = removed < limit && *e == elt;
+                removed += drop as usize;
+                !drop
+            });
+        }
+
+        remove_if_empty(&mut db, &key);
+        Ok(RedisValue::Int(removed as RedisInt))
+    }
+
+    /// `LTRIM key start stop`, keeping only the given range of the list
+    pub(super) fn ltrim(&mut self, key: &RedisValue, start: RedisInt, stop: RedisInt) -> CmdResult {
+        let key = self.keyize(key);
+        let mut db = self.map.lock().expect("unlock failed!");
+
+        if let Some(list) = read_list(&mut db, &key)? {
+            match resolve_range(start, stop, list.len()) {
+                Some((start, stop)) => {
+                    list.truncate(stop + 1);
+                    list.drain(..start);
+                }
+                None => list.clear(),
+            }
+        }
+        remove_if_empty(&mut db, &key);
+        Ok(RedisValue::SimpleString(OK.to_string()))
+    }
+
+    /// `LINSERT key BEFORE|AFTER pivot element`, replying with the length of the list after the insertion,
+    /// or -1 if `pivot` could not be found
+    pub(super) fn linsert(&mut self, args: &[RedisValue]) -> CmdResult {
+        let after = match args[1].option_name().as_str() {
+            "before" => false,
+            "after" => true,
+            _ => return Err(RedisError::Syntax),
+        };
+        let (pivot, elt) = (args[2].bytes_arg(), args[3].bytes_arg());
+        let key = self.keyize(&args[0]);
+        let mut db = self.map.lock().expect("unlock failed!");
+
+        let Some(list) = read_list(&mut db, &key)? else {
+            return Ok(RedisValue::Int(0));
+        };
+        let Some(at) = list.iter().position(|e| *e == pivot) else {
+            return Ok(RedisValue::Int(-1));
+        };
+        list.insert(at + after as usize, elt);
+        Ok(RedisValue::Int(list.len() as RedisInt))
+    }
+
+    /// `LMOVE source destination LEFT|RIGHT LEFT|RIGHT`, and `RPOPLPUSH source destination` which is
+    /// `LMOVE source destination RIGHT LEFT`. Replies with the element moved.
+    pub(super) fn lmove(&mut self, src: &RedisValue, dst: &RedisValue, from: End, to: End) -> CmdResult {
+        let (src, dst) = (self.keyize(src), self.keyize(dst));
+        let mut db = self.map.lock().expect("unlock failed!");
+
+        let moved = list_move(&mut db, &src, &dst, from, to)?;
+        Ok(moved.map_or(RedisValue::NullBulkString, RedisValue::BulkString))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::resp::test::{bulk, connected_handler};
+    use crate::{RedisError, RedisValue};
+
+    fn bulks(elts: &[&str]) -> RedisValue {
+        RedisValue::Array(elts.iter().map(|e| bulk(e)).collect())
+    }
+
+    #[tokio::test]
+    async fn push_pop() {
+        let (mut handler, _client) = connected_handler().await;
+
+        assert_eq!(handler.handle_command("rpush", vec![bulk("l"), bulk("b"), bulk("c")]).await, RedisValue::Int(2));
+        assert_eq!(handler.handle_command("lpush", vec![bulk("l"), bulk("a"), bulk("z")]).await, RedisValue::Int(4));
+        assert_eq!(handler.handle_command("lpushx", vec![bulk("nope"), bulk("a")]).await, RedisValue::Int(0));
+        assert_eq!(handler.handle_command("type", vec![bulk("l")]).await, RedisValue::SimpleString("list".into()));
+        assert_eq!(handler.handle_command("llen", vec![bulk("l")]).await, RedisValue::Int(4));
+        assert_eq!(
+            handler.handle_command("lrange", vec![bulk("l"), bulk("0"), bulk("-1")]).await,
+            bulks(&["z", "a", "b", "c"])
+        );
+
+        assert_eq!(handler.handle_command("lpop", vec![bulk("l")]).await, bulk("z"));
+        assert_eq!(handler.handle_command("rpop", vec![bulk("l"), bulk("2")]).await, bulks(&["c", "b"]));
+        assert_eq!(handler.handle_command("rpop", vec![bulk("l"), bulk("5")]).await, bulks(&["a"]));
+        assert_eq!(handler.handle_command("exists", vec![bulk("l")]).await, RedisValue::Int(0));
+        assert_eq!(handler.handle_command("lpop", vec![bulk("l")]).await, RedisValue::NullBulkString);
+        assert_eq!(handler.handle_command("lpop", vec![bulk("l"), bulk("1")]).await, RedisValue::NullArray);
+        assert_eq!(
+            handler.handle_command("lpop", vec![bulk("l"), bulk("-1")]).await,
+            RedisError::Other("value is out of range, must be positive".to_string()).into()
+        );
+    }
+
+    #[tokio::test]
+    async fn wrong_type() {
+        let (mut handler, _client) = connected_handler().await;
+        handler.handle_command("set", vec![bulk("s"), bulk("v")]).await;
+        handler.handle_command("rpush", vec![bulk("l"), bulk("v")]).await;
+
+        assert_eq!(handler.handle_command("lpush", vec![bulk("s"), bulk("a")]).await, RedisError::WrongType.into());
+        assert_eq!(handler.handle_command("llen", vec![bulk("s")]).await, RedisError::WrongType.into());
+        assert_eq!(handler.handle_command("get", vec![bulk("l")]).await, RedisError::WrongType.into());
+        assert_eq!(handler.handle_command("incr", vec![bulk("l")]).await, RedisError::WrongType.into());
+        assert_eq!(handler.handle_command("append", vec![bulk("l"), bulk("a")]).await, RedisError::WrongType.into());
+        assert_eq!(
+            handler.handle_command("lmove", vec![bulk("l"), bulk("s"), bulk("left"), bulk("left")]).await,
+            RedisError::WrongType.into()
+        );
+        // Nothing was popped by the failed move
+        assert_eq!(handler.handle_command("llen", vec![bulk("l")]).await, RedisValue::Int(1));
+        assert_eq!(
+            handler.handle_command("mget", vec![bulk("s"), bulk("l")]).await,
+            RedisValue::Array(vec![bulk("v"), RedisValue::NullBulkString])
+        );
+
+        // SET overwrites whatever the type
+        handler.handle_command("set", vec![bulk("l"), bulk("v")]).await;
+        assert_eq!(handler.handle_command("get", vec![bulk("l")]).await, bulk("v"));
+    }
+
+    #[tokio::test]
+    async fn edit() {
+        let (mut handler, _client) = connected_handler().await;
+        let elts = ["a", "b", "a", "c", "a", "d"].map(bulk);
+        handler.handle_command("rpush", [vec![bulk("l")], elts.to_vec()].concat()).await;
+
+        assert_eq!(handler.handle_command("lindex", vec![bulk("l"), bulk("-1")]).await, bulk("d"));
+        assert_eq!(handler.handle_command("lindex", vec![bulk("l"), bulk("6")]).await, RedisValue::NullBulkString);
+        assert_eq!(
+            handler.handle_command("lset", vec![bulk("l"), bulk("1"), bulk("B")]).await,
+            RedisValue::SimpleString("OK".into())
+        );
+        assert_eq!(
+            handler.handle_command("lset", vec![bulk("l"), bulk("10"), bulk("B")]).await,
+            RedisError::Other("index out of range".to_string()).into()
+        );
+        assert_eq!(
+            handler.handle_command("lset", vec![bulk("nope"), bulk("0"), bulk("B")]).await,
+            RedisError::Other("no such key".to_string()).into()
+        );
+
+        assert_eq!(handler.handle_command("lrem", vec![bulk("l"), bulk("-2"), bulk("a")]).await, RedisValue::Int(2));
+        assert_eq!(
+            handler.handle_command("lrange", vec![bulk("l"), bulk("0"), bulk("-1")]).await,
+            bulks(&["a", "B", "c", "d"])
+        );
+        assert_eq!(
+            handler.handle_command("linsert", vec![bulk("l"), bulk("AFTER"), bulk("c"), bulk("C")]).await,
+            RedisValue::Int(5)
+        );
+        assert_eq!(
+            handler.handle_command("linsert", vec![bulk("l"), bulk("before"), bulk("x"), bulk("C")]).await,
+            RedisValue::Int(-1)
+        );
+        assert_eq!(
+            handler.handle_command("ltrim", vec![bulk("l"), bulk("1"), bulk("-2")]).await,
+            RedisValue::SimpleString("OK".into())
+        );
+        assert_eq!(
+            handler.handle_command("lrange", vec![bulk("l"), bulk("0"), bulk("-1")]).await,
+            bulks(&["B", "c", "C"])
+        );
+        handler.handle_command("ltrim", vec![bulk("l"), bulk("5"), bulk("10")]).await;
+        assert_eq!(handler.handle_command("exists", vec![bulk("l")]).await, RedisValue::Int(0));
+    }
+
+    #[tokio::test]
+    async fn lmove() {
+        let (mut handler, _client) = connected_handler().await;
+        handler.handle_command("rpush", vec![bulk("src"), bulk("a"), bulk("b"), bulk("c")]).await;
+
+        assert_eq!(
+            handler.handle_command("lmove", vec![bulk("src"), bulk("dst"), bulk("right"), bulk("left")]).await,
+            bulk("c")
+        );
+        assert_eq!(handler.handle_command("rpoplpush", vec![bulk("src"), bulk("dst")]).await, bulk("b"));
+        assert_eq!(
+            handler.handle_command("lrange", vec![bulk("dst"), bulk("0"), bulk("-1")]).await,
+            bulks(&["b", "c"])
+        );
+        // Rotation of a single list
+        assert_eq!(
+            handler.handle_command("lmove", vec![bulk("dst"), bulk("dst"), bulk("left"), bulk("right")]).await,
+            bulk("b")
+        );
+        assert_eq!(
+            handler.handle_command("lrange", vec![bulk("dst"), bulk("0"), bulk("-1")]).await,
+            bulks(&["c", "b"])
+        );
+        assert_eq!(
+            handler.handle_command("lmove", vec![bulk("nope"), bulk("dst"), bulk("left"), bulk("up")]).await,
+            RedisError::Syntax.into()
+        );
+        assert_eq!(
+            handler.handle_command("lmove", vec![bulk("nope"), bulk("dst"), bulk("left"), bulk("left")]).await,
+            RedisValue::NullBulkString
+        );
+    }
+}
@@ -0,0 +1,147 @@
+// Cursor-based iteration over the members of a collection, shared by the *SCAN commands, and random picks
+// out of them
+
+use crate::error::RedisError;
+use crate::{glob_match, random_u64};
+use crate::resp::RedisValue;
+use bytes::Bytes;
+use std::collections::{BTreeMap, HashSet};
+use std::hash::{DefaultHasher, Hash, Hasher};
+
+/// Members returned by a single call when COUNT is not given
+const DEFAULT_COUNT: usize = 10;
+
+/// Positions looked at for a random pick, as Redis samples a few keys to pick a fair one
+const RANDOM_SAMPLE: usize = 16;
+
+/// `cursor [MATCH pattern] [COUNT count] [NOVALUES]`, as HSCAN, SSCAN and ZSCAN take it
+#[derive(Debug)]
+pub(super) struct ScanArgs {
+    cursor: u64,
+    pattern: Option<Bytes>,
+    count: usize,
+    pub(super) novalues: bool,
+}
+
+impl ScanArgs {
+    /// Parses the arguments following the key, NOVALUES being only accepted if `novalues` allows it
+    pub(super) fn parse(args: &[RedisValue], novalues: bool) -> Result<Self, RedisError> {
+        let cursor = args[0]
+            .unpack_bytes_variant()
+            .and_then(|c| std::str::from_utf8(c).ok()?.parse().ok())
+            .or_else(|| u64::try_from(args[0].unpack_int_variant()?).ok())
+            .ok_or_else(|| RedisError::Other("invalid cursor".to_string()))?;
+
+        let mut scan = ScanArgs { cursor, pattern: None, count: DEFAULT_COUNT, novalues: false };
+        let mut opts = args[1..].iter();
+        while let Some(opt) = opts.next() {
+            match opt.option_name().as_str() {
+                "match" => scan.pattern = Some(opts.next().ok_or(RedisError::Syntax)?.bytes_arg()),
+                "count" => match opts.next().ok_or(RedisError::Syntax)?.int_arg()? {
+                    n if n > 0 => scan.count = n as usize,
+                    _ => return Err(RedisError::Syntax),
+                },
+                "novalues" if novalues => scan.novalues = true,
+                _ => return Err(RedisError::Syntax),
+            }
+        }
+        Ok(scan)
+    }
+
+    /// Whether `name` matches the MATCH pattern, if any
+    pub(super) fn matches(&self, name: &[u8]) -> bool {
+        self.pattern.as_ref().is_none_or(|p| glob_match(p, name))
+    }
+
+    /// Picks the next page out of `index`, replying with the cursor to resume from (0 once done).
+    ///
+    /// Members are walked in the order of a fixed hash of their name, the cursor being the hash to resume
+    /// from: any member present from the first call to the last is returned at least once, whatever the
+    /// collection went through in between.
+    pub(super) fn page<'a>(&self, index: &'a ScanIndex) -> (u64, Vec<&'a Bytes>) {
+        let mut page = vec![];
+        let mut walked = 0;
+        for (&pos, names) in index.positions.range(self.cursor..) {
+            if walked >= self.count {
+                return (pos, page);
+            }
+            // Members sharing a position cannot be told apart by a cursor, so they go in the same page
+            walked += names.len();
+            page.extend(names.iter().filter(|name| self.matches(name)));
+        }
+        (0, page)
+    }
+}
+
+/// Names of the members of a collection, ordered by their position along the iteration. Kept next to the
+/// collection, so that a page is found without going through the whole of it.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ScanIndex {
+    positions: BTreeMap<u64, Vec<Bytes>>,
+    len: usize,
+}
+
+impl ScanIndex {
+    /// Adds a name, which must not be there already
+    pub(super) fn insert(&mut self, name: Bytes) {
+        self.positions.entry(position(&name)).or_default().push(name);
+        self.len += 1;
+    }
+
+    pub(super) fn remove(&mut self, name: &[u8]) {
+        let pos = position(name);
+        if let Some(names) = self.positions.get_mut(&pos) {
+            let before = names.len();
+            names.retain(|n| n != name);
+            self.len -= before - names.len();
+            if names.is_empty() {
+                self.positions.remove(&pos);
+            }
+        }
+    }
+
+    /// A random name, unless there is none.
+    ///
+    /// The names following a random position are sampled, and one of them is drawn: taking the first one
+    /// alone would favour the names after the widest gaps between positions, which the sample evens out.
+    pub(super) fn random(&self) -> Option<&Bytes> {
+        let sampled = self.positions.len().min(RANDOM_SAMPLE);
+        let after = self.positions.range(random_u64()..).chain(&self.positions);
+        let sample: Vec<&Bytes> = after.take(sampled).flat_map(|(_, names)| names).collect();
+        sample.get((random_u64() % sample.len().max(1) as u64) as usize).copied()
+    }
+
+    /// `count` distinct random names, or all of them if there are not as many
+    pub(super) fn random_distinct(&self, count: usize) -> Vec<&Bytes> {
+        if count >= self.len {
+            return self.positions.values().flatten().collect();
+        }
+        // Drawn one by one, most draws would end up on names already picked
+        if count * 3 > self.len {
+            let mut names: Vec<&Bytes> = self.positions.values().flatten().collect();
+            for i in 0..count {
+                let j = i + (random_u64() % (names.len() - i) as u64) as usize;
+                names.swap(i, j);
+            }
+            names.truncate(count);
+            return names;
+        }
+        let mut picked = HashSet::with_capacity(count);
+        while picked.len() < count {
+            picked.insert(self.random().expect("count is below the number of names"));
+        }
+        picked.into_iter().collect()
+    }
+}
+
+/// Position of a member along the iteration, never 0 so that 0 can only mean "start over" or "done"
+fn position(name: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    name.hash(&mut hasher);
+    hasher.finish().max(1)
+}
+
+/// Reply of the *SCAN commands: the cursor to resume from along with the page of members
+pub(super) fn scan_reply(cursor: u64, members: Vec<RedisValue>) -> RedisValue {
+    RedisValue::Array(vec![RedisValue::bulk(cursor.to_string()), RedisValue::Array(members)])
+}
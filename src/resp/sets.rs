@@ -0,0 +1,623 @@
+// Set commands: unordered collections of distinct strings, and the algebra between them
+
+use super::hashes::{RANDOM_PREALLOC, random_count};
+use super::scan::{ScanArgs, ScanIndex, scan_reply};
+use super::{Database, RedisObject, RespHandler, Set, lookup, remove_if_empty};
+use crate::error::{CmdResult, RedisError};
+use crate::resp::{Key, RedisInt, RedisValue};
+use crate::{parse_redis_int, random_u64};
+use bytes::Bytes;
+use std::collections::HashSet;
+
+/// Size past which a set of integers is not worth keeping compact anymore, as Redis' `set-max-intset-entries`
+const INTSET_MAX_ENTRIES: usize = 512;
+
+/// Members of a set, in either of its encodings
+#[derive(Debug, Clone, PartialEq)]
+pub enum SetObject {
+    /// Small sets holding integers only, kept sorted as plain integers rather than strings
+    IntSet(Vec<RedisInt>),
+    /// Any other set, along with the names of its members in the order SSCAN walks them
+    HashTable { members: HashSet<Bytes>, index: ScanIndex },
+}
+
+impl Default for SetObject {
+    fn default() -> Self {
+        SetObject::IntSet(vec![])
+    }
+}
+
+impl FromIterator<Bytes> for SetObject {
+    fn from_iter<I: IntoIterator<Item = Bytes>>(members: I) -> Self {
+        let mut set = SetObject::default();
+        for member in members {
+            set.insert(member);
+        }
+        set
+    }
+}
+
+impl SetObject {
+    pub fn contains(&self, member: &[u8]) -> bool {
+        match self {
+            SetObject::IntSet(ints) => parse_redis_int(member).is_some_and(|n| ints.binary_search(&n).is_ok()),
+            SetObject::HashTable { members, .. } => members.contains(member),
+        }
+    }
+
+    /// Adds a member, replying with whether it was not there already
+    pub fn insert(&mut self, member: Bytes) -> bool {
+        if let SetObject::IntSet(ints) = self {
+            match parse_redis_int(&member).map(|n| (n, ints.binary_search(&n))) {
+                Some((_, Ok(_))) => return false,
+                Some((n, Err(at))) if ints.len() < INTSET_MAX_ENTRIES => {
+                    ints.insert(at, n);
+                    return true;
+                }
+                // Not an integer, or one too many: the set outgrows its compact encoding
+                _ => self.convert(),
+            }
+        }
+        match self {
+            SetObject::HashTable { members, index } => {
+                let added = members.insert(member.clone());
+                if added {
+                    index.insert(member);
+                }
+                added
+            }
+            SetObject::IntSet(_) => unreachable!("sets are converted before they receive other members"),
+        }
+    }
+
+    /// Removes a member, replying with whether it was there
+    pub fn remove(&mut self, member: &[u8]) -> bool {
+        match self {
+            SetObject::IntSet(ints) => match parse_redis_int(member).map(|n| ints.binary_search(&n)) {
+                Some(Ok(at)) => {
+                    ints.remove(at);
+                    true
+                }
+                _ => false,
+            },
+            SetObject::HashTable { members, index } => {
+                let removed = members.remove(member);
+                if removed {
+                    index.remove(member);
+                }
+                removed
+            }
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            SetObject::IntSet(ints) => ints.len(),
+            SetObject::HashTable { members, .. } => members.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The members one after the other, integers of compact sets being turned into their text
+    pub fn iter(&self) -> impl Iterator<Item = Bytes> {
+        let (ints, members) = match self {
+            SetObject::IntSet(ints) => (&ints[..], None),
+            SetObject::HashTable { members, .. } => (&[][..], Some(members)),
+        };
+        ints.iter().map(|n| Bytes::from(n.to_string())).chain(members.into_iter().flatten().cloned())
+    }
+
+    /// All the members, integers of compact sets being turned into their text
+    pub fn members(&self) -> Vec<Bytes> {
+        self.iter().collect()
+    }
+
+    /// `count` random members, all distinct unless `repeat`, picked without going through the whole set
+    pub fn random_members(&self, count: usize, repeat: bool) -> Vec<Bytes> {
+        match self {
+            // Compact sets are small enough to be copied
+            SetObject::IntSet(_) => pick_random(self.members(), count, repeat),
+            SetObject::HashTable { index, .. } if repeat => {
+                let mut picked = Vec::with_capacity(count.min(RANDOM_PREALLOC));
+                for _ in 0..count {
+                    picked.extend(index.random().cloned());
+                }
+                picked
+            }
+            SetObject::HashTable { index, .. } => index.random_distinct(count).into_iter().cloned().collect(),
+        }
+    }
+
+    /// Name of the encoding, as `OBJECT ENCODING` would reply it
+    pub fn encoding(&self) -> &'static str {
+        match self {
+            SetObject::IntSet(_) => "intset",
+            SetObject::HashTable { .. } => "hashtable",
+        }
+    }
+
+    fn convert(&mut self) {
+        if let SetObject::IntSet(ints) = self {
+            let ints = std::mem::take(ints);
+            *self = SetObject::HashTable { members: HashSet::new(), index: ScanIndex::default() };
+            for n in ints {
+                self.insert(Bytes::from(n.to_string()));
+            }
+        }
+    }
+}
+
+/// Operation combining several sets into one
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(super) enum SetOp {
+    Inter,
+    Union,
+    /// Members of the first set that none of the others hold
+    Diff,
+}
+
+/// Looks a set up, WRONGTYPE if the key holds anything else
+fn read_set<'a>(db: &'a mut Database, key: &[u8]) -> Result<Option<&'a mut SetObject>, RedisError> {
+    lookup(db, key).map(|set| set.val.set_mut()).transpose()
+}
+
+/// Looks a set up, creating it if missing
+fn set_entry(db: &mut Database, key: Key) -> Result<&mut SetObject, RedisError> {
+    if lookup(db, &key).is_none() {
+        db.insert(key.clone(), Set::new(RedisObject::Set(SetObject::default()), None));
+    }
+    db.get_mut(&key).map_or(Err(RedisError::WrongType), |set| set.val.set_mut())
+}
+
+/// Looks the sets at `keys` up, missing keys being `None`
+fn read_sets<'a>(db: &'a mut Database, keys: &[Key]) -> Result<Vec<Option<&'a SetObject>>, RedisError> {
+    // Expired keys and wrong types are dealt with first, so that the sets can then be read all at once
+    for key in keys {
+        read_set(db, key)?;
+    }
+    let db = &*db;
+    Ok(keys
+        .iter()
+        .map(|key| match db.get(key).map(|set| &set.val) {
+            Some(RedisObject::Set(set)) => Some(set),
+            _ => None,
+        })
+        .collect())
+}
+
+/// Members held by every one of `sets`, found as they are asked for
+fn intersection(sets: Vec<Option<&SetObject>>) -> impl Iterator<Item = Bytes> {
+    let mut sets: Vec<&SetObject> = sets.into_iter().collect::<Option<_>>().unwrap_or_default();
+    // Members of the smallest set are the only candidates
+    sets.sort_by_key(|set| set.len());
+    let smallest = sets.first().copied();
+    smallest.into_iter().flat_map(SetObject::iter).filter(move |m| sets[1..].iter().all(|set| set.contains(m)))
+}
+
+/// Combines the sets at `keys` with `op`, missing keys counting as empty sets
+fn combine(db: &mut Database, op: SetOp, keys: &[Key]) -> Result<SetObject, RedisError> {
+    let sets = read_sets(db, keys)?;
+    Ok(match op {
+        SetOp::Inter => intersection(sets).collect(),
+        SetOp::Union => sets.iter().flatten().flat_map(|set| set.members()).collect(),
+        SetOp::Diff => match sets.split_first() {
+            Some((Some(first), others)) => first
+                .members()
+                .into_iter()
+                .filter(|m| !others.iter().flatten().any(|set| set.contains(m)))
+                .collect(),
+            _ => SetObject::default(),
+        },
+    })
+}
+
+fn bulk_array(members: impl IntoIterator<Item = Bytes>) -> RedisValue {
+    RedisValue::Array(members.into_iter().map(RedisValue::BulkString).collect())
+}
+
+/// Picks `count` random members out of `members`, all distinct unless `repeat`
+fn pick_random(mut members: Vec<Bytes>, count: usize, repeat: bool) -> Vec<Bytes> {
+    if members.is_empty() {
+        return members;
+    }
+    if repeat {
+        let mut picked = Vec::with_capacity(count.min(RANDOM_PREALLOC));
+        for _ in 0..count {
+            picked.push(members[(random_u64() % members.len() as u64) as usize].clone());
+        }
+        return picked;
+    }
+    // Partial Fisher-Yates shuffle
+    let count = count.min(members.len());
+    for i in 0..count {
+        let j = i + (random_u64() % (members.len() - i) as u64) as usize;
+        members.swap(i, j);
+    }
+    members.truncate(count);
+    members
+}
+
+impl RespHandler {
+    /// `SADD key member [member ...]`, replying with the number of members added
+    pub(super) fn sadd(&mut self, key: &RedisValue, members: &[RedisValue]) -> CmdResult {
+        let key = self.keyize(key);
+        let mut db = self.map.lock().expect("unlock failed!");
+
+        let set = set_entry(&mut db, key)?;
+        let added = members.iter().filter(|m| set.insert(m.bytes_arg())).count();
+        Ok(RedisValue::Int(added as RedisInt))
+    }
+
+    /// `SREM key member [member ...]`, replying with the number of members removed
+    pub(super) fn srem(&mut self, key: &RedisValue, members: &[RedisValue]) -> CmdResult {
+        let key = self.keyize(key);
+        let mut db = self.map.lock().expect("unlock failed!");
+
+        let Some(set) = read_set(&mut db, &key)? else {
+            return Ok(RedisValue::Int(0));
+        };
+        let removed = members.iter().filter(|m| set.remove(&m.keyize())).count();
+        remove_if_empty(&mut db, &key);
+        Ok(RedisValue::Int(removed as RedisInt))
+    }
+
+    /// `SMEMBERS key`
+    pub(super) fn smembers(&mut self, key: &RedisValue) -> CmdResult {
+        let key = self.keyize(key);
+        let mut db = self.map.lock().expect("unlock failed!");
+
+        let members = read_set(&mut db, &key)?.map(|set| set.members()).unwrap_or_default();
+        Ok(bulk_array(members))
+    }
+
+    /// `SISMEMBER key member`, or `SMISMEMBER key member [member ...]` if `multi`
+    pub(super) fn smismember(&mut self, key: &RedisValue, members: &[RedisValue], multi: bool) -> CmdResult {
+        let key = self.keyize(key);
+        let mut db = self.map.lock().expect("unlock failed!");
+
+        let set = read_set(&mut db, &key)?;
+        let mut found = members
+            .iter()
+            .map(|m| RedisValue::Int(set.as_ref().is_some_and(|set| set.contains(&m.keyize())) as RedisInt));
+        Ok(match multi {
+            true => RedisValue::Array(found.collect()),
+            false => found.next().unwrap_or(RedisValue::Int(0)),
+        })
+    }
+
+    /// `SCARD key`
+    pub(super) fn scard(&mut self, key: &RedisValue) -> CmdResult {
+        let key = self.keyize(key);
+        let mut db = self.map.lock().expect("unlock failed!");
+
+        let len = read_set(&mut db, &key)?.map_or(0, |set| set.len());
+        Ok(RedisValue::Int(len as RedisInt))
+    }
+
+    /// `SPOP key [count]`, removing random members and replying with them
+    pub(super) fn spop(&mut self, args: &[RedisValue]) -> CmdResult {
+        let count = match &args[1..] {
+            [] => None,
+            [count] => Some(usize::try_from(count.int_arg()?).map_err(|_| {
+                RedisError::Other("value is out of range, must be positive".to_string())
+            })?),
+            _ => return Err(RedisError::Syntax),
+        };
+        let key = self.keyize(&args[0]);
+        let mut db = self.map.lock().expect("unlock failed!");
+
+        let Some(set) = read_set(&mut db, &key)? else {
+            return Ok(count.map_or(RedisValue::NullBulkString, |_| RedisValue::Array(vec![])));
+        };
+        let popped = set.random_members(count.unwrap_or(1), false);
+        for member in &popped {
+            set.remove(member);
+        }
+        remove_if_empty(&mut db, &key);
+
+        Ok(match count {
+            Some(_) => bulk_array(popped),
+            None => popped.into_iter().next().map_or(RedisValue::NullBulkString, RedisValue::BulkString),
+        })
+    }
+
+    /// `SRANDMEMBER key [count]`: a single random member, or `count` distinct ones (as many as there are at
+    /// most), possibly repeated if `count` is negative
+    pub(super) fn srandmember(&mut self, args: &[RedisValue]) -> CmdResult {
+        let count = match &args[1..] {
+            [] => None,
+            [count] => Some(random_count(count)?),
+            _ => return Err(RedisError::Syntax),
+        };
+        let key = self.keyize(&args[0]);
+        let mut db = self.map.lock().expect("unlock failed!");
+
+        let Some(set) = read_set(&mut db, &key)? else {
+            return Ok(count.map_or(RedisValue::NullBulkString, |_| RedisValue::Array(vec![])));
+        };
+        Ok(match count {
+            None => set.random_members(1, false).pop().map_or(RedisValue::NullBulkString, RedisValue::BulkString),
+            Some(count) => bulk_array(set.random_members(count.unsigned_abs() as usize, count < 0)),
+        })
+    }
+
+    /// `SMOVE source destination member`, replying with whether the member was moved
+    pub(super) fn smove(&mut self, src: &RedisValue, dst: &RedisValue, member: &RedisValue) -> CmdResult {
+        let (src, dst) = (self.keyize(src), self.keyize(dst));
+        let member = member.bytes_arg();
+        let mut db = self.map.lock().expect("unlock failed!");
+
+        read_set(&mut db, &dst)?;
+        let Some(set) = read_set(&mut db, &src)? else {
+            return Ok(RedisValue::Int(0));
+        };
+        if src == dst {
+            return Ok(RedisValue::Int(set.contains(&member) as RedisInt));
+        }
+        if !set.remove(&member) {
+            return Ok(RedisValue::Int(0));
+        }
+        remove_if_empty(&mut db, &src);
+        set_entry(&mut db, dst)?.insert(member);
+        Ok(RedisValue::Int(1))
+    }
+
+    /// `SINTER key [key ...]`, `SUNION` and `SDIFF`, replying with the members of the resulting set, or their
+    /// `STORE` variants if `dst` is given, which store it and reply with its size
+    pub(super) fn set_op(&mut self, op: SetOp, keys: &[RedisValue], dst: Option<&RedisValue>) -> CmdResult {
+        let keys: Vec<_> = keys.iter().map(|k| self.keyize(k)).collect();
+        let dst = dst.map(|dst| self.keyize(dst));
+        let mut db = self.map.lock().expect("unlock failed!");
+
+        let result = combine(&mut db, op, &keys)?;
+        let Some(dst) = dst else {
+            return Ok(bulk_array(result.members()));
+        };
+
+        // The destination is overwritten whatever it held, or deleted if the result is empty
+        let len = result.len();
+        if result.is_empty() {
+            db.remove(&dst);
+        } else {
+            db.insert(dst, Set::new(RedisObject::Set(result), None));
+        }
+        Ok(RedisValue::Int(len as RedisInt))
+    }
+
+    /// `SINTERCARD numkeys key [key ...] [LIMIT limit]`, the size of the intersection, counting stopping at
+    /// `limit` if not 0
+    pub(super) fn sintercard(&mut self, args: &[RedisValue]) -> CmdResult {
+        let numkeys = args[0].int_arg()?;
+        if numkeys <= 0 {
+            return Err(RedisError::Other("numkeys should be greater than 0".to_string()));
+        }
+        let numkeys = numkeys as usize;
+        if args.len() < numkeys + 1 {
+            return Err(RedisError::Other("Number of keys can't be greater than number of args".to_string()));
+        }
+        let limit = match &args[numkeys + 1..] {
+            [] => 0,
+            [opt, limit] if opt.option_name() == "limit" => usize::try_from(limit.int_arg()?)
+                .map_err(|_| RedisError::Other("LIMIT can't be negative".to_string()))?,
+            _ => return Err(RedisError::Syntax),
+        };
+        let keys: Vec<_> = args[1..=numkeys].iter().map(|k| self.keyize(k)).collect();
+        let mut db = self.map.lock().expect("unlock failed!");
+
+        let members = intersection(read_sets(&mut db, &keys)?);
+        let len = if limit == 0 { members.count() } else { members.take(limit).count() };
+        Ok(RedisValue::Int(len as RedisInt))
+    }
+
+    /// `SSCAN key cursor [MATCH pattern] [COUNT count]`
+    pub(super) fn sscan(&mut self, key: &RedisValue, args: &[RedisValue]) -> CmdResult {
+        let scan = ScanArgs::parse(args, false)?;
+        let key = self.keyize(key);
+        let mut db = self.map.lock().expect("unlock failed!");
+
+        let page = match read_set(&mut db, &key)? {
+            None => vec![],
+            Some(SetObject::HashTable { index, .. }) => {
+                let (cursor, page) = scan.page(index);
+                return Ok(scan_reply(cursor, page.into_iter().map(|m| RedisValue::BulkString(m.clone())).collect()));
+            }
+            // Compact sets are small enough to come whole in a single page, as Redis does
+            Some(set) => set.members().into_iter().filter(|m| scan.matches(m)).map(RedisValue::BulkString).collect(),
+        };
+        Ok(scan_reply(0, page))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::SetObject;
+    use crate::resp::test::{bulk, connected_handler};
+    use crate::{RedisError, RedisValue};
+    use bytes::Bytes;
+    use std::collections::HashSet;
+
+    fn sorted(reply: RedisValue) -> Vec<RedisValue> {
+        let RedisValue::Array(mut members) = reply else {
+            panic!("expected an array, got {:?}", reply);
+        };
+        members.sort_by_key(|m| m.unpack_bytes_variant().map(<[u8]>::to_vec));
+        members
+    }
+
+    fn bulks(members: &[&str]) -> Vec<RedisValue> {
+        members.iter().map(|m| bulk(m)).collect()
+    }
+
+    #[test]
+    fn encodings() {
+        let mut set: SetObject = ["3", "1", "2", "1"].map(Bytes::from).into_iter().collect();
+        assert_eq!(set, SetObject::IntSet(vec![1, 2, 3]));
+        // Not the canonical form of an integer
+        assert!(!set.contains(b"01"));
+        assert!(set.remove(b"2"));
+        assert!(set.insert(Bytes::from("a")));
+        assert_eq!(set.encoding(), "hashtable");
+        assert!(set.contains(b"3") && set.contains(b"a"));
+
+        let big: SetObject = (0..=super::INTSET_MAX_ENTRIES).map(|n| Bytes::from(n.to_string())).collect();
+        assert_eq!(big.encoding(), "hashtable");
+        assert_eq!(big.len(), super::INTSET_MAX_ENTRIES + 1);
+    }
+
+    #[tokio::test]
+    async fn members() {
+        let (mut handler, _client) = connected_handler().await;
+
+        assert_eq!(handler.handle_command("sadd", bulks(&["s", "a", "b", "a", "c"])).await, RedisValue::Int(3));
+        assert_eq!(handler.handle_command("type", vec![bulk("s")]).await, RedisValue::SimpleString("set".into()));
+        assert_eq!(handler.handle_command("scard", vec![bulk("s")]).await, RedisValue::Int(3));
+        assert_eq!(handler.handle_command("sismember", bulks(&["s", "b"])).await, RedisValue::Int(1));
+        assert_eq!(
+            handler.handle_command("smismember", bulks(&["s", "b", "z"])).await,
+            RedisValue::Array(vec![RedisValue::Int(1), RedisValue::Int(0)])
+        );
+        assert_eq!(handler.handle_command("srem", bulks(&["s", "b", "z"])).await, RedisValue::Int(1));
+        assert_eq!(sorted(handler.handle_command("smembers", vec![bulk("s")]).await), bulks(&["a", "c"]));
+
+        assert_eq!(handler.handle_command("smove", bulks(&["s", "t", "a"])).await, RedisValue::Int(1));
+        assert_eq!(handler.handle_command("smove", bulks(&["s", "t", "a"])).await, RedisValue::Int(0));
+        assert_eq!(handler.handle_command("smembers", vec![bulk("t")]).await, RedisValue::Array(bulks(&["a"])));
+
+        let popped = handler.handle_command("spop", bulks(&["s", "5"])).await;
+        assert_eq!(popped, RedisValue::Array(bulks(&["c"])));
+        assert_eq!(handler.handle_command("exists", vec![bulk("s")]).await, RedisValue::Int(0));
+        assert_eq!(handler.handle_command("spop", vec![bulk("s")]).await, RedisValue::NullBulkString);
+
+        handler.handle_command("sadd", bulks(&["n", "1", "2", "3", "4"])).await;
+        let RedisValue::Array(picked) = handler.handle_command("srandmember", bulks(&["n", "-10"])).await else {
+            panic!()
+        };
+        assert_eq!(picked.len(), 10);
+        assert_eq!(sorted(handler.handle_command("srandmember", bulks(&["n", "10"])).await).len(), 4);
+        assert_eq!(
+            handler.handle_command("srandmember", bulks(&["n", "-9223372036854775808"])).await,
+            RedisError::Other("value is out of range".to_string()).into()
+        );
+        assert_eq!(handler.handle_command("scard", vec![bulk("n")]).await, RedisValue::Int(4));
+
+        handler.handle_command("set", bulks(&["str", "v"])).await;
+        assert_eq!(handler.handle_command("sadd", bulks(&["str", "a"])).await, RedisError::WrongType.into());
+        assert_eq!(handler.handle_command("smove", bulks(&["n", "str", "1"])).await, RedisError::WrongType.into());
+    }
+
+    #[tokio::test]
+    async fn random_picks() {
+        let (mut handler, _client) = connected_handler().await;
+        let mut args = vec![bulk("s")];
+        args.extend((0..100).map(|i| bulk(&format!("m{}", i))));
+        handler.handle_command("sadd", args).await;
+
+        for count in ["10", "50", "100"] {
+            let mut picked = sorted(handler.handle_command("srandmember", bulks(&["s", count])).await);
+            picked.dedup();
+            assert_eq!(picked.len().to_string(), count);
+        }
+        let mut popped = sorted(handler.handle_command("spop", bulks(&["s", "30"])).await);
+        popped.dedup();
+        assert_eq!(popped.len(), 30);
+        assert_eq!(handler.handle_command("scard", vec![bulk("s")]).await, RedisValue::Int(70));
+
+        // Every member comes up sooner or later
+        let mut seen = HashSet::new();
+        for _ in 0..2000 {
+            let RedisValue::BulkString(member) = handler.handle_command("srandmember", vec![bulk("s")]).await else {
+                panic!()
+            };
+            seen.insert(member);
+        }
+        assert_eq!(seen.len(), 70);
+    }
+
+    #[tokio::test]
+    async fn algebra() {
+        let (mut handler, _client) = connected_handler().await;
+        handler.handle_command("sadd", bulks(&["a", "1", "2", "3", "x"])).await;
+        handler.handle_command("sadd", bulks(&["b", "2", "3", "4"])).await;
+        handler.handle_command("sadd", bulks(&["c", "3", "x", "y"])).await;
+
+        assert_eq!(sorted(handler.handle_command("sinter", bulks(&["a", "b"])).await), bulks(&["2", "3"]));
+        assert_eq!(sorted(handler.handle_command("sinter", bulks(&["a", "b", "c"])).await), bulks(&["3"]));
+        assert_eq!(handler.handle_command("sinter", bulks(&["a", "nope"])).await, RedisValue::Array(vec![]));
+        assert_eq!(
+            sorted(handler.handle_command("sunion", bulks(&["a", "b", "nope"])).await),
+            bulks(&["1", "2", "3", "4", "x"])
+        );
+        assert_eq!(sorted(handler.handle_command("sdiff", bulks(&["a", "b", "c"])).await), bulks(&["1"]));
+
+        assert_eq!(handler.handle_command("sunionstore", bulks(&["d", "b", "c"])).await, RedisValue::Int(5));
+        assert_eq!(handler.handle_command("scard", vec![bulk("d")]).await, RedisValue::Int(5));
+        assert_eq!(handler.handle_command("sinterstore", bulks(&["d", "b", "nope"])).await, RedisValue::Int(0));
+        assert_eq!(handler.handle_command("exists", vec![bulk("d")]).await, RedisValue::Int(0));
+
+        assert_eq!(handler.handle_command("sintercard", bulks(&["2", "a", "b"])).await, RedisValue::Int(2));
+        assert_eq!(
+            handler.handle_command("sintercard", bulks(&["2", "a", "b", "LIMIT", "1"])).await,
+            RedisValue::Int(1)
+        );
+        assert_eq!(
+            handler.handle_command("sintercard", bulks(&["0", "a"])).await,
+            RedisError::Other("numkeys should be greater than 0".to_string()).into()
+        );
+
+        let reply = handler.handle_command("sscan", bulks(&["a", "0", "MATCH", "[0-9]"])).await;
+        let RedisValue::Array(reply) = reply else { panic!() };
+        assert_eq!(reply[0], bulk("0"));
+        assert_eq!(sorted(reply[1].clone()), bulks(&["1", "2", "3"]));
+
+        // Wildcards are not backtracked to one by one, which would take ages here
+        let long = bulk(&"a".repeat(40));
+        handler.handle_command("sadd", vec![bulk("long"), long.clone()]).await;
+        for (pattern, matched) in [("*a*a*a*a*a*a*a*a*b", vec![]), ("*a*a*a*a*a*a*a*a*a", vec![long])] {
+            let reply = handler.handle_command("sscan", bulks(&["long", "0", "MATCH", pattern])).await;
+            assert_eq!(reply, RedisValue::Array(vec![bulk("0"), RedisValue::Array(matched)]));
+        }
+        let reply = handler.handle_command("sscan", bulks(&["a", "0", "MATCH", "\\[*[^1-2]"])).await;
+        assert_eq!(reply, RedisValue::Array(vec![bulk("0"), RedisValue::Array(vec![])]));
+        let reply = handler.handle_command("sscan", bulks(&["a", "0", "MATCH", "*[^1-2]"])).await;
+        let RedisValue::Array(reply) = reply else { panic!() };
+        assert_eq!(sorted(reply[1].clone()), bulks(&["3", "x"]));
+    }
+
+    #[tokio::test]
+    async fn scan_pages() {
+        let (mut handler, _client) = connected_handler().await;
+        handler.handle_command("sadd", bulks(&["ints", "1", "2", "3"])).await;
+        assert_eq!(
+            handler.handle_command("sscan", bulks(&["ints", "0", "COUNT", "1"])).await,
+            RedisValue::Array(vec![bulk("0"), RedisValue::Array(bulks(&["1", "2", "3"]))])
+        );
+
+        let members: Vec<String> = (0..500).map(|i| format!("m{}", i)).collect();
+        let mut args = vec![bulk("s")];
+        args.extend(members.iter().map(|m| bulk(m)));
+        handler.handle_command("sadd", args).await;
+
+        // Members removed along the way may be missed, never those left in the set
+        let (mut cursor, mut seen) = ("0".to_string(), vec![]);
+        loop {
+            let reply = handler.handle_command("sscan", bulks(&["s", &cursor, "COUNT", "20"])).await;
+            let RedisValue::Array(reply) = reply else { panic!() };
+            let [RedisValue::BulkString(next), RedisValue::Array(page)] = &reply[..] else { panic!() };
+            assert!(page.len() < 40);
+            for member in page {
+                seen.push(member.clone());
+                handler.handle_command("srem", vec![bulk("s"), bulk(&format!("m{}", seen.len() * 7 % 500))]).await;
+            }
+            cursor = String::from_utf8(next.to_vec()).unwrap();
+            if cursor == "0" {
+                break;
+            }
+        }
+        let RedisValue::Array(left) = handler.handle_command("smembers", vec![bulk("s")]).await else { panic!() };
+        assert!(!left.is_empty());
+        assert!(left.iter().all(|member| seen.contains(member)));
+    }
+}
@@ -0,0 +1,293 @@
+// Skiplist ordering the members of sorted sets by score then name, spans along the links giving ranks in
+// O(log n) as in Redis' zskiplist. Nodes live in an arena and refer to each other by index.
+
+use crate::random_u64;
+use bytes::Bytes;
+use std::cmp::Ordering;
+
+const MAX_LEVEL: usize = 32;
+/// One node out of this many reaching a level also reaches the next one
+const BRANCHING: u64 = 4;
+/// Index of no node, ending the links
+const NIL: usize = usize::MAX;
+/// Index of the head node, which holds no member and reaches every level
+const HEAD: usize = 0;
+
+#[derive(Debug, Clone, Copy)]
+struct Link {
+    next: usize,
+    /// Number of nodes walked past when following the link, the nodes left until the end if it ends the level
+    span: usize,
+}
+
+#[derive(Debug, Clone)]
+struct Node {
+    score: f64,
+    member: Bytes,
+    links: Vec<Link>,
+    prev: usize,
+}
+
+impl Node {
+    fn new(score: f64, member: Bytes, level: usize) -> Self {
+        Node { score, member, links: vec![Link { next: NIL, span: 0 }; level], prev: NIL }
+    }
+
+    fn cmp(&self, score: f64, member: &[u8]) -> Ordering {
+        // Scores are never NaN
+        self.score.partial_cmp(&score).unwrap_or(Ordering::Equal).then_with(|| self.member.as_ref().cmp(member))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(super) struct SkipList {
+    nodes: Vec<Node>,
+    /// Slots of removed nodes, reused by the next insertions
+    free: Vec<usize>,
+    tail: usize,
+    level: usize,
+    len: usize,
+}
+
+impl Default for SkipList {
+    fn default() -> Self {
+        SkipList { nodes: vec![Node::new(0.0, Bytes::new(), MAX_LEVEL)], free: vec![], tail: NIL, level: 1, len: 0 }
+    }
+}
+
+impl SkipList {
+    fn link(&self, node: usize, level: usize) -> Link {
+        self.nodes[node].links[level]
+    }
+
+    fn random_level() -> usize {
+        let mut level = 1;
+        while level < MAX_LEVEL && random_u64().is_multiple_of(BRANCHING) {
+            level += 1;
+        }
+        level
+    }
+
+    /// Inserts a member, which must not be in the list already
+    pub(super) fn insert(&mut self, score: f64, member: Bytes) {
+        let mut update = [HEAD; MAX_LEVEL];
+        // Rank of the node at `update[i]`, counting the head as 0
+        let mut rank = [0; MAX_LEVEL];
+        let mut x = HEAD;
+        for i in (0..self.level).rev() {
+            rank[i] = if i + 1 == self.level { 0 } else { rank[i + 1] };
+            loop {
+                let link = self.link(x, i);
+                if link.next == NIL || self.nodes[link.next].cmp(score, &member) != Ordering::Less {
+                    break;
+                }
+                rank[i] += link.span;
+                x = link.next;
+            }
+            update[i] = x;
+        }
+
+        let level = Self::random_level();
+        if level > self.level {
+            for i in self.level..level {
+                self.nodes[HEAD].links[i].span = self.len;
+            }
+            self.level = level;
+        }
+
+        let new = Node::new(score, member, level);
+        let new = match self.free.pop() {
+            Some(slot) => {
+                self.nodes[slot] = new;
+                slot
+            }
+            None => {
+                self.nodes.push(new);
+                self.nodes.len() - 1
+            }
+        };
+        for i in 0..level {
+            let link = self.link(update[i], i);
+            self.nodes[new].links[i] = Link { next: link.next, span: link.span - (rank[0] - rank[i]) };
+            self.nodes[update[i]].links[i] = Link { next: new, span: rank[0] - rank[i] + 1 };
+        }
+        for (i, &node) in update.iter().enumerate().take(self.level).skip(level) {
+            self.nodes[node].links[i].span += 1;
+        }
+
+        self.nodes[new].prev = if update[0] == HEAD { NIL } else { update[0] };
+        match self.link(new, 0).next {
+            NIL => self.tail = new,
+            next => self.nodes[next].prev = new,
+        }
+        self.len += 1;
+    }
+
+    /// Removes a member along with the score it was inserted with, replying with whether it was found
+    pub(super) fn remove(&mut self, score: f64, member: &[u8]) -> bool {
+        let mut update = [HEAD; MAX_LEVEL];
+        let mut x = HEAD;
+        for i in (0..self.level).rev() {
+            loop {
+                let next = self.link(x, i).next;
+                if next == NIL || self.nodes[next].cmp(score, member) != Ordering::Less {
+                    break;
+                }
+                x = next;
+            }
+            update[i] = x;
+        }
+        let x = self.link(x, 0).next;
+        if x == NIL || self.nodes[x].cmp(score, member) != Ordering::Equal {
+            return false;
+        }
+
+        for (i, &node) in update.iter().enumerate().take(self.level) {
+            let link = &mut self.nodes[node].links[i];
+            if link.next == x {
+                let removed = self.nodes[x].links[i];
+                let link = &mut self.nodes[node].links[i];
+                link.span = link.span + removed.span - 1;
+                link.next = removed.next;
+            } else {
+                link.span -= 1;
+            }
+        }
+        let prev = self.nodes[x].prev;
+        match self.link(x, 0).next {
+            NIL => self.tail = prev,
+            next => self.nodes[next].prev = prev,
+        }
+        while self.level > 1 && self.link(HEAD, self.level - 1).next == NIL {
+            self.level -= 1;
+        }
+
+        // The slot keeps no reference to the member
+        self.nodes[x] = Node::new(0.0, Bytes::new(), 0);
+        self.free.push(x);
+        self.len -= 1;
+        true
+    }
+
+    /// Rank of a member, 0 being the lowest, provided the score it was inserted with
+    pub(super) fn rank(&self, score: f64, member: &[u8]) -> Option<usize> {
+        let mut rank = 0;
+        let mut x = HEAD;
+        for i in (0..self.level).rev() {
+            loop {
+                let link = self.link(x, i);
+                if link.next == NIL || self.nodes[link.next].cmp(score, member) == Ordering::Greater {
+                    break;
+                }
+                rank += link.span;
+                x = link.next;
+            }
+            if x != HEAD && self.nodes[x].cmp(score, member) == Ordering::Equal {
+                return Some(rank - 1);
+            }
+        }
+        None
+    }
+
+    /// Number of nodes, from the lowest, for which `before` holds. `before` must hold for the nodes up to some
+    /// point and not anymore past it, like a range boundary.
+    pub(super) fn count_while(&self, before: impl Fn(f64, &[u8]) -> bool) -> usize {
+        let mut rank = 0;
+        let mut x = HEAD;
+        for i in (0..self.level).rev() {
+            loop {
+                let link = self.link(x, i);
+                if link.next == NIL || !before(self.nodes[link.next].score, &self.nodes[link.next].member) {
+                    break;
+                }
+                rank += link.span;
+                x = link.next;
+            }
+        }
+        rank
+    }
+
+    /// Index of the node at a rank
+    fn node_at(&self, rank: usize) -> usize {
+        if rank >= self.len {
+            return NIL;
+        }
+        // Popping the highest member needs no walk
+        if rank + 1 == self.len {
+            return self.tail;
+        }
+        let target = rank + 1;
+        let mut traversed = 0;
+        let mut x = HEAD;
+        for i in (0..self.level).rev() {
+            loop {
+                let link = self.link(x, i);
+                if link.next == NIL || traversed + link.span > target {
+                    break;
+                }
+                traversed += link.span;
+                x = link.next;
+            }
+            if traversed == target {
+                return x;
+            }
+        }
+        NIL
+    }
+
+    /// Members along with their scores, from the one at `rank` towards the highest, or towards the lowest if
+    /// `rev`
+    pub(super) fn iter_from(&self, rank: usize, rev: bool) -> Iter<'_> {
+        Iter { list: self, node: self.node_at(rank), rev }
+    }
+}
+
+pub(super) struct Iter<'a> {
+    list: &'a SkipList,
+    node: usize,
+    rev: bool,
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = (&'a Bytes, f64);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let node = self.list.nodes.get(self.node)?;
+        self.node = if self.rev { node.prev } else { node.links[0].next };
+        Some((&node.member, node.score))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::SkipList;
+    use bytes::Bytes;
+
+    #[test]
+    fn ranks_survive_updates() {
+        let mut list = SkipList::default();
+        let mut expected: Vec<(f64, Bytes)> = vec![];
+        for i in 0..1000u64 {
+            let score = ((i * 7919) % 101) as f64;
+            let member = Bytes::from(format!("m{}", i));
+            list.insert(score, member.clone());
+            expected.push((score, member));
+        }
+        for (score, member) in expected.iter().filter(|(score, _)| (*score as u64).is_multiple_of(3)) {
+            assert!(list.remove(*score, member));
+        }
+        assert!(!list.remove(1000.0, b"m0"));
+        expected.retain(|(score, _)| !(*score as u64).is_multiple_of(3));
+        expected.sort_by(|a, b| a.0.total_cmp(&b.0).then_with(|| a.1.cmp(&b.1)));
+
+        assert_eq!(list.len, expected.len());
+        for (rank, (score, member)) in expected.iter().enumerate() {
+            assert_eq!(list.rank(*score, member), Some(rank));
+        }
+        let walked: Vec<_> = list.iter_from(0, false).map(|(m, s)| (s, m.clone())).collect();
+        assert_eq!(walked, expected);
+        let back: Vec<_> = list.iter_from(expected.len() - 1, true).map(|(m, _)| m.clone()).collect();
+        assert_eq!(back, expected.iter().rev().map(|(_, m)| m.clone()).collect::<Vec<_>>());
+        assert_eq!(list.count_while(|score, _| score < 50.0), expected.iter().filter(|(s, _)| *s < 50.0).count());
+    }
+}
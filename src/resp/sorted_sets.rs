@@ -0,0 +1,857 @@
+// Sorted set commands: members ordered by a floating point score, ranked through a skiplist
+
+use super::sets::SetOp;
+use super::skiplist::SkipList;
+use super::strings::resolve_range;
+use super::{Database, SetObject, Protocol, RedisObject, RespHandler, Set, lookup, remove_if_empty};
+use crate::error::{CmdResult, RedisError};
+use crate::resp::{Key, RedisInt, RedisValue};
+use bytes::Bytes;
+use std::collections::HashMap;
+
+/// Members of a sorted set, indexed both by name and by score
+#[derive(Debug, Clone, Default)]
+pub struct SortedSet {
+    scores: HashMap<Bytes, f64>,
+    index: SkipList,
+}
+
+// The index only orders what the scores hold
+impl PartialEq for SortedSet {
+    fn eq(&self, other: &Self) -> bool {
+        self.scores == other.scores
+    }
+}
+
+impl FromIterator<(Bytes, f64)> for SortedSet {
+    fn from_iter<I: IntoIterator<Item = (Bytes, f64)>>(entries: I) -> Self {
+        let mut zset = SortedSet::default();
+        for (member, score) in entries {
+            zset.insert(member, score);
+        }
+        zset
+    }
+}
+
+impl SortedSet {
+    pub fn len(&self) -> usize {
+        self.scores.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.scores.is_empty()
+    }
+
+    pub fn score(&self, member: &[u8]) -> Option<f64> {
+        self.scores.get(member).copied()
+    }
+
+    /// Adds a member or updates its score, replying with the score it had
+    pub fn insert(&mut self, member: Bytes, score: f64) -> Option<f64> {
+        let old = self.scores.insert(member.clone(), score);
+        match old {
+            Some(old) if old == score => {}
+            Some(old) => {
+                self.index.remove(old, &member);
+                self.index.insert(score, member);
+            }
+            None => self.index.insert(score, member),
+        }
+        old
+    }
+
+    /// Removes a member, replying with the score it had
+    pub fn remove(&mut self, member: &[u8]) -> Option<f64> {
+        let score = self.scores.remove(member)?;
+        self.index.remove(score, member);
+        Some(score)
+    }
+
+    /// Rank of a member, 0 being the lowest score
+    pub fn rank(&self, member: &[u8]) -> Option<usize> {
+        self.index.rank(self.score(member)?, member)
+    }
+
+    /// Members along with their scores, from the lowest score to the highest
+    pub fn iter(&self) -> impl Iterator<Item = (&Bytes, f64)> {
+        self.index.iter_from(0, false)
+    }
+
+    /// Members with a score from `min` included to `max` excluded, from the lowest score
+    pub(super) fn score_range(&self, min: f64, max: f64) -> impl Iterator<Item = (&Bytes, f64)> {
+        let start = self.index.count_while(|score, _| score < min);
+        let end = self.index.count_while(|score, _| score < max);
+        self.index.iter_from(start, false).take(end.saturating_sub(start))
+    }
+
+    /// Ranks, from the lowest, of the members within `range`, as a `start..end` range
+    fn ranks(&self, range: &Range) -> (usize, usize) {
+        let len = self.len();
+        let (start, end) = match range {
+            Range::Rank(start, end) => match resolve_range(*start, *end, len) {
+                Some((start, end)) => (start, end + 1),
+                None => (0, 0),
+            },
+            Range::Score(min, max) => (
+                self.index.count_while(|score, _| min.below_as_min(score)),
+                self.index.count_while(|score, _| !max.above_as_max(score)),
+            ),
+            Range::Lex(min, max) => (
+                self.index.count_while(|_, member| min.below_as_min(member)),
+                self.index.count_while(|_, member| !max.above_as_max(member)),
+            ),
+        };
+        (start, end.max(start))
+    }
+
+    /// Members within `range` along with their scores, in reverse order if `rev` (ranks counting from the
+    /// highest score then), `limit` skipping `offset` of them and keeping `count` at most (all if negative)
+    fn select(&self, range: &Range, rev: bool, limit: Option<(RedisInt, RedisInt)>) -> Vec<(Bytes, f64)> {
+        let (mut start, mut end) = self.ranks(range);
+        if rev && matches!(range, Range::Rank(..)) {
+            (start, end) = (self.len() - end, self.len() - start);
+        }
+        let (offset, count) = limit.unwrap_or((0, -1));
+        let Ok(offset) = usize::try_from(offset) else {
+            return vec![];
+        };
+        if start + offset >= end {
+            return vec![];
+        }
+        let left = end - start - offset;
+        let count = usize::try_from(count).map_or(left, |count| count.min(left));
+
+        let first = if rev { end - 1 - offset } else { start + offset };
+        self.index.iter_from(first, rev).take(count).map(|(member, score)| (member.clone(), score)).collect()
+    }
+}
+
+/// Score boundary of a range: `1.5` includes the score, `(1.5` excludes it, `-inf` and `+inf` are unbounded
+#[derive(Debug, Clone, Copy)]
+struct ScoreBound {
+    score: f64,
+    exclusive: bool,
+}
+
+impl ScoreBound {
+    fn parse(arg: &RedisValue) -> Result<Self, RedisError> {
+        let invalid = || RedisError::Other("min or max is not a float".to_string());
+        let bytes = arg.bytes_arg();
+        let (exclusive, score) = match bytes.strip_prefix(b"(") {
+            Some(score) => (true, RedisValue::BulkString(Bytes::copy_from_slice(score))),
+            None => (false, arg.clone()),
+        };
+        Ok(ScoreBound { score: score.float_arg().map_err(|_| invalid())?, exclusive })
+    }
+
+    /// Whether `score` comes before a range starting at this bound
+    fn below_as_min(&self, score: f64) -> bool {
+        if self.exclusive { score <= self.score } else { score < self.score }
+    }
+
+    /// Whether `score` comes past a range ending at this bound
+    fn above_as_max(&self, score: f64) -> bool {
+        if self.exclusive { score >= self.score } else { score > self.score }
+    }
+}
+
+/// Lexicographical boundary of a range: `[a` includes `a`, `(a` excludes it, `-` and `+` are unbounded
+#[derive(Debug, Clone)]
+enum LexBound {
+    Min,
+    Max,
+    Inclusive(Bytes),
+    Exclusive(Bytes),
+}
+
+impl LexBound {
+    fn parse(arg: &RedisValue) -> Result<Self, RedisError> {
+        let bytes = arg.bytes_arg();
+        Ok(match bytes.first() {
+            Some(b'-') if bytes.len() == 1 => LexBound::Min,
+            Some(b'+') if bytes.len() == 1 => LexBound::Max,
+            Some(b'[') => LexBound::Inclusive(bytes.slice(1..)),
+            Some(b'(') => LexBound::Exclusive(bytes.slice(1..)),
+            _ => return Err(RedisError::Other("min or max not valid string range item".to_string())),
+        })
+    }
+
+    fn below_as_min(&self, member: &[u8]) -> bool {
+        match self {
+            LexBound::Min => false,
+            LexBound::Max => true,
+            LexBound::Inclusive(bound) => member < bound.as_ref(),
+            LexBound::Exclusive(bound) => member <= bound.as_ref(),
+        }
+    }
+
+    fn above_as_max(&self, member: &[u8]) -> bool {
+        match self {
+            LexBound::Min => true,
+            LexBound::Max => false,
+            LexBound::Inclusive(bound) => member > bound.as_ref(),
+            LexBound::Exclusive(bound) => member >= bound.as_ref(),
+        }
+    }
+}
+
+/// Members to select out of a sorted set, in any of the ways ZRANGE takes
+#[derive(Debug, Clone)]
+enum Range {
+    Rank(RedisInt, RedisInt),
+    Score(ScoreBound, ScoreBound),
+    Lex(LexBound, LexBound),
+}
+
+/// `start stop [BYSCORE | BYLEX] [REV] [LIMIT offset count] [WITHSCORES]`, as ZRANGE takes it
+#[derive(Debug)]
+struct RangeArgs {
+    range: Range,
+    rev: bool,
+    limit: Option<(RedisInt, RedisInt)>,
+    withscores: bool,
+}
+
+impl RangeArgs {
+    fn parse(args: &[RedisValue]) -> Result<Self, RedisError> {
+        let (mut by, mut rev, mut limit, mut withscores) = (None, false, None, false);
+        let mut opts = args[2..].iter();
+        while let Some(opt) = opts.next() {
+            match opt.option_name().as_str() {
+                by_opt @ ("byscore" | "bylex") => by = Some(by_opt == "bylex"),
+                "rev" => rev = true,
+                "withscores" => withscores = true,
+                "limit" => {
+                    let mut next = || opts.next().ok_or(RedisError::Syntax)?.int_arg();
+                    limit = Some((next()?, next()?));
+                }
+                _ => return Err(RedisError::Syntax),
+            }
+        }
+        if limit.is_some() && by.is_none() {
+            return Err(RedisError::Other(
+                "syntax error, LIMIT is only supported in combination with either BYSCORE or BYLEX".to_string(),
+            ));
+        }
+        if withscores && by == Some(true) {
+            return Err(RedisError::Other(
+                "syntax error, WITHSCORES not supported in combination with BYLEX".to_string(),
+            ));
+        }
+
+        // Reversed score and lex ranges are given from the highest bound to the lowest
+        let (min, max) = if rev && by.is_some() { (&args[1], &args[0]) } else { (&args[0], &args[1]) };
+        let range = match by {
+            None => Range::Rank(args[0].int_arg()?, args[1].int_arg()?),
+            Some(false) => Range::Score(ScoreBound::parse(min)?, ScoreBound::parse(max)?),
+            Some(true) => Range::Lex(LexBound::parse(min)?, LexBound::parse(max)?),
+        };
+        Ok(RangeArgs { range, rev, limit, withscores })
+    }
+}
+
+/// End of a sorted set to pop from
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(super) enum ZEnd {
+    Min,
+    Max,
+}
+
+/// How the scores of a member in several sets make its score in their union or intersection
+#[derive(Debug, Clone, Copy)]
+enum Aggregate {
+    Sum,
+    Min,
+    Max,
+}
+
+impl Aggregate {
+    fn apply(self, a: f64, b: f64) -> f64 {
+        match self {
+            // inf + -inf is 0 rather than NaN, as in Redis
+            Aggregate::Sum => Some(a + b).filter(|sum| !sum.is_nan()).unwrap_or(0.0),
+            Aggregate::Min => a.min(b),
+            Aggregate::Max => a.max(b),
+        }
+    }
+}
+
+/// Looks a sorted set up, WRONGTYPE if the key holds anything else
+pub(super) fn read_zset<'a>(db: &'a mut Database, key: &[u8]) -> Result<Option<&'a mut SortedSet>, RedisError> {
+    lookup(db, key).map(|set| set.val.zset_mut()).transpose()
+}
+
+/// Looks a sorted set up to add to it, creating it if missing
+fn zset_entry(db: &mut Database, key: Key) -> Result<&mut SortedSet, RedisError> {
+    if lookup(db, &key).is_none() {
+        db.insert(key.clone(), Set::new(RedisObject::SortedSet(SortedSet::default()), None));
+    }
+    db.signal_ready(&key);
+    db.get_mut(&key).map_or(Err(RedisError::WrongType), |set| set.val.zset_mut())
+}
+
+/// Pops up to `count` members of the sorted set at `key` from `end`, along with their scores. Replies `None`
+/// if there is no sorted set at `key`.
+pub(super) fn zset_pop(
+    db: &mut Database,
+    key: &[u8],
+    end: ZEnd,
+    count: usize,
+) -> Result<Option<Vec<(Bytes, f64)>>, RedisError> {
+    let Some(zset) = read_zset(db, key)? else {
+        return Ok(None);
+    };
+    let popped: Vec<_> = match end {
+        ZEnd::Min => zset.index.iter_from(0, false),
+        ZEnd::Max => zset.index.iter_from(zset.len().saturating_sub(1), true),
+    }
+    .take(count)
+    .map(|(member, score)| (member.clone(), score))
+    .collect();
+    for (member, _) in &popped {
+        zset.remove(member);
+    }
+    remove_if_empty(db, key);
+    Ok(Some(popped))
+}
+
+/// Members of a sorted set or a set, the latter's members all scoring 1, as ZUNIONSTORE and ZINTERSTORE take
+enum Input<'a> {
+    Sorted(&'a SortedSet),
+    Plain(&'a SetObject),
+}
+
+impl Input<'_> {
+    fn len(&self) -> usize {
+        match self {
+            Input::Sorted(zset) => zset.len(),
+            Input::Plain(set) => set.len(),
+        }
+    }
+
+    fn score(&self, member: &[u8]) -> Option<f64> {
+        match self {
+            Input::Sorted(zset) => zset.score(member),
+            Input::Plain(set) => set.contains(member).then_some(1.0),
+        }
+    }
+
+    fn entries(&self) -> Vec<(Bytes, f64)> {
+        match self {
+            Input::Sorted(zset) => zset.iter().map(|(member, score)| (member.clone(), score)).collect(),
+            Input::Plain(set) => set.members().into_iter().map(|member| (member, 1.0)).collect(),
+        }
+    }
+}
+
+/// Combines the sorted sets (or sets) at `keys`, their scores multiplied by `weights`, missing keys counting
+/// as empty sets
+fn combine(
+    db: &mut Database,
+    op: SetOp,
+    keys: &[Key],
+    weights: &[f64],
+    aggregate: Aggregate,
+) -> Result<SortedSet, RedisError> {
+    // Expired keys and wrong types are dealt with first, so that the sets can then be read all at once
+    for key in keys {
+        if let Some(set) = lookup(db, key)
+            && !matches!(set.val, RedisObject::SortedSet(_) | RedisObject::Set(_))
+        {
+            return Err(RedisError::WrongType);
+        }
+    }
+    let inputs: Vec<Option<Input>> = keys
+        .iter()
+        .map(|key| match db.get(key).map(|set| &set.val) {
+            Some(RedisObject::SortedSet(zset)) => Some(Input::Sorted(zset)),
+            Some(RedisObject::Set(set)) => Some(Input::Plain(set)),
+            _ => None,
+        })
+        .collect();
+    let weighted = |i: usize, score: f64| Some(score * weights[i]).filter(|s| !s.is_nan()).unwrap_or(0.0);
+
+    Ok(match op {
+        SetOp::Union => {
+            let mut scores: HashMap<Bytes, f64> = HashMap::new();
+            for (i, input) in inputs.iter().enumerate() {
+                for (member, score) in input.iter().flat_map(Input::entries) {
+                    let score = weighted(i, score);
+                    scores.entry(member).and_modify(|s| *s = aggregate.apply(*s, score)).or_insert(score);
+                }
+            }
+            scores.into_iter().collect()
+        }
+        SetOp::Inter => {
+            let Some(inputs) = inputs.into_iter().collect::<Option<Vec<_>>>() else {
+                return Ok(SortedSet::default());
+            };
+            // Members of the smallest set are the only candidates
+            let smallest = (0..inputs.len()).min_by_key(|&i| inputs[i].len()).expect("at least one key is given");
+            inputs[smallest]
+                .entries()
+                .into_iter()
+                .filter_map(|(member, _)| {
+                    let mut scores =
+                        inputs.iter().enumerate().map(|(i, input)| Some(weighted(i, input.score(&member)?)));
+                    let first = scores.next()??;
+                    let score = scores.try_fold(first, |acc, score| Some(aggregate.apply(acc, score?)))?;
+                    Some((member, score))
+                })
+                .collect()
+        }
+        SetOp::Diff => match inputs.split_first() {
+            Some((Some(first), others)) => first
+                .entries()
+                .into_iter()
+                .filter(|(member, _)| {
+                    !others.iter().flatten().any(|other| other.score(member).is_some())
+                })
+                .collect(),
+            _ => SortedSet::default(),
+        },
+    })
+}
+
+/// Overwrites `dst` with `zset`, or deletes it if `zset` is empty, replying with the size of `zset`
+pub(super) fn store_zset(db: &mut Database, dst: Key, zset: SortedSet) -> RedisValue {
+    let len = zset.len();
+    if zset.is_empty() {
+        db.remove(&dst);
+    } else {
+        db.insert(dst, Set::new(RedisObject::SortedSet(zset), None));
+    }
+    RedisValue::Int(len as RedisInt)
+}
+
+impl RespHandler {
+    /// Replies with members, along with their scores if `withscores`: interleaved in RESP2, as pairs in RESP3
+    fn scored_reply(&self, entries: Vec<(Bytes, f64)>, withscores: bool) -> RedisValue {
+        RedisValue::Array(match (withscores, self.protocol) {
+            (false, _) => entries.into_iter().map(|(member, _)| RedisValue::BulkString(member)).collect(),
+            (true, Protocol::Resp3) => entries
+                .into_iter()
+                .map(|(m, score)| RedisValue::Array(vec![RedisValue::BulkString(m), RedisValue::Double(score)]))
+                .collect(),
+            (true, Protocol::Resp2) => entries
+                .into_iter()
+                .flat_map(|(member, score)| [RedisValue::BulkString(member), RedisValue::Double(score)])
+                .collect(),
+        })
+    }
+
+    /// `ZADD key [NX | XX] [GT | LT] [CH] [INCR] score member [score member ...]`, replying with the number of
+    /// members added (or changed too, with CH), or with the new score of the member with INCR
+    pub(super) fn zadd(&mut self, args: &[RedisValue]) -> CmdResult {
+        let (mut nx, mut xx, mut gt, mut lt, mut ch, mut incr) = (false, false, false, false, false, false);
+        let mut pairs = &args[1..];
+        while let Some(opt) = pairs.first() {
+            match opt.option_name().as_str() {
+                "nx" => nx = true,
+                "xx" => xx = true,
+                "gt" => gt = true,
+                "lt" => lt = true,
+                "ch" => ch = true,
+                "incr" => incr = true,
+                _ => break,
+            }
+            pairs = &pairs[1..];
+        }
+        if nx && xx {
+            return Err(RedisError::Other("XX and NX options at the same time are not compatible".to_string()));
+        }
+        if (gt && lt) || (nx && (gt || lt)) {
+            return Err(RedisError::Other(
+                "GT, LT, and/or NX options at the same time are not compatible".to_string(),
+            ));
+        }
+        if pairs.is_empty() || !pairs.len().is_multiple_of(2) {
+            return Err(RedisError::Syntax);
+        }
+        if incr && pairs.len() > 2 {
+            return Err(RedisError::Other("INCR option supports a single increment-element pair".to_string()));
+        }
+        let pairs = pairs
+            .chunks(2)
+            .map(|pair| Ok((pair[0].float_arg()?, pair[1].bytes_arg())))
+            .collect::<Result<Vec<_>, RedisError>>()?;
+
+        let key = self.keyize(&args[0]);
+        let mut db = self.map.lock().expect("unlock failed!");
+        let zset = zset_entry(&mut db, key.clone())?;
+
+        let (mut added, mut changed, mut incremented) = (0, 0, None);
+        for (score, member) in pairs {
+            let current = zset.score(&member);
+            let score = match (current, incr) {
+                (Some(current), true) => current + score,
+                _ => score,
+            };
+            if score.is_nan() {
+                remove_if_empty(&mut db, &key);
+                return Err(RedisError::Other("resulting score is not a number (NaN)".to_string()));
+            }
+            match current {
+                None if xx => continue,
+                Some(_) if nx => continue,
+                Some(current) if (gt && score <= current) || (lt && score >= current) => continue,
+                None => added += 1,
+                Some(current) if current != score => changed += 1,
+                Some(_) => {}
+            }
+            zset.insert(member, score);
+            incremented = Some(score);
+        }
+
+        remove_if_empty(&mut db, &key);
+        Ok(match incr {
+            // The increment was not applied, as the options asked
+            true => incremented.map_or(RedisValue::NullBulkString, RedisValue::Double),
+            false => RedisValue::Int(added + if ch { changed } else { 0 }),
+        })
+    }
+
+    /// `ZINCRBY key increment member`, replying with the new score
+    pub(super) fn zincrby(&mut self, key: &RedisValue, increment: f64, member: &RedisValue) -> CmdResult {
+        let key = self.keyize(key);
+        let member = member.bytes_arg();
+        let mut db = self.map.lock().expect("unlock failed!");
+
+        let zset = zset_entry(&mut db, key.clone())?;
+        let score = zset.score(&member).unwrap_or(0.0) + increment;
+        if score.is_nan() {
+            remove_if_empty(&mut db, &key);
+            return Err(RedisError::Other("resulting score is not a number (NaN)".to_string()));
+        }
+        zset.insert(member, score);
+        Ok(RedisValue::Double(score))
+    }
+
+    /// `ZREM key member [member ...]`, replying with the number of members removed
+    pub(super) fn zrem(&mut self, key: &RedisValue, members: &[RedisValue]) -> CmdResult {
+        let key = self.keyize(key);
+        let mut db = self.map.lock().expect("unlock failed!");
+
+        let Some(zset) = read_zset(&mut db, &key)? else {
+            return Ok(RedisValue::Int(0));
+        };
+        let removed = members.iter().filter(|m| zset.remove(&m.keyize()).is_some()).count();
+        remove_if_empty(&mut db, &key);
+        Ok(RedisValue::Int(removed as RedisInt))
+    }
+
+    /// `ZCARD key`
+    pub(super) fn zcard(&mut self, key: &RedisValue) -> CmdResult {
+        let key = self.keyize(key);
+        let mut db = self.map.lock().expect("unlock failed!");
+
+        let len = read_zset(&mut db, &key)?.map_or(0, |zset| zset.len());
+        Ok(RedisValue::Int(len as RedisInt))
+    }
+
+    /// `ZSCORE key member`, or `ZMSCORE key member [member ...]` if `multi`
+    pub(super) fn zmscore(&mut self, key: &RedisValue, members: &[RedisValue], multi: bool) -> CmdResult {
+        let key = self.keyize(key);
+        let mut db = self.map.lock().expect("unlock failed!");
+
+        let zset = read_zset(&mut db, &key)?;
+        let mut scores = members.iter().map(|m| {
+            zset.as_ref()
+                .and_then(|zset| zset.score(&m.keyize()))
+                .map_or(RedisValue::NullBulkString, RedisValue::Double)
+        });
+        Ok(match multi {
+            true => RedisValue::Array(scores.collect()),
+            false => scores.next().unwrap_or(RedisValue::NullBulkString),
+        })
+    }
+
+    /// `ZRANK key member [WITHSCORE]` and `ZREVRANK`, ranks counting from the highest score for the latter
+    pub(super) fn zrank(&mut self, args: &[RedisValue], rev: bool) -> CmdResult {
+        let withscore = match &args[2..] {
+            [] => false,
+            [opt] if opt.option_name() == "withscore" => true,
+            _ => return Err(RedisError::Syntax),
+        };
+        let key = self.keyize(&args[0]);
+        let member = args[1].keyize();
+        let mut db = self.map.lock().expect("unlock failed!");
+
+        let Some(zset) = read_zset(&mut db, &key)? else {
+            return Ok(RedisValue::NullBulkString);
+        };
+        let Some(rank) = zset.rank(&member) else {
+            return Ok(RedisValue::NullBulkString);
+        };
+        let rank = RedisValue::Int(if rev { zset.len() - 1 - rank } else { rank } as RedisInt);
+        Ok(match (withscore, zset.score(&member)) {
+            (true, Some(score)) => RedisValue::Array(vec![rank, RedisValue::Double(score)]),
+            _ => rank,
+        })
+    }
+
+    /// `ZCOUNT key min max`, or `ZLEXCOUNT key min max` if `lex`
+    pub(super) fn zcount(&mut self, key: &RedisValue, min: &RedisValue, max: &RedisValue, lex: bool) -> CmdResult {
+        let range = match lex {
+            true => Range::Lex(LexBound::parse(min)?, LexBound::parse(max)?),
+            false => Range::Score(ScoreBound::parse(min)?, ScoreBound::parse(max)?),
+        };
+        let key = self.keyize(key);
+        let mut db = self.map.lock().expect("unlock failed!");
+
+        let count = read_zset(&mut db, &key)?.map_or(0, |zset| {
+            let (start, end) = zset.ranks(&range);
+            end - start
+        });
+        Ok(RedisValue::Int(count as RedisInt))
+    }
+
+    /// `ZRANGE key start stop [BYSCORE | BYLEX] [REV] [LIMIT offset count] [WITHSCORES]`, or
+    /// `ZRANGESTORE dst src ...` (WITHSCORES aside) if `dst` is given, which stores the members selected and
+    /// replies with their number
+    pub(super) fn zrange(&mut self, key: &RedisValue, args: &[RedisValue], dst: Option<&RedisValue>) -> CmdResult {
+        let range = RangeArgs::parse(args)?;
+        if dst.is_some() && range.withscores {
+            return Err(RedisError::Syntax);
+        }
+        let key = self.keyize(key);
+        let dst = dst.map(|dst| self.keyize(dst));
+        let mut db = self.map.lock().expect("unlock failed!");
+
+        let selected = read_zset(&mut db, &key)?
+            .map(|zset| zset.select(&range.range, range.rev, range.limit))
+            .unwrap_or_default();
+        Ok(match dst {
+            Some(dst) => store_zset(&mut db, dst, selected.into_iter().collect()),
+            None => self.scored_reply(selected, range.withscores),
+        })
+    }
+
+    /// `ZPOPMIN key [count]` and `ZPOPMAX`, replying with the members popped along with their scores
+    pub(super) fn zpop(&mut self, args: &[RedisValue], end: ZEnd) -> CmdResult {
+        let count = match &args[1..] {
+            [] => None,
+            [count] => Some(usize::try_from(count.int_arg()?).map_err(|_| {
+                RedisError::Other("value is out of range, must be positive".to_string())
+            })?),
+            _ => return Err(RedisError::Syntax),
+        };
+        let key = self.keyize(&args[0]);
+        let mut db = self.map.lock().expect("unlock failed!");
+
+        let popped = zset_pop(&mut db, &key, end, count.unwrap_or(1))?.unwrap_or_default();
+        // Without a count, the member comes along with its score as a flat array whatever the protocol
+        Ok(match count {
+            Some(_) => self.scored_reply(popped, true),
+            None => RedisValue::Array(
+                popped
+                    .into_iter()
+                    .flat_map(|(m, score)| [RedisValue::BulkString(m), RedisValue::Double(score)])
+                    .collect(),
+            ),
+        })
+    }
+
+    /// `ZUNIONSTORE destination numkeys key [key ...] [WEIGHTS weight [weight ...]] [AGGREGATE SUM | MIN | MAX]`,
+    /// `ZINTERSTORE` and `ZDIFFSTORE destination numkeys key [key ...]`, replying with the size of the result
+    pub(super) fn zstore(&mut self, args: &[RedisValue], op: SetOp, command: &str) -> CmdResult {
+        let numkeys = args[1].int_arg()?;
+        if numkeys <= 0 {
+            return Err(RedisError::Other(format!("at least 1 input key is needed for '{}' command", command)));
+        }
+        let numkeys = numkeys as usize;
+        if args.len() < numkeys + 2 {
+            return Err(RedisError::Syntax);
+        }
+        let (keys, opts) = args[2..].split_at(numkeys);
+
+        let (mut weights, mut aggregate) = (vec![1.0; numkeys], Aggregate::Sum);
+        let mut opts = opts.iter();
+        while let Some(opt) = opts.next() {
+            match opt.option_name().as_str() {
+                "weights" if op != SetOp::Diff => {
+                    for weight in weights.iter_mut() {
+                        *weight = opts.next().ok_or(RedisError::Syntax)?.float_arg().map_err(|_| {
+                            RedisError::Other("weight value is not a float".to_string())
+                        })?;
+                    }
+                }
+                "aggregate" if op != SetOp::Diff => {
+                    aggregate = match opts.next().ok_or(RedisError::Syntax)?.option_name().as_str() {
+                        "sum" => Aggregate::Sum,
+                        "min" => Aggregate::Min,
+                        "max" => Aggregate::Max,
+                        _ => return Err(RedisError::Syntax),
+                    }
+                }
+                _ => return Err(RedisError::Syntax),
+            }
+        }
+
+        let dst = self.keyize(&args[0]);
+        let keys: Vec<_> = keys.iter().map(|k| self.keyize(k)).collect();
+        let mut db = self.map.lock().expect("unlock failed!");
+
+        let result = combine(&mut db, op, &keys, &weights, aggregate)?;
+        Ok(store_zset(&mut db, dst, result))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::resp::test::{bulk, connected_handler, connected_handler_on};
+    use crate::{RedisError, RedisValue};
+    use tokio::time::{Duration, sleep};
+
+    fn bulks(members: &[&str]) -> Vec<RedisValue> {
+        members.iter().map(|m| bulk(m)).collect()
+    }
+
+    fn scored(entries: &[(&str, f64)]) -> RedisValue {
+        RedisValue::Array(entries.iter().flat_map(|(m, s)| [bulk(m), RedisValue::Double(*s)]).collect())
+    }
+
+    #[tokio::test]
+    async fn add_and_rank() {
+        let (mut handler, _client) = connected_handler().await;
+
+        let added = handler.handle_command("zadd", bulks(&["z", "1", "a", "2", "b", "3", "c"])).await;
+        assert_eq!(added, RedisValue::Int(3));
+        assert_eq!(handler.handle_command("type", vec![bulk("z")]).await, RedisValue::SimpleString("zset".into()));
+        assert_eq!(handler.handle_command("zadd", bulks(&["z", "CH", "5", "a", "4", "d"])).await, RedisValue::Int(2));
+        assert_eq!(handler.handle_command("zadd", bulks(&["z", "NX", "0", "a"])).await, RedisValue::Int(0));
+        assert_eq!(handler.handle_command("zadd", bulks(&["z", "XX", "0", "e"])).await, RedisValue::Int(0));
+        assert_eq!(handler.handle_command("zadd", bulks(&["z", "GT", "CH", "1", "a"])).await, RedisValue::Int(0));
+        let aborted = handler.handle_command("zadd", bulks(&["z", "LT", "INCR", "1", "a"])).await;
+        assert_eq!(aborted, RedisValue::NullBulkString);
+        assert_eq!(handler.handle_command("zadd", bulks(&["z", "INCR", "1.5", "a"])).await, RedisValue::Double(6.5));
+        assert_eq!(
+            handler.handle_command("zadd", bulks(&["z", "NX", "XX", "1", "a"])).await,
+            RedisError::Other("XX and NX options at the same time are not compatible".to_string()).into()
+        );
+        assert_eq!(handler.handle_command("zadd", bulks(&["z", "1", "a", "2"])).await, RedisError::Syntax.into());
+        assert_eq!(handler.handle_command("zadd", bulks(&["z", "x", "a"])).await, RedisError::NotFloat.into());
+        assert_eq!(handler.handle_command("exists", vec![bulk("nope")]).await, RedisValue::Int(0));
+        handler.handle_command("zadd", bulks(&["nope", "XX", "1", "a"])).await;
+        assert_eq!(handler.handle_command("exists", vec![bulk("nope")]).await, RedisValue::Int(0));
+
+        // b: 2, c: 3, d: 4, a: 6.5
+        assert_eq!(handler.handle_command("zincrby", bulks(&["z", "-1", "b"])).await, RedisValue::Double(1.0));
+        assert_eq!(handler.handle_command("zscore", bulks(&["z", "c"])).await, RedisValue::Double(3.0));
+        assert_eq!(
+            handler.handle_command("zmscore", bulks(&["z", "a", "x"])).await,
+            RedisValue::Array(vec![RedisValue::Double(6.5), RedisValue::NullBulkString])
+        );
+        assert_eq!(handler.handle_command("zrank", bulks(&["z", "c"])).await, RedisValue::Int(1));
+        assert_eq!(
+            handler.handle_command("zrevrank", bulks(&["z", "c", "WITHSCORE"])).await,
+            RedisValue::Array(vec![RedisValue::Int(2), RedisValue::Double(3.0)])
+        );
+        assert_eq!(handler.handle_command("zrank", bulks(&["z", "x"])).await, RedisValue::NullBulkString);
+        assert_eq!(handler.handle_command("zcard", vec![bulk("z")]).await, RedisValue::Int(4));
+        assert_eq!(handler.handle_command("zrem", bulks(&["z", "c", "x"])).await, RedisValue::Int(1));
+        assert_eq!(handler.handle_command("zcount", bulks(&["z", "(1", "+inf"])).await, RedisValue::Int(2));
+
+        handler.handle_command("set", bulks(&["str", "v"])).await;
+        assert_eq!(handler.handle_command("zadd", bulks(&["str", "1", "a"])).await, RedisError::WrongType.into());
+    }
+
+    #[tokio::test]
+    async fn ranges() {
+        let (mut handler, _client) = connected_handler().await;
+        handler.handle_command("zadd", bulks(&["z", "1", "a", "2", "b", "3", "c", "4", "d", "5", "e"])).await;
+
+        let zrange = |args: &[&str]| {
+            let mut all = vec!["z"];
+            all.extend_from_slice(args);
+            bulks(&all)
+        };
+        assert_eq!(handler.handle_command("zrange", zrange(&["0", "1"])).await, RedisValue::Array(bulks(&["a", "b"])));
+        assert_eq!(
+            handler.handle_command("zrange", zrange(&["0", "1", "REV", "WITHSCORES"])).await,
+            scored(&[("e", 5.0), ("d", 4.0)])
+        );
+        assert_eq!(
+            handler.handle_command("zrange", zrange(&["(1", "4", "BYSCORE", "LIMIT", "1", "2"])).await,
+            RedisValue::Array(bulks(&["c", "d"]))
+        );
+        assert_eq!(
+            handler.handle_command("zrange", zrange(&["+inf", "3", "BYSCORE", "REV"])).await,
+            RedisValue::Array(bulks(&["e", "d", "c"]))
+        );
+        assert_eq!(
+            handler.handle_command("zrange", zrange(&["[b", "(d", "BYLEX"])).await,
+            RedisValue::Array(bulks(&["b", "c"]))
+        );
+        assert_eq!(
+            handler.handle_command("zrange", zrange(&["+", "-", "BYLEX", "REV", "LIMIT", "0", "1"])).await,
+            RedisValue::Array(bulks(&["e"]))
+        );
+        assert_eq!(handler.handle_command("zlexcount", bulks(&["z", "-", "[c"])).await, RedisValue::Int(3));
+        assert_eq!(
+            handler.handle_command("zrange", zrange(&["0", "1", "LIMIT", "0", "1"])).await,
+            RedisError::Other(
+                "syntax error, LIMIT is only supported in combination with either BYSCORE or BYLEX".to_string()
+            )
+            .into()
+        );
+        assert_eq!(
+            handler.handle_command("zrange", zrange(&["x", "1", "BYSCORE"])).await,
+            RedisError::Other("min or max is not a float".to_string()).into()
+        );
+
+        assert_eq!(handler.handle_command("zrangestore", bulks(&["dst", "z", "2", "-1"])).await, RedisValue::Int(3));
+        assert_eq!(
+            handler.handle_command("zrange", bulks(&["dst", "0", "-1"])).await,
+            RedisValue::Array(bulks(&["c", "d", "e"]))
+        );
+
+        assert_eq!(handler.handle_command("zpopmin", vec![bulk("z")]).await, scored(&[("a", 1.0)]));
+        assert_eq!(handler.handle_command("zpopmax", bulks(&["z", "2"])).await, scored(&[("e", 5.0), ("d", 4.0)]));
+        assert_eq!(handler.handle_command("zpopmax", bulks(&["z", "5"])).await, scored(&[("c", 3.0), ("b", 2.0)]));
+        assert_eq!(handler.handle_command("exists", vec![bulk("z")]).await, RedisValue::Int(0));
+        assert_eq!(handler.handle_command("zpopmin", vec![bulk("z")]).await, RedisValue::Array(vec![]));
+    }
+
+    #[tokio::test]
+    async fn store_combinations() {
+        let (mut handler, _client) = connected_handler().await;
+        handler.handle_command("zadd", bulks(&["a", "1", "x", "2", "y"])).await;
+        handler.handle_command("zadd", bulks(&["b", "10", "y", "20", "z"])).await;
+        handler.handle_command("sadd", bulks(&["s", "y", "w"])).await;
+
+        let args = bulks(&["u", "2", "a", "b", "WEIGHTS", "2", "1"]);
+        assert_eq!(handler.handle_command("zunionstore", args).await, RedisValue::Int(3));
+        assert_eq!(
+            handler.handle_command("zrange", bulks(&["u", "0", "-1", "WITHSCORES"])).await,
+            scored(&[("x", 2.0), ("y", 14.0), ("z", 20.0)])
+        );
+        let args = bulks(&["i", "3", "a", "b", "s", "AGGREGATE", "MAX"]);
+        assert_eq!(handler.handle_command("zinterstore", args).await, RedisValue::Int(1));
+        assert_eq!(handler.handle_command("zscore", bulks(&["i", "y"])).await, RedisValue::Double(10.0));
+        assert_eq!(handler.handle_command("zdiffstore", bulks(&["d", "2", "a", "s"])).await, RedisValue::Int(1));
+        assert_eq!(handler.handle_command("zrange", bulks(&["d", "0", "-1"])).await, RedisValue::Array(bulks(&["x"])));
+        assert_eq!(handler.handle_command("zinterstore", bulks(&["d", "2", "a", "nope"])).await, RedisValue::Int(0));
+        assert_eq!(handler.handle_command("exists", vec![bulk("d")]).await, RedisValue::Int(0));
+        assert_eq!(
+            handler.handle_command("zunionstore", bulks(&["u", "0", "a"])).await,
+            RedisError::Other("at least 1 input key is needed for 'zunionstore' command".to_string()).into()
+        );
+    }
+
+    #[tokio::test]
+    async fn blocking_pops() {
+        let (mut pusher, _client) = connected_handler().await;
+        let (mut waiter, _waiter_client) = connected_handler_on(&pusher.map, 1).await;
+
+        assert_eq!(waiter.handle_command("bzpopmin", bulks(&["z", "0.01"])).await, RedisValue::NullArray);
+        let waiting = tokio::spawn(async move { waiter.handle_command("bzpopmax", bulks(&["y", "z", "0"])).await });
+        sleep(Duration::from_millis(20)).await;
+        pusher.handle_command("zadd", bulks(&["z", "1", "a", "2", "b"])).await;
+        assert_eq!(
+            waiting.await.unwrap(),
+            RedisValue::Array(vec![bulk("z"), bulk("b"), RedisValue::Double(2.0)])
+        );
+        assert_eq!(pusher.handle_command("zcard", vec![bulk("z")]).await, RedisValue::Int(1));
+    }
+}
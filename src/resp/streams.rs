@@ -0,0 +1,635 @@
+// Stream commands: append-only logs of field-value entries, identified by increasing `ms-seq` IDs
+
+use super::blocking::BlockingOp;
+use super::consumer_groups::ConsumerGroup;
+use super::{Database, Protocol, RedisObject, RespHandler, Set, lookup};
+use crate::error::{CmdResult, RedisError};
+use crate::now_ms;
+use crate::resp::{RedisInt, RedisValue};
+use bytes::Bytes;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use tokio::time::Duration;
+
+/// Entries per node of Redis' stream representation, the granularity of approximate trimming
+pub(super) const NODE_ENTRIES: usize = 100;
+
+/// ID of a stream entry: the millisecond it was added at, and a sequence number within that millisecond
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct StreamId {
+    pub ms: u64,
+    pub seq: u64,
+}
+
+impl fmt::Display for StreamId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", self.ms, self.seq)
+    }
+}
+
+impl StreamId {
+    pub const MIN: StreamId = StreamId { ms: 0, seq: 0 };
+    pub const MAX: StreamId = StreamId { ms: u64::MAX, seq: u64::MAX };
+
+    /// Parses `ms-seq`, or `ms` alone with `seq` defaulting to `missing_seq`
+    pub(super) fn parse(arg: &[u8], missing_seq: u64) -> Result<Self, RedisError> {
+        let number = |s: &[u8]| std::str::from_utf8(s).ok()?.parse::<u64>().ok();
+        let parsed = match arg.iter().position(|&c| c == b'-') {
+            Some(dash) => number(&arg[..dash]).zip(number(&arg[dash + 1..])),
+            None => number(arg).map(|ms| (ms, missing_seq)),
+        };
+        parsed
+            .map(|(ms, seq)| StreamId { ms, seq })
+            .ok_or_else(|| RedisError::Other("Invalid stream ID specified as stream command argument".to_string()))
+    }
+
+    pub(super) fn next(self) -> Option<Self> {
+        match self.seq.checked_add(1) {
+            Some(seq) => Some(StreamId { seq, ..self }),
+            None => Some(StreamId { ms: self.ms.checked_add(1)?, seq: 0 }),
+        }
+    }
+
+    fn prev(self) -> Option<Self> {
+        match self.seq.checked_sub(1) {
+            Some(seq) => Some(StreamId { seq, ..self }),
+            None => Some(StreamId { ms: self.ms.checked_sub(1)?, seq: u64::MAX }),
+        }
+    }
+
+    pub(super) fn reply(&self) -> RedisValue {
+        RedisValue::bulk(self.to_string())
+    }
+}
+
+/// Field-value pairs of an entry, in the order they were given
+pub type Fields = Vec<(Bytes, Bytes)>;
+
+/// Entries of a stream, along with what is left of the ones gone
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Stream {
+    entries: BTreeMap<StreamId, Fields>,
+    /// ID of the last entry ever added, which new IDs must be greater than
+    last_id: StreamId,
+    /// Greatest ID among the entries deleted or trimmed
+    max_deleted_id: StreamId,
+    /// Number of entries ever added
+    entries_added: u64,
+    /// Consumer groups, by name
+    pub(super) groups: BTreeMap<Bytes, ConsumerGroup>,
+}
+
+impl Stream {
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn last_id(&self) -> StreamId {
+        self.last_id
+    }
+
+    pub fn max_deleted_id(&self) -> StreamId {
+        self.max_deleted_id
+    }
+
+    pub fn entries_added(&self) -> u64 {
+        self.entries_added
+    }
+
+    pub fn get(&self, id: &StreamId) -> Option<&Fields> {
+        self.entries.get(id)
+    }
+
+    pub fn first(&self) -> Option<(&StreamId, &Fields)> {
+        self.entries.first_key_value()
+    }
+
+    pub fn last(&self) -> Option<(&StreamId, &Fields)> {
+        self.entries.last_key_value()
+    }
+
+    /// Entries with IDs within `start..=end`, in order
+    pub fn range(&self, start: StreamId, end: StreamId) -> impl DoubleEndedIterator<Item = (&StreamId, &Fields)> {
+        // An empty range rather than a panic when `start` comes past `end`
+        (start <= end).then(|| self.entries.range(start..=end)).into_iter().flatten()
+    }
+
+    /// Resolves the ID XADD was given into the ID of the entry to add, which must come past any other
+    fn next_id(&self, spec: &[u8]) -> Result<StreamId, RedisError> {
+        let exhausted = || {
+            RedisError::Other("The stream has exhausted the last possible ID, unable to add more items".to_string())
+        };
+        let too_small = || {
+            let msg = "The ID specified in XADD is equal or smaller than the target stream top item";
+            RedisError::Other(msg.to_string())
+        };
+        let id = match spec {
+            b"*" => {
+                let ms = (now_ms().max(0) as u64).max(self.last_id.ms);
+                match ms == self.last_id.ms {
+                    true => self.last_id.next().ok_or_else(exhausted)?,
+                    false => StreamId { ms, seq: 0 },
+                }
+            }
+            [ms @ .., b'-', b'*'] => {
+                let ms = StreamId::parse(ms, 0)?.ms;
+                match ms.cmp(&self.last_id.ms) {
+                    Ordering::Less => return Err(too_small()),
+                    Ordering::Equal => StreamId { ms, seq: self.last_id.seq.checked_add(1).ok_or_else(too_small)? },
+                    Ordering::Greater => StreamId { ms, seq: 0 },
+                }
+            }
+            spec => StreamId::parse(spec, 0)?,
+        };
+        if id == StreamId::MIN {
+            return Err(RedisError::Other("The ID specified in XADD must be greater than 0-0".to_string()));
+        }
+        if id <= self.last_id {
+            return Err(too_small());
+        }
+        Ok(id)
+    }
+
+    fn add(&mut self, id: StreamId, fields: Fields) {
+        self.entries.insert(id, fields);
+        self.last_id = id;
+        self.entries_added += 1;
+    }
+
+    /// Deletes an entry, replying with whether it was there
+    pub fn remove(&mut self, id: &StreamId) -> bool {
+        let removed = self.entries.remove(id).is_some();
+        if removed {
+            self.max_deleted_id = self.max_deleted_id.max(*id);
+        }
+        removed
+    }
+
+    /// Evicts the oldest entries as `trim` asks, replying with their number
+    fn trim(&mut self, trim: &Trim) -> usize {
+        let mut excess = match trim.strategy {
+            Strategy::MaxLen(max) => self.len().saturating_sub(max),
+            Strategy::MinId(min) => self.entries.range(..min).count(),
+        };
+        if trim.approx {
+            // Redis evicts whole nodes only, within the limit
+            if trim.limit > 0 {
+                excess = excess.min(trim.limit);
+            }
+            excess -= excess % NODE_ENTRIES;
+        }
+        for _ in 0..excess {
+            if let Some((id, _)) = self.entries.pop_first() {
+                self.max_deleted_id = self.max_deleted_id.max(id);
+            }
+        }
+        excess
+    }
+}
+
+/// How the oldest entries are evicted
+#[derive(Debug, Clone, Copy)]
+enum Strategy {
+    /// Keep that many entries at most
+    MaxLen(usize),
+    /// Keep the entries with IDs at least that one
+    MinId(StreamId),
+}
+
+/// `MAXLEN | MINID [= | ~] threshold [LIMIT count]`, as XADD and XTRIM take it
+#[derive(Debug, Clone, Copy)]
+struct Trim {
+    strategy: Strategy,
+    approx: bool,
+    /// Entries evicted at most by approximate trimming, 0 meaning as many as needed
+    limit: usize,
+}
+
+impl Trim {
+    /// Parses a trimming clause at the start of `args`, leaving in `args` what follows it
+    fn parse(args: &mut &[RedisValue]) -> Result<Self, RedisError> {
+        let mut next = || -> Result<&RedisValue, RedisError> {
+            let (first, rest) = args.split_first().ok_or(RedisError::Syntax)?;
+            *args = rest;
+            Ok(first)
+        };
+        let maxlen = match next()?.option_name().as_str() {
+            "maxlen" => true,
+            "minid" => false,
+            _ => return Err(RedisError::Syntax),
+        };
+        let mut threshold = next()?;
+        let approx = threshold.unpack_bytes_variant() == Some(b"~");
+        if matches!(threshold.unpack_bytes_variant(), Some(b"~" | b"=")) {
+            threshold = next()?;
+        }
+        let strategy = match maxlen {
+            true => Strategy::MaxLen(
+                usize::try_from(threshold.int_arg()?)
+                    .map_err(|_| RedisError::Other("The MAXLEN argument must be >= 0.".to_string()))?,
+            ),
+            false => Strategy::MinId(StreamId::parse(&threshold.bytes_arg(), 0)?),
+        };
+
+        let mut limit = NODE_ENTRIES * NODE_ENTRIES;
+        if args.first().is_some_and(|opt| opt.option_name() == "limit") {
+            *args = &args[1..];
+            let count = args.first().ok_or(RedisError::Syntax)?.int_arg()?;
+            *args = &args[1..];
+            limit = usize::try_from(count)
+                .map_err(|_| RedisError::Other("The LIMIT argument must be >= 0.".to_string()))?;
+            if !approx {
+                return Err(RedisError::Other(
+                    "syntax error, LIMIT cannot be used without the special ~ option".to_string(),
+                ));
+            }
+        }
+        Ok(Trim { strategy, approx, limit })
+    }
+}
+
+/// Looks a stream up, WRONGTYPE if the key holds anything else
+pub(super) fn read_stream<'a>(db: &'a mut Database, key: &[u8]) -> Result<Option<&'a mut Stream>, RedisError> {
+    lookup(db, key).map(|set| set.val.stream_mut()).transpose()
+}
+
+pub(super) fn entry_reply(id: &StreamId, fields: &Fields) -> RedisValue {
+    let fields = fields.iter().flat_map(|(f, v)| [f, v]).map(|b| RedisValue::BulkString(b.clone())).collect();
+    RedisValue::Array(vec![id.reply(), RedisValue::Array(fields)])
+}
+
+/// Entries of the stream at `key` past `after`, `count` of them at most. Replies `None` if there is none.
+pub(super) fn stream_read(
+    db: &mut Database,
+    key: &[u8],
+    after: StreamId,
+    count: Option<usize>,
+) -> Result<Option<RedisValue>, RedisError> {
+    let Some(start) = after.next() else {
+        return Ok(None);
+    };
+    let Some(stream) = read_stream(db, key)? else {
+        return Ok(None);
+    };
+    let entries: Vec<_> = stream
+        .range(start, StreamId::MAX)
+        .take(count.unwrap_or(usize::MAX))
+        .map(|(id, fields)| entry_reply(id, fields))
+        .collect();
+    Ok((!entries.is_empty()).then_some(RedisValue::Array(entries)))
+}
+
+/// Reads a range boundary of XRANGE: `-` and `+` for the lowest and highest IDs, `(` for exclusive ones, and
+/// `ms` alone meaning its first ID as a start, its last as an end
+pub(super) fn range_bound(arg: &RedisValue, start: bool) -> Result<StreamId, RedisError> {
+    let arg = arg.bytes_arg();
+    match (arg.as_ref(), start) {
+        (b"-", _) => Ok(StreamId::MIN),
+        (b"+", _) => Ok(StreamId::MAX),
+        ([b'(', id @ ..], true) => StreamId::parse(id, 0)?
+            .next()
+            .ok_or_else(|| RedisError::Other("invalid start ID for the interval".to_string())),
+        ([b'(', id @ ..], false) => StreamId::parse(id, u64::MAX)?
+            .prev()
+            .ok_or_else(|| RedisError::Other("invalid end ID for the interval".to_string())),
+        (id, true) => StreamId::parse(id, 0),
+        (id, false) => StreamId::parse(id, u64::MAX),
+    }
+}
+
+/// Reads the arguments of XREAD and XREADGROUP past `STREAMS`: the keys, then as many IDs
+pub(super) fn streams_args<'a>(
+    args: &'a [RedisValue],
+    command: &str,
+) -> Result<(&'a [RedisValue], &'a [RedisValue]), RedisError> {
+    if args.is_empty() || !args.len().is_multiple_of(2) {
+        return Err(RedisError::Other(format!(
+            "Unbalanced '{}' list of streams: for each stream key an ID or '$' must be specified.",
+            command
+        )));
+    }
+    Ok(args.split_at(args.len() / 2))
+}
+
+/// Reads the `BLOCK milliseconds` option, 0 meaning forever
+pub(super) fn block_arg(arg: &RedisValue) -> Result<Option<Duration>, RedisError> {
+    match arg.int_arg()? {
+        ms if ms < 0 => Err(RedisError::Other("timeout is negative".to_string())),
+        0 => Ok(None),
+        ms => Ok(Some(Duration::from_millis(ms as u64))),
+    }
+}
+
+impl RespHandler {
+    /// Replies with `[key, entries]` pairs, turned into a map for RESP3
+    pub(super) fn streams_reply(&self, streams: Vec<RedisValue>) -> RedisValue {
+        if self.protocol == Protocol::Resp2 {
+            return RedisValue::Array(streams);
+        }
+        let pairs = streams.into_iter().filter_map(|pair| match pair {
+            RedisValue::Array(pair) => <[RedisValue; 2]>::try_from(pair).ok().map(|[key, entries]| (key, entries)),
+            _ => None,
+        });
+        RedisValue::Map(pairs.collect())
+    }
+
+    /// `XADD key [NOMKSTREAM] [MAXLEN | MINID [= | ~] threshold [LIMIT count]] * | id field value [field value
+    /// ...]`, replying with the ID of the entry added
+    pub(super) fn xadd(&mut self, args: &[RedisValue]) -> CmdResult {
+        let (mut nomkstream, mut trim) = (false, None);
+        let mut rest = &args[1..];
+        while let Some(opt) = rest.first() {
+            match opt.option_name().as_str() {
+                "nomkstream" => {
+                    nomkstream = true;
+                    rest = &rest[1..];
+                }
+                "maxlen" | "minid" => trim = Some(Trim::parse(&mut rest)?),
+                _ => break,
+            }
+        }
+        let Some((id, pairs)) = rest.split_first() else {
+            return Err(RedisError::WrongArity("xadd".to_string()));
+        };
+        if pairs.is_empty() || !pairs.len().is_multiple_of(2) {
+            return Err(RedisError::WrongArity("xadd".to_string()));
+        }
+        let fields = pairs.chunks(2).map(|pair| (pair[0].bytes_arg(), pair[1].bytes_arg())).collect();
+
+        let key = self.keyize(&args[0]);
+        let mut db = self.map.lock().expect("unlock failed!");
+        db.signal_ready(&key);
+        // The stream is only created once the ID is known to be valid
+        let id = match read_stream(&mut db, &key)? {
+            Some(stream) => stream.next_id(&id.bytes_arg())?,
+            None if nomkstream => return Ok(RedisValue::Null),
+            None => Stream::default().next_id(&id.bytes_arg())?,
+        };
+        let stream = db
+            .entry(key.clone())
+            .or_insert_with(|| Set::new(RedisObject::Stream(Stream::default()), None))
+            .val
+            .stream_mut()?;
+        stream.add(id, fields);
+        if let Some(trim) = trim {
+            stream.trim(&trim);
+        }
+        Ok(id.reply())
+    }
+
+    /// `XRANGE key start end [COUNT count]`, or `XREVRANGE key end start [COUNT count]` if `rev`
+    pub(super) fn xrange(&mut self, args: &[RedisValue], rev: bool) -> CmdResult {
+        let (start, end) = match rev {
+            true => (range_bound(&args[2], true)?, range_bound(&args[1], false)?),
+            false => (range_bound(&args[1], true)?, range_bound(&args[2], false)?),
+        };
+        let count = match &args[3..] {
+            [] => None,
+            [opt, count] if opt.option_name() == "count" => Some(usize::try_from(count.int_arg()?).unwrap_or(0)),
+            _ => return Err(RedisError::Syntax),
+        };
+        let key = self.keyize(&args[0]);
+        let mut db = self.map.lock().expect("unlock failed!");
+
+        let Some(stream) = read_stream(&mut db, &key)? else {
+            return Ok(RedisValue::Array(vec![]));
+        };
+        let count = count.unwrap_or(usize::MAX);
+        let entries = stream.range(start, end).map(|(id, fields)| entry_reply(id, fields));
+        Ok(RedisValue::Array(match rev {
+            true => entries.rev().take(count).collect(),
+            false => entries.take(count).collect(),
+        }))
+    }
+
+    /// `XLEN key`
+    pub(super) fn xlen(&mut self, key: &RedisValue) -> CmdResult {
+        let key = self.keyize(key);
+        let mut db = self.map.lock().expect("unlock failed!");
+
+        let len = read_stream(&mut db, &key)?.map_or(0, |stream| stream.len());
+        Ok(RedisValue::Int(len as RedisInt))
+    }
+
+    /// `XDEL key id [id ...]`, replying with the number of entries deleted
+    pub(super) fn xdel(&mut self, key: &RedisValue, ids: &[RedisValue]) -> CmdResult {
+        let ids = ids.iter().map(|id| StreamId::parse(&id.bytes_arg(), 0)).collect::<Result<Vec<_>, _>>()?;
+        let key = self.keyize(key);
+        let mut db = self.map.lock().expect("unlock failed!");
+
+        let Some(stream) = read_stream(&mut db, &key)? else {
+            return Ok(RedisValue::Int(0));
+        };
+        let deleted = ids.iter().filter(|id| stream.remove(id)).count();
+        Ok(RedisValue::Int(deleted as RedisInt))
+    }
+
+    /// `XTRIM key MAXLEN | MINID [= | ~] threshold [LIMIT count]`, replying with the number of entries evicted
+    pub(super) fn xtrim(&mut self, key: &RedisValue, args: &[RedisValue]) -> CmdResult {
+        let mut rest = args;
+        let trim = Trim::parse(&mut rest)?;
+        if !rest.is_empty() {
+            return Err(RedisError::Syntax);
+        }
+        let key = self.keyize(key);
+        let mut db = self.map.lock().expect("unlock failed!");
+
+        let trimmed = read_stream(&mut db, &key)?.map_or(0, |stream| stream.trim(&trim));
+        Ok(RedisValue::Int(trimmed as RedisInt))
+    }
+
+    /// `XREAD [COUNT count] [BLOCK milliseconds] STREAMS key [key ...] id [id ...]`, replying with the entries
+    /// past the IDs given, `$` standing for the last ID of the stream and `+` for its last entry. With BLOCK,
+    /// waits for entries to be added if there is none yet.
+    pub(super) async fn xread(&mut self, args: &[RedisValue]) -> CmdResult {
+        let (mut count, mut block) = (None, None);
+        let mut opts = args.iter().enumerate();
+        let streams = loop {
+            let Some((i, opt)) = opts.next() else {
+                return Err(RedisError::Syntax);
+            };
+            match opt.option_name().as_str() {
+                "count" => count = usize::try_from(opts.next().ok_or(RedisError::Syntax)?.1.int_arg()?).ok(),
+                "block" => block = Some(block_arg(opts.next().ok_or(RedisError::Syntax)?.1)?),
+                "streams" => break &args[i + 1..],
+                _ => return Err(RedisError::Syntax),
+            }
+        };
+        let count = count.filter(|&count| count > 0);
+        let (keys, ids) = streams_args(streams, "xread")?;
+        let keys: Vec<_> = keys.iter().map(|k| self.keyize(k)).collect();
+
+        let (replies, after) = {
+            let mut db = self.map.lock().expect("unlock failed!");
+            let mut after = HashMap::new();
+            for (key, id) in keys.iter().zip(ids) {
+                let stream = read_stream(&mut db, key)?;
+                let id = match id.bytes_arg().as_ref() {
+                    b"$" => stream.map_or(StreamId::MIN, |stream| stream.last_id),
+                    // Right before the last entry, or like `$` without any
+                    b"+" => stream.map_or(StreamId::MIN, |stream| {
+                        stream.last().and_then(|(id, _)| id.prev()).unwrap_or(stream.last_id)
+                    }),
+                    id => StreamId::parse(id, 0)?,
+                };
+                after.insert(key.clone(), id);
+            }
+
+            let mut replies = vec![];
+            for key in &keys {
+                if let Some(entries) = stream_read(&mut db, key, after[key], count)? {
+                    replies.push(RedisValue::Array(vec![RedisValue::BulkString(key.clone()), entries]));
+                }
+            }
+            (replies, after)
+        };
+        if !replies.is_empty() {
+            return Ok(self.streams_reply(replies));
+        }
+        let Some(timeout) = block else {
+            return Ok(RedisValue::NullArray);
+        };
+
+        match self.block(keys, BlockingOp::XRead { after, count }, timeout).await? {
+            RedisValue::Array(replies) => Ok(self.streams_reply(replies)),
+            reply => Ok(reply),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::StreamId;
+    use crate::resp::test::{bulk, connected_handler, connected_handler_on};
+    use crate::{RedisError, RedisValue};
+    use tokio::time::{Duration, sleep};
+
+    fn bulks(args: &[&str]) -> Vec<RedisValue> {
+        args.iter().map(|a| bulk(a)).collect()
+    }
+
+    fn entry(id: &str, fields: &[&str]) -> RedisValue {
+        RedisValue::Array(vec![bulk(id), RedisValue::Array(bulks(fields))])
+    }
+
+    /// Reply of XREAD for a single stream
+    fn read_reply(key: &str, entries: Vec<RedisValue>) -> RedisValue {
+        RedisValue::Array(vec![RedisValue::Array(vec![bulk(key), RedisValue::Array(entries)])])
+    }
+
+    #[test]
+    fn ids() {
+        assert_eq!(StreamId::parse(b"5-3", 0).unwrap(), StreamId { ms: 5, seq: 3 });
+        assert_eq!(StreamId::parse(b"5", u64::MAX).unwrap(), StreamId { ms: 5, seq: u64::MAX });
+        assert!(StreamId::parse(b"5-", 0).is_err() && StreamId::parse(b"-1", 0).is_err());
+        assert_eq!(StreamId { ms: 1, seq: u64::MAX }.next(), Some(StreamId { ms: 2, seq: 0 }));
+        assert_eq!(StreamId::MIN.prev(), None);
+    }
+
+    #[tokio::test]
+    async fn add_and_range() {
+        let (mut handler, _client) = connected_handler().await;
+
+        assert_eq!(handler.handle_command("xadd", bulks(&["s", "1-1", "a", "1"])).await, bulk("1-1"));
+        assert_eq!(handler.handle_command("xadd", bulks(&["s", "1-*", "b", "2"])).await, bulk("1-2"));
+        assert_eq!(handler.handle_command("xadd", bulks(&["s", "3", "c", "3", "d", "4"])).await, bulk("3-0"));
+        let too_small = "The ID specified in XADD is equal or smaller than the target stream top item";
+        assert_eq!(
+            handler.handle_command("xadd", bulks(&["s", "2-5", "x", "y"])).await,
+            RedisError::Other(too_small.to_string()).into()
+        );
+        assert_eq!(
+            handler.handle_command("xadd", bulks(&["t", "0-0", "x", "y"])).await,
+            RedisError::Other("The ID specified in XADD must be greater than 0-0".to_string()).into()
+        );
+        assert_eq!(handler.handle_command("xadd", bulks(&["t", "NOMKSTREAM", "*", "x", "y"])).await, RedisValue::Null);
+        assert_eq!(handler.handle_command("type", vec![bulk("s")]).await, RedisValue::SimpleString("stream".into()));
+        let RedisValue::BulkString(auto) = handler.handle_command("xadd", bulks(&["s", "*", "e", "5"])).await else {
+            panic!()
+        };
+        assert!(StreamId::parse(&auto, 0).unwrap() > StreamId { ms: 3, seq: 0 });
+
+        assert_eq!(
+            handler.handle_command("xrange", bulks(&["s", "-", "3"])).await,
+            RedisValue::Array(vec![
+                entry("1-1", &["a", "1"]),
+                entry("1-2", &["b", "2"]),
+                entry("3-0", &["c", "3", "d", "4"])
+            ])
+        );
+        assert_eq!(
+            handler.handle_command("xrange", bulks(&["s", "(1-1", "+", "COUNT", "1"])).await,
+            RedisValue::Array(vec![entry("1-2", &["b", "2"])])
+        );
+        assert_eq!(
+            handler.handle_command("xrevrange", bulks(&["s", "3", "1", "COUNT", "2"])).await,
+            RedisValue::Array(vec![entry("3-0", &["c", "3", "d", "4"]), entry("1-2", &["b", "2"])])
+        );
+        assert_eq!(handler.handle_command("xlen", vec![bulk("s")]).await, RedisValue::Int(4));
+        assert_eq!(handler.handle_command("xdel", bulks(&["s", "1-2", "9-9"])).await, RedisValue::Int(1));
+        assert_eq!(handler.handle_command("xlen", vec![bulk("s")]).await, RedisValue::Int(3));
+    }
+
+    #[tokio::test]
+    async fn trimming() {
+        let (mut handler, _client) = connected_handler().await;
+        for i in 1..=250 {
+            handler.handle_command("xadd", bulks(&["s", &i.to_string(), "f", "v"])).await;
+        }
+
+        // Approximate trimming goes by whole nodes of 100 entries
+        assert_eq!(handler.handle_command("xtrim", bulks(&["s", "MAXLEN", "~", "120"])).await, RedisValue::Int(100));
+        assert_eq!(handler.handle_command("xtrim", bulks(&["s", "MAXLEN", "=", "120"])).await, RedisValue::Int(30));
+        assert_eq!(handler.handle_command("xtrim", bulks(&["s", "MINID", "201"])).await, RedisValue::Int(70));
+        assert_eq!(
+            handler.handle_command("xtrim", bulks(&["s", "MAXLEN", "10", "LIMIT", "5"])).await,
+            RedisError::Other("syntax error, LIMIT cannot be used without the special ~ option".to_string()).into()
+        );
+        let added = handler.handle_command("xadd", bulks(&["s", "MAXLEN", "2", "300", "f", "v"])).await;
+        assert_eq!(added, bulk("300-0"));
+        assert_eq!(
+            handler.handle_command("xrange", bulks(&["s", "-", "+"])).await,
+            RedisValue::Array(vec![entry("250-0", &["f", "v"]), entry("300-0", &["f", "v"])])
+        );
+    }
+
+    #[tokio::test]
+    async fn reads() {
+        let (mut writer, _client) = connected_handler().await;
+        let (mut reader, _reader_client) = connected_handler_on(&writer.map, 1).await;
+        writer.handle_command("xadd", bulks(&["a", "1", "f", "1"])).await;
+        writer.handle_command("xadd", bulks(&["a", "2", "f", "2"])).await;
+
+        assert_eq!(
+            reader.handle_command("xread", bulks(&["COUNT", "1", "STREAMS", "a", "b", "0", "0"])).await,
+            read_reply("a", vec![entry("1-0", &["f", "1"])])
+        );
+        assert_eq!(reader.handle_command("xread", bulks(&["STREAMS", "a", "$"])).await, RedisValue::NullArray);
+        let timed_out = reader.handle_command("xread", bulks(&["BLOCK", "10", "STREAMS", "a", "$"])).await;
+        assert_eq!(timed_out, RedisValue::NullArray);
+        assert_eq!(
+            reader.handle_command("xread", bulks(&["STREAMS", "a", "b", "0"])).await,
+            RedisError::Other(
+                "Unbalanced 'xread' list of streams: for each stream key an ID or '$' must be specified.".to_string()
+            )
+            .into()
+        );
+
+        // Every client blocked on the stream gets the new entries, not only the first one
+        let (mut other, _other_client) = connected_handler_on(&writer.map, 2).await;
+        let first = bulks(&["BLOCK", "0", "STREAMS", "a", "$"]);
+        let first = tokio::spawn(async move { reader.handle_command("xread", first).await });
+        let second = bulks(&["BLOCK", "0", "STREAMS", "a", "2"]);
+        let second = tokio::spawn(async move { other.handle_command("xread", second).await });
+        sleep(Duration::from_millis(20)).await;
+        writer.handle_command("xadd", bulks(&["a", "3", "f", "3"])).await;
+        let expected = read_reply("a", vec![entry("3-0", &["f", "3"])]);
+        assert_eq!(first.await.unwrap(), expected);
+        assert_eq!(second.await.unwrap(), expected);
+    }
+}
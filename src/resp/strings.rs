@@ -0,0 +1,757 @@
+// String commands: plain values set, read and updated as a whole or in part
+
+use super::expire::deadline_from;
+use super::{MAX_BULK_LEN, RespHandler, Set, lookup};
+use crate::error::{CmdResult, RedisError};
+use crate::resp::{RedisInt, RedisValue};
+use crate::{OK, format_double_human, parse_redis_float, parse_redis_int};
+
+fn too_big() -> RedisError {
+    RedisError::Other("string exceeds maximum allowed size (proto-max-bulk-len)".to_string())
+}
+
+/// Resolves the inclusive `start..=end` range Redis commands take, negative indices counting from the end
+/// of a `len` long sequence. Returns `None` for an empty range.
+pub(crate) fn resolve_range(start: RedisInt, end: RedisInt, len: usize) -> Option<(usize, usize)> {
+    let len = len as RedisInt;
+    if start < 0 && end < 0 && start > end {
+        return None;
+    }
+    let start = if start < 0 { (len + start).max(0) } else { start };
+    let end = if end < 0 { (len + end).max(0) } else { end.min(len - 1) };
+    (start <= end && len > 0).then_some((start as usize, end as usize))
+}
+
+/// What becomes of the expiry of a key being written
+#[derive(PartialEq, Clone, Copy, Debug)]
+enum NewExpiry {
+    Clear,
+    Keep,
+    At(i64),
+}
+
+/// Reads the value of an `EX`/`PX`/`EXAT`/`PXAT` option, which must be strictly positive
+fn expiry_option(option: &str, value: &RedisValue, command: &str) -> Result<NewExpiry, RedisError> {
+    let (unit, at) = match option {
+        "ex" => (1000, false),
+        "px" => (1, false),
+        "exat" => (1000, true),
+        _ => (1, true),
+    };
+    let time = value.int_arg()?;
+    if time <= 0 {
+        return Err(RedisError::InvalidExpire(command.to_string()));
+    }
+    Ok(NewExpiry::At(deadline_from(time, unit, at, command)?))
+}
+
+/// Existence condition a write is subject to
+#[derive(PartialEq, Clone, Copy, Debug)]
+enum Cond {
+    Always,
+    Nx,
+    Xx,
+}
+
+impl RespHandler {
+    /// `SET key value [NX | XX] [GET] [EX seconds | PX milliseconds | EXAT unix-time-seconds |
+    /// PXAT unix-time-milliseconds | KEEPTTL]`
+    ///
+    /// Replies with OK, or nil when NX/XX prevented the write, unless GET asks for the previous value.
+    pub(super) fn set(&mut self, args: &[RedisValue]) -> CmdResult {
+        let mut cond = Cond::Always;
+        let mut get = false;
+        let mut expiry = None;
+
+        let mut opts = args[2..].iter();
+        while let Some(opt) = opts.next() {
+            let name = opt.option_name();
+            match name.as_str() {
+                "nx" if cond != Cond::Xx => cond = Cond::Nx,
+                "xx" if cond != Cond::Nx => cond = Cond::Xx,
+                "get" => get = true,
+                "keepttl" if expiry.is_none() => expiry = Some((name, None)),
+                "ex" | "px" | "exat" | "pxat" if expiry.is_none() => match opts.next() {
+                    Some(value) => expiry = Some((name, Some(value))),
+                    None => return Err(RedisError::Syntax),
+                },
+                _ => return Err(RedisError::Syntax),
+            }
+        }
+        let expiry = match expiry {
+            None => NewExpiry::Clear,
+            Some((_, None)) => NewExpiry::Keep,
+            Some((name, Some(value))) => expiry_option(&name, value, "set")?,
+        };
+
+        self.set_with(&args[0], &args[1], cond, expiry, get)
+    }
+
+    fn set_with(
+        &mut self,
+        key: &RedisValue,
+        value: &RedisValue,
+        cond: Cond,
+        expiry: NewExpiry,
+        get: bool,
+    ) -> CmdResult {
+        let key = self.keyize(key);
+        let mut db = self.map.lock().expect("unlock failed!");
+
+        // The previous value only matters to GET, which cannot reply with anything else than a string
+        let old = match lookup(&mut db, &key) {
+            Some(set) if get => Some((Some(set.val.string()?.clone()), set.deadline)),
+            Some(set) => Some((None, set.deadline)),
+            None => None,
+        };
+        let perform = match cond {
+            Cond::Always => true,
+            Cond::Nx => old.is_none(),
+            Cond::Xx => old.is_some(),
+        };
+
+        if perform {
+            let deadline = match expiry {
+                NewExpiry::Clear => None,
+                NewExpiry::Keep => old.as_ref().and_then(|(_, deadline)| *deadline),
+                NewExpiry::At(deadline) => Some(deadline),
+            };
+            db.insert(key, Set::with_deadline(value.clone(), deadline));
+        }
+
+        Ok(match (get, old) {
+            (true, Some((Some(old), _))) => old,
+            (false, _) if perform => RedisValue::SimpleString(OK.to_string()),
+            _ => RedisValue::NullBulkString,
+        })
+    }
+
+    /// `SETNX key value`, replying with whether the key was set
+    pub(super) fn setnx(&mut self, key: &RedisValue, value: &RedisValue) -> CmdResult {
+        let reply = self.set_with(key, value, Cond::Nx, NewExpiry::Clear, false)?;
+        Ok(RedisValue::Int((reply != RedisValue::NullBulkString) as i64))
+    }
+
+    /// `SETEX key seconds value` and `PSETEX key milliseconds value`
+    pub(super) fn setex(&mut self, args: &[RedisValue], option: &str, command: &str) -> CmdResult {
+        let expiry = expiry_option(option, &args[1], command)?;
+        self.set_with(&args[0], &args[2], Cond::Always, expiry, false)
+    }
+
+    /// `GETSET key value`, replying with the previous value. The expiry of the key is cleared.
+    pub(super) fn getset(&mut self, key: &RedisValue, value: &RedisValue) -> CmdResult {
+        self.set_with(key, value, Cond::Always, NewExpiry::Clear, true)
+    }
+
+    /// `GET key`
+    pub(super) fn get(&mut self, key: &RedisValue) -> CmdResult {
+        let key = self.keyize(key);
+        let mut db = self.map.lock().expect("unlock failed!");
+
+        match lookup(&mut db, &key) {
+            Some(set) => Ok(set.val.string()?.clone()),
+            None => Ok(RedisValue::NullBulkString),
+        }
+    }
+
+    /// `GETDEL key`
+    pub(super) fn getdel(&mut self, key: &RedisValue) -> CmdResult {
+        let key = self.keyize(key);
+        let mut db = self.map.lock().expect("unlock failed!");
+
+        let Some(set) = lookup(&mut db, &key) else {
+            return Ok(RedisValue::NullBulkString);
+        };
+        let value = set.val.string()?.clone();
+        db.remove(&key);
+        Ok(value)
+    }
+
+    /// `MGET key [key ...]`, replying nil for missing keys and those not holding a string
+    pub(super) fn mget(&mut self, keys: &[RedisValue]) -> CmdResult {
+        let keys: Vec<_> = keys.iter().map(|k| self.keyize(k)).collect();
+        let mut db = self.map.lock().expect("unlock failed!");
+
+        Ok(RedisValue::Array(
+            keys.iter()
+                .map(|key| {
+                    lookup(&mut db, key)
+                        .and_then(|set| set.val.as_string().cloned())
+                        .unwrap_or(RedisValue::NullBulkString)
+                })
+                .collect(),
+        ))
+    }
+
+    /// `MSET key value [key value ...]` and `MSETNX`, the latter setting nothing if any key already exists.
+    /// The whole batch is written under a single lock, so no other client sees it half done.
+    pub(super) fn mset(&mut self, args: &[RedisValue], nx: bool, command: &str) -> CmdResult {
+        if !args.len().is_multiple_of(2) {
+            return Err(RedisError::WrongArity(command.to_string()));
+        }
+        let pairs: Vec<_> = args.chunks(2).map(|kv| (self.keyize(&kv[0]), kv[1].clone())).collect();
+        let mut db = self.map.lock().expect("unlock failed!");
+
+        if nx && pairs.iter().any(|(key, _)| lookup(&mut db, key).is_some()) {
+            return Ok(RedisValue::Int(0));
+        }
+        for (key, value) in pairs {
+            db.insert(key, Set::new(value, None));
+        }
+        Ok(if nx { RedisValue::Int(1) } else { RedisValue::SimpleString(OK.to_string()) })
+    }
+
+    /// `GETEX key [EX seconds | PX milliseconds | EXAT unix-time-seconds | PXAT unix-time-milliseconds |
+    /// PERSIST]`, replying with the value while updating its expiry
+    pub(super) fn getex(&mut self, args: &[RedisValue]) -> CmdResult {
+        let expiry = match &args[1..] {
+            [] => NewExpiry::Keep,
+            [opt] if opt.option_name() == "persist" => NewExpiry::Clear,
+            [opt, value] => match opt.option_name().as_str() {
+                o @ ("ex" | "px" | "exat" | "pxat") => expiry_option(o, value, "getex")?,
+                _ => return Err(RedisError::Syntax),
+            },
+            _ => return Err(RedisError::Syntax),
+        };
+
+        let key = self.keyize(&args[0]);
+        let mut db = self.map.lock().expect("unlock failed!");
+        let Some(set) = lookup(&mut db, &key) else {
+            return Ok(RedisValue::NullBulkString);
+        };
+        let value = set.val.string()?.clone();
+        match expiry {
+            NewExpiry::Keep => {}
+            NewExpiry::Clear => set.deadline = None,
+            NewExpiry::At(deadline) => {
+                set.deadline = Some(deadline);
+                db.track_expiry(&key);
+            }
+        }
+        Ok(value)
+    }
+}
+
+impl RedisValue {
+    /// Reads a stored value as an integer, be it one already or a string holding one
+    fn stored_int(&self) -> Result<RedisInt, RedisError> {
+        match self {
+            RedisValue::Int(n) => Ok(*n),
+            v => v.unpack_bytes_variant().and_then(parse_redis_int).ok_or(RedisError::NotInteger),
+        }
+    }
+
+    /// Reads a stored value as a float, be it an integer or a string holding a number
+    fn stored_float(&self) -> Result<f64, RedisError> {
+        match self {
+            RedisValue::Int(n) => Ok(*n as f64),
+            v => v.unpack_bytes_variant().and_then(parse_redis_float).ok_or(RedisError::NotFloat),
+        }
+    }
+}
+
+impl RespHandler {
+    /// `INCR key`, `DECR key`, `INCRBY key increment` and `DECRBY key decrement`, replying with the new value.
+    ///
+    /// Missing keys count as 0. The value is stored back as a string, keeping the expiry of the key.
+    pub(super) fn incr_by(&mut self, key: &RedisValue, delta: RedisInt) -> CmdResult {
+        let key = self.keyize(key);
+        let mut db = self.map.lock().expect("unlock failed!");
+
+        let (current, deadline) = match lookup(&mut db, &key) {
+            Some(set) => (set.val.string()?.stored_int()?, set.deadline),
+            None => (0, None),
+        };
+        let new = current.checked_add(delta).ok_or(RedisError::Overflow)?;
+
+        let value = RedisValue::bulk(new.to_string());
+        db.insert(key, Set::with_deadline(value, deadline));
+        Ok(RedisValue::Int(new))
+    }
+
+    /// `INCRBYFLOAT key increment`, replying with the new value as a string
+    pub(super) fn incr_by_float(&mut self, key: &RedisValue, delta: f64) -> CmdResult {
+        let key = self.keyize(key);
+        let mut db = self.map.lock().expect("unlock failed!");
+
+        let (current, deadline) = match lookup(&mut db, &key) {
+            Some(set) => (set.val.string()?.stored_float()?, set.deadline),
+            None => (0.0, None),
+        };
+        let new = current + delta;
+        if !new.is_finite() {
+            return Err(RedisError::Other("increment would produce NaN or Infinity".to_string()));
+        }
+
+        let value = RedisValue::bulk(format_double_human(new));
+        db.insert(key, Set::with_deadline(value.clone(), deadline));
+        Ok(value)
+    }
+}
+
+impl RespHandler {
+    /// `APPEND key value`, replying with the length of the string after the append
+    pub(super) fn append(&mut self, key: &RedisValue, value: &RedisValue) -> CmdResult {
+        let value = value.string_bytes().unwrap_or_default();
+        let key = self.keyize(key);
+        let mut db = self.map.lock().expect("unlock failed!");
+
+        let len = match lookup(&mut db, &key) {
+            Some(set) => {
+                let current = set.val.string_bytes().ok_or(RedisError::WrongType)?.len();
+                if current + value.len() > MAX_BULK_LEN {
+                    return Err(too_big());
+                }
+                set.update_string(|s| {
+                    s.extend_from_slice(&value);
+                    s.len()
+                })?
+            }
+            None => {
+                db.insert(key, Set::new(RedisValue::bulk(value.to_vec()), None));
+                value.len()
+            }
+        };
+        Ok(RedisValue::Int(len as RedisInt))
+    }
+
+    /// `STRLEN key`
+    pub(super) fn strlen(&mut self, key: &RedisValue) -> CmdResult {
+        let key = self.keyize(key);
+        let mut db = self.map.lock().expect("unlock failed!");
+
+        let len = match lookup(&mut db, &key) {
+            Some(set) => set.val.string_bytes().ok_or(RedisError::WrongType)?.len(),
+            None => 0,
+        };
+        Ok(RedisValue::Int(len as RedisInt))
+    }
+
+    /// `GETRANGE key start end`, both ends being inclusive and possibly negative
+    pub(super) fn getrange(&mut self, key: &RedisValue, start: RedisInt, end: RedisInt) -> CmdResult {
+        let key = self.keyize(key);
+        let mut db = self.map.lock().expect("unlock failed!");
+
+        let Some(set) = lookup(&mut db, &key) else {
+            return Ok(RedisValue::bulk(""));
+        };
+        let s = set.val.string_bytes().ok_or(RedisError::WrongType)?;
+        Ok(match resolve_range(start, end, s.len()) {
+            Some((start, end)) => RedisValue::bulk(s[start..=end].to_vec()),
+            None => RedisValue::bulk(""),
+        })
+    }
+
+    /// `SETRANGE key offset value`, zero-padding the string up to `offset` if it is shorter.
+    /// Replies with the length of the string after the update.
+    pub(super) fn setrange(&mut self, key: &RedisValue, offset: RedisInt, value: &RedisValue) -> CmdResult {
+        let value = value.string_bytes().unwrap_or_default();
+        let offset = usize::try_from(offset)
+            .map_err(|_| RedisError::Other("offset is out of range".to_string()))?;
+        // Checked before anything gets created
+        if !value.is_empty() && offset + value.len() > MAX_BULK_LEN {
+            return Err(too_big());
+        }
+        let key = self.keyize(key);
+        let mut db = self.map.lock().expect("unlock failed!");
+
+        let current = match lookup(&mut db, &key) {
+            Some(set) => set.val.string_bytes().ok_or(RedisError::WrongType)?.len(),
+            // Nothing to create out of an empty value
+            None if value.is_empty() => return Ok(RedisValue::Int(0)),
+            None => {
+                db.insert(key.clone(), Set::new(RedisValue::bulk(""), None));
+                0
+            }
+        };
+        if value.is_empty() {
+            return Ok(RedisValue::Int(current as RedisInt));
+        }
+
+        let set = lookup(&mut db, &key).ok_or(RedisError::WrongType)?;
+        let len = set.update_string(|s| {
+            if s.len() < offset + value.len() {
+                s.resize(offset + value.len(), 0);
+            }
+            s[offset..offset + value.len()].copy_from_slice(&value);
+            s.len()
+        })?;
+        Ok(RedisValue::Int(len as RedisInt))
+    }
+
+    /// `LCS key1 key2 [LEN] [IDX] [MINMATCHLEN min-match-len] [WITHMATCHLEN]`
+    ///
+    /// Replies with the longest common subsequence of both strings, its length only with LEN, or the
+    /// ranges of the matches (last ones first) with IDX.
+    pub(super) fn lcs(&mut self, args: &[RedisValue]) -> CmdResult {
+        let (mut len_only, mut idx, mut with_match_len, mut min_match_len) = (false, false, false, 0);
+        let mut opts = args[2..].iter();
+        while let Some(opt) = opts.next() {
+            match opt.option_name().as_str() {
+                "len" => len_only = true,
+                "idx" => idx = true,
+                "withmatchlen" => with_match_len = true,
+                "minmatchlen" => {
+                    let min = opts.next().ok_or(RedisError::Syntax)?.int_arg()?;
+                    min_match_len = min.max(0) as usize;
+                }
+                _ => return Err(RedisError::Syntax),
+            }
+        }
+        if len_only && idx {
+            return Err(RedisError::Other(
+                "If you want both the length and indexes, please just use IDX.".to_string(),
+            ));
+        }
+
+        let (a, b) = {
+            let (ka, kb) = (self.keyize(&args[0]), self.keyize(&args[1]));
+            let mut db = self.map.lock().expect("unlock failed!");
+            let mut read = |key| match lookup(&mut db, key) {
+                Some(set) => set.val.string_bytes().map(|s| s.into_owned()).ok_or_else(|| {
+                    RedisError::Other("The specified keys must contain string values".to_string())
+                }),
+                None => Ok(vec![]),
+            };
+            (read(&ka)?, read(&kb)?)
+        };
+
+        // lcs[i][j]: length of the LCS of a[..i] and b[..j]
+        let width = b.len() + 1;
+        let cells = (a.len() + 1).checked_mul(width).filter(|&cells| cells < u32::MAX as usize);
+        let Some(cells) = cells.filter(|&cells| cells * size_of::<u32>() <= MAX_BULK_LEN) else {
+            return Err(RedisError::Other(
+                "Insufficient memory, transient memory for LCS exceeds proto-max-bulk-len".to_string(),
+            ));
+        };
+        let mut lcs = vec![0u32; cells];
+        for i in 1..=a.len() {
+            for j in 1..=b.len() {
+                lcs[i * width + j] = if a[i - 1] == b[j - 1] {
+                    lcs[(i - 1) * width + j - 1] + 1
+                } else {
+                    lcs[(i - 1) * width + j].max(lcs[i * width + j - 1])
+                };
+            }
+        }
+        let len = lcs[a.len() * width + b.len()] as usize;
+        if len_only {
+            return Ok(RedisValue::Int(len as RedisInt));
+        }
+
+        // Walk the table back from the end, collecting the common bytes and the ranges they form
+        let mut result = vec![0u8; len];
+        let mut matches = vec![];
+        let (mut i, mut j, mut k) = (a.len(), b.len(), len);
+        let mut range: Option<(usize, usize, usize, usize)> = None; // a start, a end, b start, b end
+        while i > 0 && j > 0 {
+            let mut emit = false;
+            if a[i - 1] == b[j - 1] {
+                result[k - 1] = a[i - 1];
+                range = match range {
+                    None => Some((i - 1, i - 1, j - 1, j - 1)),
+                    // Contiguous with the current range, which extends backward
+                    Some((sa, ea, sb, eb)) if sa == i && sb == j => Some((sa - 1, ea, sb - 1, eb)),
+                    Some(r) => {
+                        emit = true;
+                        Some(r)
+                    }
+                };
+                emit |= range.is_some_and(|(sa, _, sb, _)| sa == 0 || sb == 0);
+                k -= 1;
+                i -= 1;
+                j -= 1;
+            } else {
+                if lcs[(i - 1) * width + j] > lcs[i * width + j - 1] {
+                    i -= 1;
+                } else {
+                    j -= 1;
+                }
+                emit = range.is_some();
+            }
+
+            if emit && let Some((sa, ea, sb, eb)) = range.take() {
+                let match_len = ea - sa + 1;
+                if idx && match_len >= min_match_len {
+                    let mut m = vec![
+                        RedisValue::Array(vec![RedisValue::Int(sa as RedisInt), RedisValue::Int(ea as RedisInt)]),
+                        RedisValue::Array(vec![RedisValue::Int(sb as RedisInt), RedisValue::Int(eb as RedisInt)]),
+                    ];
+                    if with_match_len {
+                        m.push(RedisValue::Int(match_len as RedisInt));
+                    }
+                    matches.push(RedisValue::Array(m));
+                }
+            }
+        }
+
+        if idx {
+            return Ok(RedisValue::Map(vec![
+                (RedisValue::bulk("matches"), RedisValue::Array(matches)),
+                (RedisValue::bulk("len"), RedisValue::Int(len as RedisInt)),
+            ]));
+        }
+        Ok(RedisValue::bulk(result))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::resp::test::{bulk, connected_handler};
+    use crate::{RedisError, RedisValue};
+
+    fn ok() -> RedisValue {
+        RedisValue::SimpleString("OK".to_string())
+    }
+
+    #[tokio::test]
+    async fn set_options() {
+        let (mut handler, _client) = connected_handler().await;
+        let mut set = async |args: &[&str]| {
+            handler.handle_command("set", args.iter().map(|a| bulk(a)).collect()).await
+        };
+
+        assert_eq!(set(&["k", "v", "XX"]).await, RedisValue::NullBulkString);
+        assert_eq!(set(&["k", "v", "NX", "PX", "100000"]).await, ok());
+        assert_eq!(set(&["k", "w", "NX"]).await, RedisValue::NullBulkString);
+        assert_eq!(set(&["k", "w", "XX", "GET", "KEEPTTL"]).await, bulk("v"));
+        assert_eq!(set(&["k", "x", "NX", "GET"]).await, bulk("w"));
+
+        assert_eq!(set(&["k", "v", "NX", "XX"]).await, RedisError::Syntax.into());
+        assert_eq!(set(&["k", "v", "EX", "1", "PX", "1"]).await, RedisError::Syntax.into());
+        assert_eq!(set(&["k", "v", "KEEPTTL", "EX", "1"]).await, RedisError::Syntax.into());
+        assert_eq!(set(&["k", "v", "EX"]).await, RedisError::Syntax.into());
+        assert_eq!(set(&["k", "v", "EX", "0"]).await, RedisError::InvalidExpire("set".to_string()).into());
+        assert_eq!(set(&["k", "v", "EXAT", "soon"]).await, RedisError::NotInteger.into());
+
+        let ttl = handler.handle_command("pttl", vec![bulk("k")]).await;
+        assert!(matches!(ttl, RedisValue::Int(99_000..=100_000)));
+        handler.handle_command("set", vec![bulk("k"), bulk("v")]).await;
+        assert_eq!(handler.handle_command("ttl", vec![bulk("k")]).await, RedisValue::Int(-1));
+    }
+
+    #[tokio::test]
+    async fn set_variants() {
+        let (mut handler, _client) = connected_handler().await;
+
+        assert_eq!(handler.handle_command("setnx", vec![bulk("k"), bulk("v")]).await, RedisValue::Int(1));
+        assert_eq!(handler.handle_command("setnx", vec![bulk("k"), bulk("w")]).await, RedisValue::Int(0));
+        let setex = handler.handle_command("setex", vec![bulk("k"), bulk("10"), bulk("w")]).await;
+        assert_eq!(setex, ok());
+        assert_eq!(handler.handle_command("ttl", vec![bulk("k")]).await, RedisValue::Int(10));
+        let psetex = handler.handle_command("psetex", vec![bulk("k"), bulk("-5"), bulk("w")]).await;
+        assert_eq!(psetex, RedisError::InvalidExpire("psetex".to_string()).into());
+
+        assert_eq!(handler.handle_command("getset", vec![bulk("k"), bulk("x")]).await, bulk("w"));
+        assert_eq!(handler.handle_command("ttl", vec![bulk("k")]).await, RedisValue::Int(-1));
+        assert_eq!(handler.handle_command("getset", vec![bulk("n"), bulk("x")]).await, RedisValue::NullBulkString);
+    }
+
+    #[tokio::test]
+    async fn incr_family() {
+        let (mut handler, _client) = connected_handler().await;
+
+        assert_eq!(handler.handle_command("incr", vec![bulk("n")]).await, RedisValue::Int(1));
+        assert_eq!(handler.handle_command("get", vec![bulk("n")]).await, bulk("1"));
+        handler.handle_command("set", vec![bulk("n"), bulk("10"), bulk("ex"), bulk("100")]).await;
+        assert_eq!(handler.handle_command("incrby", vec![bulk("n"), bulk("5")]).await, RedisValue::Int(15));
+        assert_eq!(handler.handle_command("decr", vec![bulk("n")]).await, RedisValue::Int(14));
+        assert_eq!(handler.handle_command("decrby", vec![bulk("n"), bulk("20")]).await, RedisValue::Int(-6));
+        assert_eq!(handler.handle_command("ttl", vec![bulk("n")]).await, RedisValue::Int(100));
+
+        handler.handle_command("set", vec![bulk("i"), RedisValue::Int(41)]).await;
+        assert_eq!(handler.handle_command("incr", vec![bulk("i")]).await, RedisValue::Int(42));
+
+        handler.handle_command("set", vec![bulk("s"), bulk("x1")]).await;
+        assert_eq!(handler.handle_command("incr", vec![bulk("s")]).await, RedisError::NotInteger.into());
+        assert_eq!(
+            handler.handle_command("incrby", vec![bulk("n"), bulk("1.5")]).await,
+            RedisError::NotInteger.into()
+        );
+
+        handler.handle_command("set", vec![bulk("m"), bulk(&i64::MAX.to_string())]).await;
+        assert_eq!(handler.handle_command("incr", vec![bulk("m")]).await, RedisError::Overflow.into());
+        let decrby = handler.handle_command("decrby", vec![bulk("m"), bulk(&i64::MIN.to_string())]).await;
+        assert_eq!(decrby, RedisError::Other("decrement would overflow".to_string()).into());
+    }
+
+    #[tokio::test]
+    async fn incrbyfloat() {
+        let (mut handler, _client) = connected_handler().await;
+
+        assert_eq!(handler.handle_command("incrbyfloat", vec![bulk("f"), bulk("0.1")]).await, bulk("0.1"));
+        assert_eq!(handler.handle_command("incrbyfloat", vec![bulk("f"), bulk("0.2")]).await, bulk("0.3"));
+        handler.handle_command("set", vec![bulk("f"), bulk("10.50")]).await;
+        assert_eq!(handler.handle_command("incrbyfloat", vec![bulk("f"), bulk("0.1")]).await, bulk("10.6"));
+        assert_eq!(handler.handle_command("incrbyfloat", vec![bulk("f"), bulk("-5.6")]).await, bulk("5"));
+        assert_eq!(handler.handle_command("incrbyfloat", vec![bulk("f"), bulk("5.0e3")]).await, bulk("5005"));
+        assert_eq!(handler.handle_command("incr", vec![bulk("f")]).await, RedisValue::Int(5006));
+
+        assert_eq!(
+            handler.handle_command("incrbyfloat", vec![bulk("f"), bulk("inf")]).await,
+            RedisError::Other("increment would produce NaN or Infinity".to_string()).into()
+        );
+        assert_eq!(
+            handler.handle_command("incrbyfloat", vec![bulk("f"), bulk("abc")]).await,
+            RedisError::NotFloat.into()
+        );
+    }
+
+    #[test]
+    fn ranges() {
+        use super::resolve_range;
+        assert_eq!(resolve_range(0, -1, 5), Some((0, 4)));
+        assert_eq!(resolve_range(-3, -2, 5), Some((2, 3)));
+        assert_eq!(resolve_range(3, 100, 5), Some((3, 4)));
+        assert_eq!(resolve_range(-100, 1, 5), Some((0, 1)));
+        assert_eq!(resolve_range(-1, -3, 5), None);
+        assert_eq!(resolve_range(4, 2, 5), None);
+        assert_eq!(resolve_range(0, -1, 0), None);
+    }
+
+    #[tokio::test]
+    async fn append_strlen_ranges() {
+        let (mut handler, _client) = connected_handler().await;
+
+        assert_eq!(handler.handle_command("append", vec![bulk("k"), bulk("Hello")]).await, RedisValue::Int(5));
+        assert_eq!(handler.handle_command("append", vec![bulk("k"), bulk(" World")]).await, RedisValue::Int(11));
+        assert_eq!(handler.handle_command("strlen", vec![bulk("k")]).await, RedisValue::Int(11));
+        assert_eq!(handler.handle_command("strlen", vec![bulk("nope")]).await, RedisValue::Int(0));
+
+        let getrange = async |handler: &mut crate::RespHandler, start: &str, end: &str| {
+            handler.handle_command("getrange", vec![bulk("k"), bulk(start), bulk(end)]).await
+        };
+        assert_eq!(getrange(&mut handler, "0", "4").await, bulk("Hello"));
+        assert_eq!(getrange(&mut handler, "-5", "-1").await, bulk("World"));
+        assert_eq!(getrange(&mut handler, "5", "1").await, bulk(""));
+
+        assert_eq!(
+            handler.handle_command("setrange", vec![bulk("k"), bulk("6"), bulk("Redis")]).await,
+            RedisValue::Int(11)
+        );
+        assert_eq!(handler.handle_command("get", vec![bulk("k")]).await, bulk("Hello Redis"));
+        assert_eq!(
+            handler.handle_command("setrange", vec![bulk("p"), bulk("3"), bulk("ab")]).await,
+            RedisValue::Int(5)
+        );
+        assert_eq!(handler.handle_command("get", vec![bulk("p")]).await, RedisValue::bulk(&b"\0\0\0ab"[..]));
+        assert_eq!(
+            handler.handle_command("setrange", vec![bulk("q"), bulk("3"), bulk("")]).await,
+            RedisValue::Int(0)
+        );
+        assert_eq!(handler.handle_command("exists", vec![bulk("q")]).await, RedisValue::Int(0));
+        assert_eq!(
+            handler.handle_command("setrange", vec![bulk("q"), bulk("-1"), bulk("a")]).await,
+            RedisError::Other("offset is out of range".to_string()).into()
+        );
+        assert_eq!(
+            handler.handle_command("setrange", vec![bulk("q"), bulk("536870911"), bulk("xy")]).await,
+            RedisError::Other("string exceeds maximum allowed size (proto-max-bulk-len)".to_string()).into()
+        );
+        assert_eq!(handler.handle_command("exists", vec![bulk("q")]).await, RedisValue::Int(0));
+
+        handler.handle_command("incr", vec![bulk("n")]).await;
+        assert_eq!(handler.handle_command("append", vec![bulk("n"), bulk("0")]).await, RedisValue::Int(2));
+        assert_eq!(handler.handle_command("incr", vec![bulk("n")]).await, RedisValue::Int(11));
+    }
+
+    #[tokio::test]
+    async fn lcs() {
+        let (mut handler, _client) = connected_handler().await;
+        handler.handle_command("set", vec![bulk("key1"), bulk("ohmytext")]).await;
+        handler.handle_command("set", vec![bulk("key2"), bulk("mynewtext")]).await;
+
+        let mut lcs = async |opts: &[&str]| {
+            let mut args = vec![bulk("key1"), bulk("key2")];
+            args.extend(opts.iter().map(|o| bulk(o)));
+            handler.handle_command("lcs", args).await
+        };
+        assert_eq!(lcs(&[]).await, bulk("mytext"));
+        assert_eq!(lcs(&["LEN"]).await, RedisValue::Int(6));
+
+        let range = |a: i64, b: i64| RedisValue::Array(vec![RedisValue::Int(a), RedisValue::Int(b)]);
+        assert_eq!(
+            lcs(&["IDX", "MINMATCHLEN", "4", "WITHMATCHLEN"]).await,
+            RedisValue::Map(vec![
+                (
+                    bulk("matches"),
+                    RedisValue::Array(vec![RedisValue::Array(vec![range(4, 7), range(5, 8), RedisValue::Int(4)])])
+                ),
+                (bulk("len"), RedisValue::Int(6)),
+            ])
+        );
+        assert_eq!(
+            lcs(&["IDX"]).await,
+            RedisValue::Map(vec![
+                (
+                    bulk("matches"),
+                    RedisValue::Array(vec![
+                        RedisValue::Array(vec![range(4, 7), range(5, 8)]),
+                        RedisValue::Array(vec![range(2, 3), range(0, 1)]),
+                    ])
+                ),
+                (bulk("len"), RedisValue::Int(6)),
+            ])
+        );
+
+        let long = "a".repeat(100_000);
+        handler.handle_command("set", vec![bulk("key1"), bulk(&long)]).await;
+        handler.handle_command("set", vec![bulk("key2"), bulk(&long)]).await;
+        let too_big = "Insufficient memory, transient memory for LCS exceeds proto-max-bulk-len";
+        assert_eq!(
+            handler.handle_command("lcs", vec![bulk("key1"), bulk("key2")]).await,
+            RedisError::Other(too_big.to_string()).into()
+        );
+    }
+
+    #[tokio::test]
+    async fn multi_keys() {
+        let (mut handler, _client) = connected_handler().await;
+
+        assert_eq!(
+            handler.handle_command("mset", vec![bulk("a"), bulk("1"), bulk("b"), bulk("2")]).await,
+            RedisValue::SimpleString("OK".to_string())
+        );
+        assert_eq!(
+            handler.handle_command("mset", vec![bulk("a"), bulk("1"), bulk("b")]).await,
+            RedisError::WrongArity("mset".to_string()).into()
+        );
+        assert_eq!(
+            handler.handle_command("mget", vec![bulk("a"), bulk("nope"), bulk("b")]).await,
+            RedisValue::Array(vec![bulk("1"), RedisValue::NullBulkString, bulk("2")])
+        );
+
+        assert_eq!(
+            handler.handle_command("msetnx", vec![bulk("c"), bulk("3"), bulk("a"), bulk("x")]).await,
+            RedisValue::Int(0)
+        );
+        assert_eq!(handler.handle_command("exists", vec![bulk("c")]).await, RedisValue::Int(0));
+        assert_eq!(
+            handler.handle_command("msetnx", vec![bulk("c"), bulk("3"), bulk("d"), bulk("4")]).await,
+            RedisValue::Int(1)
+        );
+        assert_eq!(
+            handler.handle_command("mget", vec![bulk("c"), bulk("d"), bulk("a")]).await,
+            RedisValue::Array(vec![bulk("3"), bulk("4"), bulk("1")])
+        );
+    }
+
+    #[tokio::test]
+    async fn getdel_getex() {
+        let (mut handler, _client) = connected_handler().await;
+        handler.handle_command("set", vec![bulk("k"), bulk("v")]).await;
+
+        assert_eq!(handler.handle_command("getex", vec![bulk("k"), bulk("ex"), bulk("50")]).await, bulk("v"));
+        assert_eq!(handler.handle_command("ttl", vec![bulk("k")]).await, RedisValue::Int(50));
+        assert_eq!(handler.handle_command("getex", vec![bulk("k"), bulk("persist")]).await, bulk("v"));
+        assert_eq!(handler.handle_command("ttl", vec![bulk("k")]).await, RedisValue::Int(-1));
+        assert_eq!(
+            handler.handle_command("getex", vec![bulk("k"), bulk("persist"), bulk("ex")]).await,
+            RedisError::Syntax.into()
+        );
+
+        assert_eq!(handler.handle_command("getdel", vec![bulk("k")]).await, bulk("v"));
+        assert_eq!(handler.handle_command("getdel", vec![bulk("k")]).await, RedisValue::NullBulkString);
+        assert_eq!(handler.handle_command("getex", vec![bulk("k")]).await, RedisValue::NullBulkString);
+    }
+}